@@ -1,5 +1,9 @@
 // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
 fn main() -> anyhow::Result<()> {
+    // The tests built on the host don't link with the ESP-IDF
+    if std::env::var_os("CARGO_FEATURE_ESP").is_none() {
+        return Ok(());
+    }
     embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
     embuild::build::LinkArgs::output_propagated("ESP_IDF")
 }
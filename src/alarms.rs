@@ -0,0 +1,604 @@
+#[cfg(feature = "esp")]
+use anyhow::Result;
+
+#[cfg(feature = "esp")]
+use log::*;
+
+use pcf8563::DateTime;
+#[cfg(feature = "esp")]
+use pcf8563::{Control, PCF8563};
+
+#[cfg(feature = "esp")]
+use crate::calendar;
+use crate::storage::Persist;
+#[cfg(feature = "esp")]
+use crate::storage::Storage;
+#[cfg(feature = "esp")]
+use crate::twatch::{Device, TwatchError};
+#[cfg(feature = "esp")]
+use crate::types::EspSharedBusI2c0;
+#[cfg(feature = "esp")]
+use crate::tz::TimeZone;
+
+const STORAGE_KEY: &str = "alarms";
+const STORAGE_VERSION: u8 = 2;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+pub const MAX_ALARMS: usize = 8;
+pub const SNOOZE_MINUTES: u32 = 9;
+
+/// Set of days of the week, bit 0 being Sunday as in the PCF8563 weekday register
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Weekdays(u8);
+
+impl Weekdays {
+    pub const NONE: Weekdays = Weekdays(0);
+    pub const WORKDAYS: Weekdays = Weekdays(0b0011_1110);
+    pub const ALL: Weekdays = Weekdays(0b0111_1111);
+
+    pub const LABELS: [&'static str; 7] = ["Su", "Mo", "Tu", "We", "Th", "Fr", "Sa"];
+
+    pub fn contains(&self, weekday: u8) -> bool {
+        self.0 & (1 << (weekday % 7)) != 0
+    }
+
+    pub fn toggle(&mut self, weekday: u8) {
+        self.0 ^= 1 << (weekday % 7);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 & Self::ALL.0 == 0
+    }
+}
+
+impl std::fmt::Display for Weekdays {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Weekdays::NONE => f.write_str("once"),
+            Weekdays::ALL => f.write_str("every day"),
+            Weekdays::WORKDAYS => f.write_str("Mo-Fr"),
+            days => {
+                let labels: Vec<&str> = (0..7)
+                    .filter(|d| days.contains(*d))
+                    .map(|d| Weekdays::LABELS[d as usize])
+                    .collect();
+                f.write_str(&labels.join(" "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alarm {
+    pub hours: u8,
+    pub minutes: u8,
+    /// Days the alarm repeats on, a one-shot alarm when empty
+    pub repeat: Weekdays,
+    pub enabled: bool,
+}
+
+impl Alarm {
+    pub fn new(hours: u8, minutes: u8) -> Self {
+        Self {
+            hours,
+            minutes,
+            repeat: Weekdays::NONE,
+            enabled: true,
+        }
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        self.hours as u32 * 60 + self.minutes as u32
+    }
+
+    /// Minutes from `now` until this alarm next rings, strictly in the future
+    pub fn minutes_until(&self, now: &DateTime) -> Option<u32> {
+        if !self.enabled {
+            return None;
+        }
+        let now_minute = now.hours as u32 * 60 + now.minutes as u32;
+        (0..=7u32)
+            .filter(|d| self.repeat.is_empty() || self.repeat.contains(now.weekday + *d as u8))
+            .map(|d| d * MINUTES_PER_DAY + self.minute_of_day())
+            .find(|m| *m > now_minute)
+            .map(|m| m - now_minute)
+    }
+
+    fn rings_at(&self, now: &DateTime) -> bool {
+        self.enabled
+            && self.hours == now.hours
+            && self.minutes == now.minutes
+            && (self.repeat.is_empty() || self.repeat.contains(now.weekday))
+    }
+}
+
+impl std::fmt::Display for Alarm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!("{:02}:{:02}", self.hours, self.minutes))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct AlarmList {
+    alarms: Vec<Alarm>,
+    snoozed: Option<Alarm>,
+    /// Alarm programmed in the RTC, the one ringing when its flag is set
+    programmed: Option<Alarm>,
+}
+
+impl AlarmList {
+    /// Next alarm to ring after `now`, and the minutes until then
+    fn next(&self, now: &DateTime) -> Option<(Alarm, u32)> {
+        self.alarms
+            .iter()
+            .chain(self.snoozed.iter())
+            .filter_map(|a| Some((*a, a.minutes_until(now)?)))
+            .min_by_key(|(_, minutes)| *minutes)
+    }
+
+    /// Ring the programmed alarm, the snoozed and one-shot alarms being done
+    fn ring(&mut self) -> Option<Alarm> {
+        let rang = self.programmed.take()?;
+        if self.snoozed == Some(rang) {
+            self.snoozed = None;
+        }
+        for alarm in self.alarms.iter_mut().filter(|a| **a == rang) {
+            if alarm.repeat.is_empty() {
+                alarm.enabled = false;
+            }
+        }
+        Some(rang)
+    }
+}
+
+impl Persist for AlarmList {
+    fn encode(&self) -> Vec<u8> {
+        let encode_alarm = |a: &Alarm| [a.hours, a.minutes, a.repeat.0, a.enabled as u8];
+        let mut data = vec![
+            STORAGE_VERSION,
+            self.snoozed.is_some() as u8,
+            self.programmed.is_some() as u8,
+        ];
+        for alarm in self
+            .snoozed
+            .iter()
+            .chain(&self.programmed)
+            .chain(&self.alarms)
+        {
+            data.extend_from_slice(&encode_alarm(alarm));
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let decode_alarm = |c: &[u8]| Alarm {
+            hours: c[0],
+            minutes: c[1],
+            repeat: Weekdays(c[2]),
+            enabled: c[3] != 0,
+        };
+        let (has_snoozed, has_programmed, rest) = match data {
+            [STORAGE_VERSION, has_snoozed, has_programmed, rest @ ..] => {
+                (*has_snoozed != 0, *has_programmed != 0, rest)
+            }
+            // Before the programmed alarm was kept
+            [1, has_snoozed, rest @ ..] => (*has_snoozed != 0, false, rest),
+            _ => return None,
+        };
+        if rest.len() % 4 != 0 {
+            return None;
+        }
+        let mut alarms = rest.chunks(4).map(decode_alarm);
+        let snoozed = if has_snoozed {
+            Some(alarms.next()?)
+        } else {
+            None
+        };
+        let programmed = if has_programmed {
+            Some(alarms.next()?)
+        } else {
+            None
+        };
+        Some(Self {
+            alarms: alarms.take(MAX_ALARMS).collect(),
+            snoozed,
+            programmed,
+        })
+    }
+}
+
+/// Alarms programmed in the PCF8563 and persisted in NVS
+///
+/// Only the next alarm to ring is programmed in the RTC, it is reprogrammed
+/// each time the list changes or an alarm rings. The RTC keeps ringing while
+/// the ESP32 is in deep sleep, the programmed alarm is stored with the list to
+/// know which one rang.
+#[cfg(feature = "esp")]
+#[derive(Default)]
+pub struct AlarmService {
+    list: AlarmList,
+}
+
+#[cfg(feature = "esp")]
+impl AlarmService {
+    pub fn load(&mut self, storage: &Storage) -> Result<()> {
+        self.list = storage.get(STORAGE_KEY)?.unwrap_or_default();
+        info!("{} alarms loaded", self.list.alarms.len());
+        Ok(())
+    }
+
+    pub fn alarms(&self) -> &[Alarm] {
+        &self.list.alarms
+    }
+
+    pub fn add(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        storage: &mut Storage,
+        tz: &TimeZone,
+        alarm: Alarm,
+    ) -> Result<()> {
+        if self.list.alarms.len() >= MAX_ALARMS {
+            anyhow::bail!("Too many alarms");
+        }
+        self.list.alarms.push(alarm);
+        self.schedule(clock, storage, tz)
+    }
+
+    pub fn update(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        storage: &mut Storage,
+        tz: &TimeZone,
+        index: usize,
+        alarm: Alarm,
+    ) -> Result<()> {
+        match self.list.alarms.get_mut(index) {
+            Some(a) => *a = alarm,
+            None => anyhow::bail!("No alarm at index {index}"),
+        }
+        self.schedule(clock, storage, tz)
+    }
+
+    pub fn remove(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        storage: &mut Storage,
+        tz: &TimeZone,
+        index: usize,
+    ) -> Result<()> {
+        if index < self.list.alarms.len() {
+            self.list.alarms.remove(index);
+        }
+        self.schedule(clock, storage, tz)
+    }
+
+    /// Ring again `SNOOZE_MINUTES` from now
+    pub fn snooze(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        storage: &mut Storage,
+        tz: &TimeZone,
+    ) -> Result<()> {
+        let now = local_now(clock, tz)?;
+        let minute = (now.hours as u32 * 60 + now.minutes as u32 + SNOOZE_MINUTES) % MINUTES_PER_DAY;
+        self.list.snoozed = Some(Alarm::new((minute / 60) as u8, (minute % 60) as u8));
+        self.schedule(clock, storage, tz)
+    }
+
+    /// Handle the RTC interrupt, returning the alarm which rang if any
+    pub fn on_irq(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        storage: &mut Storage,
+        tz: &TimeZone,
+    ) -> Result<Option<Alarm>> {
+        if !clock
+            .get_alarm_flag()
+            .map_err(TwatchError::driver(Device::Clock, "get alarm flag"))?
+        {
+            return Ok(None);
+        }
+        clock
+            .clear_alarm_flag()
+            .map_err(TwatchError::driver(Device::Clock, "clear alarm flag"))?;
+        let rang = self.list.ring();
+        match rang {
+            Some(alarm) => {
+                // Rung anyway, e.g. after waking the watch up from deep sleep
+                if let Ok(now) = local_now(clock, tz) {
+                    if !alarm.rings_at(&now) {
+                        info!(
+                            "Alarm {alarm} handled late, at {:02}:{:02}",
+                            now.hours, now.minutes
+                        );
+                    }
+                }
+            }
+            None => warn!("RTC alarm flag set but no alarm was programmed"),
+        }
+
+        self.schedule(clock, storage, tz)?;
+        Ok(rang)
+    }
+
+    /// Program the RTC with the next alarm to ring and store it
+    ///
+    /// Alarms are set in local time while the RTC runs in UTC.
+    pub fn schedule(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        storage: &mut Storage,
+        tz: &TimeZone,
+    ) -> Result<()> {
+        let programmed = self.program(clock, tz);
+        storage.put(STORAGE_KEY, &self.list)?;
+        programmed
+    }
+
+    fn program(
+        &mut self,
+        clock: &mut PCF8563<EspSharedBusI2c0<'static>>,
+        tz: &TimeZone,
+    ) -> Result<()> {
+        self.list.programmed = None;
+        let now = local_now(clock, tz)?;
+        let next = self.list.next(&now);
+
+        clock
+            .disable_all_alarms()
+            .map_err(TwatchError::driver(Device::Clock, "disable all alarms"))?;
+        match next {
+            Some((alarm, minutes)) => {
+                let local = calendar::to_timestamp(&now) - now.seconds as i64 + minutes as i64 * 60;
+                let at = calendar::from_timestamp(tz.to_utc(local));
+                info!(
+                    "Next alarm in {minutes} min: {:02}:{:02} UTC weekday {}",
+                    at.hours, at.minutes, at.weekday
+                );
+                clock
+                    .set_alarm_minutes(at.minutes)
+                    .map_err(TwatchError::driver(Device::Clock, "set alarm minutes"))?;
+                clock
+                    .set_alarm_hours(at.hours)
+                    .map_err(TwatchError::driver(Device::Clock, "set alarm hours"))?;
+                clock
+                    .set_alarm_weekday(at.weekday)
+                    .map_err(TwatchError::driver(Device::Clock, "set alarm weekday"))?;
+                clock
+                    .control_alarm_minutes(Control::On)
+                    .map_err(TwatchError::driver(Device::Clock, "control alarm minutes"))?;
+                clock
+                    .control_alarm_hours(Control::On)
+                    .map_err(TwatchError::driver(Device::Clock, "control alarm hours"))?;
+                clock
+                    .control_alarm_weekday(Control::On)
+                    .map_err(TwatchError::driver(Device::Clock, "control alarm weekday"))?;
+                clock
+                    .control_alarm_interrupt(Control::On)
+                    .map_err(TwatchError::driver(
+                        Device::Clock,
+                        "control alarm interrupt",
+                    ))?;
+                self.list.programmed = Some(alarm);
+            }
+            None => {
+                info!("No alarm scheduled");
+                clock
+                    .control_alarm_interrupt(Control::Off)
+                    .map_err(TwatchError::driver(
+                        Device::Clock,
+                        "control alarm interrupt",
+                    ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "esp")]
+fn local_now(clock: &mut PCF8563<EspSharedBusI2c0<'static>>, tz: &TimeZone) -> Result<DateTime> {
+    let utc = clock
+        .get_datetime()
+        .map_err(TwatchError::driver(Device::Clock, "get datetime"))?;
+    Ok(calendar::from_timestamp(
+        tz.to_local(calendar::to_timestamp(&utc)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Time on the week of Sunday 2024-01-07
+    fn at(weekday: u8, hours: u8, minutes: u8) -> DateTime {
+        DateTime {
+            year: 24,
+            month: 1,
+            day: 7 + weekday,
+            weekday,
+            hours,
+            minutes,
+            seconds: 0,
+        }
+    }
+
+    fn repeating(hours: u8, minutes: u8, repeat: Weekdays) -> Alarm {
+        Alarm {
+            repeat,
+            ..Alarm::new(hours, minutes)
+        }
+    }
+
+    #[test]
+    fn counts_the_minutes_until_a_one_shot_alarm() {
+        let alarm = Alarm::new(7, 30);
+        assert_eq!(alarm.minutes_until(&at(1, 7, 0)), Some(30));
+        assert_eq!(alarm.minutes_until(&at(1, 7, 29)), Some(1));
+        // Strictly in the future, the next day when it is due now
+        assert_eq!(alarm.minutes_until(&at(1, 7, 30)), Some(MINUTES_PER_DAY));
+        assert_eq!(alarm.minutes_until(&at(6, 23, 59)), Some(7 * 60 + 31));
+
+        let disabled = Alarm {
+            enabled: false,
+            ..alarm
+        };
+        assert_eq!(disabled.minutes_until(&at(1, 7, 0)), None);
+    }
+
+    #[test]
+    fn counts_the_minutes_until_a_repeating_alarm() {
+        let workdays = repeating(7, 0, Weekdays::WORKDAYS);
+        assert_eq!(workdays.minutes_until(&at(1, 6, 0)), Some(60));
+        // Friday after the alarm, the next one is on Monday
+        assert_eq!(
+            workdays.minutes_until(&at(5, 8, 0)),
+            Some(3 * MINUTES_PER_DAY - 60)
+        );
+
+        // Across the end of the week
+        let sunday = repeating(7, 0, Weekdays(0b1));
+        assert_eq!(
+            sunday.minutes_until(&at(6, 8, 0)),
+            Some(MINUTES_PER_DAY - 60)
+        );
+        // Due now, a week later
+        assert_eq!(
+            sunday.minutes_until(&at(0, 7, 0)),
+            Some(7 * MINUTES_PER_DAY)
+        );
+        assert_eq!(
+            sunday.minutes_until(&at(0, 8, 0)),
+            Some(7 * MINUTES_PER_DAY - 60)
+        );
+    }
+
+    #[test]
+    fn rings_at_its_minute_and_days() {
+        let alarm = Alarm::new(7, 30);
+        assert!(alarm.rings_at(&at(3, 7, 30)));
+        assert!(!alarm.rings_at(&at(3, 7, 31)));
+        assert!(!alarm.rings_at(&at(3, 8, 30)));
+
+        let workdays = repeating(7, 30, Weekdays::WORKDAYS);
+        assert!(workdays.rings_at(&at(1, 7, 30)));
+        assert!(!workdays.rings_at(&at(0, 7, 30)));
+        assert!(!workdays.rings_at(&at(6, 7, 30)));
+
+        let disabled = Alarm {
+            enabled: false,
+            ..alarm
+        };
+        assert!(!disabled.rings_at(&at(3, 7, 30)));
+    }
+
+    #[test]
+    fn describes_the_days() {
+        assert_eq!(Weekdays::NONE.to_string(), "once");
+        assert_eq!(Weekdays::ALL.to_string(), "every day");
+        assert_eq!(Weekdays::WORKDAYS.to_string(), "Mo-Fr");
+        let mut weekend = Weekdays::NONE;
+        weekend.toggle(6);
+        weekend.toggle(0);
+        assert_eq!(weekend.to_string(), "Su Sa");
+        weekend.toggle(6);
+        assert!(weekend.contains(7));
+        assert!(!weekend.contains(6));
+    }
+
+    #[test]
+    fn rings_the_programmed_alarm() {
+        let once = Alarm::new(7, 0);
+        let daily = repeating(8, 0, Weekdays::ALL);
+        let mut list = AlarmList {
+            alarms: vec![once, daily],
+            ..Default::default()
+        };
+        assert_eq!(list.ring(), None);
+
+        assert_eq!(list.next(&at(1, 6, 0)), Some((once, 60)));
+        list.programmed = Some(once);
+        // Even when handled after its minute
+        assert_eq!(list.ring(), Some(once));
+        assert!(!list.alarms[0].enabled);
+        assert_eq!(list.programmed, None);
+
+        assert_eq!(list.next(&at(1, 7, 0)), Some((daily, 60)));
+        list.programmed = Some(daily);
+        assert_eq!(list.ring(), Some(daily));
+        assert!(list.alarms[1].enabled);
+    }
+
+    #[test]
+    fn rings_the_snoozed_alarm_once() {
+        let daily = repeating(8, 0, Weekdays::ALL);
+        let snoozed = Alarm::new(8, 9);
+        let mut list = AlarmList {
+            alarms: vec![daily],
+            snoozed: Some(snoozed),
+            programmed: None,
+        };
+        assert_eq!(list.next(&at(1, 8, 0)), Some((snoozed, 9)));
+        list.programmed = Some(snoozed);
+        assert_eq!(list.ring(), Some(snoozed));
+        assert_eq!(list.snoozed, None);
+        assert_eq!(list.alarms, vec![daily]);
+        assert_eq!(list.next(&at(1, 8, 9)), Some((daily, MINUTES_PER_DAY - 9)));
+    }
+
+    #[test]
+    fn encodes_and_decodes_the_list() {
+        let list = AlarmList {
+            alarms: vec![
+                repeating(6, 45, Weekdays::WORKDAYS),
+                Alarm {
+                    enabled: false,
+                    ..Alarm::new(23, 59)
+                },
+            ],
+            snoozed: Some(Alarm::new(7, 9)),
+            programmed: Some(Alarm::new(7, 9)),
+        };
+        assert_eq!(AlarmList::decode(&list.encode()), Some(list));
+        let empty = AlarmList::default();
+        assert_eq!(AlarmList::decode(&empty.encode()), Some(empty));
+
+        // Before the programmed alarm was kept
+        assert_eq!(
+            AlarmList::decode(&[1, 1, 7, 9, 0, 1, 6, 45, 0b0011_1110, 1]),
+            Some(AlarmList {
+                alarms: vec![repeating(6, 45, Weekdays::WORKDAYS)],
+                snoozed: Some(Alarm::new(7, 9)),
+                programmed: None,
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_broken_lists() {
+        assert_eq!(AlarmList::decode(&[]), None);
+        assert_eq!(AlarmList::decode(&[STORAGE_VERSION]), None);
+        assert_eq!(AlarmList::decode(&[STORAGE_VERSION + 1, 0, 0]), None);
+        // Flagged without the alarm
+        assert_eq!(AlarmList::decode(&[STORAGE_VERSION, 1, 0]), None);
+        assert_eq!(AlarmList::decode(&[STORAGE_VERSION, 0, 1]), None);
+        assert_eq!(
+            AlarmList::decode(&[STORAGE_VERSION, 1, 1, 7, 9, 0, 1]),
+            None
+        );
+        assert_eq!(AlarmList::decode(&[1, 1]), None);
+        // Cut alarm
+        assert_eq!(AlarmList::decode(&[STORAGE_VERSION, 0, 0, 7, 0, 0]), None);
+    }
+
+    #[test]
+    fn decodes_at_most_the_maximum() {
+        let list = AlarmList {
+            alarms: vec![Alarm::new(7, 0); MAX_ALARMS + 2],
+            ..Default::default()
+        };
+        let decoded = AlarmList::decode(&list.encode()).unwrap();
+        assert_eq!(decoded.alarms.len(), MAX_ALARMS);
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use esp_idf_svc::notify::EspNotify;
+use esp_idf_sys::*;
+
+use log::*;
+
+use crate::dfu::{self, DfuError};
+use crate::events::TwatchRawEvent;
+use crate::gadgetbridge::{Command, Decoder, PhoneEvent};
+
+/// Gadgetbridge recognizes Bangle.js devices by their name
+const DEVICE_NAME: &str = "Bangle.js TWatch";
+const APP_ID: u16 = 0;
+const LOCAL_MTU: u16 = 185;
+/// Events kept while the watch is busy, older ones are dropped
+const MAX_PENDING_EVENTS: usize = 16;
+/// Service instances, the firmware update service is created after the UART one
+const NUS_INSTANCE: u8 = 0;
+const DFU_INSTANCE: u8 = 1;
+
+/// Nordic UART service, as 128-bit little-endian UUIDs
+static NUS_SERVICE_UUID: [u8; 16] = nus_uuid(0x01);
+/// Written by the phone
+static NUS_RX_UUID: [u8; 16] = nus_uuid(0x02);
+/// Notified to the phone
+static NUS_TX_UUID: [u8; 16] = nus_uuid(0x03);
+
+/// Firmware update service, see `dfu`
+static DFU_SERVICE_UUID: [u8; 16] = dfu_uuid(0x01);
+/// Requests written and responses notified
+static DFU_CONTROL_UUID: [u8; 16] = dfu_uuid(0x02);
+/// Chunks of the image, written without response
+static DFU_DATA_UUID: [u8; 16] = dfu_uuid(0x03);
+
+static PRIMARY_SERVICE_UUID: u16 = ESP_GATT_UUID_PRI_SERVICE as u16;
+static CHARACTERISTIC_UUID: u16 = ESP_GATT_UUID_CHAR_DECLARE as u16;
+static CLIENT_CONFIG_UUID: u16 = ESP_GATT_UUID_CHAR_CLIENT_CONFIG as u16;
+static RX_PROPERTIES: u8 = (ESP_GATT_CHAR_PROP_BIT_WRITE | ESP_GATT_CHAR_PROP_BIT_WRITE_NR) as u8;
+static TX_PROPERTIES: u8 = ESP_GATT_CHAR_PROP_BIT_NOTIFY as u8;
+static CLIENT_CONFIG: [u8; 2] = [0, 0];
+static DFU_CONTROL_PROPERTIES: u8 =
+    (ESP_GATT_CHAR_PROP_BIT_WRITE | ESP_GATT_CHAR_PROP_BIT_NOTIFY) as u8;
+static DFU_DATA_PROPERTIES: u8 = ESP_GATT_CHAR_PROP_BIT_WRITE_NR as u8;
+
+/// Indexes in the attribute table, characteristics declarations are at 1 and 3
+const IDX_SERVICE: usize = 0;
+const IDX_RX_VALUE: usize = 2;
+const IDX_TX_VALUE: usize = 4;
+const IDX_TX_CLIENT_CONFIG: usize = 5;
+const ATTRIBUTE_COUNT: usize = 6;
+
+/// Indexes in the attribute table of the update service, characteristics
+/// declarations are at 1 and 4
+const IDX_DFU_CONTROL_VALUE: usize = 2;
+const IDX_DFU_CONTROL_CLIENT_CONFIG: usize = 3;
+const IDX_DFU_DATA_VALUE: usize = 5;
+const DFU_ATTRIBUTE_COUNT: usize = 6;
+
+const fn nus_uuid(id: u8) -> [u8; 16] {
+    [
+        0x9e, 0xca, 0xdc, 0x24, 0x0e, 0xe5, 0xa9, 0xe0, 0x93, 0xf3, 0xa3, 0xb5, id, 0x00, 0x40,
+        0x6e,
+    ]
+}
+
+const fn dfu_uuid(id: u8) -> [u8; 16] {
+    [
+        0xe1, 0xa0, 0xd1, 0x71, 0x2b, 0x4c, 0x0e, 0x8f, 0x43, 0x4b, 0x63, 0x77, id, 0x00, 0x1e,
+        0x5a,
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BleEvent {
+    /// The phone subscribed to the notifications
+    Connected,
+    Disconnected,
+    /// The phone pairs, asking for this code to be typed
+    Passkey(u32),
+    Phone(PhoneEvent),
+    /// Firmware update request
+    Dfu(Result<dfu::Request, DfuError>),
+}
+
+/// State shared with the Bluedroid callbacks
+struct Shared {
+    eventloop: EspNotify,
+    gatts_if: Option<esp_gatt_if_t>,
+    conn_id: Option<u16>,
+    subscribed: bool,
+    handles: [u16; ATTRIBUTE_COUNT],
+    dfu_handles: [u16; DFU_ATTRIBUTE_COUNT],
+    dfu_subscribed: bool,
+    /// The link is encrypted with the keys of a bond made with the passkey,
+    /// needed by the updates
+    bonded: bool,
+    mtu: u16,
+    decoder: Decoder,
+    events: VecDeque<BleEvent>,
+}
+
+impl Shared {
+    fn push(&mut self, event: BleEvent) {
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            warn!("Dropping BLE event {:?}", self.events.pop_front());
+        }
+        self.events.push_back(event);
+        let _ = self
+            .eventloop
+            .post(&TwatchRawEvent::Ble.into(), Some(Duration::from_millis(0)));
+    }
+}
+
+static SHARED: Mutex<Option<Shared>> = Mutex::new(None);
+
+/// Phone companion link over the BLE Nordic UART service
+pub struct Ble {}
+
+impl Ble {
+    pub fn new(eventloop: EspNotify) -> Result<Self> {
+        *SHARED.lock().unwrap() = Some(Shared {
+            eventloop,
+            gatts_if: None,
+            conn_id: None,
+            subscribed: false,
+            handles: [0; ATTRIBUTE_COUNT],
+            dfu_handles: [0; DFU_ATTRIBUTE_COUNT],
+            dfu_subscribed: false,
+            bonded: false,
+            mtu: 23,
+            decoder: Decoder::default(),
+            events: VecDeque::new(),
+        });
+
+        unsafe {
+            esp!(esp_bt_controller_mem_release(
+                esp_bt_mode_t_ESP_BT_MODE_CLASSIC_BT
+            ))?;
+            let mut config = controller_config();
+            esp!(esp_bt_controller_init(&mut config))?;
+            esp!(esp_bt_controller_enable(esp_bt_mode_t_ESP_BT_MODE_BLE))?;
+            esp!(esp_bluedroid_init())?;
+            esp!(esp_bluedroid_enable())?;
+            esp!(esp_ble_gatts_register_callback(Some(gatts_event_handler)))?;
+            esp!(esp_ble_gap_register_callback(Some(gap_event_handler)))?;
+            esp!(esp_ble_gatts_app_register(APP_ID))?;
+            esp!(esp_ble_gatt_set_local_mtu(LOCAL_MTU))?;
+            configure_security()?;
+        }
+        info!("BLE initialized as {DEVICE_NAME}");
+        Ok(Self {})
+    }
+
+    /// Link which is never started, the phone can't connect
+    pub fn disabled() -> Self {
+        Self {}
+    }
+
+    pub fn is_connected(&self) -> bool {
+        with_shared(|shared| shared.subscribed).unwrap_or(false)
+    }
+
+    /// Next event received from the phone, and whether more are pending
+    pub fn take_event(&self) -> Option<(BleEvent, bool)> {
+        with_shared(|shared| {
+            shared
+                .events
+                .pop_front()
+                .map(|event| (event, !shared.events.is_empty()))
+        })
+        .flatten()
+    }
+
+    pub fn send(&self, command: &Command) -> Result<()> {
+        let line = command.encode();
+        let (gatts_if, conn_id, handle, mtu) =
+            with_shared(
+                |shared| match (shared.gatts_if, shared.conn_id, shared.subscribed) {
+                    (Some(gatts_if), Some(conn_id), true) => {
+                        Some((gatts_if, conn_id, shared.handles[IDX_TX_VALUE], shared.mtu))
+                    }
+                    _ => None,
+                },
+            )
+            .flatten()
+            .ok_or_else(|| anyhow::anyhow!("No phone connected"))?;
+
+        debug!("Sending {}", line.trim_end());
+        for chunk in line.as_bytes().chunks(mtu as usize - 3) {
+            esp!(unsafe {
+                esp_ble_gatts_send_indicate(
+                    gatts_if,
+                    conn_id,
+                    handle,
+                    chunk.len() as u16,
+                    chunk.as_ptr() as *mut u8,
+                    false,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Notify the response to a firmware update request
+    pub fn send_dfu(&self, response: &dfu::Response) -> Result<()> {
+        let (gatts_if, conn_id, handle) =
+            with_shared(
+                |shared| match (shared.gatts_if, shared.conn_id, shared.dfu_subscribed) {
+                    (Some(gatts_if), Some(conn_id), true) => {
+                        Some((gatts_if, conn_id, shared.dfu_handles[IDX_DFU_CONTROL_VALUE]))
+                    }
+                    _ => None,
+                },
+            )
+            .flatten()
+            .ok_or_else(|| anyhow::anyhow!("No update client connected"))?;
+
+        let mut data = response.encode();
+        esp!(unsafe {
+            esp_ble_gatts_send_indicate(
+                gatts_if,
+                conn_id,
+                handle,
+                data.len() as u16,
+                data.as_mut_ptr(),
+                false,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+fn with_shared<T>(f: impl FnOnce(&mut Shared) -> T) -> Option<T> {
+    SHARED.lock().unwrap().as_mut().map(f)
+}
+
+/// Same values as `BT_CONTROLLER_INIT_CONFIG_DEFAULT` for a BLE only controller
+fn controller_config() -> esp_bt_controller_config_t {
+    esp_bt_controller_config_t {
+        controller_task_stack_size: 3584,
+        controller_task_prio: 23,
+        hci_uart_no: 1,
+        hci_uart_baudrate: 921_600,
+        scan_duplicate_mode: 0,
+        scan_duplicate_type: 0,
+        normal_adv_size: 200,
+        mesh_adv_size: 200,
+        send_adv_reserved_size: 1000,
+        controller_debug_flag: 0,
+        mode: esp_bt_mode_t_ESP_BT_MODE_BLE as u8,
+        ble_max_conn: CONFIG_BTDM_CTRL_BLE_MAX_CONN_EFF as u8,
+        bt_max_acl_conn: 0,
+        bt_sco_datapath: 0,
+        auto_latency: false,
+        bt_legacy_auth_vs_evt: false,
+        bt_max_sync_conn: 0,
+        ble_sca: 0,
+        pcm_role: 0,
+        pcm_polar: 0,
+        hli: false,
+        magic: ESP_BT_CONTROLLER_CONFIG_MAGIC_VAL,
+    }
+}
+
+fn attribute(
+    uuid: *const u8,
+    uuid_length: u32,
+    permissions: u32,
+    value: &'static [u8],
+    max_length: usize,
+) -> esp_gatts_attr_db_t {
+    esp_gatts_attr_db_t {
+        attr_control: esp_attr_control_t {
+            auto_rsp: ESP_GATT_AUTO_RSP as u8,
+        },
+        att_desc: esp_attr_desc_t {
+            uuid_length: uuid_length as u16,
+            uuid_p: uuid as *mut u8,
+            perm: permissions as u16,
+            max_length: max_length as u16,
+            length: value.len() as u16,
+            value: value.as_ptr() as *mut u8,
+        },
+    }
+}
+
+fn attribute_table() -> [esp_gatts_attr_db_t; ATTRIBUTE_COUNT] {
+    let uuid16 = |uuid: &'static u16| uuid as *const u16 as *const u8;
+    [
+        attribute(
+            uuid16(&PRIMARY_SERVICE_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ,
+            &NUS_SERVICE_UUID,
+            NUS_SERVICE_UUID.len(),
+        ),
+        attribute(
+            uuid16(&CHARACTERISTIC_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ,
+            std::slice::from_ref(&RX_PROPERTIES),
+            1,
+        ),
+        attribute(
+            NUS_RX_UUID.as_ptr(),
+            ESP_UUID_LEN_128,
+            ESP_GATT_PERM_WRITE,
+            &[],
+            LOCAL_MTU as usize,
+        ),
+        attribute(
+            uuid16(&CHARACTERISTIC_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ,
+            std::slice::from_ref(&TX_PROPERTIES),
+            1,
+        ),
+        attribute(
+            NUS_TX_UUID.as_ptr(),
+            ESP_UUID_LEN_128,
+            ESP_GATT_PERM_READ,
+            &[],
+            LOCAL_MTU as usize,
+        ),
+        attribute(
+            uuid16(&CLIENT_CONFIG_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE,
+            &CLIENT_CONFIG,
+            CLIENT_CONFIG.len(),
+        ),
+    ]
+}
+
+fn dfu_attribute_table() -> [esp_gatts_attr_db_t; DFU_ATTRIBUTE_COUNT] {
+    let uuid16 = |uuid: &'static u16| uuid as *const u16 as *const u8;
+    [
+        attribute(
+            uuid16(&PRIMARY_SERVICE_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ,
+            &DFU_SERVICE_UUID,
+            DFU_SERVICE_UUID.len(),
+        ),
+        attribute(
+            uuid16(&CHARACTERISTIC_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ,
+            std::slice::from_ref(&DFU_CONTROL_PROPERTIES),
+            1,
+        ),
+        attribute(
+            DFU_CONTROL_UUID.as_ptr(),
+            ESP_UUID_LEN_128,
+            ESP_GATT_PERM_WRITE_ENCRYPTED,
+            &[],
+            LOCAL_MTU as usize,
+        ),
+        attribute(
+            uuid16(&CLIENT_CONFIG_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ_ENCRYPTED | ESP_GATT_PERM_WRITE_ENCRYPTED,
+            &CLIENT_CONFIG,
+            CLIENT_CONFIG.len(),
+        ),
+        attribute(
+            uuid16(&CHARACTERISTIC_UUID),
+            ESP_UUID_LEN_16,
+            ESP_GATT_PERM_READ,
+            std::slice::from_ref(&DFU_DATA_PROPERTIES),
+            1,
+        ),
+        attribute(
+            DFU_DATA_UUID.as_ptr(),
+            ESP_UUID_LEN_128,
+            ESP_GATT_PERM_WRITE_ENCRYPTED,
+            &[],
+            LOCAL_MTU as usize,
+        ),
+    ]
+}
+
+unsafe fn configure_advertising() -> Result<(), EspError> {
+    let name = std::ffi::CString::new(DEVICE_NAME).unwrap();
+    esp!(esp_ble_gap_set_device_name(name.as_ptr()))?;
+
+    let flags = (ESP_BLE_ADV_FLAG_GEN_DISC | ESP_BLE_ADV_FLAG_BREDR_NOT_SPT) as u8;
+    let mut adv_data = esp_ble_adv_data_t {
+        set_scan_rsp: false,
+        include_name: true,
+        include_txpower: false,
+        min_interval: 0x0006,
+        max_interval: 0x0010,
+        appearance: 0x00c0,
+        flag: flags,
+        ..Default::default()
+    };
+    esp!(esp_ble_gap_config_adv_data(&mut adv_data))?;
+
+    // The 128-bit service UUID doesn't fit along with the name
+    let mut scan_response = esp_ble_adv_data_t {
+        set_scan_rsp: true,
+        service_uuid_len: NUS_SERVICE_UUID.len() as u16,
+        p_service_uuid: NUS_SERVICE_UUID.as_ptr() as *mut u8,
+        flag: flags,
+        ..Default::default()
+    };
+    esp!(esp_ble_gap_config_adv_data(&mut scan_response))
+}
+
+/// Bond with the phone when it pairs, showing the passkey to type on the
+/// phone, the updates are only accepted from a phone bonded that way
+unsafe fn configure_security() -> Result<(), EspError> {
+    let set = |param: esp_ble_sm_param_t, mut value: u8| {
+        esp!(esp_ble_gap_set_security_param(
+            param,
+            &mut value as *mut u8 as *mut _,
+            1
+        ))
+    };
+    set(
+        esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE,
+        ESP_LE_AUTH_REQ_SC_MITM_BOND as u8,
+    )?;
+    set(
+        esp_ble_sm_param_t_ESP_BLE_SM_IOCAP_MODE,
+        ESP_IO_CAP_OUT as u8,
+    )?;
+    set(esp_ble_sm_param_t_ESP_BLE_SM_MAX_KEY_SIZE, 16)?;
+    let keys = (ESP_BLE_ENC_KEY_MASK | ESP_BLE_ID_KEY_MASK) as u8;
+    set(esp_ble_sm_param_t_ESP_BLE_SM_SET_INIT_KEY, keys)?;
+    set(esp_ble_sm_param_t_ESP_BLE_SM_SET_RSP_KEY, keys)
+}
+
+unsafe fn start_advertising() {
+    let mut params = esp_ble_adv_params_t {
+        adv_int_min: 0x100,
+        adv_int_max: 0x200,
+        adv_type: esp_ble_adv_type_t_ADV_TYPE_IND,
+        own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+        channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
+        adv_filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+        ..Default::default()
+    };
+    if let Err(e) = esp!(esp_ble_gap_start_advertising(&mut params)) {
+        warn!("Unable to start BLE advertising: {e:?}");
+    }
+}
+
+unsafe extern "C" fn gap_event_handler(
+    event: esp_gap_ble_cb_event_t,
+    param: *mut esp_ble_gap_cb_param_t,
+) {
+    match event {
+        esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_SET_COMPLETE_EVT => start_advertising(),
+        esp_gap_ble_cb_event_t_ESP_GAP_BLE_SEC_REQ_EVT => {
+            let mut address = (*param).ble_security.ble_req.bd_addr;
+            if let Err(e) = esp!(esp_ble_gap_security_rsp(address.as_mut_ptr(), true)) {
+                warn!("Unable to accept BLE pairing: {e:?}");
+            }
+        }
+        esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_NOTIF_EVT => {
+            let passkey = (*param).ble_security.key_notif.passkey;
+            with_shared(|shared| shared.push(BleEvent::Passkey(passkey)));
+        }
+        esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT => {
+            let auth = (*param).ble_security.auth_cmpl;
+            // Without the MITM protection of the passkey, any phone nearby
+            // could have paired
+            let required = ESP_LE_AUTH_BOND | ESP_LE_AUTH_REQ_MITM;
+            let bonded = auth.success && auth.auth_mode as u32 & required == required;
+            if auth.success {
+                info!("BLE link encrypted, bonded: {bonded}");
+            } else {
+                warn!("BLE pairing failed: {:#x}", auth.fail_reason);
+            }
+            with_shared(|shared| shared.bonded = bonded);
+        }
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn gatts_event_handler(
+    event: esp_gatts_cb_event_t,
+    gatts_if: esp_gatt_if_t,
+    param: *mut esp_ble_gatts_cb_param_t,
+) {
+    let mut guard = SHARED.lock().unwrap();
+    let shared = match guard.as_mut() {
+        Some(shared) => shared,
+        None => return,
+    };
+
+    match event {
+        esp_gatts_cb_event_t_ESP_GATTS_REG_EVT => {
+            shared.gatts_if = Some(gatts_if);
+            if let Err(e) = configure_advertising() {
+                warn!("Unable to configure BLE advertising: {e:?}");
+            }
+            let table = attribute_table();
+            if let Err(e) = esp!(esp_ble_gatts_create_attr_tab(
+                table.as_ptr(),
+                gatts_if,
+                ATTRIBUTE_COUNT as u8,
+                NUS_INSTANCE
+            )) {
+                warn!("Unable to create BLE service: {e:?}");
+            }
+        }
+        esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT => {
+            let param = (*param).add_attr_tab;
+            let (handles, instance) = match param.svc_inst_id {
+                NUS_INSTANCE => (&mut shared.handles[..], NUS_INSTANCE),
+                _ => (&mut shared.dfu_handles[..], DFU_INSTANCE),
+            };
+            if param.status != esp_gatt_status_t_ESP_GATT_OK
+                || param.num_handle as usize != handles.len()
+            {
+                warn!("Unable to create BLE attribute table: {}", param.status);
+                return;
+            }
+            handles.copy_from_slice(std::slice::from_raw_parts(param.handles, handles.len()));
+            if let Err(e) = esp!(esp_ble_gatts_start_service(handles[IDX_SERVICE])) {
+                warn!("Unable to start BLE service: {e:?}");
+            }
+            if instance == NUS_INSTANCE {
+                let table = dfu_attribute_table();
+                if let Err(e) = esp!(esp_ble_gatts_create_attr_tab(
+                    table.as_ptr(),
+                    gatts_if,
+                    DFU_ATTRIBUTE_COUNT as u8,
+                    DFU_INSTANCE
+                )) {
+                    warn!("Unable to create BLE update service: {e:?}");
+                }
+            }
+        }
+        esp_gatts_cb_event_t_ESP_GATTS_CONNECT_EVT => {
+            info!("BLE client connected");
+            shared.conn_id = Some((*param).connect.conn_id);
+            shared.decoder = Decoder::default();
+        }
+        esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT => {
+            info!("BLE client disconnected: {:#x}", (*param).disconnect.reason);
+            shared.conn_id = None;
+            shared.mtu = 23;
+            shared.dfu_subscribed = false;
+            shared.bonded = false;
+            if shared.subscribed {
+                shared.subscribed = false;
+                shared.push(BleEvent::Disconnected);
+            }
+            start_advertising();
+        }
+        esp_gatts_cb_event_t_ESP_GATTS_MTU_EVT => {
+            shared.mtu = (*param).mtu.mtu;
+        }
+        esp_gatts_cb_event_t_ESP_GATTS_WRITE_EVT => {
+            let param = (*param).write;
+            let data = std::slice::from_raw_parts(param.value, param.len as usize);
+            if param.handle == shared.handles[IDX_RX_VALUE] {
+                for event in shared.decoder.push(data) {
+                    shared.push(BleEvent::Phone(event));
+                }
+            } else if param.handle == shared.dfu_handles[IDX_DFU_DATA_VALUE] {
+                let request = if shared.bonded {
+                    dfu::Request::parse_chunk(data)
+                } else {
+                    Err(DfuError::NotBonded)
+                };
+                shared.push(BleEvent::Dfu(request));
+            } else if param.handle == shared.dfu_handles[IDX_DFU_CONTROL_VALUE] {
+                let request = if shared.bonded {
+                    dfu::Request::parse_control(data)
+                } else {
+                    Err(DfuError::NotBonded)
+                };
+                shared.push(BleEvent::Dfu(request));
+            } else if param.handle == shared.dfu_handles[IDX_DFU_CONTROL_CLIENT_CONFIG]
+                && data.len() == 2
+            {
+                shared.dfu_subscribed = data[0] & 0x01 != 0;
+            } else if param.handle == shared.handles[IDX_TX_CLIENT_CONFIG] && data.len() == 2 {
+                let subscribed = data[0] & 0x01 != 0;
+                if subscribed != shared.subscribed {
+                    shared.subscribed = subscribed;
+                    shared.push(if subscribed {
+                        BleEvent::Connected
+                    } else {
+                        BleEvent::Disconnected
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,95 @@
+//! Recovery of an I2C bus held by one of its devices, see `health`.
+
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+use esp_idf_sys::*;
+
+use shared_bus::BusMutex;
+
+use crate::health::Bus;
+use crate::types::EspI2c0;
+
+/// Half period of the recovery clock, 100kHz
+const HALF_PERIOD_US: u32 = 5;
+/// Pulses shifting out the rest of a byte and its acknowledge bit
+const MAX_PULSES: usize = 9;
+
+/// Port, SDA and SCL pins of a bus, as wired in `Twatch::new`
+fn pins(bus: Bus) -> (i2c_port_t, gpio_num_t, gpio_num_t) {
+    match bus {
+        Bus::I2c0 => (0, gpio_num_t_GPIO_NUM_21, gpio_num_t_GPIO_NUM_22),
+        Bus::I2c1 => (1, gpio_num_t_GPIO_NUM_23, gpio_num_t_GPIO_NUM_32),
+    }
+}
+
+/// I2C0, shared by the drivers through `SharedI2c0`
+static I2C0: Mutex<Option<EspI2c0>> = Mutex::new(None);
+
+/// Mutex of the shared I2C0, which is also held while the bus is cleared so
+/// that no transfer starts with the pins driven by hand
+pub struct SharedI2c0;
+
+impl BusMutex for SharedI2c0 {
+    type Bus = EspI2c0;
+
+    fn create(bus: EspI2c0) -> Self {
+        *I2C0.lock().unwrap() = Some(bus);
+        Self
+    }
+
+    fn lock<R, F: FnOnce(&mut EspI2c0) -> R>(&self, f: F) -> R {
+        let mut bus = I2C0.lock().unwrap();
+        f(bus.as_mut().expect("I2C0 not created"))
+    }
+}
+
+fn set_level(pin: gpio_num_t, level: u32) -> Result<()> {
+    esp!(unsafe { gpio_set_level(pin, level) })?;
+    unsafe { ets_delay_us(HALF_PERIOD_US) };
+    Ok(())
+}
+
+/// Release SDA held low by a device stopped in the middle of a transfer
+///
+/// SCL is pulsed until the device lets SDA go, then a STOP condition resets
+/// the devices before the pins are given back to the I2C controller. The
+/// shared I2C0 is locked meanwhile, I2C1 is only used by the touch screen
+/// whose driver is held by the caller.
+pub fn clear(bus: Bus) -> Result<()> {
+    let _lock = match bus {
+        Bus::I2c0 => Some(I2C0.lock().unwrap()),
+        Bus::I2c1 => None,
+    };
+    let (port, sda, scl) = pins(bus);
+    for pin in [sda, scl] {
+        esp!(unsafe { gpio_reset_pin(pin) })?;
+        esp!(unsafe { gpio_set_direction(pin, gpio_mode_t_GPIO_MODE_INPUT_OUTPUT_OD) })?;
+        esp!(unsafe { gpio_set_pull_mode(pin, gpio_pull_mode_t_GPIO_PULLUP_ONLY) })?;
+        set_level(pin, 1)?;
+    }
+
+    let mut pulses = 0;
+    while unsafe { gpio_get_level(sda) } == 0 && pulses < MAX_PULSES {
+        set_level(scl, 0)?;
+        set_level(scl, 1)?;
+        pulses += 1;
+    }
+    // STOP: SDA rising while SCL is high
+    set_level(scl, 0)?;
+    set_level(sda, 0)?;
+    set_level(scl, 1)?;
+    set_level(sda, 1)?;
+    let released = unsafe { gpio_get_level(sda) } == 1;
+
+    esp!(unsafe { i2c_set_pin(port, sda, scl, true, true, i2c_mode_t_I2C_MODE_MASTER) })?;
+    esp!(unsafe { i2c_reset_tx_fifo(port) })?;
+    esp!(unsafe { i2c_reset_rx_fifo(port) })?;
+
+    if !released {
+        anyhow::bail!("SDA of {bus:?} still held low after {pulses} pulses");
+    }
+    log::info!("{bus:?} cleared with {pulses} pulses");
+    Ok(())
+}
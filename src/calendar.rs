@@ -0,0 +1,254 @@
+//! Calendar arithmetic for the PCF8563, which stores years as 2000 + 0..=99 and
+//! leaves day and leap year validation to its users.
+
+use pcf8563::DateTime;
+
+pub const MIN_YEAR: u16 = 2000;
+pub const MAX_YEAR: u16 = 2099;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateError {
+    Year(u16),
+    Month(u8),
+    Day(u8),
+    Hours(u8),
+    Minutes(u8),
+    Seconds(u8),
+}
+
+impl std::fmt::Display for DateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DateError::Year(v) => f.write_fmt(format_args!("invalid year {v}")),
+            DateError::Month(v) => f.write_fmt(format_args!("invalid month {v}")),
+            DateError::Day(v) => f.write_fmt(format_args!("invalid day {v}")),
+            DateError::Hours(v) => f.write_fmt(format_args!("invalid hours {v}")),
+            DateError::Minutes(v) => f.write_fmt(format_args!("invalid minutes {v}")),
+            DateError::Seconds(v) => f.write_fmt(format_args!("invalid seconds {v}")),
+        }
+    }
+}
+
+impl std::error::Error for DateError {}
+
+pub fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Number of days of `month` (1..=12), 0 for an invalid month
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// Day of the week, 0 being Sunday
+pub fn weekday(year: u16, month: u8, day: u8) -> u8 {
+    // Sakamoto's method
+    const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let year = if month < 3 { year - 1 } else { year };
+    ((year + year / 4 - year / 100 + year / 400 + OFFSETS[(month - 1) as usize] + day as u16) % 7)
+        as u8
+}
+
+pub fn validate(
+    year: u16,
+    month: u8,
+    day: u8,
+    hours: u8,
+    minutes: u8,
+    seconds: u8,
+) -> Result<(), DateError> {
+    if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+        return Err(DateError::Year(year));
+    }
+    if !(1..=12).contains(&month) {
+        return Err(DateError::Month(month));
+    }
+    if day < 1 || day > days_in_month(year, month) {
+        return Err(DateError::Day(day));
+    }
+    if hours > 23 {
+        return Err(DateError::Hours(hours));
+    }
+    if minutes > 59 {
+        return Err(DateError::Minutes(minutes));
+    }
+    if seconds > 59 {
+        return Err(DateError::Seconds(seconds));
+    }
+    Ok(())
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian date of a number of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Seconds since the Unix epoch of a PCF8563 date and time
+pub fn to_timestamp(time: &DateTime) -> i64 {
+    days_from_civil(MIN_YEAR as i64 + time.year as i64, time.month, time.day) * 86_400
+        + time.hours as i64 * 3600
+        + time.minutes as i64 * 60
+        + time.seconds as i64
+}
+
+/// PCF8563 date and time of a number of seconds since the Unix epoch
+///
+/// Timestamps outside of the PCF8563 range are clamped to it.
+pub fn from_timestamp(timestamp: i64) -> DateTime {
+    let min = days_from_civil(MIN_YEAR as i64, 1, 1) * 86_400;
+    let max = days_from_civil(MAX_YEAR as i64 + 1, 1, 1) * 86_400 - 1;
+    let timestamp = timestamp.clamp(min, max);
+    let days = timestamp.div_euclid(86_400);
+    let seconds = timestamp.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    DateTime {
+        year: (year - MIN_YEAR as i64) as u8,
+        month,
+        day,
+        // 1970-01-01 was a Thursday
+        weekday: (days + 4).rem_euclid(7) as u8,
+        hours: (seconds / 3600) as u8,
+        minutes: (seconds / 60 % 60) as u8,
+        seconds: (seconds % 60) as u8,
+    }
+}
+
+/// Seconds since the Unix epoch of the system clock, which is seeded from the RTC
+pub fn utc_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(timestamp: i64) -> (u16, u8, u8, u8, u8, u8, u8) {
+        let t = from_timestamp(timestamp);
+        (
+            MIN_YEAR + t.year as u16,
+            t.month,
+            t.day,
+            t.weekday,
+            t.hours,
+            t.minutes,
+            t.seconds,
+        )
+    }
+
+    #[test]
+    fn knows_the_leap_years() {
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(2023));
+        assert!(!is_leap_year(2100));
+        assert!(!is_leap_year(1900));
+        assert!(is_leap_year(2400));
+    }
+
+    #[test]
+    fn counts_the_days_of_the_months() {
+        let days: Vec<u8> = (1..=12).map(|month| days_in_month(2023, month)).collect();
+        assert_eq!(days, [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]);
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2024, 0), 0);
+        assert_eq!(days_in_month(2024, 13), 0);
+    }
+
+    #[test]
+    fn finds_the_weekday() {
+        assert_eq!(weekday(2000, 1, 1), 6);
+        assert_eq!(weekday(2000, 2, 29), 2);
+        assert_eq!(weekday(2000, 3, 1), 3);
+        assert_eq!(weekday(2024, 1, 7), 0);
+        assert_eq!(weekday(2024, 2, 29), 4);
+        assert_eq!(weekday(2099, 12, 31), 4);
+    }
+
+    #[test]
+    fn validates_dates() {
+        assert_eq!(validate(2024, 2, 29, 23, 59, 59), Ok(()));
+        assert_eq!(validate(2000, 2, 29, 0, 0, 0), Ok(()));
+        assert_eq!(validate(2023, 2, 29, 0, 0, 0), Err(DateError::Day(29)));
+        assert_eq!(validate(2024, 4, 31, 0, 0, 0), Err(DateError::Day(31)));
+        assert_eq!(validate(2024, 1, 0, 0, 0, 0), Err(DateError::Day(0)));
+        assert_eq!(validate(2024, 0, 1, 0, 0, 0), Err(DateError::Month(0)));
+        assert_eq!(validate(2024, 13, 1, 0, 0, 0), Err(DateError::Month(13)));
+        assert_eq!(validate(2024, 1, 1, 24, 0, 0), Err(DateError::Hours(24)));
+        assert_eq!(validate(2024, 1, 1, 0, 60, 0), Err(DateError::Minutes(60)));
+        assert_eq!(validate(2024, 1, 1, 0, 0, 60), Err(DateError::Seconds(60)));
+    }
+
+    #[test]
+    fn validates_the_years_of_the_clock() {
+        assert_eq!(validate(MIN_YEAR, 1, 1, 0, 0, 0), Ok(()));
+        assert_eq!(validate(MAX_YEAR, 12, 31, 23, 59, 59), Ok(()));
+        assert_eq!(validate(1999, 12, 31, 0, 0, 0), Err(DateError::Year(1999)));
+        assert_eq!(validate(2100, 1, 1, 0, 0, 0), Err(DateError::Year(2100)));
+    }
+
+    #[test]
+    fn converts_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in [-1, 0, 11_016, 11_017, 19_782, 47_481] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn converts_timestamps() {
+        // 2000-01-01 was a Saturday
+        assert_eq!(datetime(946_684_800), (2000, 1, 1, 6, 0, 0, 0));
+        assert_eq!(datetime(951_782_400 + 3599), (2000, 2, 29, 2, 0, 59, 59));
+        assert_eq!(datetime(1_709_164_800), (2024, 2, 29, 4, 0, 0, 0));
+        for timestamp in [946_684_800, 951_868_799, 1_709_251_199, 1_700_000_123] {
+            assert_eq!(to_timestamp(&from_timestamp(timestamp)), timestamp);
+        }
+    }
+
+    #[test]
+    fn clamps_timestamps_to_the_clock() {
+        let min = 946_684_800;
+        let max = days_from_civil(2100, 1, 1) * 86_400 - 1;
+        assert_eq!(datetime(0), datetime(min));
+        assert_eq!(datetime(max), (MAX_YEAR, 12, 31, 4, 23, 59, 59));
+        assert_eq!(datetime(max + 1), datetime(max));
+        assert_eq!(to_timestamp(&from_timestamp(max)), max);
+    }
+}
@@ -0,0 +1,102 @@
+//! Serial console on UART0, the port of the USB bridge also used by the logs.
+//!
+//! Lines are read by a background thread and parsed by `shell`, the commands
+//! are then run by the event loop, woken up with `TwatchRawEvent::Shell`.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use esp_idf_svc::notify::EspNotify;
+use esp_idf_sys::*;
+
+use log::*;
+
+use crate::events::TwatchRawEvent;
+use crate::shell::{self, Command};
+
+const UART: uart_port_t = 0;
+const RX_BUFFER_SIZE: i32 = 256;
+/// Commands are short, more of them are a paste gone wrong
+const MAX_PENDING: usize = 4;
+const STACK_SIZE: usize = 4096;
+
+pub struct Console {
+    pending: Arc<Mutex<VecDeque<Command>>>,
+    eventloop: EspNotify,
+}
+
+impl Console {
+    pub fn new(eventloop: EspNotify) -> Self {
+        Self {
+            pending: Default::default(),
+            eventloop,
+        }
+    }
+
+    /// Read the commands from the UART
+    pub fn start(&mut self) -> Result<()> {
+        // Without the driver, reading stdin doesn't wait for the input
+        esp!(unsafe { uart_driver_install(UART, RX_BUFFER_SIZE, 0, 0, std::ptr::null_mut(), 0) })?;
+        unsafe { esp_vfs_dev_uart_use_driver(UART as _) };
+
+        let pending = self.pending.clone();
+        let mut eventloop = self.eventloop.clone();
+        thread::Builder::new()
+            .name("console".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                for line in std::io::stdin().lock().lines() {
+                    let command = match line.map(|line| shell::parse(&line)) {
+                        Ok(Ok(Some(command))) => command,
+                        Ok(Ok(None)) => continue,
+                        Ok(Err(e)) => {
+                            println!("{e}");
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!("Error reading console: {e:?}");
+                            thread::sleep(Duration::from_secs(1));
+                            continue;
+                        }
+                    };
+                    {
+                        let mut pending = pending.lock().unwrap();
+                        if pending.len() == MAX_PENDING {
+                            println!("busy, {command:?} ignored");
+                            continue;
+                        }
+                        pending.push_back(command);
+                    }
+                    let _ = eventloop.post(
+                        &TwatchRawEvent::Shell.into(),
+                        Some(Duration::from_millis(0)),
+                    );
+                }
+            })?;
+        info!("Console started, type help for the commands");
+        Ok(())
+    }
+
+    /// Write binary data, which the standard output would alter
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        std::io::stdout().flush()?;
+        let written = unsafe { uart_write_bytes(UART, data.as_ptr() as *const _, data.len() as _) };
+        if written != data.len() as i32 {
+            anyhow::bail!("Unable to write to the console");
+        }
+        Ok(())
+    }
+
+    /// Next command to run, and whether more are pending
+    pub fn take(&self) -> Option<(Command, bool)> {
+        let mut pending = self.pending.lock().unwrap();
+        let command = pending.pop_front()?;
+        Some((command, !pending.is_empty()))
+    }
+}
@@ -0,0 +1,365 @@
+//! Reports of the crashes, see `panic` for the handler.
+//!
+//! The last log lines and the panic message are kept in RTC memory, which
+//! survives a reset but not a power-on. At the next boot they are made into a
+//! report, kept in NVS until cleared. Crashes in a row are counted to boot in
+//! safe mode when the firmware keeps crashing.
+
+use std::time::Duration;
+
+use crate::storage::Persist;
+
+pub const LOG_LINES: usize = 16;
+pub const LINE_LEN: usize = 96;
+const PANIC_LEN: usize = 384;
+
+/// Crashes in a row before booting in safe mode, without Wi-Fi nor BLE
+pub const MAX_CRASHES: u32 = 3;
+/// Running time after which a boot is not counted as a crash anymore
+pub const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Marks RTC memory written by the firmware, it is random after a power-on
+const MAGIC: u32 = 0x7477_6372;
+const STORAGE_VERSION: u8 = 1;
+
+/// Last lines logged, oldest first from `next`
+#[repr(C)]
+pub struct LogRing {
+    magic: u32,
+    next: u32,
+    lines: [[u8; LINE_LEN]; LOG_LINES],
+}
+
+impl LogRing {
+    pub const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            next: 0,
+            lines: [[0; LINE_LEN]; LOG_LINES],
+        }
+    }
+
+    /// Start again when the memory was not written by the firmware
+    pub fn validate(&mut self) {
+        if self.magic != MAGIC || self.next as usize >= LOG_LINES {
+            *self = Self::new();
+        }
+    }
+
+    /// Keep a line, truncated to `LINE_LEN` bytes
+    pub fn push(&mut self, line: &str) {
+        let line = truncate(line, LINE_LEN);
+        let slot = &mut self.lines[self.next as usize];
+        slot[..line.len()].copy_from_slice(line.as_bytes());
+        slot[line.len()..].fill(0);
+        self.next = (self.next + 1) % LOG_LINES as u32;
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let (newest, oldest) = self.lines.split_at(self.next as usize);
+        oldest
+            .iter()
+            .chain(newest)
+            .map(|line| {
+                let len = line.iter().position(|&b| b == 0).unwrap_or(LINE_LEN);
+                String::from_utf8_lossy(&line[..len]).into_owned()
+            })
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+}
+
+impl Default for LogRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Crashes in a row and the last panic
+#[repr(C)]
+pub struct BootRecord {
+    magic: u32,
+    crashes: u32,
+    uptime: u32,
+    len: u32,
+    crc: u32,
+    panic: [u8; PANIC_LEN],
+}
+
+impl BootRecord {
+    pub const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            crashes: 0,
+            uptime: 0,
+            len: 0,
+            crc: 0,
+            panic: [0; PANIC_LEN],
+        }
+    }
+
+    /// Count a boot after a crash or a normal reset, returning the crashes in
+    /// a row
+    pub fn boot(&mut self, crashed: bool) -> u32 {
+        if self.magic != MAGIC || self.len as usize > PANIC_LEN {
+            *self = Self::new();
+        }
+        self.crashes = if crashed {
+            self.crashes.saturating_add(1)
+        } else {
+            0
+        };
+        self.crashes
+    }
+
+    /// The firmware ran long enough, the next crash starts a new count
+    pub fn stable(&mut self) {
+        self.crashes = 0;
+    }
+
+    pub fn store_panic(&mut self, message: &str, location: &str, uptime: Duration) {
+        let location = truncate(location, PANIC_LEN / 2);
+        let message = truncate(message, PANIC_LEN - location.len() - 1);
+        let len = message.len() + 1 + location.len();
+        self.panic[..message.len()].copy_from_slice(message.as_bytes());
+        self.panic[message.len()] = 0;
+        self.panic[message.len() + 1..len].copy_from_slice(location.as_bytes());
+        self.uptime = uptime.as_secs() as u32;
+        self.len = len as u32;
+        self.crc = crate::utils::crc32(&self.panic[..len]);
+    }
+
+    /// Message, location and uptime of the last panic, which is cleared
+    pub fn take_panic(&mut self) -> Option<(String, String, u32)> {
+        let len = std::mem::take(&mut self.len) as usize;
+        if len == 0 || len > PANIC_LEN || crate::utils::crc32(&self.panic[..len]) != self.crc {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&self.panic[..len]);
+        let (message, location) = text.split_once('\0')?;
+        Some((message.to_string(), location.to_string(), self.uptime))
+    }
+}
+
+impl Default for BootRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Longest prefix of `text` fitting in `len` bytes
+fn truncate(text: &str, len: usize) -> &str {
+    let mut end = text.len().min(len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    /// Cause of the reset, e.g. "panic" or "task watchdog"
+    pub reason: String,
+    /// Message and location of the panic, empty for the other resets
+    pub message: String,
+    pub location: String,
+    /// Seconds between the boot and the panic, 0 when unknown
+    pub uptime: u32,
+    pub log: Vec<String>,
+}
+
+impl std::fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.reason)?;
+        if self.uptime > 0 {
+            f.write_fmt(format_args!(" after {}s", self.uptime))?;
+        }
+        if !self.message.is_empty() {
+            f.write_fmt(format_args!(": {}", self.message))?;
+        }
+        if !self.location.is_empty() {
+            f.write_fmt(format_args!(" at {}", self.location))?;
+        }
+        for line in &self.log {
+            f.write_fmt(format_args!("\n  {line}"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Persist for CrashReport {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![STORAGE_VERSION];
+        data.extend_from_slice(&self.uptime.to_le_bytes());
+        let fields = [&self.reason, &self.message, &self.location];
+        for field in fields.into_iter().chain(&self.log) {
+            data.extend_from_slice(&(field.len() as u16).to_le_bytes());
+            data.extend_from_slice(field.as_bytes());
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (version, data) = data.split_first()?;
+        if *version != STORAGE_VERSION || data.len() < 4 {
+            return None;
+        }
+        let (uptime, mut data) = data.split_at(4);
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            if data.len() < 2 {
+                return None;
+            }
+            let (len, rest) = data.split_at(2);
+            let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+            if rest.len() < len {
+                return None;
+            }
+            let (field, rest) = rest.split_at(len);
+            fields.push(String::from_utf8(field.to_vec()).ok()?);
+            data = rest;
+        }
+        if fields.len() < 3 {
+            return None;
+        }
+        let log = fields.split_off(3);
+        let mut fields = fields.into_iter();
+        Some(Self {
+            reason: fields.next()?,
+            message: fields.next()?,
+            location: fields.next()?,
+            uptime: u32::from_le_bytes(uptime.try_into().ok()?),
+            log,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_lines() {
+        let mut ring = LogRing::default();
+        assert!(ring.lines().is_empty());
+        ring.push("first");
+        ring.push("second");
+        assert_eq!(ring.lines(), ["first", "second"]);
+
+        for i in 0..LOG_LINES + 2 {
+            ring.push(&format!("line {i}"));
+        }
+        let lines = ring.lines();
+        assert_eq!(lines.len(), LOG_LINES);
+        assert_eq!(lines[0], "line 2");
+        assert_eq!(lines[LOG_LINES - 1], format!("line {}", LOG_LINES + 1));
+    }
+
+    #[test]
+    fn truncates_the_lines() {
+        let mut ring = LogRing::default();
+        ring.push(&"\u{e9}".repeat(LINE_LEN));
+        ring.push("short");
+        let lines = ring.lines();
+        assert_eq!(lines[0], "\u{e9}".repeat(LINE_LEN / 2));
+        // Not followed by the end of the longer line
+        assert_eq!(lines[1], "short");
+    }
+
+    #[test]
+    fn resets_unwritten_memory() {
+        let mut ring = LogRing::default();
+        ring.push("kept");
+        ring.validate();
+        assert_eq!(ring.lines(), ["kept"]);
+
+        ring.next = LOG_LINES as u32;
+        ring.validate();
+        assert!(ring.lines().is_empty());
+        ring.magic = 0;
+        ring.validate();
+        assert_eq!(ring.magic, MAGIC);
+    }
+
+    #[test]
+    fn counts_the_crashes_in_a_row() {
+        let mut record = BootRecord::default();
+        assert_eq!(record.boot(true), 1);
+        assert_eq!(record.boot(true), 2);
+        record.stable();
+        assert_eq!(record.boot(true), 1);
+        assert_eq!(record.boot(false), 0);
+
+        record.magic = 0;
+        record.crashes = 7;
+        assert_eq!(record.boot(true), 1);
+    }
+
+    #[test]
+    fn keeps_the_panic() {
+        let mut record = BootRecord::default();
+        assert_eq!(record.take_panic(), None);
+        record.store_panic("oops", "src/main.rs:1", Duration::from_secs(42));
+        assert_eq!(
+            record.take_panic(),
+            Some(("oops".to_string(), "src/main.rs:1".to_string(), 42))
+        );
+        // Cleared once taken
+        assert_eq!(record.take_panic(), None);
+
+        let long = "x".repeat(PANIC_LEN);
+        record.store_panic(&long, &long, Duration::ZERO);
+        let (message, location, _) = record.take_panic().unwrap();
+        assert_eq!(location.len(), PANIC_LEN / 2);
+        assert_eq!(message.len() + location.len() + 1, PANIC_LEN);
+
+        // Corrupted
+        record.store_panic("oops", "here", Duration::ZERO);
+        record.panic[0] = b'O';
+        assert_eq!(record.take_panic(), None);
+    }
+
+    #[test]
+    fn encodes_and_decodes_reports() {
+        let report = CrashReport {
+            reason: "panic".to_string(),
+            message: "index out of bounds".to_string(),
+            location: "src/tiles/time.rs:42".to_string(),
+            uptime: 3600,
+            log: vec!["I (1) twatch: booting".to_string(), "".to_string()],
+        };
+        let data = report.encode();
+        assert_eq!(CrashReport::decode(&data), Some(report));
+
+        let watchdog = CrashReport {
+            reason: "task watchdog".to_string(),
+            message: String::new(),
+            location: String::new(),
+            uptime: 0,
+            log: vec![],
+        };
+        assert_eq!(CrashReport::decode(&watchdog.encode()), Some(watchdog));
+
+        assert_eq!(CrashReport::decode(&[]), None);
+        assert_eq!(CrashReport::decode(&[STORAGE_VERSION + 1]), None);
+        assert_eq!(CrashReport::decode(&data[..3]), None);
+        assert_eq!(CrashReport::decode(&data[..data.len() - 1]), None);
+        // Without the location
+        assert_eq!(CrashReport::decode(&data[..5 + 2 + 5 + 2 + 19]), None);
+    }
+
+    #[test]
+    fn describes_reports() {
+        let report = CrashReport {
+            reason: "panic".to_string(),
+            message: "oops".to_string(),
+            location: "src/main.rs:1".to_string(),
+            uptime: 12,
+            log: vec!["last line".to_string()],
+        };
+        assert_eq!(
+            report.to_string(),
+            "panic after 12s: oops at src/main.rs:1\n  last line"
+        );
+    }
+}
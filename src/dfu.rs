@@ -0,0 +1,744 @@
+//! Firmware update received over BLE, in the manner of the Nordic DFU.
+//!
+//! The phone writes requests to the control point and chunks of the image to
+//! the data characteristic, and the watch answers with notifications of the
+//! control point. All integers are little-endian. The start request needs the
+//! MTU to be raised from its default of 23 bytes, which phones do. Both
+//! characteristics are only written over an encrypted link, and the requests
+//! are refused until the phone is bonded with the passkey shown by the watch.
+//!
+//! Requests:
+//! - `0x01` start: image size (u32), SHA-256 digest (32 bytes), Ed25519
+//!   signature of the digest followed by the version (64 bytes), then the
+//!   version as UTF-8, as in the manifests of `ota`
+//! - `0x02` finish: verify the image and restart on it
+//! - `0x03` abort
+//!
+//! Chunks are made of their offset in the image (u32), their data and the
+//! CRC-32 of the offset and data (u32).
+//!
+//! Responses:
+//! - `0x10` ready: offset to send from (u32), number of chunks which can be
+//!   sent ahead of the acknowledgements (u8)
+//! - `0x11` acknowledged: offset received so far (u32)
+//! - `0x12` resend: offset to send from again (u32)
+//! - `0x13` done, the watch restarts
+//! - `0x14` error: `Failure` code (u8)
+//!
+//! The image is refused at the start unless its signature is valid and its
+//! version later than the running one. When the phone disconnects or stops
+//! sending for `IDLE_TIMEOUT`, the slot is released for the update over Wi-Fi
+//! to use it, and only the offset and the digest of what was received are
+//! kept: starting again with the same image resumes it from the offset given
+//! in the ready response, unless the slot was written meanwhile.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::ota::{Image, ImageInfo, OtaError, PublicKey, Released, Slot};
+use crate::utils::crc32;
+
+/// Chunks which can be in flight, they are queued until handled by the event loop
+pub const WINDOW: u8 = 8;
+/// Time without requests after which the slot is released
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Chunks received between two acknowledgements
+const ACK_EVERY: u32 = 4;
+
+const START: u8 = 0x01;
+const FINISH: u8 = 0x02;
+const ABORT: u8 = 0x03;
+
+const READY: u8 = 0x10;
+const ACK: u8 = 0x11;
+const RESEND: u8 = 0x12;
+const DONE: u8 = 0x13;
+const ERROR: u8 = 0x14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Start { info: ImageInfo },
+    Data { offset: u32, data: Vec<u8> },
+    Finish,
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfuError {
+    Invalid,
+    /// The chunk was corrupted on the way
+    Crc,
+    /// Written before the phone was bonded
+    NotBonded,
+}
+
+impl Request {
+    /// Parse a write to the control point
+    pub fn parse_control(data: &[u8]) -> Result<Self, DfuError> {
+        match data.split_first() {
+            Some((&START, rest)) if rest.len() > 100 => {
+                let (size, rest) = rest.split_at(4);
+                let (sha256, rest) = rest.split_at(32);
+                let (signature, version) = rest.split_at(64);
+                let version = std::str::from_utf8(version).map_err(|_| DfuError::Invalid)?;
+                Ok(Request::Start {
+                    info: ImageInfo {
+                        version: version.to_string(),
+                        size: u32::from_le_bytes(size.try_into().unwrap()) as usize,
+                        sha256: sha256.try_into().unwrap(),
+                        signature: signature.try_into().unwrap(),
+                    },
+                })
+            }
+            Some((&FINISH, [])) => Ok(Request::Finish),
+            Some((&ABORT, [])) => Ok(Request::Abort),
+            _ => Err(DfuError::Invalid),
+        }
+    }
+
+    /// Parse a write to the data characteristic
+    pub fn parse_chunk(chunk: &[u8]) -> Result<Self, DfuError> {
+        if chunk.len() < 8 {
+            return Err(DfuError::Invalid);
+        }
+        let (content, crc) = chunk.split_at(chunk.len() - 4);
+        if crc32(content) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(DfuError::Crc);
+        }
+        let (offset, data) = content.split_at(4);
+        Ok(Request::Data {
+            offset: u32::from_le_bytes(offset.try_into().unwrap()),
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Reasons of the error responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Invalid = 1,
+    NoTransfer = 2,
+    NoKey = 3,
+    TooLarge = 4,
+    Flash = 5,
+    Size = 6,
+    Digest = 7,
+    Signature = 8,
+    /// The signed version is not later than the running one
+    NotNewer = 9,
+    /// An update over Wi-Fi is running
+    Busy = 10,
+    NotBonded = 11,
+}
+
+impl Failure {
+    fn from_error(e: &anyhow::Error) -> Self {
+        e.downcast_ref::<OtaError>()
+            .map_or(Failure::Flash, Self::from_ota)
+    }
+
+    fn from_ota(e: &OtaError) -> Self {
+        match e {
+            OtaError::NoKey => Failure::NoKey,
+            OtaError::TooLarge(_) => Failure::TooLarge,
+            OtaError::Size { .. } => Failure::Size,
+            OtaError::Digest => Failure::Digest,
+            OtaError::Signature => Failure::Signature,
+            OtaError::NotNewer(_) => Failure::NotNewer,
+            OtaError::Busy => Failure::Busy,
+            _ => Failure::Invalid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    Ready { offset: u32 },
+    Ack { offset: u32 },
+    Resend { offset: u32 },
+    Done,
+    Error(Failure),
+}
+
+impl Response {
+    pub fn encode(&self) -> Vec<u8> {
+        let with_offset = |code: u8, offset: u32| {
+            let mut data = vec![code];
+            data.extend_from_slice(&offset.to_le_bytes());
+            data
+        };
+        match *self {
+            Response::Ready { offset } => {
+                let mut data = with_offset(READY, offset);
+                data.push(WINDOW);
+                data
+            }
+            Response::Ack { offset } => with_offset(ACK, offset),
+            Response::Resend { offset } => with_offset(RESEND, offset),
+            Response::Done => vec![DONE],
+            Response::Error(failure) => vec![ERROR, failure as u8],
+        }
+    }
+}
+
+/// Assembly of the image from the chunks, across disconnections
+pub struct Transfer<S: Slot> {
+    key: Option<PublicKey>,
+    /// Version running, updates have to be later
+    current: &'static str,
+    /// Opens the slot the image is written to, at the offset to write from
+    open: fn(usize) -> Result<S>,
+    image: Option<Image<S>>,
+    /// Image whose slot was released, to be resumed
+    released: Option<Released>,
+    /// Uptime of the last request
+    active_at: Duration,
+    version: String,
+    unacknowledged: u32,
+    /// A resend was requested, the chunks in flight are ignored until it starts
+    resending: bool,
+}
+
+impl<S: Slot> Transfer<S> {
+    pub fn new(
+        key: Option<PublicKey>,
+        current: &'static str,
+        open: fn(usize) -> Result<S>,
+    ) -> Self {
+        Self {
+            key,
+            current,
+            open,
+            image: None,
+            released: None,
+            active_at: Duration::ZERO,
+            version: String::new(),
+            unacknowledged: 0,
+            resending: false,
+        }
+    }
+
+    /// Version of the image being received
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Percentage of the image received, while receiving one
+    pub fn percent(&self) -> Option<u8> {
+        self.image.as_ref().map(|image| image.percent())
+    }
+
+    /// Whether an image is being received without requests for `IDLE_TIMEOUT`,
+    /// `now` being the uptime
+    pub fn is_idle(&self, now: Duration) -> bool {
+        self.image.is_some() && now.saturating_sub(self.active_at) >= IDLE_TIMEOUT
+    }
+
+    /// Close the slot, keeping what is needed to resume the transfer when the
+    /// phone starts it again, returning whether an image was being received
+    pub fn release(&mut self) -> bool {
+        match self.image.take() {
+            Some(image) => {
+                log::info!("Update to {} released at {}", self.version, image.written());
+                self.released = Some(image.release());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handle a request of the phone, returning the response to notify, `now`
+    /// being the uptime
+    pub fn handle(
+        &mut self,
+        now: Duration,
+        request: Result<Request, DfuError>,
+    ) -> Option<Response> {
+        self.active_at = now;
+        match request {
+            Ok(Request::Start { info }) => Some(self.start(info)),
+            Ok(Request::Data { offset, data }) => self.write(offset, &data),
+            Ok(Request::Finish) => Some(self.finish()),
+            Ok(Request::Abort) => {
+                if let Some(image) = self.image.take() {
+                    log::info!("Update aborted by the phone");
+                    image.abort();
+                }
+                self.released = None;
+                None
+            }
+            Err(DfuError::Crc) => self.resend(),
+            Err(DfuError::Invalid) => Some(Response::Error(Failure::Invalid)),
+            Err(DfuError::NotBonded) => Some(Response::Error(Failure::NotBonded)),
+        }
+    }
+
+    fn start(&mut self, info: ImageInfo) -> Response {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return Response::Error(Failure::NoKey),
+        };
+        if info.version.is_empty() {
+            return Response::Error(Failure::Invalid);
+        }
+        if info.size > crate::ota::MAX_IMAGE_SIZE {
+            return Response::Error(Failure::TooLarge);
+        }
+        // Checked before erasing the slot, the signature covers the version
+        if let Err(e) = info.authenticate(key, self.current) {
+            log::warn!("Update to {} refused: {e}", info.version);
+            return Response::Error(Failure::from_ota(&e));
+        }
+        self.unacknowledged = 0;
+        self.resending = false;
+        match &self.image {
+            Some(image) if image.info() == &info => {
+                log::info!("Resuming update to {} at {}", info.version, image.written());
+                return Response::Ready {
+                    offset: image.written() as u32,
+                };
+            }
+            _ => {}
+        }
+
+        if let Some(image) = self.image.take() {
+            image.abort();
+        }
+        if let Some(released) = self.released.take() {
+            if released.info() == &info {
+                let offset = released.written();
+                match (self.open)(offset) {
+                    Ok(slot) => {
+                        log::info!("Resuming update to {} at {offset}", info.version);
+                        self.image = Some(released.resume(slot));
+                        return Response::Ready {
+                            offset: offset as u32,
+                        };
+                    }
+                    Err(e) if matches!(e.downcast_ref(), Some(OtaError::Busy)) => {
+                        self.released = Some(released);
+                        return Response::Error(Failure::Busy);
+                    }
+                    Err(e) => log::warn!("Unable to resume the update, starting again: {e:?}"),
+                }
+            }
+        }
+        match (self.open)(0) {
+            Ok(slot) => {
+                log::info!("Receiving update to {}, {} bytes", info.version, info.size);
+                self.version = info.version.clone();
+                self.image = Some(Image::new(info, slot));
+                Response::Ready { offset: 0 }
+            }
+            Err(e) => {
+                log::warn!("Unable to open the update slot: {e:?}");
+                Response::Error(Failure::from_error(&e))
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Option<Response> {
+        let image = match &mut self.image {
+            Some(image) => image,
+            None => return Some(Response::Error(Failure::NoTransfer)),
+        };
+        if offset as usize != image.written() {
+            return self.resend();
+        }
+        self.resending = false;
+        if let Err(e) = image.write(data) {
+            log::warn!("Unable to write the update: {e:?}");
+            let failure = Failure::from_error(&e);
+            if let Some(image) = self.image.take() {
+                image.abort();
+            }
+            return Some(Response::Error(failure));
+        }
+        self.unacknowledged += 1;
+        let complete = image.written() == image.info().size;
+        if self.unacknowledged < ACK_EVERY && !complete {
+            return None;
+        }
+        self.unacknowledged = 0;
+        Some(Response::Ack {
+            offset: image.written() as u32,
+        })
+    }
+
+    /// Ask for the chunks following the last one written, once until it comes
+    fn resend(&mut self) -> Option<Response> {
+        let offset = match &self.image {
+            Some(image) => image.written() as u32,
+            None => return Some(Response::Error(Failure::NoTransfer)),
+        };
+        if std::mem::replace(&mut self.resending, true) {
+            return None;
+        }
+        self.unacknowledged = 0;
+        Some(Response::Resend { offset })
+    }
+
+    fn finish(&mut self) -> Response {
+        let (image, key) = match (self.image.take(), &self.key) {
+            (Some(image), Some(key)) => (image, key),
+            _ => return Response::Error(Failure::NoTransfer),
+        };
+        match image.finish(key, self.current) {
+            Ok(()) => Response::Done,
+            Err(e) => {
+                log::warn!("Update to {} rejected: {e:?}", self.version);
+                Response::Error(Failure::from_error(&e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    const CURRENT: &str = "0.2.0";
+    /// Uptime of the requests
+    const NOW: Duration = Duration::from_secs(100);
+
+    /// Content of the slot, per test thread
+    #[derive(Default)]
+    struct Flash {
+        opened: usize,
+        data: Vec<u8>,
+        active: bool,
+        aborted: bool,
+        /// Open by the update over Wi-Fi
+        busy: bool,
+    }
+
+    thread_local! {
+        static FLASH: RefCell<Flash> = RefCell::new(Flash::default());
+    }
+
+    struct FakeSlot;
+
+    impl FakeSlot {
+        fn open(offset: usize) -> Result<Self> {
+            FLASH.with(|flash| {
+                let mut flash = flash.borrow_mut();
+                if flash.busy {
+                    return Err(OtaError::Busy.into());
+                }
+                if offset == 0 {
+                    flash.data.clear();
+                } else if flash.data.len() != offset {
+                    anyhow::bail!("Slot written meanwhile");
+                }
+                flash.opened += 1;
+                Ok(FakeSlot)
+            })
+        }
+    }
+
+    impl Slot for FakeSlot {
+        fn write(&mut self, data: &[u8]) -> Result<()> {
+            FLASH.with(|flash| flash.borrow_mut().data.extend_from_slice(data));
+            Ok(())
+        }
+
+        fn activate(&mut self) -> Result<()> {
+            FLASH.with(|flash| flash.borrow_mut().active = true);
+            Ok(())
+        }
+
+        fn abort(&mut self) {
+            FLASH.with(|flash| flash.borrow_mut().aborted = true);
+        }
+    }
+
+    fn flash<T>(f: impl FnOnce(&Flash) -> T) -> T {
+        FLASH.with(|flash| f(&flash.borrow()))
+    }
+
+    fn key_pair() -> ed25519_compact::KeyPair {
+        ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new([3; 32]))
+    }
+
+    fn transfer() -> Transfer<FakeSlot> {
+        let hex: String = key_pair().pk.iter().map(|b| format!("{b:02x}")).collect();
+        Transfer::new(PublicKey::from_hex(&hex).ok(), CURRENT, FakeSlot::open)
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    /// Start request of the phone, signed with `signed_version`
+    fn start(image: &[u8], version: &str, signed_version: &str) -> Vec<u8> {
+        let sha256: [u8; 32] = Sha256::digest(image).into();
+        let mut message = sha256.to_vec();
+        message.extend_from_slice(signed_version.as_bytes());
+        let signature = key_pair().sk.sign(message, None);
+        let mut data = vec![START];
+        data.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        data.extend_from_slice(&sha256);
+        data.extend_from_slice(signature.as_ref());
+        data.extend_from_slice(version.as_bytes());
+        data
+    }
+
+    fn chunk(offset: usize, data: &[u8]) -> Vec<u8> {
+        let mut chunk = (offset as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(data);
+        let crc = crc32(&chunk);
+        chunk.extend_from_slice(&crc.to_le_bytes());
+        chunk
+    }
+
+    fn send_start(transfer: &mut Transfer<FakeSlot>, image: &[u8]) -> Option<Response> {
+        transfer.handle(NOW, Request::parse_control(&start(image, "0.3.0", "0.3.0")))
+    }
+
+    /// Send the chunks of `size` bytes from `offset`, returning the responses
+    fn send_chunks(
+        transfer: &mut Transfer<FakeSlot>,
+        image: &[u8],
+        offset: usize,
+        size: usize,
+    ) -> Vec<Response> {
+        (offset..image.len())
+            .step_by(size)
+            .filter_map(|offset| {
+                let end = (offset + size).min(image.len());
+                transfer.handle(
+                    NOW,
+                    Request::parse_chunk(&chunk(offset, &image[offset..end])),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request = Request::parse_control(&start(&image(10), "0.3.0", "0.3.0")).unwrap();
+        match request {
+            Request::Start { info } => {
+                assert_eq!(info.version, "0.3.0");
+                assert_eq!(info.size, 10);
+            }
+            _ => panic!("{request:?}"),
+        }
+        assert_eq!(Request::parse_control(&[FINISH]), Ok(Request::Finish));
+        assert_eq!(Request::parse_control(&[ABORT]), Ok(Request::Abort));
+        assert_eq!(
+            Request::parse_control(&[START, 1, 2]),
+            Err(DfuError::Invalid)
+        );
+
+        assert_eq!(
+            Request::parse_chunk(&chunk(8, b"data")),
+            Ok(Request::Data {
+                offset: 8,
+                data: b"data".to_vec()
+            })
+        );
+        let mut corrupted = chunk(8, b"data");
+        corrupted[5] ^= 1;
+        assert_eq!(Request::parse_chunk(&corrupted), Err(DfuError::Crc));
+        assert_eq!(Request::parse_chunk(&[0; 7]), Err(DfuError::Invalid));
+    }
+
+    #[test]
+    fn assembles_chunks() {
+        let image = image(1000);
+        let mut transfer = transfer();
+        assert_eq!(
+            send_start(&mut transfer, &image),
+            Some(Response::Ready { offset: 0 })
+        );
+        // Acknowledged every 4 chunks, and at the end
+        let responses = send_chunks(&mut transfer, &image, 0, 100);
+        let acks: Vec<_> = [400, 800, 1000]
+            .iter()
+            .map(|&offset| Response::Ack { offset })
+            .collect();
+        assert_eq!(responses, acks);
+        assert_eq!(transfer.percent(), Some(100));
+
+        assert_eq!(
+            transfer.handle(NOW, Ok(Request::Finish)),
+            Some(Response::Done)
+        );
+        assert_eq!(transfer.version(), "0.3.0");
+        assert!(flash(|flash| flash.data == image && flash.active));
+    }
+
+    #[test]
+    fn resends_after_lost_or_corrupted_chunks() {
+        let image = image(600);
+        let mut transfer = transfer();
+        send_start(&mut transfer, &image);
+        send_chunks(&mut transfer, &image[..200], 0, 100);
+
+        // Chunk at 200 lost, the next ones in flight are ignored
+        let responses = send_chunks(&mut transfer, &image, 300, 100);
+        assert_eq!(responses, [Response::Resend { offset: 200 }]);
+
+        // Requested once until the chunk comes
+        let mut corrupted = chunk(200, &image[200..300]);
+        corrupted[10] ^= 1;
+        assert_eq!(transfer.handle(NOW, Request::parse_chunk(&corrupted)), None);
+        send_chunks(&mut transfer, &image[..300], 200, 100);
+
+        let mut corrupted = chunk(300, &image[300..400]);
+        corrupted[10] ^= 1;
+        assert_eq!(
+            transfer.handle(NOW, Request::parse_chunk(&corrupted)),
+            Some(Response::Resend { offset: 300 })
+        );
+
+        send_chunks(&mut transfer, &image, 300, 100);
+        assert_eq!(
+            transfer.handle(NOW, Ok(Request::Finish)),
+            Some(Response::Done)
+        );
+        assert!(flash(|flash| flash.data == image));
+    }
+
+    #[test]
+    fn resumes_after_disconnection() {
+        let image = image(1000);
+        let mut transfer = transfer();
+        send_start(&mut transfer, &image);
+        send_chunks(&mut transfer, &image[..450], 0, 150);
+
+        // The slot is released when the phone disconnects
+        assert!(transfer.release());
+        assert!(!transfer.release());
+        assert_eq!(transfer.percent(), None);
+        assert_eq!(
+            send_chunks(&mut transfer, &image, 450, 150)[..1],
+            [Response::Error(Failure::NoTransfer)]
+        );
+
+        // The phone reconnects and starts the same image again
+        assert_eq!(
+            send_start(&mut transfer, &image),
+            Some(Response::Ready { offset: 450 })
+        );
+        send_chunks(&mut transfer, &image, 450, 150);
+        assert_eq!(
+            transfer.handle(NOW, Ok(Request::Finish)),
+            Some(Response::Done)
+        );
+        assert!(flash(|flash| flash.data == image && flash.active));
+        assert_eq!(flash(|flash| flash.opened), 2);
+    }
+
+    #[test]
+    fn releases_idle_transfers() {
+        let image = image(1000);
+        let mut transfer = transfer();
+        assert!(!transfer.is_idle(NOW + IDLE_TIMEOUT));
+        send_start(&mut transfer, &image);
+        send_chunks(&mut transfer, &image[..300], 0, 100);
+        assert!(!transfer.is_idle(NOW + IDLE_TIMEOUT - Duration::from_secs(1)));
+        assert!(transfer.is_idle(NOW + IDLE_TIMEOUT));
+        transfer.release();
+        assert!(!transfer.is_idle(NOW + IDLE_TIMEOUT));
+    }
+
+    #[test]
+    fn restarts_when_the_slot_was_written_meanwhile() {
+        let image = image(1000);
+        let mut transfer = transfer();
+        send_start(&mut transfer, &image);
+        send_chunks(&mut transfer, &image[..300], 0, 100);
+        transfer.release();
+
+        // Used by the update over Wi-Fi, then freed
+        FLASH.with(|flash| flash.borrow_mut().busy = true);
+        assert_eq!(
+            send_start(&mut transfer, &image),
+            Some(Response::Error(Failure::Busy))
+        );
+        FLASH.with(|flash| {
+            let mut flash = flash.borrow_mut();
+            flash.busy = false;
+            flash.data = vec![0; 200];
+        });
+        assert_eq!(
+            send_start(&mut transfer, &image),
+            Some(Response::Ready { offset: 0 })
+        );
+        send_chunks(&mut transfer, &image, 0, 100);
+        assert_eq!(
+            transfer.handle(NOW, Ok(Request::Finish)),
+            Some(Response::Done)
+        );
+        assert!(flash(|flash| flash.data == image && flash.active));
+    }
+
+    #[test]
+    fn restarts_with_another_image() {
+        let first = image(1000);
+        let mut transfer = transfer();
+        send_start(&mut transfer, &first);
+        send_chunks(&mut transfer, &first[..300], 0, 100);
+
+        let second = image(800);
+        assert_eq!(
+            send_start(&mut transfer, &second),
+            Some(Response::Ready { offset: 0 })
+        );
+        assert!(flash(|flash| flash.aborted && flash.opened == 2));
+    }
+
+    #[test]
+    fn refuses_unauthenticated_images() {
+        let image = image(100);
+        let mut transfer = transfer();
+
+        let older = start(&image, "0.1.0", "0.1.0");
+        assert_eq!(
+            transfer.handle(NOW, Request::parse_control(&older)),
+            Some(Response::Error(Failure::NotNewer))
+        );
+        let forged = start(&image, "0.3.0", "0.1.0");
+        assert_eq!(
+            transfer.handle(NOW, Request::parse_control(&forged)),
+            Some(Response::Error(Failure::Signature))
+        );
+        assert_eq!(flash(|flash| flash.opened), 0);
+
+        let mut without_key = Transfer::new(None, CURRENT, FakeSlot::open);
+        assert_eq!(
+            send_start(&mut without_key, &image),
+            Some(Response::Error(Failure::NoKey))
+        );
+        assert_eq!(
+            transfer.handle(NOW, Err(DfuError::NotBonded)),
+            Some(Response::Error(Failure::NotBonded))
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_images() {
+        let image = image(200);
+        let mut transfer = transfer();
+        send_start(&mut transfer, &image);
+        let mut wrong = image.clone();
+        wrong[150] ^= 1;
+        send_chunks(&mut transfer, &wrong, 0, 100);
+        assert_eq!(
+            transfer.handle(NOW, Ok(Request::Finish)),
+            Some(Response::Error(Failure::Digest))
+        );
+        assert!(flash(|flash| flash.aborted && !flash.active));
+        assert_eq!(
+            transfer.handle(NOW, Ok(Request::Finish)),
+            Some(Response::Error(Failure::NoTransfer))
+        );
+    }
+}
@@ -1,8 +1,16 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Result;
 
-use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, RoundedRectangle, Triangle},
+    text::Text,
+};
 
 use embedded_graphics_framebuf::{AsWords, FrameBuf};
 use embedded_hal_0_2::blocking::delay::DelayUs;
@@ -12,16 +20,160 @@ use esp_idf_hal::{
     ledc::{config::TimerConfig, Channel, Timer, CHANNEL0, TIMER0},
     prelude::*,
 };
-use log::*;
 use mipidsi::{Display, DisplayOptions, ColorOrder};
+use profont::{PROFONT_12_POINT, PROFONT_14_POINT, PROFONT_18_POINT};
 
 pub use crate::errors::*;
+use crate::metrics::{self, Metric};
+use crate::screenshot;
+use crate::text::wrap_text;
 use crate::types::EspSpi2InterfaceNoCS;
 
+/// Set while the framebuffer is sent, when the panic handler can't draw
+static FLUSHING: AtomicBool = AtomicBool::new(false);
+
 pub struct TwatchDisplay {
     pub display: Display<EspSpi2InterfaceNoCS, mipidsi::NoPin, mipidsi::models::ST7789>,
     pub backlight: Backlight,
     pub framebuffer: &'static mut FrameBuf<Rgb565, 240_usize, 240_usize, 57600_usize>,
+    pub status: StatusBar,
+    /// Drawn over the current tile until dismissed
+    pub popup: Option<Popup>,
+    /// First pixel drawn of the frame not flushed yet, for `Metric::Render`
+    drawing_since: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiIndicator {
+    Off,
+    Connecting,
+    Connected { rssi: i8 },
+    AccessPoint,
+}
+
+/// Indicators drawn over every tile on commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusBar {
+    pub wifi: WifiIndicator,
+    /// A device of the I2C buses keeps failing, see `health`
+    pub degraded: bool,
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self {
+            wifi: WifiIndicator::Off,
+            degraded: false,
+        }
+    }
+}
+
+impl StatusBar {
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if self.degraded {
+            Self::draw_warning(target)?;
+        }
+        self.draw_wifi(target)
+    }
+
+    /// Warning sign left of the Wi-Fi bars
+    fn draw_warning<D>(target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        Triangle::new(Point::new(200, 2), Point::new(193, 14), Point::new(207, 14))
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_ORANGE))
+            .draw(target)?;
+        let mark = PrimitiveStyle::with_fill(Rgb565::BLACK);
+        Rectangle::new(Point::new(199, 6), Size::new(2, 4))
+            .into_styled(mark)
+            .draw(target)?;
+        Rectangle::new(Point::new(199, 11), Size::new(2, 2))
+            .into_styled(mark)
+            .draw(target)
+    }
+
+    /// Wi-Fi signal bars in the top right corner, grey while connecting and blue
+    /// while provisioning
+    fn draw_wifi<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let (bars, color) = match self.wifi {
+            WifiIndicator::Off => return Ok(()),
+            WifiIndicator::Connecting => (4, Rgb565::CSS_DIM_GRAY),
+            WifiIndicator::AccessPoint => (4, Rgb565::BLUE),
+            WifiIndicator::Connected { rssi } => (
+                match rssi {
+                    -55..=i8::MAX => 4,
+                    -67..=-56 => 3,
+                    -78..=-68 => 2,
+                    _ => 1,
+                },
+                Rgb565::WHITE,
+            ),
+        };
+        for bar in 0..4 {
+            let height = 3 + 3 * bar as u32;
+            let style = if bar < bars {
+                PrimitiveStyle::with_fill(color)
+            } else {
+                PrimitiveStyle::with_stroke(Rgb565::CSS_DIM_GRAY, 1)
+            };
+            Rectangle::new(
+                Point::new(212 + 6 * bar, 14 - height as i32),
+                Size::new(4, height),
+            )
+            .into_styled(style)
+            .draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Message box drawn over every tile on commit, e.g. a new notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Popup {
+    pub title: String,
+    pub text: String,
+}
+
+impl Popup {
+    /// Screen area covered by the pop-up
+    pub const AREA: Rectangle = Rectangle::new(Point::new(10, 40), Size::new(220, 130));
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let style = PrimitiveStyleBuilder::new()
+            .fill_color(Rgb565::BLACK)
+            .stroke_color(Rgb565::CSS_ORANGE)
+            .stroke_width(2)
+            .build();
+        RoundedRectangle::with_equal_corners(Self::AREA, Size::new(8, 8))
+            .into_styled(style)
+            .draw(target)?;
+
+        let origin = Self::AREA.top_left + Point::new(10, 24);
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::CSS_ORANGE);
+        let title = wrap_text(&self.title, 16, 1);
+        Text::new(
+            title.first().map(String::as_str).unwrap_or_default(),
+            origin,
+            title_style,
+        )
+        .draw(target)?;
+
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+        for (i, line) in wrap_text(&self.text, 20, 4).iter().enumerate() {
+            Text::new(line, origin + Point::new(0, 26 + 22 * i as i32), text_style).draw(target)?;
+        }
+        Ok(())
+    }
 }
 
 impl DrawTarget for TwatchDisplay {
@@ -33,10 +185,11 @@ impl DrawTarget for TwatchDisplay {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
+        self.drawing_since.get_or_insert_with(Instant::now);
         self.framebuffer
             //self.display
             .draw_iter(pixels)
-            .map_err(|_| TwatchError::Display)
+            .map_err(TwatchError::display("draw"))
     }
 }
 
@@ -79,6 +232,9 @@ impl TwatchDisplay {
             display,
             backlight,
             framebuffer,
+            status: StatusBar::default(),
+            popup: None,
+            drawing_since: None,
         })
     }
 
@@ -89,14 +245,21 @@ impl TwatchDisplay {
         };
         self.display
             .init(delay_source, display_options)
-            .map_err(|e| {
-                info!("Error initializing display {e:?}");
-                TwatchError::Display
-            })?;
+            .map_err(TwatchError::display("init"))?;
         Ok(())
     }
 
     pub fn commit_display_partial(&mut self, rect: Rectangle) -> Result<()> {
+        if let Some(since) = self.drawing_since.take() {
+            metrics::record(Metric::Render, since.elapsed());
+        }
+        FLUSHING.store(true, Ordering::Release);
+        let result = metrics::time(Metric::Flush, || self.write_partial(rect));
+        FLUSHING.store(false, Ordering::Release);
+        result
+    }
+
+    fn write_partial(&mut self, rect: Rectangle) -> Result<()> {
         if rect.size == self.bounding_box().size {
             self.display
                 .write_raw(
@@ -106,7 +269,7 @@ impl TwatchDisplay {
                     rect.top_left.y as u16 + rect.size.height as u16,
                     self.framebuffer.as_words(),
                 )
-                .map_err(|_| TwatchError::Display)?;
+                .map_err(TwatchError::display("write"))?;
         } else {
             let mut partial_fb: Vec<u16> = vec![0; (rect.size.width * rect.size.height) as usize];
             let sx: usize = rect.top_left.x as _;
@@ -128,12 +291,42 @@ impl TwatchDisplay {
                     rect.top_left.y as u16 + rect.size.height as u16 - 1,
                     &mut partial_fb,
                 )
-                .map_err(|_| TwatchError::Display)?;
+                .map_err(TwatchError::display("write"))?;
         }
         Ok(())
     }
 
+    /// Draw the status bar and the pop-up over the tile
+    fn draw_overlays(&mut self) -> Result<()> {
+        let status = self.status;
+        status.draw(self)?;
+        if let Some(popup) = self.popup.take() {
+            popup.draw(self)?;
+            self.popup = Some(popup);
+        }
+        Ok(())
+    }
+
+    /// Hand the frame drawn out as screenshot frames instead of committing it
+    pub fn screenshot(&mut self, mut write: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        self.draw_overlays()?;
+        let result = self
+            .framebuffer
+            .0
+            .chunks(240)
+            .enumerate()
+            .try_for_each(|(row, pixels)| {
+                write(&screenshot::encode_row(240, 240, row as u16, pixels, |pixel| {
+                    pixel.into_storage()
+                }))
+            });
+        self.framebuffer.clear_black();
+        self.drawing_since = None;
+        result
+    }
+
     pub fn commit_display(&mut self) -> Result<()> {
+        self.draw_overlays()?;
         self.commit_display_partial(Rectangle {
             top_left: Point::default(),
             size: Size {
@@ -146,6 +339,29 @@ impl TwatchDisplay {
         Ok(())
     }
 
+    /// Show a panic over whatever was being drawn, unless the panic happened
+    /// while sending the framebuffer
+    pub fn draw_panic(&mut self, message: &str, location: &str) -> Result<()> {
+        if FLUSHING.load(Ordering::Acquire) {
+            anyhow::bail!("Display busy");
+        }
+        self.framebuffer.clear_black();
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::RED);
+        Text::new("Panic", Point::new(10, 30), title_style).draw(self)?;
+
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+        for (i, line) in wrap_text(message, 22, 7).iter().enumerate() {
+            Text::new(line, Point::new(10, 60 + 20 * i as i32), text_style).draw(self)?;
+        }
+        let location_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_LIGHT_GRAY);
+        for (i, line) in wrap_text(location, 30, 2).iter().enumerate() {
+            Text::new(line, Point::new(10, 210 + 16 * i as i32), location_style).draw(self)?;
+        }
+
+        self.commit_display_partial(self.bounding_box())?;
+        self.set_display_level(100u32)
+    }
+
     pub fn get_display_level(&self) -> u32 {
         self.backlight.level
     }
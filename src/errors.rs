@@ -1,52 +1,291 @@
-#[derive(Debug)]
-pub enum TwatchError {
-    Clock,
-    Display,
+//! Errors of the peripherals of the watch.
+//!
+//! Each error has a stable code, short enough to be shown on screen: the
+//! hundreds give the device and the units the fault, e.g. 201 is a NACK from
+//! the clock.
+
+#[cfg(feature = "esp")]
+use embedded_hal::i2c::{Error as _, ErrorKind};
+#[cfg(feature = "esp")]
+use esp_idf_hal::i2c::I2cError;
+#[cfg(feature = "esp")]
+use esp_idf_sys::ESP_ERR_TIMEOUT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
     Pmu,
-    I2c,
+    Clock,
     Accel,
+    Touch,
+    Display,
 }
 
-impl std::fmt::Display for TwatchError {
+impl Device {
+    pub const COUNT: usize = 5;
+
+    pub const ALL: [Device; Device::COUNT] = [
+        Device::Pmu,
+        Device::Clock,
+        Device::Accel,
+        Device::Touch,
+        Device::Display,
+    ];
+
+    /// Device answering at an address of the I2C buses
+    pub fn from_address(address: u8) -> Option<Device> {
+        Device::ALL
+            .into_iter()
+            .find(|device| device.address() == Some(address))
+    }
+
+    /// Address on the I2C bus, the display being on SPI
+    pub fn address(&self) -> Option<u8> {
+        match self {
+            Device::Pmu => Some(0x35),
+            Device::Clock => Some(0x51),
+            Device::Accel => Some(0x19),
+            Device::Touch => Some(0x38),
+            Device::Display => None,
+        }
+    }
+
+    fn code(&self) -> u16 {
+        match self {
+            Device::Pmu => 100,
+            Device::Clock => 200,
+            Device::Accel => 300,
+            Device::Touch => 400,
+            Device::Display => 500,
+        }
+    }
+}
+
+impl std::fmt::Display for Device {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.write_fmt(format_args!("{:?}", self))
     }
 }
 
-impl std::error::Error for TwatchError {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The device didn't acknowledge its address or the data
+    Nack = 1,
+    /// The transfer didn't complete in time, e.g. SCL held low
+    Timeout = 2,
+    /// Bus error or lost arbitration, e.g. SDA held low
+    Bus = 3,
+    /// Reported by the driver of the device
+    Driver = 4,
+    Other = 9,
+}
+
+/// Errors of the I2C buses, sorted by fault
+pub trait BusError {
+    fn fault(&self) -> Fault;
+}
+
+#[cfg(feature = "esp")]
+impl BusError for I2cError {
+    fn fault(&self) -> Fault {
+        match self.kind() {
+            ErrorKind::NoAcknowledge(_) => Fault::Nack,
+            _ if self.cause().code() == ESP_ERR_TIMEOUT as i32 => Fault::Timeout,
+            ErrorKind::Bus | ErrorKind::ArbitrationLoss => Fault::Bus,
+            _ => Fault::Other,
+        }
+    }
+}
+
+/// Error of the driver of a device on the I2C buses, which mostly wraps an
+/// error of the bus
+#[cfg(feature = "esp")]
+pub trait I2cDriverError: std::fmt::Debug + Sized {
+    /// The error of the bus, or the error itself when the driver failed
+    /// without a transfer error
+    fn into_i2c(self) -> Result<I2cError, Self>;
+}
 
-impl From<axp20x::AxpError<esp_idf_hal::i2c::I2cError>> for TwatchError {
-    fn from(_e: axp20x::AxpError<esp_idf_hal::i2c::I2cError>) -> Self {
-        TwatchError::Pmu
+/// The touch screen driver returns the errors of the bus as they are
+#[cfg(feature = "esp")]
+impl I2cDriverError for I2cError {
+    fn into_i2c(self) -> Result<I2cError, Self> {
+        Ok(self)
     }
 }
 
-impl From<esp_idf_hal::i2c::I2cError> for TwatchError {
-    fn from(_e: esp_idf_hal::i2c::I2cError) -> Self {
-        TwatchError::I2c
+#[cfg(feature = "esp")]
+impl I2cDriverError for axp20x::AxpError<I2cError> {
+    fn into_i2c(self) -> Result<I2cError, Self> {
+        match self {
+            axp20x::AxpError::I2c(e) => Ok(e),
+            e => Err(e),
+        }
     }
 }
 
-impl From<mipidsi::Error<std::convert::Infallible>> for TwatchError {
-    fn from(_e: mipidsi::Error<std::convert::Infallible>) -> Self {
-        TwatchError::Display
+#[cfg(feature = "esp")]
+impl I2cDriverError for pcf8563::Error<I2cError> {
+    fn into_i2c(self) -> Result<I2cError, Self> {
+        match self {
+            pcf8563::Error::I2C(e) => Ok(e),
+            e => Err(e),
+        }
     }
 }
 
-impl From<std::convert::Infallible> for TwatchError {
-    fn from(_e: std::convert::Infallible) -> Self {
-        TwatchError::Display
+#[cfg(feature = "esp")]
+impl I2cDriverError for bma423::Error<I2cError> {
+    fn into_i2c(self) -> Result<I2cError, Self> {
+        match self {
+            bma423::Error::I2c(e) => Ok(e),
+            e => Err(e),
+        }
     }
 }
 
-impl From<bma423::Error<esp_idf_hal::i2c::I2cError>> for TwatchError {
-    fn from(_e: bma423::Error<esp_idf_hal::i2c::I2cError>) -> Self {
-        TwatchError::Accel
+/// Returned by the `Accelerometer` trait of the accelerometer driver
+#[cfg(feature = "esp")]
+impl I2cDriverError for accelerometer::Error<bma423::Error<I2cError>> {
+    fn into_i2c(self) -> Result<I2cError, Self> {
+        let kind = self.kind();
+        match self.into_cause() {
+            Some(bma423::Error::I2c(e)) => Ok(e),
+            Some(cause) => Err(Self::new_with_cause(kind, cause)),
+            None => Err(Self::new(kind)),
+        }
     }
 }
 
-impl From<pcf8563::Error<esp_idf_hal::i2c::I2cError>> for TwatchError {
-    fn from(_e: pcf8563::Error<esp_idf_hal::i2c::I2cError>) -> Self {
-        TwatchError::Clock
+/// Error of a driver crate, kept as its debug output since they don't all
+/// implement `std::error::Error`
+#[derive(Debug)]
+pub struct DriverError(String);
+
+impl std::fmt::Display for DriverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
 }
+
+impl std::error::Error for DriverError {}
+
+#[cfg(feature = "esp")]
+#[derive(Debug)]
+pub enum TwatchError {
+    /// Transfer with a device on one of the I2C buses
+    I2c {
+        device: Device,
+        operation: &'static str,
+        register: Option<u8>,
+        source: I2cError,
+    },
+    /// Failure reported by the driver of a device
+    Driver {
+        device: Device,
+        operation: &'static str,
+        source: DriverError,
+    },
+}
+
+#[cfg(feature = "esp")]
+impl TwatchError {
+    /// Error of a register access, for `map_err`
+    pub fn i2c(
+        device: Device,
+        operation: &'static str,
+        register: Option<u8>,
+    ) -> impl FnOnce(I2cError) -> Self {
+        move |source| TwatchError::I2c {
+            device,
+            operation,
+            register,
+            source,
+        }
+    }
+
+    /// Error of the driver of a device on the I2C buses, for `map_err`
+    ///
+    /// The errors of the bus are kept as such, to be sorted by fault.
+    pub fn driver<E: I2cDriverError>(
+        device: Device,
+        operation: &'static str,
+    ) -> impl FnOnce(E) -> Self {
+        move |source| match source.into_i2c() {
+            Ok(source) => TwatchError::I2c {
+                device,
+                operation,
+                register: None,
+                source,
+            },
+            Err(source) => TwatchError::Driver {
+                device,
+                operation,
+                source: DriverError(format!("{source:?}")),
+            },
+        }
+    }
+
+    /// Error of the display driver, for `map_err`
+    pub fn display<E: std::fmt::Debug>(operation: &'static str) -> impl FnOnce(E) -> Self {
+        move |source| TwatchError::Driver {
+            device: Device::Display,
+            operation,
+            source: DriverError(format!("{source:?}")),
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        match self {
+            TwatchError::I2c { device, .. } | TwatchError::Driver { device, .. } => *device,
+        }
+    }
+
+    pub fn fault(&self) -> Fault {
+        match self {
+            TwatchError::I2c { source, .. } => source.fault(),
+            TwatchError::Driver { .. } => Fault::Driver,
+        }
+    }
+
+    /// Stable code of the device and the fault
+    pub fn code(&self) -> u16 {
+        self.device().code() + self.fault() as u16
+    }
+}
+
+#[cfg(feature = "esp")]
+impl std::fmt::Display for TwatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!("E{} {}", self.code(), self.device()))?;
+        if let Some(address) = self.device().address() {
+            f.write_fmt(format_args!("@{address:#04x}"))?;
+        }
+        match self {
+            TwatchError::I2c {
+                operation,
+                register: Some(register),
+                ..
+            } => f.write_fmt(format_args!(" {operation} register {register:#04x}")),
+            TwatchError::I2c { operation, .. } | TwatchError::Driver { operation, .. } => {
+                f.write_fmt(format_args!(" {operation}"))
+            }
+        }?;
+        f.write_fmt(format_args!(": {:?}", self.fault()))
+    }
+}
+
+#[cfg(feature = "esp")]
+impl std::error::Error for TwatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TwatchError::I2c { source, .. } => Some(source),
+            TwatchError::Driver { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Code of an error of the peripherals, to be shown on screen
+#[cfg(feature = "esp")]
+pub fn code(error: &anyhow::Error) -> Option<u16> {
+    error.downcast_ref::<TwatchError>().map(TwatchError::code)
+}
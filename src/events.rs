@@ -1,11 +1,14 @@
 use core::time::Duration;
 
-use embedded_svc::sys_time::SystemTime;
-
 use ft6x36::TouchEvent;
 use num_enum::{FromPrimitive, IntoPrimitive};
 
+use crate::alarms::Alarm;
+use crate::gadgetbridge::PhoneEvent;
+use crate::notifications::Notification;
+use crate::ota::UpdateStatus;
 use crate::tiles::WatchTile;
+use crate::wifi::WifiEvent;
 
 #[repr(u32)]
 #[derive(Copy, Clone, Debug, FromPrimitive, IntoPrimitive)]
@@ -15,10 +18,29 @@ pub enum TwatchRawEvent {
     Touch = 1 << 2,
     Pmu = 1 << 3,
     Accel = 1 << 4,
+    Network = 1 << 5,
+    Ble = 1 << 6,
+    Notification = 1 << 7,
+    Overlay = 1 << 8,
+    Update = 1 << 9,
+    Shell = 1 << 10,
+    /// Feeds the watchdog while idle
+    Heartbeat = 1 << 11,
     #[default]
     Unknown = 1 << 31,
 }
 
+impl TwatchRawEvent {
+    /// Events whose bits are set, the notification merging the posts made
+    /// before the event loop wakes up
+    pub fn split(bits: u32) -> impl Iterator<Item = TwatchRawEvent> {
+        (0..u32::BITS)
+            .map(|i| 1 << i)
+            .filter(move |bit| bits & bit != 0)
+            .map(TwatchRawEvent::from)
+    }
+}
+
 #[derive(Debug)]
 pub struct TwatchEvent {
     pub time: Duration,
@@ -27,7 +49,7 @@ pub struct TwatchEvent {
 
 impl TwatchEvent {
     pub fn new(kind: Kind) -> Self {
-        let time = (esp_idf_svc::systime::EspSystemTime {}).now();
+        let time = crate::utils::uptime();
         TwatchEvent { time, kind }
     }
 }
@@ -40,5 +62,13 @@ pub enum Kind {
     Accel,
     Touch(TouchEvent),
     PmuButtonPressed,
+    Alarm(Alarm),
+    Wifi(WifiEvent),
+    Phone(PhoneEvent),
+    Notification(Notification),
+    /// The notification pop-up has been shown long enough
+    OverlayTimeout,
+    /// Progress of the firmware update
+    Update(UpdateStatus),
     NewTile(Box<dyn WatchTile + Send>),
 }
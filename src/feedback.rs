@@ -0,0 +1,227 @@
+//! Short vibrations confirming the input, see `haptics` for the patterns.
+//!
+//! A cue is played when a tap is handled by a tile, a value steps, the tile
+//! changes or a long press is recognized. Each cue can be turned off, and the
+//! feedback as a whole, but not the alarms nor the notifications. The
+//! intensity applies to every pattern.
+
+use std::time::Duration;
+
+use crate::haptics::Pattern;
+use crate::storage::Persist;
+
+const STORAGE_VERSION: u8 = 1;
+
+/// Lowest intensity which can be set, the motor hardly spins below it
+pub const MIN_INTENSITY: u8 = 20;
+/// Time a finger stays down for a long press
+pub const LONG_PRESS: Duration = Duration::from_millis(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cue {
+    /// A tap handled by the tile
+    Tap,
+    /// A value stepped, as the backlight level or an alarm wheel
+    Detent,
+    TileChange,
+    LongPress,
+}
+
+impl Cue {
+    pub const ALL: [Cue; 4] = [Cue::Tap, Cue::Detent, Cue::TileChange, Cue::LongPress];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cue::Tap => "tap",
+            Cue::Detent => "detent",
+            Cue::TileChange => "tile",
+            Cue::LongPress => "hold",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Cue> {
+        Self::ALL.into_iter().find(|cue| cue.name() == name)
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        match self {
+            Cue::LongPress => Pattern::DoubleBuzz,
+            _ => Pattern::Tick,
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// Settings of the vibrations, kept in NVS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feedback {
+    /// Cues are played, the other patterns are not affected
+    pub enabled: bool,
+    /// Percent of the full speed of every pattern
+    pub intensity: u8,
+    /// Bits of the cues played
+    cues: u8,
+}
+
+impl Default for Feedback {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 100,
+            cues: Cue::ALL.iter().fold(0, |cues, cue| cues | cue.bit()),
+        }
+    }
+}
+
+impl Feedback {
+    /// Whether the cue is played, given the feedback is enabled
+    pub fn has_cue(&self, cue: Cue) -> bool {
+        self.cues & cue.bit() != 0
+    }
+
+    pub fn set_cue(&mut self, cue: Cue, on: bool) {
+        if on {
+            self.cues |= cue.bit();
+        } else {
+            self.cues &= !cue.bit();
+        }
+    }
+
+    pub fn plays(&self, cue: Cue) -> bool {
+        self.enabled && self.has_cue(cue)
+    }
+}
+
+impl std::fmt::Display for Feedback {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        f.write_fmt(format_args!("{state}, intensity {}%, cues", self.intensity))?;
+        for cue in Cue::ALL.iter().filter(|cue| self.has_cue(**cue)) {
+            f.write_fmt(format_args!(" {}", cue.name()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Persist for Feedback {
+    fn encode(&self) -> Vec<u8> {
+        vec![
+            STORAGE_VERSION,
+            self.enabled as u8,
+            self.intensity,
+            self.cues,
+        ]
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        match data {
+            [STORAGE_VERSION, enabled, intensity, cues] => Some(Self {
+                enabled: *enabled != 0,
+                intensity: (*intensity).clamp(MIN_INTENSITY, 100),
+                cues: *cues,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Recognizes a finger staying down, from the touch reports of the screen
+#[derive(Debug, Default)]
+pub struct LongPress {
+    /// When the finger went down
+    since: Option<Duration>,
+    recognized: bool,
+}
+
+impl LongPress {
+    /// Follow a report of the touch screen, returning true once per press
+    /// when the finger has been down for `LONG_PRESS`
+    pub fn touch(&mut self, now: Duration, down: bool) -> bool {
+        if !down {
+            *self = Self::default();
+            return false;
+        }
+        let since = *self.since.get_or_insert(now);
+        if self.recognized || now.saturating_sub(since) < LONG_PRESS {
+            return false;
+        }
+        self.recognized = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn recognizes_long_presses() {
+        let mut press = LongPress::default();
+        assert!(!press.touch(ms(1000), true));
+        assert!(!press.touch(ms(1599), true));
+        assert!(press.touch(ms(1600), true));
+        // Once per press
+        assert!(!press.touch(ms(1700), true));
+        assert!(!press.touch(ms(5000), true));
+
+        assert!(!press.touch(ms(5100), false));
+        assert!(!press.touch(ms(5200), true));
+        assert!(press.touch(ms(5800), true));
+    }
+
+    #[test]
+    fn ignores_short_presses() {
+        let mut press = LongPress::default();
+        for time in (0..3000).step_by(500) {
+            assert!(!press.touch(ms(time), true));
+            assert!(!press.touch(ms(time + 400), false));
+        }
+        assert!(!press.touch(ms(0), false));
+    }
+
+    #[test]
+    fn sets_cues() {
+        let mut feedback = Feedback::default();
+        assert!(Cue::ALL.iter().all(|&cue| feedback.plays(cue)));
+        feedback.set_cue(Cue::Detent, false);
+        assert!(!feedback.plays(Cue::Detent) && feedback.plays(Cue::Tap));
+        feedback.enabled = false;
+        assert!(!feedback.plays(Cue::Tap) && feedback.has_cue(Cue::Tap));
+        assert_eq!(
+            feedback.to_string(),
+            "off, intensity 100%, cues tap tile hold"
+        );
+        assert_eq!(Cue::from_name("hold"), Some(Cue::LongPress));
+        assert_eq!(Cue::from_name("swipe"), None);
+    }
+
+    #[test]
+    fn decodes() {
+        let mut feedback = Feedback {
+            enabled: false,
+            intensity: 60,
+            ..Default::default()
+        };
+        feedback.set_cue(Cue::Tap, false);
+        assert_eq!(Feedback::decode(&feedback.encode()), Some(feedback));
+
+        assert_eq!(
+            Feedback::decode(&[STORAGE_VERSION, 1, 5, 0x0f]).map(|f| f.intensity),
+            Some(MIN_INTENSITY)
+        );
+        assert_eq!(
+            Feedback::decode(&[STORAGE_VERSION, 1, 200, 0x0f]).map(|f| f.intensity),
+            Some(100)
+        );
+        assert_eq!(Feedback::decode(&[STORAGE_VERSION + 1, 1, 50, 0x0f]), None);
+        assert_eq!(Feedback::decode(&[STORAGE_VERSION, 1, 50]), None);
+        assert_eq!(Feedback::decode(&[]), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Firmware slots of the ESP32 and the update over Wi-Fi.
+//!
+//! An updated firmware is booted once pending verification: it has to confirm
+//! it works with `confirm_boot`, otherwise the bootloader rolls back to the
+//! previous one on the next reset.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use esp_idf_svc::notify::EspNotify;
+use esp_idf_sys::*;
+
+use log::*;
+
+use crate::events::TwatchRawEvent;
+use crate::http;
+use crate::ota::{self, Image, Installer, Manifest, OtaError, PublicKey, Slot, UpdateStatus};
+
+/// The HTTP client and the hashing of the image need a larger stack than the
+/// default one of the threads
+const STACK_SIZE: usize = 12 * 1024;
+
+/// Version of the running firmware
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Set while an `OtaSlot` is open, the updates over Wi-Fi and BLE can't
+/// write the slot at the same time
+static SLOT_OPEN: AtomicBool = AtomicBool::new(false);
+/// Bytes written to the slot when it was released unfinished, `usize::MAX`
+/// when it can't be resumed
+static RELEASED_AT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+const SECTOR_SIZE: usize = 4096;
+
+/// The OTA slot not running, erased as it is written
+pub struct OtaSlot {
+    partition: *const esp_partition_t,
+    /// Handle of the update, none once resumed as the rest of the image is
+    /// then written to the partition directly
+    handle: Option<esp_ota_handle_t>,
+    written: usize,
+    open: bool,
+}
+
+unsafe impl Send for OtaSlot {}
+
+impl OtaSlot {
+    /// Open the slot, failing with `OtaError::Busy` while another update has
+    /// it open
+    pub fn next() -> Result<Self> {
+        Self::claim(Self::begin)
+    }
+
+    /// Open the slot to write an image from `offset`, the bytes written when
+    /// it was released, or from the start when it is 0
+    pub fn open_at(offset: usize) -> Result<Self> {
+        match offset {
+            0 => Self::next(),
+            offset => Self::claim(|| Self::resume(offset)),
+        }
+    }
+
+    fn claim(open: impl FnOnce() -> Result<Self>) -> Result<Self> {
+        if SLOT_OPEN.swap(true, Ordering::Acquire) {
+            return Err(OtaError::Busy.into());
+        }
+        let slot = open();
+        if slot.is_err() {
+            SLOT_OPEN.store(false, Ordering::Release);
+        }
+        slot
+    }
+
+    fn partition() -> Result<*const esp_partition_t> {
+        let partition = unsafe { esp_ota_get_next_update_partition(std::ptr::null()) };
+        if partition.is_null() {
+            anyhow::bail!("No OTA slot, see partitions.csv");
+        }
+        Ok(partition)
+    }
+
+    fn begin() -> Result<Self> {
+        RELEASED_AT.store(usize::MAX, Ordering::Relaxed);
+        let partition = Self::partition()?;
+        let mut handle: esp_ota_handle_t = 0;
+        esp!(unsafe { esp_ota_begin(partition, OTA_WITH_SEQUENTIAL_WRITES as _, &mut handle) })?;
+        Ok(Self {
+            partition,
+            handle: Some(handle),
+            written: 0,
+            open: true,
+        })
+    }
+
+    fn resume(offset: usize) -> Result<Self> {
+        if RELEASED_AT.swap(usize::MAX, Ordering::Relaxed) != offset {
+            anyhow::bail!("The OTA slot was written since the update was released");
+        }
+        Ok(Self {
+            partition: Self::partition()?,
+            handle: None,
+            written: offset,
+            open: true,
+        })
+    }
+}
+
+impl Slot for OtaSlot {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        match self.handle {
+            Some(handle) => {
+                esp!(unsafe { esp_ota_write(handle, data.as_ptr() as *const _, data.len() as _) })?
+            }
+            None => {
+                // Erase the sectors starting within the data, the one it
+                // starts in was erased when its first bytes were written
+                let sector = |offset: usize| (offset + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+                let (start, end) = (sector(self.written), sector(self.written + data.len()));
+                if end > start {
+                    esp!(unsafe {
+                        esp_partition_erase_range(self.partition, start as _, (end - start) as _)
+                    })?;
+                }
+                esp!(unsafe {
+                    esp_partition_write(
+                        self.partition,
+                        self.written as _,
+                        data.as_ptr() as *const _,
+                        data.len() as _,
+                    )
+                })?;
+            }
+        }
+        self.written += data.len();
+        Ok(())
+    }
+
+    fn activate(&mut self) -> Result<()> {
+        self.open = false;
+        if let Some(handle) = self.handle {
+            esp!(unsafe { esp_ota_end(handle) })?;
+        }
+        // Also checks the format of the image
+        esp!(unsafe { esp_ota_set_boot_partition(self.partition) })?;
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        if std::mem::take(&mut self.open) {
+            if let Some(handle) = self.handle {
+                unsafe { esp_ota_abort(handle) };
+            }
+        }
+    }
+}
+
+impl Drop for OtaSlot {
+    /// Keep what was written unless the update was aborted, for it to be
+    /// resumed with `open_at`
+    fn drop(&mut self) {
+        if self.open {
+            self.abort();
+            RELEASED_AT.store(self.written, Ordering::Relaxed);
+        }
+        SLOT_OPEN.store(false, Ordering::Release);
+    }
+}
+
+/// Whether the running firmware was just updated and not confirmed yet
+fn is_pending_verify() -> bool {
+    let mut state: esp_ota_img_states_t = 0;
+    let partition = unsafe { esp_ota_get_running_partition() };
+    let result = unsafe { esp_ota_get_state_partition(partition, &mut state) };
+    result == ESP_OK && state == esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY
+}
+
+/// Keep booting the running firmware, to be called once it is initialized
+pub fn confirm_boot() -> Result<()> {
+    if is_pending_verify() {
+        info!("Confirming firmware {VERSION}");
+        esp!(unsafe { esp_ota_mark_app_valid_cancel_rollback() })?;
+    }
+    Ok(())
+}
+
+/// Reboot on the previous firmware if the running one was just updated,
+/// otherwise return
+pub fn rollback() {
+    if is_pending_verify() {
+        error!("Firmware {VERSION} is not working, rolling back");
+        unsafe { esp_ota_mark_app_invalid_rollback_and_reboot() };
+    }
+}
+
+pub fn restart() {
+    info!("Restarting");
+    unsafe { esp_restart() };
+}
+
+/// Shares the progress of the update with the event loop
+#[derive(Clone)]
+struct Reporter {
+    status: Arc<Mutex<UpdateStatus>>,
+    eventloop: EspNotify,
+}
+
+impl Reporter {
+    fn report(&mut self, status: UpdateStatus) {
+        *self.status.lock().unwrap() = status;
+        let _ = self.eventloop.post(
+            &TwatchRawEvent::Update.into(),
+            Some(Duration::from_millis(0)),
+        );
+    }
+}
+
+/// Update from the manifest found at an HTTP URL, done in a background thread
+pub struct HttpInstaller {
+    reporter: Reporter,
+}
+
+impl HttpInstaller {
+    pub fn new(eventloop: EspNotify) -> Self {
+        Self {
+            reporter: Reporter {
+                status: Arc::new(Mutex::new(UpdateStatus::Idle)),
+                eventloop,
+            },
+        }
+    }
+}
+
+impl Installer for HttpInstaller {
+    fn start(&mut self, url: &str) -> Result<()> {
+        self.report(UpdateStatus::Checking);
+        let url = url.to_string();
+        let mut reporter = self.reporter.clone();
+        thread::Builder::new()
+            .name("ota".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let status = update(&url, &mut reporter).unwrap_or_else(|e| {
+                    warn!("Update failed: {e:?}");
+                    UpdateStatus::Failed(format!("{e}"))
+                });
+                reporter.report(status);
+            })?;
+        Ok(())
+    }
+
+    fn report(&mut self, status: UpdateStatus) {
+        self.reporter.report(status);
+    }
+
+    fn status(&self) -> UpdateStatus {
+        self.reporter.status.lock().unwrap().clone()
+    }
+}
+
+fn update(url: &str, reporter: &mut Reporter) -> Result<UpdateStatus> {
+    let key = PublicKey::builtin()?;
+    let manifest = http::get(url, ota::MAX_MANIFEST_LEN)?;
+    let manifest = Manifest::parse(std::str::from_utf8(&manifest)?)?;
+    // Only the signed version is trusted
+    let version = manifest.image.version.clone();
+    match manifest.image.authenticate(&key, VERSION) {
+        Err(OtaError::NotNewer(_)) => {
+            info!("Firmware {VERSION} is up to date, latest is {version}");
+            return Ok(UpdateStatus::UpToDate);
+        }
+        result => result?,
+    }
+
+    info!("Updating firmware {VERSION} to {version}");
+    reporter.report(UpdateStatus::Downloading(0));
+    let image = Image::new(manifest.image, OtaSlot::next()?);
+    http::read(&manifest.url, |read| {
+        ota::install(image, read, &key, VERSION, |percent| {
+            reporter.report(UpdateStatus::Downloading(percent))
+        })
+    })?;
+    info!("Firmware {version} installed");
+    Ok(UpdateStatus::Done { version })
+}
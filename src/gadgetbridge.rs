@@ -0,0 +1,562 @@
+//! Phone companion protocol of Gadgetbridge for Bangle.js.
+//!
+//! The phone writes JavaScript statements over a serial link (the Nordic UART
+//! service in BLE), one per line, e.g. `GB({t:"notify",id:1,title:"Hi"})` or
+//! `setTime(1700000000);E.setTimeZone(1.0);`. The watch answers with JSON
+//! lines, e.g. `{"t":"music","n":"next"}`.
+//!
+//! See <https://www.espruino.com/Gadgetbridge> for the message list.
+
+use std::time::Duration;
+
+use crate::json::{self, Value};
+
+/// Longer lines are dropped, notifications bodies are truncated by the phone
+const MAX_LINE_LEN: usize = 4096;
+
+/// Clears the current line, sent by the phone before each statement
+const CTRL_C: u8 = 0x03;
+/// Disables the echo of the statement on a real Bangle.js
+const DLE: u8 = 0x10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub id: i64,
+    /// Application, e.g. "Messages"
+    pub src: String,
+    pub title: String,
+    pub subject: String,
+    pub body: String,
+    pub sender: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallState {
+    Incoming,
+    Outgoing,
+    Accepted,
+    Rejected,
+    Started,
+    Ended,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub state: CallState,
+    pub name: String,
+    pub number: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MusicInfo {
+    pub artist: String,
+    pub album: String,
+    pub track: String,
+    /// Track duration in seconds
+    pub duration: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MusicState {
+    pub state: PlaybackState,
+    /// Position in the track in seconds
+    pub position: Option<u32>,
+}
+
+/// Music playing on the phone, as last reported
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NowPlaying {
+    pub info: MusicInfo,
+    pub state: Option<MusicState>,
+    /// Uptime when the state was received, to extrapolate the position
+    pub received: Duration,
+}
+
+impl NowPlaying {
+    /// Apply a music event, returning whether it was one
+    pub fn update(&mut self, event: &PhoneEvent, now: Duration) -> bool {
+        match event {
+            PhoneEvent::MusicInfo(info) => {
+                if info.track != self.info.track || info.artist != self.info.artist {
+                    // The state of the previous track doesn't apply anymore
+                    self.state = self.state.map(|state| MusicState {
+                        position: None,
+                        ..state
+                    });
+                }
+                self.info = info.clone();
+            }
+            PhoneEvent::MusicState(state) => {
+                self.state = Some(*state);
+                self.received = now;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(
+            self.state,
+            Some(MusicState {
+                state: PlaybackState::Playing,
+                ..
+            })
+        )
+    }
+
+    /// Position in the track in seconds, advanced while playing
+    pub fn position(&self, now: Duration) -> Option<u32> {
+        let state = self.state?;
+        let mut position = state.position?;
+        if state.state == PlaybackState::Playing {
+            position += now.saturating_sub(self.received).as_secs() as u32;
+        }
+        Some(match self.info.duration {
+            Some(duration) => position.min(duration),
+            None => position,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Weather {
+    /// Current temperature in °C
+    pub temperature: f32,
+    pub high: Option<f32>,
+    pub low: Option<f32>,
+    /// Relative humidity in %
+    pub humidity: Option<u8>,
+    /// OpenWeatherMap condition code
+    pub code: Option<u16>,
+    pub text: String,
+    /// Wind speed in km/h
+    pub wind: Option<f32>,
+    /// Wind direction in degrees
+    pub wind_direction: Option<u16>,
+    pub location: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhoneEvent {
+    Notification(Notification),
+    NotificationDismissed(i64),
+    Call(Call),
+    MusicInfo(MusicInfo),
+    MusicState(MusicState),
+    Weather(Weather),
+    /// UTC time in seconds since the Unix epoch, and the phone offset in hours
+    SetTime {
+        utc: i64,
+        offset: Option<f32>,
+    },
+    /// Ring the watch to find it
+    Find(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MusicCommand {
+    Play,
+    Pause,
+    PlayPause,
+    Next,
+    Previous,
+    VolumeUp,
+    VolumeDown,
+}
+
+impl MusicCommand {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MusicCommand::Play => "play",
+            MusicCommand::Pause => "pause",
+            MusicCommand::PlayPause => "playpause",
+            MusicCommand::Next => "next",
+            MusicCommand::Previous => "previous",
+            MusicCommand::VolumeUp => "volumeup",
+            MusicCommand::VolumeDown => "volumedown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationAction {
+    Dismiss,
+}
+
+/// Messages sent from the watch to the phone
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Version { firmware: String, hardware: String },
+    Status { battery: u8, charging: bool },
+    Music(MusicCommand),
+    Notification { id: i64, action: NotificationAction },
+}
+
+impl Command {
+    /// JSON line to write to the phone
+    pub fn encode(&self) -> String {
+        let string = |s: &str| Value::String(s.to_string());
+        let members: Vec<(&str, Value)> = match self {
+            Command::Version { firmware, hardware } => vec![
+                ("t", string("ver")),
+                ("fw", string(firmware)),
+                ("hw", string(hardware)),
+            ],
+            Command::Status { battery, charging } => vec![
+                ("t", string("status")),
+                ("bat", Value::Number(*battery as f64)),
+                ("chg", Value::Number(*charging as u8 as f64)),
+            ],
+            Command::Music(command) => {
+                vec![("t", string("music")), ("n", string(command.as_str()))]
+            }
+            Command::Notification { id, action } => vec![
+                ("t", string("notify")),
+                ("id", Value::Number(*id as f64)),
+                (
+                    "n",
+                    string(match action {
+                        NotificationAction::Dismiss => "DISMISS",
+                    }),
+                ),
+            ],
+        };
+        let object = Value::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        );
+        format!("{object}\r\n")
+    }
+}
+
+/// Split the received bytes in statements and decode them
+#[derive(Debug, Default)]
+pub struct Decoder {
+    line: Vec<u8>,
+    overflow: bool,
+}
+
+impl Decoder {
+    /// Feed bytes received from the phone, returning the decoded events
+    pub fn push(&mut self, data: &[u8]) -> Vec<PhoneEvent> {
+        let mut events = Vec::new();
+        for &b in data {
+            match b {
+                b'\n' | b'\r' => {
+                    if !self.overflow && !self.line.is_empty() {
+                        let line = String::from_utf8_lossy(&self.line);
+                        events.extend(parse_line(&line));
+                    }
+                    self.line.clear();
+                    self.overflow = false;
+                }
+                CTRL_C => {
+                    self.line.clear();
+                    self.overflow = false;
+                }
+                DLE if self.line.is_empty() => {}
+                b => {
+                    if self.line.len() < MAX_LINE_LEN {
+                        self.line.push(b);
+                    } else {
+                        self.overflow = true;
+                    }
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Decode the statements of a line, ignoring the unsupported ones
+pub fn parse_line(line: &str) -> Vec<PhoneEvent> {
+    let mut events = Vec::new();
+    let mut rest = line;
+    let mut utc = None;
+    let mut offset = None;
+    while !rest.is_empty() {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';' || c == '\u{10}');
+        if let Some(call) = rest.strip_prefix("GB(") {
+            match json::parse_prefix(call) {
+                Ok((value, len)) => {
+                    if let Some(event) = parse_message(&value) {
+                        events.push(event);
+                    }
+                    rest = &call[len..];
+                }
+                Err(e) => {
+                    log::warn!("Invalid Gadgetbridge message: {e}");
+                    break;
+                }
+            }
+        } else if let Some(args) = rest.strip_prefix("setTime(") {
+            let (value, len) = number_argument(args);
+            utc = value.map(|v| v as i64);
+            rest = &args[len..];
+        } else if let Some(args) = rest.strip_prefix("E.setTimeZone(") {
+            let (value, len) = number_argument(args);
+            offset = value.map(|v| v as f32);
+            rest = &args[len..];
+        } else {
+            // Skip the statement, e.g. settings written to the Bangle.js storage
+            rest = match rest.find(';') {
+                Some(end) => &rest[end + 1..],
+                None => "",
+            };
+        }
+    }
+    if let Some(utc) = utc {
+        events.push(PhoneEvent::SetTime { utc, offset });
+    }
+    events
+}
+
+/// Leading number of a function call arguments, and the length up to `)`
+fn number_argument(args: &str) -> (Option<f64>, usize) {
+    let end = args.find(')').unwrap_or(args.len());
+    let value = args[..end].trim().parse().ok();
+    (value, (end + 1).min(args.len()))
+}
+
+fn parse_message(message: &Value) -> Option<PhoneEvent> {
+    let opt_u32 = |key| {
+        message
+            .get(key)
+            .and_then(Value::as_f64)
+            .filter(|v| *v >= 0.0)
+            .map(|v| v as u32)
+    };
+    let opt_f32 = |key| message.get(key).and_then(Value::as_f64).map(|v| v as f32);
+    // Temperatures are sent in Kelvin
+    let celsius = |kelvin: f32| kelvin - 273.15;
+
+    let event = match message.get("t")?.as_str()? {
+        "notify" => PhoneEvent::Notification(Notification {
+            id: message.get("id").and_then(Value::as_i64)?,
+            src: message.str_or_empty("src"),
+            title: message.str_or_empty("title"),
+            subject: message.str_or_empty("subject"),
+            body: message.str_or_empty("body"),
+            sender: message.str_or_empty("sender"),
+        }),
+        "notify-" => PhoneEvent::NotificationDismissed(message.get("id")?.as_i64()?),
+        "call" => PhoneEvent::Call(Call {
+            state: match message.get("cmd")?.as_str()? {
+                "incoming" => CallState::Incoming,
+                "outgoing" => CallState::Outgoing,
+                "accept" => CallState::Accepted,
+                "reject" => CallState::Rejected,
+                "start" => CallState::Started,
+                "end" => CallState::Ended,
+                _ => return None,
+            },
+            name: message.str_or_empty("name"),
+            number: message.str_or_empty("number"),
+        }),
+        "musicinfo" => PhoneEvent::MusicInfo(MusicInfo {
+            artist: message.str_or_empty("artist"),
+            album: message.str_or_empty("album"),
+            track: message.str_or_empty("track"),
+            duration: opt_u32("dur"),
+        }),
+        "musicstate" => PhoneEvent::MusicState(MusicState {
+            state: match message.get("state")?.as_str()? {
+                "play" => PlaybackState::Playing,
+                "pause" => PlaybackState::Paused,
+                _ => PlaybackState::Stopped,
+            },
+            position: opt_u32("position"),
+        }),
+        "weather" => PhoneEvent::Weather(Weather {
+            temperature: celsius(opt_f32("temp")?),
+            high: opt_f32("hi").map(celsius),
+            low: opt_f32("lo").map(celsius),
+            humidity: opt_u32("hum").map(|h| h.min(100) as u8),
+            code: opt_u32("code").map(|c| c as u16),
+            text: message.str_or_empty("txt"),
+            wind: opt_f32("wind"),
+            wind_direction: opt_u32("wdir").map(|d| (d % 360) as u16),
+            location: message.str_or_empty("loc"),
+        }),
+        "find" => PhoneEvent::Find(message.get("n").and_then(Value::as_bool).unwrap_or(false)),
+        t => {
+            log::debug!("Ignoring Gadgetbridge message {t}");
+            return None;
+        }
+    };
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes written by Gadgetbridge, in the 20 bytes chunks of the default MTU
+    fn decode(log: &[u8]) -> Vec<PhoneEvent> {
+        let mut decoder = Decoder::default();
+        log.chunks(20)
+            .flat_map(|chunk| decoder.push(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_notifications() {
+        let log = b"\x03\x10GB({t:\"notify\",id:1700000123,src:\"Messages\",title:\"Alice\",\
+                    body:\"Caf\\u00e9 at 8?\",sender:\"Alice\",tel:\"+33600000000\"})\n\
+                    \x10GB({t:\"notify-\",id:1700000123})\n";
+        assert_eq!(
+            decode(log),
+            [
+                PhoneEvent::Notification(Notification {
+                    id: 1700000123,
+                    src: "Messages".to_string(),
+                    title: "Alice".to_string(),
+                    subject: String::new(),
+                    body: "Café at 8?".to_string(),
+                    sender: "Alice".to_string(),
+                }),
+                PhoneEvent::NotificationDismissed(1700000123),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_the_time() {
+        let log = b"\x10setTime(1700000000);E.setTimeZone(1.0);(s=>s&&(s.timezone=1.0,\
+                    require('Storage').write('setting.json',s)))\
+                    (require('Storage').readJSON('setting.json',1))\n";
+        assert_eq!(
+            decode(log),
+            [PhoneEvent::SetTime {
+                utc: 1700000000,
+                offset: Some(1.0),
+            }]
+        );
+    }
+
+    #[test]
+    fn decodes_calls() {
+        let log = b"\x10GB({t:\"call\",cmd:\"incoming\",name:\"Bob\",number:\"+33612345678\"})\n\
+                    \x10GB({t:\"call\",cmd:\"end\",name:\"\",number:\"\"})\n";
+        let states: Vec<_> = decode(log)
+            .into_iter()
+            .map(|event| match event {
+                PhoneEvent::Call(call) => (call.state, call.name),
+                event => panic!("unexpected {event:?}"),
+            })
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (CallState::Incoming, "Bob".to_string()),
+                (CallState::Ended, String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_music() {
+        let log = b"\x10GB({t:\"musicinfo\",artist:\"Daft Punk\",album:\"Discovery\",\
+                    track:\"One More Time\",dur:320,c:-1,n:-1})\n\
+                    \x10GB({t:\"musicstate\",state:\"play\",position:42,shuffle:1,repeat:1})\n";
+        let events = decode(log);
+        assert_eq!(
+            events,
+            [
+                PhoneEvent::MusicInfo(MusicInfo {
+                    artist: "Daft Punk".to_string(),
+                    album: "Discovery".to_string(),
+                    track: "One More Time".to_string(),
+                    duration: Some(320),
+                }),
+                PhoneEvent::MusicState(MusicState {
+                    state: PlaybackState::Playing,
+                    position: Some(42),
+                }),
+            ]
+        );
+
+        let mut now_playing = NowPlaying::default();
+        for event in &events {
+            assert!(now_playing.update(event, Duration::from_secs(100)));
+        }
+        assert!(now_playing.is_playing());
+        assert_eq!(now_playing.position(Duration::from_secs(110)), Some(52));
+        // Not past the end of the track
+        assert_eq!(now_playing.position(Duration::from_secs(1000)), Some(320));
+    }
+
+    #[test]
+    fn decodes_the_weather() {
+        let log = b"\x10GB({t:\"weather\",temp:291.15,hum:72,code:803,txt:\"broken clouds\",\
+                    wind:11.2,wdir:610,loc:\"Lyon\",hi:294.15,lo:285.15})\n";
+        let events = decode(log);
+        let weather = match events.as_slice() {
+            [PhoneEvent::Weather(weather)] => weather,
+            events => panic!("unexpected {events:?}"),
+        };
+        assert!((weather.temperature - 18.0).abs() < 0.01);
+        assert!((weather.high.unwrap() - 21.0).abs() < 0.01);
+        assert!((weather.low.unwrap() - 12.0).abs() < 0.01);
+        assert_eq!(weather.humidity, Some(72));
+        assert_eq!(weather.code, Some(803));
+        assert_eq!(weather.text, "broken clouds");
+        assert_eq!(weather.wind_direction, Some(250));
+        assert_eq!(weather.location, "Lyon");
+    }
+
+    #[test]
+    fn skips_unsupported_statements() {
+        let log = b"\x10GB({t:\"is_gps_active\"})\n\
+                    \x10Bangle.buzz();GB({t:\"find\",n:true})\n\
+                    \x10GB({t:\"notify\",title:\"no id\"})\n";
+        assert_eq!(decode(log), [PhoneEvent::Find(true)]);
+    }
+
+    #[test]
+    fn drops_broken_lines() {
+        // Cleared by Ctrl-C before being completed
+        let mut log = b"\x10GB({t:\"find\",n:tr\x03\x10GB({t:\"find\",n:false})\n".to_vec();
+        // Too long, dropped as a whole
+        log.extend_from_slice(b"GB({t:\"notify\",id:1,body:\"");
+        log.resize(log.len() + MAX_LINE_LEN, b'a');
+        log.extend_from_slice(b"\"})\n");
+        // Invalid message
+        log.extend_from_slice(b"GB({t:\"notify\",id:)\n");
+        assert_eq!(decode(&log), [PhoneEvent::Find(false)]);
+    }
+
+    #[test]
+    fn encodes_commands() {
+        assert_eq!(
+            Command::Music(MusicCommand::Next).encode(),
+            "{\"t\":\"music\",\"n\":\"next\"}\r\n"
+        );
+        assert_eq!(
+            Command::Notification {
+                id: 1700000123,
+                action: NotificationAction::Dismiss
+            }
+            .encode(),
+            "{\"t\":\"notify\",\"id\":1700000123,\"n\":\"DISMISS\"}\r\n"
+        );
+        assert_eq!(
+            Command::Status {
+                battery: 87,
+                charging: true
+            }
+            .encode(),
+            "{\"t\":\"status\",\"bat\":87,\"chg\":1}\r\n"
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! Vibration patterns played by the motor, see `vibrator` for the PWM.
+//!
+//! Patterns are sequences of steps holding the motor at a level for a while.
+//! They are queued and played by a task of their own, so the event loop never
+//! waits for the motor. A repeating pattern, as the alarm, plays until it is
+//! cancelled, the patterns played meanwhile being dropped.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use log::*;
+
+const STACK_SIZE: usize = 3072;
+
+/// Patterns waiting for the one playing, the next ones are dropped
+pub const MAX_QUEUED: usize = 4;
+
+/// Motor driven by the patterns
+pub trait Motor {
+    /// Spin at `level` percent of the full speed, stopping at 0
+    fn set_level(&mut self, level: u8) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// Percent of the intensity, 0 for a pause
+    pub level: u8,
+    pub duration: Duration,
+}
+
+const fn on(millis: u64) -> Step {
+    Step {
+        level: 100,
+        duration: Duration::from_millis(millis),
+    }
+}
+
+const fn weak(millis: u64) -> Step {
+    Step {
+        level: 60,
+        duration: Duration::from_millis(millis),
+    }
+}
+
+const fn off(millis: u64) -> Step {
+    Step {
+        level: 0,
+        duration: Duration::from_millis(millis),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Short confirmation of a touch
+    Tick,
+    DoubleBuzz,
+    /// Repeated until cancelled
+    Alarm,
+    Notification,
+    /// A strong then a weak beat
+    Heartbeat,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 5] = [
+        Pattern::Tick,
+        Pattern::DoubleBuzz,
+        Pattern::Alarm,
+        Pattern::Notification,
+        Pattern::Heartbeat,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pattern::Tick => "tick",
+            Pattern::DoubleBuzz => "double",
+            Pattern::Alarm => "alarm",
+            Pattern::Notification => "notification",
+            Pattern::Heartbeat => "heartbeat",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pattern> {
+        Self::ALL.into_iter().find(|pattern| pattern.name() == name)
+    }
+
+    /// Steps of the pattern, the last pause keeping it apart from the next one
+    pub fn steps(&self) -> &'static [Step] {
+        const TICK: &[Step] = &[on(20), off(30)];
+        const DOUBLE_BUZZ: &[Step] = &[on(80), off(80), on(80), off(100)];
+        const ALARM: &[Step] = &[on(400), off(200), on(400), off(1000)];
+        const NOTIFICATION: &[Step] = &[on(150), off(100), on(60), off(200)];
+        const HEARTBEAT: &[Step] = &[on(60), off(120), weak(60), off(760)];
+        match self {
+            Pattern::Tick => TICK,
+            Pattern::DoubleBuzz => DOUBLE_BUZZ,
+            Pattern::Alarm => ALARM,
+            Pattern::Notification => NOTIFICATION,
+            Pattern::Heartbeat => HEARTBEAT,
+        }
+    }
+
+    pub fn repeats(&self) -> bool {
+        matches!(self, Pattern::Alarm)
+    }
+}
+
+/// Queue of the patterns and position in the one playing
+#[derive(Debug)]
+pub struct Sequencer {
+    queue: VecDeque<Pattern>,
+    playing: Option<Pattern>,
+    /// Next step of the pattern playing
+    step: usize,
+    /// Percent of the full speed of the steps at level 100
+    pub intensity: u8,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            playing: None,
+            step: 0,
+            intensity: 100,
+        }
+    }
+}
+
+impl Sequencer {
+    pub fn playing(&self) -> Option<Pattern> {
+        self.playing
+    }
+
+    /// Queue a pattern, returning false when it is dropped, the queue being
+    /// full or a repeating pattern waiting to be cancelled
+    pub fn play(&mut self, pattern: Pattern) -> bool {
+        let repeating = self.playing.iter().chain(&self.queue).any(Pattern::repeats);
+        if repeating || self.queue.len() >= MAX_QUEUED {
+            return false;
+        }
+        self.queue.push_back(pattern);
+        true
+    }
+
+    /// Stop the pattern playing and forget the queued ones
+    pub fn cancel(&mut self, motor: &mut dyn Motor) {
+        self.queue.clear();
+        self.playing = None;
+        self.step = 0;
+        motor
+            .set_level(0)
+            .unwrap_or_else(|e| warn!("Unable to stop motor: {e:?}"));
+    }
+
+    /// Drive the motor for the next step, returning how long it lasts, or
+    /// `None` once there is nothing left to play and the motor is stopped
+    pub fn advance(&mut self, motor: &mut dyn Motor) -> Option<Duration> {
+        let done = match self.playing {
+            Some(pattern) => self.step >= pattern.steps().len(),
+            None => true,
+        };
+        if done {
+            match self.playing {
+                Some(pattern) if pattern.repeats() => {}
+                _ => self.playing = self.queue.pop_front(),
+            }
+            self.step = 0;
+        }
+        let step = match self.playing {
+            Some(pattern) => pattern.steps()[self.step],
+            None => {
+                motor
+                    .set_level(0)
+                    .unwrap_or_else(|e| warn!("Unable to stop motor: {e:?}"));
+                return None;
+            }
+        };
+        self.step += 1;
+        let level = (step.level as u32 * self.intensity.min(100) as u32 / 100) as u8;
+        motor
+            .set_level(level)
+            .unwrap_or_else(|e| warn!("Unable to drive motor: {e:?}"));
+        Some(step.duration)
+    }
+}
+
+struct State {
+    sequencer: Sequencer,
+    motor: Box<dyn Motor + Send>,
+    /// End of the current step, `None` while idle
+    deadline: Option<Instant>,
+}
+
+/// Plays the patterns on a task of its own, cloned to be used from anywhere
+#[derive(Clone)]
+pub struct Haptics {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Haptics {
+    pub fn start(motor: impl Motor + Send + 'static) -> Result<Self> {
+        let state = Arc::new((
+            Mutex::new(State {
+                sequencer: Sequencer::default(),
+                motor: Box::new(motor),
+                deadline: None,
+            }),
+            Condvar::new(),
+        ));
+        let task_state = state.clone();
+        thread::Builder::new()
+            .name("haptics".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || Self::run(&task_state))?;
+        Ok(Self { state })
+    }
+
+    fn run(state: &(Mutex<State>, Condvar)) {
+        let (lock, condvar) = state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let deadline = state.deadline;
+            state = match deadline {
+                None => condvar.wait(state).unwrap(),
+                Some(deadline) if deadline > now => {
+                    condvar.wait_timeout(state, deadline - now).unwrap().0
+                }
+                Some(_) => {
+                    let State {
+                        sequencer, motor, ..
+                    } = &mut *state;
+                    let step = sequencer.advance(motor.as_mut());
+                    state.deadline = step.map(|step| now + step);
+                    state
+                }
+            };
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.0.lock().unwrap()
+    }
+
+    /// Queue a pattern, played once the ones before it are done
+    pub fn play(&self, pattern: Pattern) {
+        let mut state = self.lock();
+        if !state.sequencer.play(pattern) {
+            debug!("Haptics busy, {} dropped", pattern.name());
+            return;
+        }
+        if state.deadline.is_none() {
+            state.deadline = Some(Instant::now());
+            self.state.1.notify_one();
+        }
+    }
+
+    /// Stop the motor at once, forgetting the queued patterns
+    pub fn cancel(&self) {
+        let mut state = self.lock();
+        let State {
+            sequencer, motor, ..
+        } = &mut *state;
+        sequencer.cancel(motor.as_mut());
+        state.deadline = None;
+        self.state.1.notify_one();
+    }
+
+    pub fn playing(&self) -> Option<Pattern> {
+        self.lock().sequencer.playing()
+    }
+
+    /// Nothing is playing nor queued
+    pub fn is_idle(&self) -> bool {
+        self.lock().deadline.is_none()
+    }
+
+    /// Percent of the full speed the patterns are played at
+    pub fn set_intensity(&self, intensity: u8) {
+        self.lock().sequencer.intensity = intensity.min(100);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Motor keeping the levels it was driven at
+    #[derive(Default)]
+    struct MockMotor {
+        levels: Vec<u8>,
+    }
+
+    impl Motor for MockMotor {
+        fn set_level(&mut self, level: u8) -> Result<()> {
+            self.levels.push(level);
+            Ok(())
+        }
+    }
+
+    /// Advance until nothing is left or `max` steps, returning the steps played
+    fn run(sequencer: &mut Sequencer, motor: &mut MockMotor, max: usize) -> Vec<Step> {
+        let mut steps = Vec::new();
+        while steps.len() < max {
+            let start = motor.levels.len();
+            match sequencer.advance(motor) {
+                Some(duration) => steps.push(Step {
+                    level: motor.levels[start],
+                    duration,
+                }),
+                None => break,
+            }
+        }
+        steps
+    }
+
+    #[test]
+    fn plays_the_steps() {
+        let mut sequencer = Sequencer::default();
+        let mut motor = MockMotor::default();
+        assert_eq!(sequencer.advance(&mut motor), None);
+        assert!(sequencer.play(Pattern::DoubleBuzz));
+        assert_eq!(
+            run(&mut sequencer, &mut motor, 10),
+            Pattern::DoubleBuzz.steps()
+        );
+        // Stopped once done
+        assert_eq!(motor.levels.last(), Some(&0));
+        assert_eq!(sequencer.playing(), None);
+    }
+
+    #[test]
+    fn scales_the_intensity() {
+        let mut sequencer = Sequencer {
+            intensity: 50,
+            ..Sequencer::default()
+        };
+        let mut motor = MockMotor::default();
+        sequencer.play(Pattern::Heartbeat);
+        run(&mut sequencer, &mut motor, 10);
+        assert_eq!(motor.levels, [50, 0, 30, 0, 0]);
+    }
+
+    #[test]
+    fn plays_the_queue_in_order() {
+        let mut sequencer = Sequencer::default();
+        let mut motor = MockMotor::default();
+        for _ in 0..MAX_QUEUED - 1 {
+            assert!(sequencer.play(Pattern::Tick));
+        }
+        assert!(sequencer.play(Pattern::Notification));
+        assert!(!sequencer.play(Pattern::Tick));
+
+        let steps = run(&mut sequencer, &mut motor, 100);
+        let ticks = Pattern::Tick.steps().len() * (MAX_QUEUED - 1);
+        assert_eq!(steps.len(), ticks + Pattern::Notification.steps().len());
+        assert_eq!(&steps[ticks..], Pattern::Notification.steps());
+    }
+
+    #[test]
+    fn repeats_the_alarm_until_cancelled() {
+        let mut sequencer = Sequencer::default();
+        let mut motor = MockMotor::default();
+        // Patterns queued before the alarm still play first
+        sequencer.play(Pattern::Tick);
+        assert!(sequencer.play(Pattern::Alarm));
+        assert!(!sequencer.play(Pattern::Notification));
+
+        let alarm = Pattern::Alarm.steps();
+        let steps = run(
+            &mut sequencer,
+            &mut motor,
+            Pattern::Tick.steps().len() + alarm.len(),
+        );
+        assert_eq!(sequencer.playing(), Some(Pattern::Alarm));
+        assert_eq!(&steps[Pattern::Tick.steps().len()..], alarm);
+
+        // Other patterns don't stop it
+        assert!(!sequencer.play(Pattern::Tick));
+        assert!(!sequencer.play(Pattern::Alarm));
+        assert_eq!(
+            run(&mut sequencer, &mut motor, alarm.len() * 3),
+            alarm.repeat(3)
+        );
+        assert_eq!(sequencer.playing(), Some(Pattern::Alarm));
+
+        sequencer.cancel(&mut motor);
+        assert_eq!(motor.levels.last(), Some(&0));
+        assert_eq!(sequencer.playing(), None);
+        assert_eq!(sequencer.advance(&mut motor), None);
+        assert!(sequencer.play(Pattern::Tick));
+        assert_eq!(run(&mut sequencer, &mut motor, 10), Pattern::Tick.steps());
+    }
+}
@@ -0,0 +1,487 @@
+//! Health of the devices on the I2C buses.
+//!
+//! Every transfer goes through `Monitored`, which counts the failures of the
+//! device addressed. A device stopped in the middle of a byte holds SDA low and
+//! blocks its whole bus, so after a few consecutive failures the bus is cleared
+//! and the device initialized again. A device still failing after a few
+//! recoveries is marked degraded, shown in the status bar, and only recovered
+//! from time to time.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_hal_0_2::blocking::i2c::{Read, Write, WriteRead};
+
+use crate::errors::{BusError, Device, Fault};
+use crate::metrics::{self, Metric};
+
+/// Consecutive failures of a device before recovering it
+pub const FAILURE_THRESHOLD: u32 = 3;
+/// Recoveries without a successful transfer before a device is degraded
+pub const MAX_RECOVERIES: u32 = 3;
+/// Time between two recoveries of a degraded device
+pub const DEGRADED_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    I2c0,
+    I2c1,
+}
+
+impl Bus {
+    /// Bus of a device, the display being on SPI
+    pub fn of(device: Device) -> Option<Bus> {
+        match device {
+            Device::Pmu | Device::Clock | Device::Accel => Some(Bus::I2c0),
+            Device::Touch => Some(Bus::I2c1),
+            Device::Display => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceHealth {
+    pub transfers: u32,
+    pub failures: u32,
+    /// Failures since the last successful transfer or recovery
+    pub consecutive: u32,
+    pub last_fault: Option<Fault>,
+    /// Recoveries since the last successful transfer
+    pub recoveries: u32,
+    pub last_recovery: Option<Duration>,
+    pub degraded: bool,
+}
+
+impl DeviceHealth {
+    fn needs_recovery(&self, now: Duration) -> bool {
+        if self.consecutive < FAILURE_THRESHOLD {
+            return false;
+        }
+        match self.last_recovery {
+            Some(at) if self.degraded => now.saturating_sub(at) >= DEGRADED_RETRY,
+            _ => true,
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}/{} failed", self.failures, self.transfers))?;
+        if let Some(fault) = self.last_fault {
+            f.write_fmt(format_args!(", last {fault:?}"))?;
+        }
+        if self.recoveries > 0 {
+            f.write_fmt(format_args!(", {} recoveries", self.recoveries))?;
+        }
+        if self.degraded {
+            f.write_str(", degraded")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BusHealth {
+    devices: [DeviceHealth; Device::COUNT],
+    /// Device being recovered, whose transfers don't count as a success
+    recovering: Option<Device>,
+}
+
+impl BusHealth {
+    pub fn device(&self, device: Device) -> &DeviceHealth {
+        &self.devices[device as usize]
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.devices.iter().any(|health| health.degraded)
+    }
+
+    /// Count a transfer with a device
+    pub fn record(&mut self, device: Device, result: Result<(), Fault>) {
+        let recovering = self.recovering == Some(device);
+        let health = &mut self.devices[device as usize];
+        health.transfers = health.transfers.wrapping_add(1);
+        match result {
+            Ok(()) if recovering => {}
+            Ok(()) => {
+                health.consecutive = 0;
+                health.recoveries = 0;
+                health.degraded = false;
+            }
+            Err(fault) => {
+                health.failures = health.failures.wrapping_add(1);
+                health.last_fault = Some(fault);
+                if !recovering {
+                    health.consecutive = health.consecutive.saturating_add(1);
+                }
+            }
+        }
+    }
+
+    /// Count a recovery of a device, which failed when its bus couldn't be
+    /// cleared or the device initialized
+    fn recovered(&mut self, device: Device, now: Duration, succeeded: bool) {
+        let health = &mut self.devices[device as usize];
+        health.recoveries = health.recoveries.saturating_add(1);
+        health.last_recovery = Some(now);
+        if succeeded {
+            health.consecutive = 0;
+        }
+        if health.recoveries >= MAX_RECOVERIES {
+            health.degraded = true;
+        }
+    }
+}
+
+/// Recovery of the buses and the devices, by the hardware
+pub trait Recovery {
+    /// Release SDA held low by a device, clocking SCL until it is released
+    fn clear_bus(&mut self, bus: Bus) -> Result<()>;
+
+    /// Initialize a device again, its state being unknown after a recovery
+    fn reinit(&mut self, device: Device) -> Result<()>;
+}
+
+/// Counts the failures of the devices, shared by their `Monitored` buses
+#[derive(Debug, Clone, Default)]
+pub struct HealthMonitor {
+    health: Arc<Mutex<BusHealth>>,
+}
+
+impl HealthMonitor {
+    /// Count the transfers made through `i2c`
+    pub fn monitor<I>(&self, i2c: I) -> Monitored<I> {
+        Monitored {
+            i2c,
+            health: self.health.clone(),
+        }
+    }
+
+    pub fn health(&self) -> BusHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.health.lock().unwrap().is_degraded()
+    }
+
+    /// Recover the devices failing repeatedly, clearing each bus once,
+    /// returning the devices recovered and the outcome
+    ///
+    /// The transfers of the recovery must be made from the calling thread.
+    pub fn check(&self, now: Duration, recovery: &mut impl Recovery) -> Vec<(Device, Result<()>)> {
+        let mut cleared: Vec<Bus> = Vec::new();
+        let mut recovered = Vec::new();
+        for device in Device::ALL {
+            let bus = match Bus::of(device) {
+                Some(bus) => bus,
+                None => continue,
+            };
+            {
+                let mut health = self.health.lock().unwrap();
+                if !health.device(device).needs_recovery(now) {
+                    continue;
+                }
+                health.recovering = Some(device);
+            }
+            let result = if cleared.contains(&bus) {
+                Ok(())
+            } else {
+                cleared.push(bus);
+                recovery.clear_bus(bus)
+            }
+            .and_then(|_| recovery.reinit(device));
+
+            let mut health = self.health.lock().unwrap();
+            health.recovering = None;
+            health.recovered(device, now, result.is_ok());
+            recovered.push((device, result));
+        }
+        recovered
+    }
+}
+
+/// I2C bus counting the transfers with each device in a `HealthMonitor`, and
+/// their time in `Metric::I2c`
+pub struct Monitored<I> {
+    i2c: I,
+    health: Arc<Mutex<BusHealth>>,
+}
+
+impl<I> Monitored<I> {
+    fn record<E: BusError>(&self, address: u8, result: &Result<(), E>) {
+        if let Some(device) = Device::from_address(address) {
+            let result = result.as_ref().map(|_| ()).map_err(BusError::fault);
+            self.health.lock().unwrap().record(device, result);
+        }
+    }
+}
+
+impl<I: Write> Write for Monitored<I>
+where
+    I::Error: BusError,
+{
+    type Error = I::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        let result = metrics::time(Metric::I2c, || self.i2c.write(address, bytes));
+        self.record(address, &result);
+        result
+    }
+}
+
+impl<I: Read> Read for Monitored<I>
+where
+    I::Error: BusError,
+{
+    type Error = I::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let result = metrics::time(Metric::I2c, || self.i2c.read(address, buffer));
+        self.record(address, &result);
+        result
+    }
+}
+
+impl<I: WriteRead> WriteRead for Monitored<I>
+where
+    I::Error: BusError,
+{
+    type Error = I::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let result = metrics::time(Metric::I2c, || self.i2c.write_read(address, bytes, buffer));
+        self.record(address, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct MockError(Fault);
+
+    impl BusError for MockError {
+        fn fault(&self) -> Fault {
+            self.0
+        }
+    }
+
+    /// Bus failing with the queued faults, then succeeding
+    #[derive(Default)]
+    struct MockI2c {
+        faults: VecDeque<Fault>,
+    }
+
+    impl MockI2c {
+        fn transfer(&mut self) -> Result<(), MockError> {
+            self.faults
+                .pop_front()
+                .map_or(Ok(()), |fault| Err(MockError(fault)))
+        }
+    }
+
+    impl Write for MockI2c {
+        type Error = MockError;
+
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), MockError> {
+            self.transfer()
+        }
+    }
+
+    impl Read for MockI2c {
+        type Error = MockError;
+
+        fn read(&mut self, _address: u8, _buffer: &mut [u8]) -> Result<(), MockError> {
+            self.transfer()
+        }
+    }
+
+    impl WriteRead for MockI2c {
+        type Error = MockError;
+
+        fn write_read(&mut self, _: u8, _: &[u8], _: &mut [u8]) -> Result<(), MockError> {
+            self.transfer()
+        }
+    }
+
+    /// Recovery reinitializing the devices through a monitored bus
+    struct MockRecovery {
+        i2c: Monitored<MockI2c>,
+        clear_fails: bool,
+        cleared: Vec<Bus>,
+        reinitialized: Vec<Device>,
+    }
+
+    impl MockRecovery {
+        fn new(monitor: &HealthMonitor) -> Self {
+            Self {
+                i2c: monitor.monitor(MockI2c::default()),
+                clear_fails: false,
+                cleared: Vec::new(),
+                reinitialized: Vec::new(),
+            }
+        }
+    }
+
+    impl Recovery for MockRecovery {
+        fn clear_bus(&mut self, bus: Bus) -> Result<()> {
+            self.cleared.push(bus);
+            if self.clear_fails {
+                anyhow::bail!("SDA still held low");
+            }
+            Ok(())
+        }
+
+        fn reinit(&mut self, device: Device) -> Result<()> {
+            self.reinitialized.push(device);
+            let address = device.address().unwrap();
+            self.i2c
+                .write(address, &[0])
+                .map_err(|e| anyhow::anyhow!("{e:?}"))
+        }
+    }
+
+    fn fail(i2c: &mut Monitored<MockI2c>, device: Device, fault: Fault) {
+        i2c.i2c.faults.push_back(fault);
+        let mut buffer = [0];
+        assert!(i2c
+            .write_read(device.address().unwrap(), &[0], &mut buffer)
+            .is_err());
+    }
+
+    fn succeed(i2c: &mut Monitored<MockI2c>, device: Device) {
+        assert!(i2c.write(device.address().unwrap(), &[0]).is_ok());
+    }
+
+    #[test]
+    fn counts_transfers_per_device() {
+        let monitor = HealthMonitor::default();
+        let mut i2c = monitor.monitor(MockI2c::default());
+        succeed(&mut i2c, Device::Clock);
+        fail(&mut i2c, Device::Pmu, Fault::Nack);
+        fail(&mut i2c, Device::Pmu, Fault::Timeout);
+        // Unknown devices are not counted
+        assert!(i2c.read(0x42, &mut [0]).is_ok());
+
+        let health = monitor.health();
+        assert_eq!(health.device(Device::Clock).transfers, 1);
+        assert_eq!(health.device(Device::Clock).failures, 0);
+        let pmu = health.device(Device::Pmu);
+        assert_eq!((pmu.transfers, pmu.failures, pmu.consecutive), (2, 2, 2));
+        assert_eq!(pmu.last_fault, Some(Fault::Timeout));
+        assert_eq!(pmu.to_string(), "2/2 failed, last Timeout");
+        assert!(!monitor.is_degraded());
+
+        succeed(&mut i2c, Device::Pmu);
+        assert_eq!(monitor.health().device(Device::Pmu).consecutive, 0);
+    }
+
+    #[test]
+    fn recovers_after_consecutive_failures() {
+        let monitor = HealthMonitor::default();
+        let mut i2c = monitor.monitor(MockI2c::default());
+        let mut recovery = MockRecovery::new(&monitor);
+        for _ in 1..FAILURE_THRESHOLD {
+            fail(&mut i2c, Device::Touch, Fault::Bus);
+        }
+        assert!(monitor.check(Duration::ZERO, &mut recovery).is_empty());
+
+        fail(&mut i2c, Device::Touch, Fault::Bus);
+        let recovered = monitor.check(Duration::from_secs(1), &mut recovery);
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].0, Device::Touch);
+        assert!(recovered[0].1.is_ok());
+        assert_eq!(recovery.cleared, [Bus::I2c1]);
+        assert_eq!(recovery.reinitialized, [Device::Touch]);
+
+        let touch = *monitor.health().device(Device::Touch);
+        assert_eq!(touch.consecutive, 0);
+        assert_eq!(touch.recoveries, 1);
+        assert_eq!(touch.last_recovery, Some(Duration::from_secs(1)));
+        // Only the next transfer out of the recovery shows the device works
+        succeed(&mut i2c, Device::Touch);
+        assert_eq!(monitor.health().device(Device::Touch).recoveries, 0);
+    }
+
+    #[test]
+    fn clears_each_bus_once() {
+        let monitor = HealthMonitor::default();
+        let mut i2c = monitor.monitor(MockI2c::default());
+        let mut recovery = MockRecovery::new(&monitor);
+        for _ in 0..FAILURE_THRESHOLD {
+            fail(&mut i2c, Device::Pmu, Fault::Bus);
+            fail(&mut i2c, Device::Clock, Fault::Bus);
+        }
+        let recovered = monitor.check(Duration::ZERO, &mut recovery);
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(recovery.cleared, [Bus::I2c0]);
+        assert_eq!(recovery.reinitialized, [Device::Pmu, Device::Clock]);
+    }
+
+    #[test]
+    fn ignores_failures_while_recovering() {
+        let monitor = HealthMonitor::default();
+        let mut i2c = monitor.monitor(MockI2c::default());
+        let mut recovery = MockRecovery::new(&monitor);
+        for _ in 0..FAILURE_THRESHOLD {
+            fail(&mut i2c, Device::Accel, Fault::Nack);
+        }
+        // The device doesn't answer its initialization
+        recovery.i2c.i2c.faults.push_back(Fault::Nack);
+        let recovered = monitor.check(Duration::ZERO, &mut recovery);
+        assert!(recovered[0].1.is_err());
+
+        let accel = *monitor.health().device(Device::Accel);
+        assert_eq!(accel.failures, FAILURE_THRESHOLD + 1);
+        assert_eq!(accel.consecutive, FAILURE_THRESHOLD);
+        assert_eq!(accel.recoveries, 1);
+    }
+
+    #[test]
+    fn degrades_after_failed_recoveries() {
+        let monitor = HealthMonitor::default();
+        let mut i2c = monitor.monitor(MockI2c::default());
+        let mut recovery = MockRecovery::new(&monitor);
+        recovery.clear_fails = true;
+        for _ in 0..FAILURE_THRESHOLD {
+            fail(&mut i2c, Device::Clock, Fault::Bus);
+        }
+        let mut now = Duration::ZERO;
+        for _ in 0..MAX_RECOVERIES {
+            let recovered = monitor.check(now, &mut recovery);
+            assert!(recovered[0].1.is_err());
+            now += Duration::from_secs(1);
+        }
+        // The devices of a bus still held are not initialized
+        assert!(recovery.reinitialized.is_empty());
+        assert!(monitor.is_degraded());
+        assert!(monitor.health().device(Device::Clock).degraded);
+
+        // Then only retried from time to time
+        let last = now - Duration::from_secs(1);
+        assert!(monitor
+            .check(last + DEGRADED_RETRY / 2, &mut recovery)
+            .is_empty());
+        recovery.clear_fails = false;
+        let recovered = monitor.check(last + DEGRADED_RETRY, &mut recovery);
+        assert!(recovered[0].1.is_ok());
+        assert!(monitor.is_degraded());
+
+        succeed(&mut i2c, Device::Clock);
+        assert!(!monitor.is_degraded());
+        let clock = *monitor.health().device(Device::Clock);
+        assert_eq!((clock.recoveries, clock.consecutive), (0, 0));
+    }
+}
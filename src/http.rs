@@ -0,0 +1,99 @@
+//! HTTP downloads over the Wi-Fi station.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+
+use embedded_svc::http::client::{Client, Request, Response};
+use embedded_svc::http::Status;
+use embedded_svc::io::Read;
+use esp_idf_svc::http::client::{EspHttpClient, EspHttpClientConfiguration};
+
+use log::*;
+
+use crate::weather::Fetcher;
+
+/// TLS needs a larger stack than the default one of the threads
+const STACK_SIZE: usize = 12 * 1024;
+
+/// Download `url`, failing if the body is larger than `max_len`
+pub fn get(url: &str, max_len: usize) -> Result<Vec<u8>> {
+    read(url, |read| {
+        let mut body = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let len = read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            if body.len() + len > max_len {
+                anyhow::bail!("Response of {url} larger than {max_len} bytes");
+            }
+            body.extend_from_slice(&buf[..len]);
+        }
+        Ok(body)
+    })
+}
+
+/// Request `url` and hand its body to `f`, without keeping it in memory
+///
+/// `f` is given a function filling its buffer with the next bytes of the body
+/// and returning their count, 0 at the end of the body.
+pub fn read<T>(
+    url: &str,
+    f: impl FnOnce(&mut dyn FnMut(&mut [u8]) -> Result<usize>) -> Result<T>,
+) -> Result<T> {
+    // The certificate bundle is disabled in sdkconfig.defaults, only plain HTTP works
+    let mut client = EspHttpClient::new(&EspHttpClientConfiguration::default())?;
+    let mut response = client.get(url)?.submit()?;
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        anyhow::bail!("HTTP status {status} for {url}");
+    }
+
+    let mut reader = response.reader();
+    f(&mut |buf: &mut [u8]| Ok(reader.read(buf)?))
+}
+
+/// Text downloads in a background thread, not to block the event loop
+pub struct HttpFetcher {
+    max_len: usize,
+    /// Each download has its own slot, a stopped download may still complete
+    result: Arc<Mutex<Option<Result<String>>>>,
+}
+
+impl HttpFetcher {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            result: Default::default(),
+        }
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn start(&mut self, url: &str) -> Result<()> {
+        info!("Downloading {url}");
+        let result = Arc::new(Mutex::new(None));
+        self.result = result.clone();
+        let url = url.to_string();
+        let max_len = self.max_len;
+        thread::Builder::new()
+            .name("http".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let body = get(&url, max_len).and_then(|body| Ok(String::from_utf8(body)?));
+                *result.lock().unwrap() = Some(body);
+            })?;
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Option<Result<String>> {
+        self.result.lock().unwrap().take()
+    }
+
+    fn stop(&mut self) {
+        self.result = Default::default();
+    }
+}
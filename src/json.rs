@@ -0,0 +1,323 @@
+//! Minimal JSON reader and writer.
+//!
+//! The reader is lenient enough for the JavaScript object literals sent by
+//! phone companions: keys may be unquoted, strings may use single quotes and
+//! `\xNN` escapes.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonError {
+    /// Byte offset where parsing failed
+    pub offset: usize,
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!("invalid JSON at offset {}", self.offset))
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Nesting limit, the stack of the callers is small
+const MAX_DEPTH: usize = 16;
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            // Some companions send numbers as strings
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_f64()
+            .filter(|n| n.is_finite() && n.abs() < 9.0e15)
+            .map(|n| n as i64)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Number(n) => Some(*n != 0.0),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// String member `key`, empty when missing
+    pub fn str_or_empty(&self, key: &str) -> String {
+        self.get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => f.write_fmt(format_args!("{b}")),
+            Value::Number(n) if n.is_finite() => f.write_fmt(format_args!("{n}")),
+            Value::Number(_) => f.write_str("null"),
+            Value::String(s) => f.write_str(&quote(s)),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    value.fmt(f)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    f.write_fmt(format_args!("{}:{value}", quote(key)))?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Quote and escape a string
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn parse(input: &str) -> Result<Value, JsonError> {
+    let mut parser = Parser { input, offset: 0 };
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.offset != input.len() {
+        return Err(parser.error());
+    }
+    Ok(value)
+}
+
+/// Parse a value at the start of `input`, returning the number of bytes read
+pub fn parse_prefix(input: &str) -> Result<(Value, usize), JsonError> {
+    let mut parser = Parser { input, offset: 0 };
+    let value = parser.value(0)?;
+    Ok((value, parser.offset))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self) -> JsonError {
+        JsonError {
+            offset: self.offset,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.whitespace();
+        if self.peek() == Some(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error());
+        }
+        self.whitespace();
+        match self.peek().ok_or_else(|| self.error())? {
+            '{' => self.object(depth),
+            '[' => self.array(depth),
+            '"' | '\'' => self.string().map(Value::String),
+            c if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => self.number(),
+            _ => match self.identifier().as_str() {
+                "null" | "undefined" => Ok(Value::Null),
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(self.error()),
+            },
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, JsonError> {
+        self.next();
+        let mut members = Vec::new();
+        if self.eat('}') {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.whitespace();
+            let key = match self.peek() {
+                Some('"') | Some('\'') => self.string()?,
+                _ => self.identifier(),
+            };
+            if key.is_empty() || !self.eat(':') {
+                return Err(self.error());
+            }
+            let value = self.value(depth + 1)?;
+            members.push((key, value));
+            if self.eat(',') {
+                continue;
+            }
+            if self.eat('}') {
+                return Ok(Value::Object(members));
+            }
+            return Err(self.error());
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, JsonError> {
+        self.next();
+        let mut values = Vec::new();
+        if self.eat(']') {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+            if self.eat(',') {
+                continue;
+            }
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            return Err(self.error());
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        let start = self.offset;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '$') {
+            self.next();
+        }
+        self.input[start..self.offset].to_string()
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.offset;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.next();
+        }
+        self.input[start..self.offset]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| JsonError { offset: start })
+    }
+
+    fn hex(&mut self, digits: usize) -> Result<u32, JsonError> {
+        let rest = self.rest();
+        let hex = rest.get(..digits).ok_or_else(|| self.error())?;
+        let value = u32::from_str_radix(hex, 16).map_err(|_| self.error())?;
+        self.offset += digits;
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        let quote = self.next();
+        let mut s = String::new();
+        loop {
+            match self.next().ok_or_else(|| self.error())? {
+                c if Some(c) == quote => return Ok(s),
+                '\\' => {
+                    let c = match self.next().ok_or_else(|| self.error())? {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        '0' => '\0',
+                        // Latin-1 code point, as escaped by Espruino
+                        'x' => char::from_u32(self.hex(2)?).ok_or_else(|| self.error())?,
+                        'u' => {
+                            let mut code = self.hex(4)?;
+                            // Surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.rest().starts_with("\\u") {
+                                self.offset += 2;
+                                let low = self.hex(4)?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        c => c,
+                    };
+                    s.push(c);
+                }
+                c => s.push(c),
+            }
+        }
+    }
+}
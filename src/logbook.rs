@@ -0,0 +1,403 @@
+//! Recent log records, shown by the log tile and the `log` console command.
+//!
+//! The logger keeps every record it prints in a ring in RAM. Built with
+//! `TWATCH_PERSIST_LOGS`, the warnings and errors are also kept in NVS, at
+//! most once a minute, and shown again after a reboot. Unlike the lines kept
+//! for the crash reports, they survive a power-off.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{warn, Level};
+
+use crate::storage::{Persist, Storage};
+
+const STORAGE_KEY: &str = "logs";
+const STORAGE_VERSION: u8 = 1;
+
+/// Keep the warnings and errors in NVS across reboots, given at build time
+const PERSIST: bool = option_env!("TWATCH_PERSIST_LOGS").is_some();
+/// Time between two writes of the warnings and errors to NVS
+pub const SAVE_PERIOD: Duration = Duration::from_secs(60);
+
+pub const CAPACITY: usize = 64;
+/// Warnings and errors kept in NVS, the latest ones
+const SAVED: usize = 32;
+/// Longer messages are truncated to bound the memory used
+const MAX_MESSAGE_LEN: usize = 160;
+const MAX_TARGET_LEN: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Time since the boot it was logged at, see `utils::uptime`, which
+    /// unlike the wall clock doesn't jump when the time is set
+    pub uptime: Duration,
+    pub level: Level,
+    /// Last part of the module path, e.g. `wifi`
+    pub target: String,
+    pub message: String,
+    /// Logged before the last reboot, loaded from NVS
+    pub earlier: bool,
+}
+
+impl Entry {
+    pub fn new(uptime: Duration, level: Level, target: &str, message: &str) -> Self {
+        Self {
+            uptime,
+            level,
+            target: truncate(target, MAX_TARGET_LEN),
+            message: truncate(message, MAX_MESSAGE_LEN),
+            earlier: false,
+        }
+    }
+
+    /// Uptime as H:MM:SS
+    pub fn time(&self) -> String {
+        let secs = self.uptime.as_secs();
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.earlier {
+            f.write_str("(before reboot) ")?;
+        }
+        f.write_fmt(format_args!(
+            "{} {} {}: {}",
+            self.time(),
+            &self.level.as_str()[..1],
+            self.target,
+            self.message
+        ))
+    }
+}
+
+fn truncate(s: &str, max_len: usize) -> String {
+    let mut end = s.len().min(max_len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_string()
+}
+
+/// Last `CAPACITY` records, overwriting the oldest one
+#[derive(Debug, Default)]
+pub struct Logbook {
+    entries: Vec<Entry>,
+    /// Slot of the next entry once full, which is the oldest one
+    next: usize,
+    /// Records pushed since the boot, telling a viewer when to refresh
+    pub count: u32,
+    /// A warning or an error was pushed since the last save
+    unsaved: bool,
+}
+
+impl Logbook {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next: 0,
+            count: 0,
+            unsaved: false,
+        }
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.unsaved |= entry.level <= Level::Warn;
+        if self.entries.len() < CAPACITY {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+            self.next = (self.next + 1) % CAPACITY;
+        }
+        self.count = self.count.wrapping_add(1);
+    }
+
+    /// Entries of `level` or more severe, newest first
+    pub fn entries(&self, level: Level) -> Vec<Entry> {
+        let (newest, oldest) = self.entries.split_at(self.next);
+        oldest
+            .iter()
+            .chain(newest)
+            .rev()
+            .filter(|entry| entry.level <= level)
+            .cloned()
+            .collect()
+    }
+
+    /// Put the entries of the last boot before the ones of this boot
+    fn restore(&mut self, earlier: Vec<Entry>) {
+        let mut entries = self.entries(Level::Trace);
+        entries.reverse();
+        self.entries = Vec::new();
+        self.next = 0;
+        let unsaved = self.unsaved;
+        for mut entry in earlier {
+            entry.earlier = true;
+            self.push(entry);
+        }
+        for entry in entries {
+            self.push(entry);
+        }
+        self.unsaved = unsaved;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+        self.unsaved = true;
+    }
+}
+
+/// Warnings and errors kept in NVS, oldest first
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SavedEntries(Vec<Entry>);
+
+impl Persist for SavedEntries {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![STORAGE_VERSION];
+        for entry in &self.0 {
+            data.extend_from_slice(&(entry.uptime.as_millis() as u64).to_le_bytes());
+            data.push(entry.level as u8);
+            for field in [&entry.target, &entry.message] {
+                data.extend_from_slice(&(field.len() as u16).to_le_bytes());
+                data.extend_from_slice(field.as_bytes());
+            }
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if data.len() < len {
+                return None;
+            }
+            let (taken, rest) = data.split_at(len);
+            *data = rest;
+            Some(taken)
+        }
+        fn field(data: &mut &[u8]) -> Option<String> {
+            let len = u16::from_le_bytes(take(data, 2)?.try_into().ok()?);
+            String::from_utf8(take(data, len as usize)?.to_vec()).ok()
+        }
+
+        let (version, mut data) = data.split_first()?;
+        if *version != STORAGE_VERSION {
+            return None;
+        }
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            let millis = u64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+            let level = match take(&mut data, 1)?[0] {
+                1 => Level::Error,
+                2 => Level::Warn,
+                3 => Level::Info,
+                4 => Level::Debug,
+                _ => Level::Trace,
+            };
+            entries.push(Entry {
+                uptime: Duration::from_millis(millis),
+                level,
+                target: field(&mut data)?,
+                message: field(&mut data)?,
+                earlier: true,
+            });
+        }
+        Some(Self(entries))
+    }
+}
+
+static LOGBOOK: Mutex<Logbook> = Mutex::new(Logbook::new());
+
+/// Keep a record, called by the logger
+pub fn push(entry: Entry) {
+    if let Ok(mut logbook) = LOGBOOK.lock() {
+        logbook.push(entry);
+    }
+}
+
+/// Entries of `level` or more severe, newest first
+pub fn entries(level: Level) -> Vec<Entry> {
+    LOGBOOK.lock().unwrap().entries(level)
+}
+
+/// Records pushed since the boot
+pub fn count() -> u32 {
+    LOGBOOK.lock().unwrap().count
+}
+
+pub fn clear(storage: &mut Storage) {
+    LOGBOOK.lock().unwrap().clear();
+    save(storage);
+}
+
+/// Show the warnings and errors of the last boot before the new records
+pub fn load(storage: &Storage) {
+    if !PERSIST {
+        return;
+    }
+    match storage.get::<SavedEntries>(STORAGE_KEY) {
+        Ok(saved) => LOGBOOK.lock().unwrap().restore(saved.unwrap_or_default().0),
+        Err(e) => warn!("Unable to read logs: {e:?}"),
+    }
+}
+
+/// Keep the warnings and errors in NVS when there are new ones
+pub fn save(storage: &mut Storage) {
+    if !PERSIST {
+        return;
+    }
+    let mut saved = {
+        let mut logbook = LOGBOOK.lock().unwrap();
+        if !std::mem::take(&mut logbook.unsaved) {
+            return;
+        }
+        logbook.entries(Level::Warn)
+    };
+    saved.truncate(SAVED);
+    saved.reverse();
+    // Logged after the lock is released, as the logger takes it
+    if let Err(e) = storage.put(STORAGE_KEY, &SavedEntries(saved)) {
+        warn!("Unable to store logs: {e:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(secs: u64, level: Level) -> Entry {
+        Entry::new(Duration::from_secs(secs), level, "test", &secs.to_string())
+    }
+
+    fn messages(entries: &[Entry]) -> Vec<u64> {
+        entries
+            .iter()
+            .map(|entry| entry.message.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lists_the_newest_entries_first() {
+        let mut logbook = Logbook::new();
+        assert!(logbook.entries(Level::Trace).is_empty());
+        logbook.push(entry(1, Level::Info));
+        logbook.push(entry(2, Level::Error));
+        logbook.push(entry(3, Level::Warn));
+        logbook.push(entry(4, Level::Debug));
+        assert_eq!(messages(&logbook.entries(Level::Trace)), [4, 3, 2, 1]);
+        assert_eq!(messages(&logbook.entries(Level::Info)), [3, 2, 1]);
+        assert_eq!(messages(&logbook.entries(Level::Warn)), [3, 2]);
+        assert_eq!(messages(&logbook.entries(Level::Error)), [2]);
+        assert_eq!(logbook.count, 4);
+    }
+
+    #[test]
+    fn overwrites_the_oldest_entries() {
+        let mut logbook = Logbook::new();
+        let pushed = CAPACITY as u64 * 2 + 5;
+        for secs in 0..pushed {
+            logbook.push(entry(secs, Level::Info));
+            let entries = logbook.entries(Level::Trace);
+            assert_eq!(entries.len(), CAPACITY.min(secs as usize + 1));
+            assert_eq!(messages(&entries[..1]), [secs]);
+        }
+        let expected: Vec<u64> = (pushed - CAPACITY as u64..pushed).rev().collect();
+        assert_eq!(messages(&logbook.entries(Level::Trace)), expected);
+        assert_eq!(logbook.count, pushed as u32);
+
+        logbook.clear();
+        assert!(logbook.entries(Level::Trace).is_empty());
+        logbook.push(entry(1, Level::Info));
+        assert_eq!(messages(&logbook.entries(Level::Trace)), [1]);
+    }
+
+    #[test]
+    fn tracks_the_unsaved_warnings() {
+        let mut logbook = Logbook::new();
+        logbook.push(entry(1, Level::Info));
+        assert!(!logbook.unsaved);
+        logbook.push(entry(2, Level::Warn));
+        assert!(logbook.unsaved);
+    }
+
+    #[test]
+    fn restores_the_earlier_entries_before_the_new_ones() {
+        let mut logbook = Logbook::new();
+        for secs in 0..CAPACITY as u64 + 3 {
+            logbook.push(entry(100 + secs, Level::Info));
+        }
+        logbook.restore(vec![entry(1, Level::Warn), entry(2, Level::Error)]);
+        let entries = logbook.entries(Level::Trace);
+        assert_eq!(entries.len(), CAPACITY);
+        // The new entries are kept when there isn't room for all of them
+        assert_eq!(messages(&entries[..1]), [100 + CAPACITY as u64 + 2]);
+        assert!(entries.iter().all(|entry| !entry.earlier));
+
+        let mut logbook = Logbook::new();
+        logbook.push(entry(10, Level::Info));
+        logbook.restore(vec![entry(1, Level::Warn), entry(2, Level::Error)]);
+        let entries = logbook.entries(Level::Trace);
+        assert_eq!(messages(&entries), [10, 2, 1]);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.earlier)
+                .collect::<Vec<_>>(),
+            [false, true, true]
+        );
+        assert!(!logbook.unsaved);
+    }
+
+    #[test]
+    fn truncates_the_fields() {
+        let long = "\u{e9}".repeat(MAX_MESSAGE_LEN);
+        let entry = Entry::new(Duration::ZERO, Level::Info, &long, &long);
+        assert_eq!(entry.target.len(), MAX_TARGET_LEN);
+        assert_eq!(entry.message.len(), MAX_MESSAGE_LEN);
+        // Not within a character
+        let entry = Entry::new(Duration::ZERO, Level::Info, &format!("a{long}"), "");
+        assert_eq!(entry.target.len(), MAX_TARGET_LEN - 1);
+    }
+
+    #[test]
+    fn describes_entries() {
+        let mut entry = Entry::new(Duration::from_secs(3723), Level::Warn, "wifi", "lost");
+        assert_eq!(entry.to_string(), "1:02:03 W wifi: lost");
+        entry.earlier = true;
+        assert_eq!(entry.to_string(), "(before reboot) 1:02:03 W wifi: lost");
+    }
+
+    #[test]
+    fn encodes_and_decodes_the_saved_entries() {
+        let mut entries = vec![
+            Entry::new(Duration::from_millis(1234), Level::Error, "ble", "failed"),
+            Entry::new(Duration::from_secs(86400), Level::Warn, "", "caf\u{e9}"),
+        ];
+        for entry in &mut entries {
+            entry.earlier = true;
+        }
+        let saved = SavedEntries(entries);
+        let data = saved.encode();
+        assert_eq!(SavedEntries::decode(&data).as_ref(), Some(&saved));
+        assert_eq!(
+            SavedEntries::decode(&[STORAGE_VERSION]),
+            Some(SavedEntries::default())
+        );
+
+        // Truncated within an entry, of another version or not UTF-8
+        let first = SavedEntries(saved.0[..1].to_vec()).encode().len();
+        for len in (2..data.len()).filter(|&len| len != first) {
+            assert_eq!(SavedEntries::decode(&data[..len]), None, "{len}");
+        }
+        assert_eq!(SavedEntries::decode(&[]), None);
+        let mut data = data;
+        data[0] = STORAGE_VERSION + 1;
+        assert_eq!(SavedEntries::decode(&data), None);
+        data[0] = STORAGE_VERSION;
+        let last = data.len() - 1;
+        data[last] = 0xff;
+        assert_eq!(SavedEntries::decode(&data), None);
+    }
+}
@@ -0,0 +1,50 @@
+//! Logger forwarding to the ESP-IDF one, keeping the last lines for the crash
+//! reports and the records for the log tile.
+
+use esp_idf_svc::log::EspLogger;
+use log::{Log, Metadata, Record};
+
+use crate::logbook::{self, Entry};
+use crate::panic;
+use crate::utils;
+
+static ESP_LOGGER: EspLogger = EspLogger;
+static LOGGER: Logger = Logger;
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        ESP_LOGGER.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        ESP_LOGGER.log(record);
+
+        let uptime = utils::uptime();
+        let target = record.target().rsplit("::").next().unwrap_or_default();
+        let message = record.args().to_string();
+        panic::log_line(&format!(
+            "{}.{:03} {} {target}: {message}",
+            uptime.as_secs(),
+            uptime.subsec_millis(),
+            &record.level().as_str()[..1],
+        ));
+        logbook::push(Entry::new(uptime, record.level(), target, &message));
+    }
+
+    fn flush(&self) {
+        ESP_LOGGER.flush();
+    }
+}
+
+/// Bind the log crate to the ESP logging facilities, as
+/// `EspLogger::initialize_default`
+pub fn init() {
+    log::set_logger(&LOGGER)
+        .map(|()| ESP_LOGGER.initialize())
+        .unwrap();
+}
@@ -1,52 +1,164 @@
-mod display;
+// Without the `esp` feature, only the modules which don't need the ESP-IDF
+// are built, to run their tests on the host with `make test`
+#![cfg_attr(not(feature = "esp"), allow(dead_code))]
+
+mod alarms;
+mod calendar;
+mod crash;
+mod dfu;
 mod errors;
+mod feedback;
+mod gadgetbridge;
+mod haptics;
+mod health;
+mod json;
+mod logbook;
+mod metrics;
+mod notifications;
+mod ota;
+mod provisioning;
+mod screenshot;
+mod shell;
+mod storage;
+mod text;
+mod timesync;
+mod tz;
+mod utils;
+mod weather;
+
+#[cfg(feature = "esp")]
+mod ble;
+#[cfg(feature = "esp")]
+mod bus;
+#[cfg(feature = "esp")]
+mod console;
+#[cfg(feature = "esp")]
+mod display;
+#[cfg(feature = "esp")]
 mod events;
+#[cfg(feature = "esp")]
+mod firmware;
+#[cfg(feature = "esp")]
+mod http;
+#[cfg(feature = "esp")]
+mod logger;
+#[cfg(feature = "esp")]
+mod memory;
+#[cfg(feature = "esp")]
+mod panic;
+#[cfg(feature = "esp")]
 mod pmu;
+#[cfg(feature = "esp")]
+mod portal;
+#[cfg(feature = "esp")]
 mod tiles;
+#[cfg(feature = "esp")]
 mod twatch;
+#[cfg(feature = "esp")]
 mod types;
-mod utils;
+#[cfg(feature = "esp")]
+mod vibrator;
+#[cfg(feature = "esp")]
+mod watchdog;
+#[cfg(feature = "esp")]
+mod wifi;
+
+#[cfg(feature = "esp")]
+use std::sync::Arc;
 
+#[cfg(feature = "esp")]
 use embedded_svc::event_bus::EventBus;
 
+#[cfg(feature = "esp")]
 use esp_idf_hal::peripherals;
+#[cfg(feature = "esp")]
+use esp_idf_svc::netif::EspNetifStack;
+#[cfg(feature = "esp")]
 use esp_idf_svc::notify::EspNotify;
+#[cfg(feature = "esp")]
+use esp_idf_svc::nvs::EspDefaultNvs;
+#[cfg(feature = "esp")]
+use esp_idf_svc::sysloop::EspSysLoopStack;
+#[cfg(feature = "esp")]
 use esp_idf_sys::EspError;
 
+#[cfg(feature = "esp")]
 use log::*;
 
+#[cfg(feature = "esp")]
 fn main() {
-    let mut eventloop = init_esp().expect("Error initializing ESP");
+    // Before the logger overwrites the lines logged before the reset
+    let boot = panic::boot();
+    let (mut eventloop, default_nvs, netif_stack, sys_loop_stack) =
+        init_esp().expect("Error initializing ESP");
+    panic::install();
+    if let Some(report) = &boot.report {
+        error!("Reset by {report}");
+    }
+    if boot.safe_mode() {
+        warn!("{} crashes in a row, starting in safe mode", boot.crashes);
+        // An update which keeps crashing is not kept
+        firmware::rollback();
+    }
     let twatch_eventloop = eventloop.clone();
 
     let peripherals = peripherals::Peripherals::take().expect("Failed to take esp peripherals");
 
-    let mut twatch = twatch::Twatch::new(peripherals, twatch_eventloop);
+    // Boxed for the panic handler to find the display
+    let mut twatch = Box::new(twatch::Twatch::new(
+        peripherals,
+        twatch_eventloop,
+        default_nvs,
+        netif_stack,
+        sys_loop_stack,
+        boot.safe_mode(),
+    ));
+    unsafe { panic::set_display(&mut twatch.hal.display) };
     info!("TWatch created");
-    twatch.init().expect("Error initializing TWatch");
+    let display = panic::lock_display();
+    if let Err(e) = twatch.init() {
+        // An update which doesn't initialize is not kept
+        firmware::rollback();
+        panic!("Error initializing TWatch: {e:?}");
+    }
     info!("TWatch initialized");
+    firmware::confirm_boot().unwrap_or_else(|e| warn!("Unable to confirm firmware: {e:?}"));
+    if let Some(report) = boot.report {
+        twatch.show_crash(report);
+    }
     twatch.run().expect("Run default Tile");
-    let _subscription =
-        eventloop.subscribe(move |raw_event: &u32| twatch.process_event((*raw_event).into()));
+    drop(display);
+    let _subscription = eventloop.subscribe(move |bits: &u32| twatch.process_events(*bits));
+    let mut stable = false;
     loop {
         std::thread::sleep(std::time::Duration::from_millis(500));
+        watchdog::check();
+        if !stable && utils::uptime() >= crash::STABLE_AFTER {
+            panic::stable();
+            stable = true;
+        }
     }
 }
 
-fn init_esp() -> Result<EspNotify, EspError> {
+#[cfg(feature = "esp")]
+#[allow(clippy::type_complexity)]
+fn init_esp() -> Result<
+    (
+        EspNotify,
+        Arc<EspDefaultNvs>,
+        Arc<EspNetifStack>,
+        Arc<EspSysLoopStack>,
+    ),
+    EspError,
+> {
     esp_idf_sys::link_patches();
 
     // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
-
-    use esp_idf_svc::{netif::EspNetifStack, sysloop::EspSysLoopStack};
-    // use esp_idf_svc::nvs::EspDefaultNvs;
-    use std::sync::Arc;
+    logger::init();
 
-    #[allow(unused)]
     let netif_stack = Arc::new(EspNetifStack::new()?);
-    #[allow(unused)]
     let sys_loop_stack = Arc::new(EspSysLoopStack::new()?);
+    let default_nvs = Arc::new(EspDefaultNvs::new()?);
 
     let notify_configuration = esp_idf_svc::notify::Configuration {
         task_name: "BackgroundNotify",
@@ -55,5 +167,14 @@ fn init_esp() -> Result<EspNotify, EspError> {
         task_pin_to_core: None,
     };
 
-    EspNotify::new(&notify_configuration)
+    Ok((
+        EspNotify::new(&notify_configuration)?,
+        default_nvs,
+        netif_stack,
+        sys_loop_stack,
+    ))
 }
+
+/// The firmware needs the `esp` feature, only the tests are built without it
+#[cfg(not(feature = "esp"))]
+fn main() {}
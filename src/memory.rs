@@ -0,0 +1,66 @@
+//! Free heap and stacks of the FreeRTOS tasks, see `metrics` for the timings.
+
+use std::ffi::CStr;
+
+use esp_idf_sys::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heap {
+    pub free: u32,
+    /// Lowest free heap since the boot
+    pub min_free: u32,
+    /// Largest allocation which can succeed, lower than `free` when fragmented
+    pub largest_block: u32,
+}
+
+impl std::fmt::Display for Heap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{}k free, {}k at least, {}k block",
+            self.free / 1024,
+            self.min_free / 1024,
+            self.largest_block / 1024
+        ))
+    }
+}
+
+pub fn heap() -> Heap {
+    unsafe {
+        Heap {
+            free: esp_get_free_heap_size(),
+            min_free: esp_get_minimum_free_heap_size(),
+            largest_block: heap_caps_get_largest_free_block(MALLOC_CAP_8BIT) as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskStack {
+    pub name: String,
+    /// Bytes of the stack never used since the task started
+    pub high_water: u32,
+}
+
+/// Stacks of all the tasks, the closest to overflowing first
+///
+/// Needs `CONFIG_FREERTOS_USE_TRACE_FACILITY`, see `sdkconfig.defaults`.
+pub fn stacks() -> Vec<TaskStack> {
+    // Room for the tasks created meanwhile
+    let capacity = unsafe { uxTaskGetNumberOfTasks() } as usize + 4;
+    let mut tasks: Vec<TaskStatus_t> = Vec::with_capacity(capacity);
+    unsafe {
+        let len = uxTaskGetSystemState(tasks.as_mut_ptr(), capacity as _, std::ptr::null_mut());
+        tasks.set_len(len as usize);
+    }
+    let mut stacks: Vec<TaskStack> = tasks
+        .iter()
+        .map(|task| TaskStack {
+            name: unsafe { CStr::from_ptr(task.pcTaskName) }
+                .to_string_lossy()
+                .into_owned(),
+            high_water: task.usStackHighWaterMark as u32,
+        })
+        .collect();
+    stacks.sort_by_key(|stack| stack.high_water);
+    stacks
+}
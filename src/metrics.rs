@@ -0,0 +1,237 @@
+//! Histograms of the time taken by the event loop, the display and the buses.
+//!
+//! Durations are counted in buckets doubling from `FIRST_BUCKET`, so the
+//! percentiles are the upper bound of their bucket, at most twice the real
+//! value. They are kept since the boot or the last `reset`, and shown by the
+//! diagnostics tile and the `metrics` command of the console.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bound of the first bucket, in microseconds
+const FIRST_BUCKET: u64 = 64;
+/// Buckets up to about 1s, the last one counting the longer durations
+const BUCKETS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Handling of an event, from the event loop to the tile
+    Event,
+    /// Drawing of a frame, from its first pixel to its flush
+    Render,
+    /// Sending of a frame, or a part of it, over SPI
+    Flush,
+    /// Transfer with a device of the I2C buses
+    I2c,
+}
+
+impl Metric {
+    pub const COUNT: usize = 4;
+    pub const ALL: [Metric; Metric::COUNT] =
+        [Metric::Event, Metric::Render, Metric::Flush, Metric::I2c];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Event => "event",
+            Metric::Render => "render",
+            Metric::Flush => "flush",
+            Metric::I2c => "i2c",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u32; BUCKETS],
+    pub count: u32,
+    total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            total: Duration::ZERO,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let bucket = (0..BUCKETS - 1)
+            .find(|&i| micros <= FIRST_BUCKET << i)
+            .unwrap_or(BUCKETS - 1);
+        self.buckets[bucket] = self.buckets[bucket].saturating_add(1);
+        self.count = self.count.saturating_add(1);
+        self.total = self.total.saturating_add(elapsed);
+        self.min = self.min.min(elapsed);
+        self.max = self.max.max(elapsed);
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+
+    /// Duration under which `percent` of the ones recorded are, rounded up to
+    /// the bound of their bucket but not above the longest one
+    pub fn percentile(&self, percent: u32) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (self.count as u64 * percent.min(100) as u64 + 99) / 100;
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count as u64;
+            if seen >= rank.max(1) {
+                let bound = match i {
+                    i if i == BUCKETS - 1 => self.max,
+                    i => Duration::from_micros(FIRST_BUCKET << i),
+                };
+                return Some(bound.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+/// Milliseconds with one decimal, as shown on the watch
+pub fn millis(duration: Duration) -> String {
+    format!("{:.1}", duration.as_secs_f32() * 1000.0)
+}
+
+impl std::fmt::Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.mean(), self.percentile(50), self.percentile(95)) {
+            (Some(mean), Some(p50), Some(p95)) => f.write_fmt(format_args!(
+                "{} times, mean {}ms, p50 {}ms, p95 {}ms, min {}ms, max {}ms",
+                self.count,
+                millis(mean),
+                millis(p50),
+                millis(p95),
+                millis(self.min),
+                millis(self.max)
+            )),
+            _ => f.write_str("never"),
+        }
+    }
+}
+
+static HISTOGRAMS: Mutex<[Histogram; Metric::COUNT]> =
+    Mutex::new([Histogram::new(); Metric::COUNT]);
+
+pub fn record(metric: Metric, elapsed: Duration) {
+    HISTOGRAMS.lock().unwrap()[metric as usize].record(elapsed);
+}
+
+/// Evaluate `f`, counting the time it takes under `metric`
+pub fn time<R>(metric: Metric, f: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let result = f();
+    record(metric, start.elapsed());
+    result
+}
+
+pub fn histograms() -> [Histogram; Metric::COUNT] {
+    *HISTOGRAMS.lock().unwrap()
+}
+
+pub fn reset() {
+    *HISTOGRAMS.lock().unwrap() = [Histogram::new(); Metric::COUNT];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn micros(micros: u64) -> Duration {
+        Duration::from_micros(micros)
+    }
+
+    fn bucket_of(elapsed: Duration) -> usize {
+        let mut histogram = Histogram::new();
+        histogram.record(elapsed);
+        histogram
+            .buckets
+            .iter()
+            .position(|&count| count == 1)
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_in_doubling_buckets() {
+        assert_eq!(bucket_of(micros(0)), 0);
+        assert_eq!(bucket_of(micros(64)), 0);
+        assert_eq!(bucket_of(micros(65)), 1);
+        assert_eq!(bucket_of(micros(128)), 1);
+        assert_eq!(bucket_of(micros(129)), 2);
+        assert_eq!(bucket_of(micros(64 << 14)), BUCKETS - 2);
+        assert_eq!(bucket_of(micros((64 << 14) + 1)), BUCKETS - 1);
+        assert_eq!(bucket_of(Duration::from_secs(3600)), BUCKETS - 1);
+    }
+
+    #[test]
+    fn keeps_the_extremes_and_the_mean() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.mean(), None);
+        for elapsed in [300, 100, 200] {
+            histogram.record(micros(elapsed));
+        }
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.min, micros(100));
+        assert_eq!(histogram.max, micros(300));
+        assert_eq!(histogram.mean(), Some(micros(200)));
+    }
+
+    #[test]
+    fn rounds_percentiles_up_to_their_bucket() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.percentile(50), None);
+        for _ in 0..50 {
+            histogram.record(micros(10));
+        }
+        for _ in 0..45 {
+            histogram.record(micros(100));
+        }
+        for _ in 0..5 {
+            histogram.record(micros(1000));
+        }
+        assert_eq!(histogram.percentile(0), Some(micros(64)));
+        assert_eq!(histogram.percentile(50), Some(micros(64)));
+        assert_eq!(histogram.percentile(51), Some(micros(128)));
+        assert_eq!(histogram.percentile(95), Some(micros(128)));
+        // The bound of the bucket is above the longest duration
+        assert_eq!(histogram.percentile(96), Some(micros(1000)));
+        assert_eq!(histogram.percentile(100), Some(micros(1000)));
+        assert_eq!(histogram.percentile(200), Some(micros(1000)));
+    }
+
+    #[test]
+    fn uses_the_longest_duration_for_the_last_bucket() {
+        let mut histogram = Histogram::new();
+        histogram.record(micros(10));
+        histogram.record(Duration::from_secs(5));
+        assert_eq!(histogram.percentile(50), Some(micros(64)));
+        assert_eq!(histogram.percentile(99), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn describes_histograms() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.to_string(), "never");
+        histogram.record(micros(1500));
+        assert_eq!(
+            histogram.to_string(),
+            "1 times, mean 1.5ms, p50 1.5ms, p95 1.5ms, min 1.5ms, max 1.5ms"
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+#[cfg(feature = "esp")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "esp")]
+use std::time::Duration;
+
+use anyhow::Result;
+
+#[cfg(feature = "esp")]
+use embedded_svc::event_bus::Postbox;
+#[cfg(feature = "esp")]
+use esp_idf_svc::notify::EspNotify;
+
+use log::*;
+
+use crate::calendar;
+#[cfg(feature = "esp")]
+use crate::events::TwatchRawEvent;
+use crate::gadgetbridge;
+use crate::storage::{Persist, Storage};
+
+const STORAGE_KEY: &str = "notifs";
+const STORAGE_VERSION: u8 = 1;
+
+/// Keep the inbox in NVS across reboots, given at build time
+const PERSIST: bool = option_env!("TWATCH_PERSIST_NOTIFICATIONS").is_some();
+
+pub const INBOX_CAPACITY: usize = 20;
+/// Longer bodies are truncated to bound the memory used by the inbox
+const MAX_BODY_LEN: usize = 512;
+const MAX_FIELD_LEN: usize = 64;
+/// Notifications not yet handled by the event loop, the oldest are dropped
+const MAX_PENDING: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Only stored in the inbox
+    Low,
+    /// Shown in a pop-up when the screen is on
+    Normal,
+    /// Wakes the watch up to show the pop-up
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Assigned by the inbox
+    pub id: u32,
+    /// Identifier given by the sender, e.g. the phone, to update or dismiss it
+    pub remote_id: Option<i64>,
+    pub app: String,
+    pub title: String,
+    pub body: String,
+    /// UTC time of reception in seconds since the Unix epoch
+    pub timestamp: i64,
+    pub priority: Priority,
+}
+
+impl Notification {
+    pub fn new(app: &str, title: &str, body: &str, priority: Priority) -> Self {
+        Self {
+            id: 0,
+            remote_id: None,
+            app: truncate(app, MAX_FIELD_LEN),
+            title: truncate(title, MAX_FIELD_LEN),
+            body: truncate(body, MAX_BODY_LEN),
+            timestamp: calendar::utc_now(),
+            priority,
+        }
+    }
+
+    pub fn from_phone(notification: gadgetbridge::Notification) -> Self {
+        let title = [
+            &notification.title,
+            &notification.sender,
+            &notification.subject,
+        ]
+        .into_iter()
+        .find(|t| !t.is_empty())
+        .cloned()
+        .unwrap_or_default();
+        Self {
+            remote_id: Some(notification.id),
+            ..Self::new(
+                &notification.src,
+                &title,
+                &notification.body,
+                Priority::Normal,
+            )
+        }
+    }
+
+    pub fn from_call(call: gadgetbridge::Call) -> Self {
+        let caller = if call.name.is_empty() {
+            &call.number
+        } else {
+            &call.name
+        };
+        Self::new("Call", caller, &call.number, Priority::High)
+    }
+
+    /// Age of the notification, e.g. "5 min"
+    pub fn age(&self, now: i64) -> String {
+        match (now - self.timestamp).max(0) {
+            s if s < 60 => "now".to_string(),
+            s if s < 3600 => format!("{} min", s / 60),
+            s if s < 86_400 => format!("{} h", s / 3600),
+            s => format!("{} d", s / 86_400),
+        }
+    }
+}
+
+fn truncate(s: &str, max_len: usize) -> String {
+    let mut end = s.len().min(max_len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_string()
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct NotificationList(VecDeque<Notification>);
+
+impl Persist for NotificationList {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![STORAGE_VERSION];
+        for n in &self.0 {
+            data.extend_from_slice(&n.id.to_le_bytes());
+            data.extend_from_slice(&n.timestamp.to_le_bytes());
+            data.push(n.priority as u8);
+            data.push(n.remote_id.is_some() as u8);
+            data.extend_from_slice(&n.remote_id.unwrap_or_default().to_le_bytes());
+            for field in [&n.app, &n.title, &n.body] {
+                data.extend_from_slice(&(field.len() as u16).to_le_bytes());
+                data.extend_from_slice(field.as_bytes());
+            }
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if data.len() < len {
+                return None;
+            }
+            let (taken, rest) = data.split_at(len);
+            *data = rest;
+            Some(taken)
+        }
+        fn field(data: &mut &[u8]) -> Option<String> {
+            let len = u16::from_le_bytes(take(data, 2)?.try_into().ok()?);
+            String::from_utf8(take(data, len as usize)?.to_vec()).ok()
+        }
+
+        let (version, mut data) = data.split_first()?;
+        if *version != STORAGE_VERSION {
+            return None;
+        }
+        let mut notifications = VecDeque::new();
+        while !data.is_empty() {
+            let id = u32::from_le_bytes(take(&mut data, 4)?.try_into().ok()?);
+            let timestamp = i64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+            let priority = match take(&mut data, 1)?[0] {
+                0 => Priority::Low,
+                1 => Priority::Normal,
+                _ => Priority::High,
+            };
+            let has_remote_id = take(&mut data, 1)?[0] != 0;
+            let remote_id = i64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+            notifications.push_back(Notification {
+                id,
+                remote_id: has_remote_id.then_some(remote_id),
+                app: field(&mut data)?,
+                title: field(&mut data)?,
+                body: field(&mut data)?,
+                timestamp,
+                priority,
+            });
+        }
+        notifications.truncate(INBOX_CAPACITY);
+        Some(Self(notifications))
+    }
+}
+
+/// Received notifications, newest first
+///
+/// The oldest notifications are dropped once `INBOX_CAPACITY` is reached.
+#[derive(Default)]
+pub struct Inbox {
+    list: NotificationList,
+    next_id: u32,
+}
+
+impl Inbox {
+    /// Restore the inbox, which stays empty when it can't be read
+    pub fn load(&mut self, storage: &Storage) -> Result<()> {
+        self.next_id = 1;
+        if PERSIST {
+            self.list = storage.get(STORAGE_KEY)?.unwrap_or_default();
+            info!("{} notifications loaded", self.list.0.len());
+        }
+        self.next_id = self
+            .list
+            .0
+            .iter()
+            .map(|n| n.id.wrapping_add(1))
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.0.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Notification> {
+        self.list.0.get(index)
+    }
+
+    /// Add a notification, replacing the one with the same remote id
+    ///
+    /// Returns the id assigned to the notification.
+    pub fn push(&mut self, storage: &mut Storage, mut notification: Notification) -> u32 {
+        if let Some(remote_id) = notification.remote_id {
+            self.list.0.retain(|n| n.remote_id != Some(remote_id));
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        notification.id = id;
+        self.list.0.push_front(notification);
+        self.list.0.truncate(INBOX_CAPACITY);
+        self.save(storage);
+        id
+    }
+
+    pub fn dismiss(&mut self, storage: &mut Storage, id: u32) -> Option<Notification> {
+        let index = self.list.0.iter().position(|n| n.id == id)?;
+        let notification = self.list.0.remove(index);
+        self.save(storage);
+        notification
+    }
+
+    /// Dismiss a notification on request of its sender
+    pub fn dismiss_remote(
+        &mut self,
+        storage: &mut Storage,
+        remote_id: i64,
+    ) -> Option<Notification> {
+        let id = self
+            .list
+            .0
+            .iter()
+            .find(|n| n.remote_id == Some(remote_id))?
+            .id;
+        self.dismiss(storage, id)
+    }
+
+    pub fn clear(&mut self, storage: &mut Storage) {
+        self.list.0.clear();
+        self.save(storage);
+    }
+
+    fn save(&self, storage: &mut Storage) {
+        if PERSIST {
+            if let Err(e) = storage.put(STORAGE_KEY, &self.list) {
+                warn!("Unable to store notifications: {e:?}");
+            }
+        }
+    }
+}
+
+/// Handle given to the transports to deliver notifications
+///
+/// Notifications are queued and the event loop is woken up with
+/// `TwatchRawEvent::Notification`, so they can be sent from any thread.
+#[cfg(feature = "esp")]
+#[derive(Clone)]
+pub struct Notifier {
+    pending: Arc<Mutex<VecDeque<Notification>>>,
+    eventloop: EspNotify,
+}
+
+#[cfg(feature = "esp")]
+impl Notifier {
+    pub fn new(eventloop: EspNotify) -> Self {
+        Self {
+            pending: Default::default(),
+            eventloop,
+        }
+    }
+
+    pub fn send(&mut self, notification: Notification) {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.len() == MAX_PENDING {
+                warn!("Dropping notification {:?}", pending.pop_front());
+            }
+            pending.push_back(notification);
+        }
+        let _ = self.eventloop.post(
+            &TwatchRawEvent::Notification.into(),
+            Some(Duration::from_millis(0)),
+        );
+    }
+
+    /// Next pending notification, and whether more are pending
+    pub fn take(&self) -> Option<(Notification, bool)> {
+        let mut pending = self.pending.lock().unwrap();
+        let notification = pending.pop_front()?;
+        Some((notification, !pending.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(title: &str, remote_id: Option<i64>) -> Notification {
+        Notification {
+            remote_id,
+            ..Notification::new("App", title, "Body", Priority::Normal)
+        }
+    }
+
+    fn inbox() -> (Inbox, Storage) {
+        let storage = Storage::default();
+        let mut inbox = Inbox::default();
+        inbox.load(&storage).unwrap();
+        (inbox, storage)
+    }
+
+    #[test]
+    fn encodes_and_decodes_the_list() {
+        let list = NotificationList(VecDeque::from([
+            Notification {
+                id: 2,
+                timestamp: 1_700_000_000,
+                ..notification("Caf\u{e9}", Some(-42))
+            },
+            Notification {
+                id: 1,
+                priority: Priority::High,
+                ..Notification::new("Call", "Alice", "", Priority::High)
+            },
+        ]));
+        let data = list.encode();
+        assert_eq!(NotificationList::decode(&data), Some(list));
+
+        assert_eq!(
+            NotificationList::decode(&[STORAGE_VERSION]),
+            Some(NotificationList::default())
+        );
+        assert_eq!(NotificationList::decode(&[]), None);
+        assert_eq!(NotificationList::decode(&[STORAGE_VERSION + 1]), None);
+        // Cut in the middle of a notification
+        assert_eq!(NotificationList::decode(&data[..data.len() - 1]), None);
+        assert_eq!(NotificationList::decode(&data[..10]), None);
+    }
+
+    #[test]
+    fn decodes_at_most_the_capacity() {
+        let list = NotificationList(
+            (0..INBOX_CAPACITY as u32 + 5)
+                .map(|id| Notification {
+                    id,
+                    ..notification("Title", None)
+                })
+                .collect(),
+        );
+        let decoded = NotificationList::decode(&list.encode()).unwrap();
+        assert_eq!(decoded.0.len(), INBOX_CAPACITY);
+        assert_eq!(decoded.0[0].id, 0);
+    }
+
+    #[test]
+    fn truncates_the_fields() {
+        let long = "\u{e9}".repeat(MAX_BODY_LEN);
+        let n = Notification::new(&long, &long, &long, Priority::Low);
+        assert_eq!(n.app.len(), MAX_FIELD_LEN);
+        assert_eq!(n.title.len(), MAX_FIELD_LEN);
+        assert_eq!(n.body.len(), MAX_BODY_LEN);
+
+        // Not in the middle of a character
+        let n = Notification::new(&format!("a{long}"), "", "", Priority::Low);
+        assert_eq!(n.app.len(), MAX_FIELD_LEN - 1);
+    }
+
+    #[test]
+    fn evicts_the_oldest_notifications() {
+        let (mut inbox, mut storage) = inbox();
+        for i in 0..INBOX_CAPACITY + 3 {
+            inbox.push(&mut storage, notification(&format!("{i}"), None));
+        }
+        assert_eq!(inbox.len(), INBOX_CAPACITY);
+        // Newest first
+        let last = INBOX_CAPACITY + 2;
+        assert_eq!(inbox.get(0).unwrap().title, format!("{last}"));
+        assert_eq!(inbox.get(INBOX_CAPACITY - 1).unwrap().title, "3");
+        assert_eq!(inbox.get(0).unwrap().id, last as u32 + 1);
+    }
+
+    #[test]
+    fn replaces_and_dismisses_by_remote_id() {
+        let (mut inbox, mut storage) = inbox();
+        inbox.push(&mut storage, notification("First", Some(7)));
+        let other = inbox.push(&mut storage, notification("Other", None));
+        let id = inbox.push(&mut storage, notification("Updated", Some(7)));
+        assert_eq!(inbox.len(), 2);
+        assert_eq!(inbox.get(0).unwrap().title, "Updated");
+
+        assert_eq!(inbox.dismiss_remote(&mut storage, 7).unwrap().id, id);
+        assert_eq!(inbox.dismiss_remote(&mut storage, 7), None);
+        assert_eq!(inbox.dismiss(&mut storage, other).unwrap().title, "Other");
+        assert!(inbox.is_empty());
+    }
+}
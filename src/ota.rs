@@ -0,0 +1,672 @@
+//! Firmware updates, verified before being booted.
+//!
+//! An update is described by a JSON manifest giving the version of the
+//! firmware, the URL of its image, its size, its SHA-256 digest and the
+//! Ed25519 signature of that digest followed by the version, e.g.
+//!
+//! ```json
+//! {"version": "0.2.0", "url": "http://host/twatch.bin", "size": 1234567,
+//!  "sha256": "<64 hex digits>", "signature": "<128 hex digits>"}
+//! ```
+//!
+//! The image is written to the inactive slot while being received, and only
+//! booted once its digest and signature are verified with the key given at
+//! build time in `TWATCH_OTA_PUBLIC_KEY`. As the version is signed, an older
+//! firmware, signed as well, can't be passed off as a newer one. The flash is
+//! reached through the `Slot` trait, so this module does not depend on the
+//! ESP32 and is shared by all the update paths.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use sha2::{Digest, Sha256};
+
+use crate::json::{self, Value};
+use crate::timesync::Link;
+
+/// Hex encoded Ed25519 public key, updates are refused without it
+pub const PUBLIC_KEY: Option<&str> = option_env!("TWATCH_OTA_PUBLIC_KEY");
+/// URL of the manifest of the latest firmware
+pub const MANIFEST_URL: Option<&str> = option_env!("TWATCH_OTA_URL");
+/// Manifests are a few hundred bytes
+pub const MAX_MANIFEST_LEN: usize = 1024;
+/// Size of the OTA slots of `partitions.csv`
+pub const MAX_IMAGE_SIZE: usize = 0x40_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OtaError {
+    Json(json::JsonError),
+    Missing(&'static str),
+    Invalid(&'static str),
+    /// No public key was given at build time
+    NoKey,
+    TooLarge(usize),
+    Size {
+        expected: usize,
+        actual: usize,
+    },
+    Digest,
+    Signature,
+    /// The signed version is not later than the running one
+    NotNewer(String),
+    /// Another update is writing the slot
+    Busy,
+}
+
+impl std::fmt::Display for OtaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OtaError::Json(e) => write!(f, "Invalid manifest: {e}"),
+            OtaError::Missing(field) => write!(f, "Missing {field} in manifest"),
+            OtaError::Invalid(field) => write!(f, "Invalid {field} in manifest"),
+            OtaError::NoKey => write!(f, "No public key to verify updates"),
+            OtaError::TooLarge(size) => write!(f, "Image of {size} bytes too large"),
+            OtaError::Size { expected, actual } => {
+                write!(f, "Image of {actual} bytes instead of {expected}")
+            }
+            OtaError::Digest => write!(f, "Image digest mismatch"),
+            OtaError::Signature => write!(f, "Invalid image signature"),
+            OtaError::NotNewer(version) => write!(f, "Version {version} is not newer"),
+            OtaError::Busy => write!(f, "Another update is running"),
+        }
+    }
+}
+
+impl std::error::Error for OtaError {}
+
+/// Key used to verify the signature of the images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    pub fn from_hex(hex: &str) -> Result<Self, OtaError> {
+        parse_hex(hex).map(Self).ok_or(OtaError::NoKey)
+    }
+
+    /// The key given at build time
+    pub fn builtin() -> Result<Self, OtaError> {
+        Self::from_hex(PUBLIC_KEY.ok_or(OtaError::NoKey)?)
+    }
+}
+
+/// What is needed to check an image once received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub version: String,
+    pub size: usize,
+    pub sha256: [u8; 32],
+    /// Ed25519 signature of `sha256` followed by `version` in UTF-8
+    pub signature: [u8; 64],
+}
+
+impl ImageInfo {
+    /// Check the signature of the digest and version, and that the version is
+    /// later than `current`, the one running
+    ///
+    /// Done before receiving the image, and again once received.
+    pub fn authenticate(&self, key: &PublicKey, current: &str) -> Result<(), OtaError> {
+        let key = ed25519_compact::PublicKey::from_slice(&key.0).map_err(|_| OtaError::NoKey)?;
+        let signature = ed25519_compact::Signature::from_slice(&self.signature)
+            .map_err(|_| OtaError::Signature)?;
+        let mut message = self.sha256.to_vec();
+        message.extend_from_slice(self.version.as_bytes());
+        key.verify(&message, &signature)
+            .map_err(|_| OtaError::Signature)?;
+        if !is_newer(&self.version, current) {
+            return Err(OtaError::NotNewer(self.version.clone()));
+        }
+        Ok(())
+    }
+
+    /// Check the digest of a received image, then authenticate it
+    pub fn verify(
+        &self,
+        sha256: &[u8; 32],
+        key: &PublicKey,
+        current: &str,
+    ) -> Result<(), OtaError> {
+        if sha256 != &self.sha256 {
+            return Err(OtaError::Digest);
+        }
+        self.authenticate(key, current)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub url: String,
+    pub image: ImageInfo,
+}
+
+impl Manifest {
+    pub fn parse(body: &str) -> Result<Self, OtaError> {
+        let root = json::parse(body).map_err(OtaError::Json)?;
+        let field = |name: &'static str| root.get(name).ok_or(OtaError::Missing(name));
+        let string = |name: &'static str| {
+            field(name)?
+                .as_str()
+                .map(String::from)
+                .ok_or(OtaError::Invalid(name))
+        };
+
+        let size = field("size")?
+            .as_i64()
+            .and_then(|size| usize::try_from(size).ok())
+            .ok_or(OtaError::Invalid("size"))?;
+        if size > MAX_IMAGE_SIZE {
+            return Err(OtaError::TooLarge(size));
+        }
+        Ok(Self {
+            url: string("url")?,
+            image: ImageInfo {
+                version: string("version")?,
+                size,
+                sha256: hex_field(field("sha256")?).ok_or(OtaError::Invalid("sha256"))?,
+                signature: hex_field(field("signature")?).ok_or(OtaError::Invalid("signature"))?,
+            },
+        })
+    }
+}
+
+fn hex_field<const N: usize>(value: &Value) -> Option<[u8; N]> {
+    value.as_str().and_then(parse_hex)
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != 2 * N {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (byte, digits) in bytes.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Whether `candidate` is a later version than `current`, both made of
+/// numbers separated by dots, e.g. "0.2.1"
+///
+/// A suffix such as "-rc1" is ignored.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn numbers(version: &str) -> Vec<u32> {
+        let version = version.split(['-', '+']).next().unwrap_or_default();
+        version
+            .split('.')
+            .map(|n| n.trim().parse().unwrap_or(0))
+            .collect()
+    }
+    numbers(candidate) > numbers(current)
+}
+
+/// Inactive firmware slot, written while the image is received
+///
+/// Dropped without being activated or aborted, it keeps what was written for
+/// the update to be resumed.
+pub trait Slot {
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Boot the written image on the next restart, pending its confirmation
+    fn activate(&mut self) -> Result<()>;
+
+    /// Give up the update, the running firmware stays the one booted
+    fn abort(&mut self);
+}
+
+/// An image being written to a slot
+pub struct Image<S: Slot> {
+    info: ImageInfo,
+    slot: S,
+    hasher: Sha256,
+    written: usize,
+}
+
+impl<S: Slot> Image<S> {
+    pub fn new(info: ImageInfo, slot: S) -> Self {
+        Self {
+            info,
+            slot,
+            hasher: Sha256::new(),
+            written: 0,
+        }
+    }
+
+    pub fn info(&self) -> &ImageInfo {
+        &self.info
+    }
+
+    /// Bytes received so far
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    pub fn percent(&self) -> u8 {
+        (self.written * 100)
+            .checked_div(self.info.size)
+            .unwrap_or(100)
+            .min(100) as u8
+    }
+
+    /// Append the next bytes of the image
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        let actual = self.written + data.len();
+        if actual > self.info.size {
+            return Err(OtaError::Size {
+                expected: self.info.size,
+                actual,
+            }
+            .into());
+        }
+        self.slot.write(data)?;
+        self.hasher.update(data);
+        self.written = actual;
+        Ok(())
+    }
+
+    /// Verify the complete image and boot it on the next restart, `current`
+    /// being the version running
+    ///
+    /// The slot is left untouched when the verification fails.
+    pub fn finish(self, key: &PublicKey, current: &str) -> Result<()> {
+        let Self {
+            info,
+            mut slot,
+            hasher,
+            written,
+        } = self;
+        let result = if written != info.size {
+            Err(OtaError::Size {
+                expected: info.size,
+                actual: written,
+            })
+        } else {
+            info.verify(&hasher.finalize().into(), key, current)
+        };
+        match result {
+            Ok(()) => slot.activate(),
+            Err(e) => {
+                slot.abort();
+                Err(e.into())
+            }
+        }
+    }
+
+    pub fn abort(mut self) {
+        self.slot.abort();
+    }
+
+    /// Close the slot without giving up the update, to resume it later
+    pub fn release(self) -> Released {
+        Released {
+            info: self.info,
+            hasher: self.hasher,
+            written: self.written,
+        }
+    }
+}
+
+/// An image whose slot was released before it was complete, with what is
+/// needed to write the rest of it
+pub struct Released {
+    info: ImageInfo,
+    hasher: Sha256,
+    written: usize,
+}
+
+impl Released {
+    pub fn info(&self) -> &ImageInfo {
+        &self.info
+    }
+
+    /// Bytes received before the release
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Write the rest of the image to `slot`, opened at `written`
+    pub fn resume<S: Slot>(self, slot: S) -> Image<S> {
+        Image {
+            info: self.info,
+            slot,
+            hasher: self.hasher,
+            written: self.written,
+        }
+    }
+}
+
+/// Copy the whole image, calling `progress` with the percentage received each
+/// time it changes, then verify and activate it, `current` being the version
+/// running
+///
+/// `read` fills its buffer with the next bytes of the image, e.g. the body of
+/// an HTTP response, and returns their count, 0 at the end of the image.
+pub fn install<S: Slot>(
+    mut image: Image<S>,
+    mut read: impl FnMut(&mut [u8]) -> Result<usize>,
+    key: &PublicKey,
+    current: &str,
+    mut progress: impl FnMut(u8),
+) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let mut percent = image.percent();
+    loop {
+        let len = match read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) => {
+                image.abort();
+                return Err(e);
+            }
+        };
+        if let Err(e) = image.write(&buf[..len]) {
+            image.abort();
+            return Err(e);
+        }
+        if image.percent() != percent {
+            percent = image.percent();
+            progress(percent);
+        }
+    }
+    image.finish(key, current)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateStatus {
+    Idle,
+    Connecting,
+    Checking,
+    UpToDate,
+    /// Percentage of the image received
+    Downloading(u8),
+    /// The new firmware is booted on the next restart
+    Done {
+        version: String,
+    },
+    Failed(String),
+}
+
+impl UpdateStatus {
+    /// Whether the update is over, successfully or not
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            UpdateStatus::Idle
+                | UpdateStatus::UpToDate
+                | UpdateStatus::Done { .. }
+                | UpdateStatus::Failed(_)
+        )
+    }
+}
+
+impl std::fmt::Display for UpdateStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UpdateStatus::Idle => write!(f, "Idle"),
+            UpdateStatus::Connecting => write!(f, "Connecting..."),
+            UpdateStatus::Checking => write!(f, "Checking..."),
+            UpdateStatus::UpToDate => write!(f, "Up to date"),
+            UpdateStatus::Downloading(percent) => write!(f, "Downloading {percent}%"),
+            UpdateStatus::Done { version } => write!(f, "Restarting on {version}"),
+            UpdateStatus::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Update run in the background, which reports its progress
+pub trait Installer {
+    /// Check `url` for a manifest and install the image it describes
+    fn start(&mut self, url: &str) -> Result<()>;
+
+    fn report(&mut self, status: UpdateStatus);
+
+    /// Last status reported
+    fn status(&self) -> UpdateStatus;
+}
+
+/// Update over the network, brought up only for it
+pub struct UpdateService {
+    pub url: Option<String>,
+    /// Maximum duration of the connection
+    pub timeout: Duration,
+    connecting_since: Option<Duration>,
+    running: bool,
+    requested: bool,
+}
+
+impl Default for UpdateService {
+    fn default() -> Self {
+        Self {
+            url: None,
+            timeout: Duration::from_secs(30),
+            connecting_since: None,
+            running: false,
+            requested: false,
+        }
+    }
+}
+
+impl UpdateService {
+    /// Check for an update at the next poll
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Advance the update, `now` being a monotonic time
+    pub fn poll(&mut self, now: Duration, link: &mut impl Link, installer: &mut impl Installer) {
+        let url = match &self.url {
+            Some(url) => url,
+            None => {
+                if std::mem::take(&mut self.requested) {
+                    installer.report(UpdateStatus::Failed("No update URL".to_string()));
+                }
+                return;
+            }
+        };
+        if !self.running {
+            if !std::mem::take(&mut self.requested) {
+                return;
+            }
+            if let Err(e) = link.connect() {
+                installer.report(UpdateStatus::Failed(format!("{e}")));
+                return;
+            }
+            self.running = true;
+            self.connecting_since = Some(now);
+            installer.report(UpdateStatus::Connecting);
+            return;
+        }
+
+        let result = match self.connecting_since {
+            Some(_) if link.is_connected() => {
+                self.connecting_since = None;
+                installer.start(url)
+            }
+            Some(since) if now.saturating_sub(since) > self.timeout => {
+                Err(anyhow::anyhow!("Timeout connecting"))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            installer.report(UpdateStatus::Failed(format!("{e}")));
+        }
+        if installer.status().is_finished() {
+            self.running = false;
+            self.connecting_since = None;
+            if let Err(e) = link.disconnect() {
+                log::warn!("Unable to disconnect: {e:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION: &str = "0.2.0";
+
+    fn key_pair() -> ed25519_compact::KeyPair {
+        ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new([7; 32]))
+    }
+
+    fn public_key() -> PublicKey {
+        PublicKey(*key_pair().pk)
+    }
+
+    fn signed(data: &[u8], version: &str) -> ImageInfo {
+        let sha256: [u8; 32] = Sha256::digest(data).into();
+        let mut message = sha256.to_vec();
+        message.extend_from_slice(version.as_bytes());
+        ImageInfo {
+            version: version.to_string(),
+            size: data.len(),
+            sha256,
+            signature: *key_pair().sk.sign(message, None),
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeSlot {
+        data: Vec<u8>,
+        active: bool,
+        aborted: bool,
+    }
+
+    impl Slot for &mut FakeSlot {
+        fn write(&mut self, data: &[u8]) -> Result<()> {
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn activate(&mut self) -> Result<()> {
+            self.active = true;
+            Ok(())
+        }
+
+        fn abort(&mut self) {
+            self.aborted = true;
+        }
+    }
+
+    #[test]
+    fn authenticates_the_version() {
+        let info = signed(b"firmware", "0.3.0");
+        assert_eq!(info.authenticate(&public_key(), VERSION), Ok(()));
+
+        // Same image, but the version is changed
+        let mut forged = signed(b"firmware", "0.1.0");
+        forged.version = "0.3.0".to_string();
+        assert_eq!(
+            forged.authenticate(&public_key(), VERSION),
+            Err(OtaError::Signature)
+        );
+
+        let other_key = PublicKey([1; 32]);
+        assert!(info.authenticate(&other_key, VERSION).is_err());
+    }
+
+    #[test]
+    fn refuses_downgrades() {
+        for version in ["0.1.9", "0.2.0", "0.2.0-rc1"] {
+            let info = signed(b"firmware", version);
+            assert_eq!(
+                info.authenticate(&public_key(), VERSION),
+                Err(OtaError::NotNewer(version.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn compares_versions() {
+        assert!(is_newer("0.2.1", "0.2.0"));
+        assert!(is_newer("0.10.0", "0.9.9"));
+        assert!(is_newer("1.0", "0.9.9"));
+        assert!(!is_newer("0.2.0-rc2", "0.2.0"));
+        assert!(!is_newer("0.1.10", "0.2"));
+    }
+
+    #[test]
+    fn installs() {
+        let data = vec![0x5a; 3000];
+        let mut slot = FakeSlot::default();
+        let mut progress = vec![];
+        let mut remaining = &data[..];
+        let read = |buf: &mut [u8]| {
+            let len = remaining.len().min(buf.len());
+            buf[..len].copy_from_slice(&remaining[..len]);
+            remaining = &remaining[len..];
+            Ok(len)
+        };
+        let image = Image::new(signed(&data, "0.3.0"), &mut slot);
+        install(image, read, &public_key(), VERSION, |p| progress.push(p)).unwrap();
+        assert_eq!(progress, [34, 68, 100]);
+        assert_eq!(slot.data, data);
+        assert!(slot.active && !slot.aborted);
+    }
+
+    #[test]
+    fn aborts_corrupted_images() {
+        let info = signed(b"firmware", "0.3.0");
+        let mut slot = FakeSlot::default();
+        let mut image = Image::new(info.clone(), &mut slot);
+        image.write(b"firmwarf").unwrap();
+        let e = image.finish(&public_key(), VERSION).unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&OtaError::Digest));
+        assert!(!slot.active && slot.aborted);
+
+        let mut slot = FakeSlot::default();
+        let mut image = Image::new(info, &mut slot);
+        assert!(image.write(b"firmware!").is_err());
+        image.write(b"firm").unwrap();
+        let e = image.finish(&public_key(), VERSION).unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(OtaError::Size { .. })));
+        assert!(slot.aborted);
+    }
+
+    #[test]
+    fn resumes_released_images() {
+        let info = signed(b"firmware", "0.3.0");
+        let mut first = FakeSlot::default();
+        let mut image = Image::new(info.clone(), &mut first);
+        image.write(b"firm").unwrap();
+        let released = image.release();
+        assert_eq!(released.written(), 4);
+        assert_eq!(released.info(), &info);
+        assert!(!first.aborted);
+
+        let mut second = FakeSlot::default();
+        let mut image = released.resume(&mut second);
+        assert_eq!(image.percent(), 50);
+        image.write(b"ware").unwrap();
+        image.finish(&public_key(), VERSION).unwrap();
+        assert_eq!(second.data, b"ware");
+        assert!(second.active);
+    }
+
+    #[test]
+    fn parses_manifests() {
+        let info = signed(b"firmware", "0.3.0");
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let body = format!(
+            r#"{{"version": "0.3.0", "url": "http://host/twatch.bin", "size": 8,
+                "sha256": "{}", "signature": "{}"}}"#,
+            hex(&info.sha256),
+            hex(&info.signature)
+        );
+        let manifest = Manifest::parse(&body).unwrap();
+        assert_eq!(manifest.url, "http://host/twatch.bin");
+        assert_eq!(manifest.image, info);
+
+        let large = body.replace("\"size\": 8", "\"size\": 99999999");
+        assert_eq!(Manifest::parse(&large), Err(OtaError::TooLarge(99999999)));
+        let short = body.replace(&hex(&info.sha256), "abcd");
+        assert_eq!(Manifest::parse(&short), Err(OtaError::Invalid("sha256")));
+        let missing = body.replace("\"url\"", "\"link\"");
+        assert_eq!(Manifest::parse(&missing), Err(OtaError::Missing("url")));
+    }
+}
@@ -0,0 +1,148 @@
+//! Panic handler and boot checks, see `crash` for the reports.
+//!
+//! A panic is drawn on the display when it is not in use, and kept in RTC
+//! memory with the last log lines before the reset. The next boot makes them into a
+//! report, and counts the crashes in a row to fall back to safe mode.
+
+use std::ptr::{addr_of_mut, null_mut};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use esp_idf_sys::*;
+
+use crate::crash::{BootRecord, CrashReport, LogRing, MAX_CRASHES};
+use crate::display::TwatchDisplay;
+use crate::utils;
+
+/// Kept across resets, the loader leaves `.rtc_noinit` as it is
+#[link_section = ".rtc_noinit"]
+static mut BOOT_RECORD: BootRecord = BootRecord::new();
+#[link_section = ".rtc_noinit"]
+static mut LOG_RING: LogRing = LogRing::new();
+static LOG_RING_LOCK: Mutex<()> = Mutex::new(());
+
+/// Display drawn on by the panic handler, see `set_display`
+static DISPLAY: AtomicPtr<TwatchDisplay> = AtomicPtr::new(null_mut());
+/// Held while the display is in use, see `lock_display`
+static DISPLAY_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct Boot {
+    /// Crashes in a row, the reset before this boot included
+    pub crashes: u32,
+    /// Crash which caused the reset before this boot
+    pub report: Option<CrashReport>,
+}
+
+impl Boot {
+    /// Start without Wi-Fi nor BLE, as the firmware keeps crashing
+    pub fn safe_mode(&self) -> bool {
+        self.crashes >= MAX_CRASHES
+    }
+}
+
+/// Cause of the last reset, when it was a crash
+#[allow(non_upper_case_globals)]
+fn crash_reason() -> Option<&'static str> {
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_PANIC => Some("panic"),
+        esp_reset_reason_t_ESP_RST_INT_WDT => Some("interrupt watchdog"),
+        esp_reset_reason_t_ESP_RST_TASK_WDT => Some("task watchdog"),
+        esp_reset_reason_t_ESP_RST_WDT => Some("watchdog"),
+        esp_reset_reason_t_ESP_RST_BROWNOUT => Some("brownout"),
+        _ => None,
+    }
+}
+
+/// Count this boot and take the report of the crash before it, to be called
+/// before the logger overwrites the lines logged before the reset
+pub fn boot() -> Boot {
+    let reason = crash_reason();
+    let _guard = LOG_RING_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let (record, ring) = unsafe {
+        (
+            &mut *addr_of_mut!(BOOT_RECORD),
+            &mut *addr_of_mut!(LOG_RING),
+        )
+    };
+    ring.validate();
+    let crashes = record.boot(reason.is_some());
+    let panic = record.take_panic();
+    let report = reason.map(|reason| {
+        let (message, location, uptime) = panic.unwrap_or_default();
+        CrashReport {
+            reason: reason.to_string(),
+            message,
+            location,
+            uptime,
+            log: ring.lines(),
+        }
+    });
+    Boot { crashes, report }
+}
+
+/// The firmware ran long enough, a crash doesn't make a crash loop anymore
+pub fn stable() {
+    unsafe { (*addr_of_mut!(BOOT_RECORD)).stable() };
+}
+
+/// Keep a line for the report of the next crash
+pub fn log_line(line: &str) {
+    let _guard = LOG_RING_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    unsafe { (*addr_of_mut!(LOG_RING)).push(line) };
+}
+
+/// Let the panic handler draw on `display` while it is not locked
+///
+/// # Safety
+///
+/// `display` must stay at this address for the rest of the program, e.g. in a
+/// `Box` which is never dropped, and only be used with `lock_display` held.
+pub unsafe fn set_display(display: *mut TwatchDisplay) {
+    DISPLAY.store(display, Ordering::Release);
+}
+
+/// Hold while using the display given to `set_display`
+///
+/// A panic meanwhile is only kept for the next boot: the display is borrowed
+/// and drawing on it would alias the borrow.
+pub fn lock_display() -> MutexGuard<'static, ()> {
+    DISPLAY_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Keep the panics in RTC memory and draw them, before the default handler
+/// prints them and the system resets
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_default(),
+        };
+        let location = info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line()))
+            .unwrap_or_default();
+        let uptime = utils::uptime();
+        unsafe { (*addr_of_mut!(BOOT_RECORD)).store_panic(&message, &location, uptime) };
+
+        default_hook(info);
+
+        // Fails from the task holding it as well
+        let _guard = match DISPLAY_LOCK.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        // Taken, a panic while drawing doesn't draw again. Not logged, the
+        // panic may come from the logger.
+        let display = DISPLAY.swap(null_mut(), Ordering::AcqRel);
+        if let Some(display) = unsafe { display.as_mut() } {
+            if let Err(e) = display.draw_panic(&message, &location) {
+                println!("Unable to draw panic: {e:?}");
+            }
+        }
+    }));
+}
@@ -1,11 +1,39 @@
+use std::time::Duration;
+
 use anyhow::Result;
 
+use embedded_hal_0_2::blocking::i2c::{Write, WriteRead};
 use esp_idf_hal::delay;
 
+use log::*;
+
+use crate::twatch::{Device, TwatchError};
 use crate::types::EspSharedBusI2c0;
 
+const AXP202_ADDRESS: u8 = 0x35;
+
+/// AXP202 registers not covered by the `axp20x` driver
+mod reg {
+    pub const ADC_ENABLE1: u8 = 0x82;
+    pub const ADC_SPEED: u8 = 0x84;
+    pub const BATT_CHARGE_CURRENT_H: u8 = 0x7A;
+    pub const BATT_DISCHARGE_CURRENT_H: u8 = 0x7C;
+    pub const COULOMB_CHARGE: u8 = 0xB0;
+    pub const COULOMB_DISCHARGE: u8 = 0xB4;
+    pub const COULOMB_CONTROL: u8 = 0xB8;
+}
+
+const ADC_BATT_VOLTAGE: u8 = 1 << 7;
+const ADC_BATT_CURRENT: u8 = 1 << 6;
+
+const COULOMB_ENABLE: u8 = 1 << 7;
+const COULOMB_CLEAR: u8 = 1 << 5;
+
 pub struct Pmu<'a> {
     axp20x: axp20x::Axpxx<EspSharedBusI2c0<'a>>,
+    registers: EspSharedBusI2c0<'a>,
+    rails: [RailState; Rail::COUNT],
+    coulomb_reset_at: Duration,
 }
 
 #[allow(dead_code)]
@@ -24,53 +52,194 @@ impl From<State> for axp20x::PowerState {
     }
 }
 
+/// Power outputs of the AXP202 which can be switched by the firmware.
+///
+/// DCDC3 feeds the ESP32 itself and is deliberately not part of this list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rail {
+    Ldo2,
+    Ldo3,
+    Ldo4,
+    DcDc2,
+    Exten,
+}
+
+impl Rail {
+    pub const COUNT: usize = 5;
+
+    pub const ALL: [Rail; Rail::COUNT] = [
+        Rail::Ldo2,
+        Rail::Ldo3,
+        Rail::Ldo4,
+        Rail::DcDc2,
+        Rail::Exten,
+    ];
+
+    /// Alias for the rail powering the screen backlight
+    pub const BACKLIGHT: Rail = Rail::Ldo2;
+
+    /// Peripheral wired on this rail on the T-Watch 2020
+    pub fn peripheral(&self) -> &'static str {
+        match self {
+            Rail::Ldo2 => "backlight",
+            Rail::Ldo3 => "audio amp",
+            Rail::Ldo4 => "GPS (exp.)",
+            Rail::DcDc2 => "LoRa (exp.)",
+            Rail::Exten => "expansion",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl From<Rail> for axp20x::Power {
+    fn from(rail: Rail) -> Self {
+        match rail {
+            Rail::Ldo2 => axp20x::Power::Ldo2,
+            Rail::Ldo3 => axp20x::Power::Ldo3,
+            Rail::Ldo4 => axp20x::Power::Ldo4,
+            Rail::DcDc2 => axp20x::Power::DcDc2,
+            Rail::Exten => axp20x::Power::Exten,
+        }
+    }
+}
+
+impl std::fmt::Display for Rail {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!("{:?}", self))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RailState {
+    pub rail: Rail,
+    pub state: State,
+    pub users: u8,
+}
+
+impl std::fmt::Display for RailState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{:<6}{:<4}x{} {}",
+            self.rail,
+            match self.state {
+                State::On => "on",
+                State::Off => "off",
+            },
+            self.users,
+            self.rail.peripheral()
+        ))
+    }
+}
+
+/// Battery consumption measured by the coulomb counter since its last reset
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Consumption {
+    pub charged_mah: f32,
+    pub discharged_mah: f32,
+    /// Average current drawn from the battery, negative while charging
+    pub average_ma: f32,
+    pub elapsed: Duration,
+}
+
 impl Pmu<'static> {
-    pub fn new(i2c: EspSharedBusI2c0<'static>) -> Self {
+    /// `registers` is a second proxy on the same bus, used for the registers
+    /// the `axp20x` driver does not expose
+    pub fn new(i2c: EspSharedBusI2c0<'static>, registers: EspSharedBusI2c0<'static>) -> Self {
         Self {
             axp20x: axp20x::Axpxx::new(i2c),
+            registers,
+            coulomb_reset_at: Duration::ZERO,
+            rails: Rail::ALL.map(|rail| RailState {
+                rail,
+                state: State::Off,
+                users: 0,
+            }),
         }
     }
 
     pub fn init(&mut self) -> Result<()> {
-        self.axp20x.init()?;
-
-        self.axp20x
-            .set_power_output(
-                axp20x::Power::Exten,
-                axp20x::PowerState::Off,
-                &mut delay::Ets,
-            )
-            .map_err(crate::twatch::TwatchError::from)?;
         self.axp20x
-            .set_power_output(
-                axp20x::Power::DcDc2,
-                axp20x::PowerState::Off,
-                &mut delay::Ets,
-            )
-            .map_err(crate::twatch::TwatchError::from)?;
-        self.axp20x
-            .set_power_output(
-                axp20x::Power::Ldo4,
-                axp20x::PowerState::Off,
-                &mut delay::Ets,
-            )
-            .map_err(crate::twatch::TwatchError::from)?;
+            .init()
+            .map_err(TwatchError::driver(Device::Pmu, "init"))?;
+
+        for rail in Rail::ALL {
+            self.switch_rail(rail, State::Off)?;
+        }
 
         self.init_irq()?;
+        self.init_coulomb_counter()?;
         Ok(())
     }
 
-    pub fn set_screen_power(&mut self, state: State) -> Result<()> {
+    /// Initialize the AXP202 again after a recovery of its bus, keeping the
+    /// power rails as they are
+    pub fn reinit(&mut self) -> Result<()> {
         self.axp20x
-            .set_power_output(axp20x::Power::Ldo2, state.into(), &mut delay::Ets)
-            .map_err(crate::twatch::TwatchError::from)?;
+            .init()
+            .map_err(TwatchError::driver(Device::Pmu, "init"))?;
+
+        for rail_state in self.rails {
+            self.switch_rail(rail_state.rail, rail_state.state)?;
+        }
+
+        self.init_irq()
+    }
+
+    /// Take a reference on `rail`, switching it on for the first user
+    pub fn acquire(&mut self, rail: Rail) -> Result<()> {
+        if self.rails[rail.index()].users == 0 {
+            self.switch_rail(rail, State::On)?;
+        }
+        let rail_state = &mut self.rails[rail.index()];
+        rail_state.users = rail_state.users.saturating_add(1);
         Ok(())
     }
 
-    pub fn set_audio_power(&mut self, state: State) -> Result<()> {
+    /// Drop a reference on `rail`, switching it off when the last user releases it
+    pub fn release(&mut self, rail: Rail) -> Result<()> {
+        match self.rails[rail.index()].users {
+            0 => {
+                warn!("Release of unused power rail {rail}");
+                Ok(())
+            }
+            1 => {
+                self.switch_rail(rail, State::Off)?;
+                self.rails[rail.index()].users = 0;
+                Ok(())
+            }
+            _ => {
+                self.rails[rail.index()].users -= 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Switch every rail off regardless of its users, e.g. before deep sleep
+    pub fn power_off_rails(&mut self) -> Result<()> {
+        for rail in Rail::ALL {
+            self.switch_rail(rail, State::Off)?;
+            self.rails[rail.index()].users = 0;
+        }
+        Ok(())
+    }
+
+    pub fn rail_state(&self, rail: Rail) -> RailState {
+        self.rails[rail.index()]
+    }
+
+    pub fn rail_states(&self) -> [RailState; Rail::COUNT] {
+        self.rails
+    }
+
+    fn switch_rail(&mut self, rail: Rail, state: State) -> Result<()> {
+        debug!("Switching power rail {rail} ({}) {state:?}", rail.peripheral());
         self.axp20x
-            .set_power_output(axp20x::Power::Ldo3, state.into(), &mut delay::Ets)
-            .map_err(crate::twatch::TwatchError::from)?;
+            .set_power_output(rail.into(), state.into(), &mut delay::Ets)
+            .map_err(TwatchError::driver(Device::Pmu, "set power output"))?;
+        self.rails[rail.index()].state = state;
         Ok(())
     }
 
@@ -82,31 +251,119 @@ impl Pmu<'static> {
             .map(|irq| irq.intersects(axp20x::EventsIrq::PowerKeyShortPress))
         {
             Ok(true) => {
-                self.axp20x.clear_irq()?;
+                self.clear_irq()?;
                 Ok(true)
             }
             Ok(false) => Ok(false),
-            Err(e) => Err(e.into()),
+            Err(e) => Err(TwatchError::driver(Device::Pmu, "read irq")(e).into()),
         }
     }
 
     pub fn init_irq(&mut self) -> Result<()> {
         self.axp20x
-            .toggle_irq(axp20x::EventsIrq::PowerKeyShortPress, true)?;
+            .toggle_irq(axp20x::EventsIrq::PowerKeyShortPress, true)
+            .map_err(TwatchError::driver(Device::Pmu, "toggle irq"))?;
 
-        self.axp20x.clear_irq()?;
+        self.clear_irq()
+    }
 
+    fn clear_irq(&mut self) -> Result<()> {
+        self.axp20x
+            .clear_irq()
+            .map_err(TwatchError::driver(Device::Pmu, "clear irq"))?;
+        Ok(())
+    }
+
+    fn init_coulomb_counter(&mut self) -> Result<()> {
+        let adc = self.read_register(reg::ADC_ENABLE1)?;
+        self.write_register(reg::ADC_ENABLE1, adc | ADC_BATT_VOLTAGE | ADC_BATT_CURRENT)?;
+        self.write_register(reg::COULOMB_CONTROL, COULOMB_ENABLE)?;
+        self.reset_coulomb_counter()
+    }
+
+    pub fn reset_coulomb_counter(&mut self) -> Result<()> {
+        self.write_register(reg::COULOMB_CONTROL, COULOMB_ENABLE | COULOMB_CLEAR)?;
+        self.coulomb_reset_at = crate::utils::uptime();
+        Ok(())
+    }
+
+    /// Instantaneous battery current in mA, positive when discharging
+    pub fn get_battery_current(&mut self) -> Result<f32> {
+        let mut buf = [0u8; 2];
+        self.read_registers(reg::BATT_DISCHARGE_CURRENT_H, &mut buf)?;
+        let discharge = ((buf[0] as u16) << 5) | (buf[1] & 0x1F) as u16;
+        self.read_registers(reg::BATT_CHARGE_CURRENT_H, &mut buf)?;
+        let charge = ((buf[0] as u16) << 4) | (buf[1] & 0x0F) as u16;
+        Ok((discharge as f32 - charge as f32) * 0.5)
+    }
+
+    pub fn get_consumption(&mut self) -> Result<Consumption> {
+        let mut buf = [0u8; 4];
+        self.read_registers(reg::COULOMB_CHARGE, &mut buf)?;
+        let charged = u32::from_be_bytes(buf);
+        self.read_registers(reg::COULOMB_DISCHARGE, &mut buf)?;
+        let discharged = u32::from_be_bytes(buf);
+
+        // The counters accumulate the current once per ADC sample, at 25Hz * 2^n
+        let rate = 25.0 * (1 << (self.read_register(reg::ADC_SPEED)? >> 6)) as f32;
+        let to_mah = |count: u32| 65536.0 * 0.5 * count as f32 / 3600.0 / rate;
+
+        let charged_mah = to_mah(charged);
+        let discharged_mah = to_mah(discharged);
+        let elapsed = crate::utils::uptime().saturating_sub(self.coulomb_reset_at);
+        let hours = elapsed.as_secs_f32() / 3600.0;
+        let average_ma = if hours > 0.0 {
+            (discharged_mah - charged_mah) / hours
+        } else {
+            0.0
+        };
+
+        Ok(Consumption {
+            charged_mah,
+            discharged_mah,
+            average_ma,
+            elapsed,
+        })
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_registers(register, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_registers(&mut self, register: u8, buf: &mut [u8]) -> Result<()> {
+        self.registers
+            .write_read(AXP202_ADDRESS, &[register], buf)
+            .map_err(TwatchError::i2c(Device::Pmu, "read", Some(register)))?;
+        Ok(())
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<()> {
+        self.registers
+            .write(AXP202_ADDRESS, &[register, value])
+            .map_err(TwatchError::i2c(Device::Pmu, "write", Some(register)))?;
         Ok(())
     }
 
     pub fn get_battery_percentage(&mut self) -> Result<f32> {
-        if self.axp20x.is_battery_charging()? {
-            let percent = self.axp20x.get_battery_percentage()?;
+        let charging = self
+            .axp20x
+            .is_battery_charging()
+            .map_err(TwatchError::driver(Device::Pmu, "is battery charging"))?;
+        if charging {
+            let percent = self
+                .axp20x
+                .get_battery_percentage()
+                .map_err(TwatchError::driver(Device::Pmu, "get battery percentage"))?;
             if percent != 0x7F {
                 return Ok(percent as f32);
             }
         }
-        let voltage = self.axp20x.get_battery_voltage()?;
+        let voltage = self
+            .axp20x
+            .get_battery_voltage()
+            .map_err(TwatchError::driver(Device::Pmu, "get battery voltage"))?;
         let level: f32 = ((voltage as f32 - 3200.0) * 100.0) / 1000.0;
         if level < 0.0 {
             Ok(0.0)
@@ -0,0 +1,127 @@
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::http::server::registry::Registry;
+use embedded_svc::http::server::{Request, Response};
+use embedded_svc::io::Read;
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+
+use log::*;
+
+use crate::provisioning::{self, Submission};
+
+/// Web server and captive DNS of the provisioning access point
+pub struct Portal {
+    _server: EspHttpServer,
+    submission: Arc<Mutex<Option<Submission>>>,
+    dns_running: Arc<AtomicBool>,
+    dns_thread: Option<JoinHandle<()>>,
+}
+
+impl Portal {
+    pub fn start(ip: Ipv4Addr, current_tz: &str) -> Result<Self> {
+        let submission = Arc::new(Mutex::new(None));
+        let mut server = EspHttpServer::new(&Configuration::default())?;
+
+        let page = provisioning::render_page(None, current_tz);
+        // Paths probed by Android and Apple devices to detect captive portals
+        for uri in ["/", "/generate_204", "/hotspot-detect.html"] {
+            let page = page.clone();
+            server.handle_get(uri, move |_req, resp| {
+                resp.send_str(&page)?;
+                Ok(())
+            })?;
+        }
+
+        let form_submission = submission.clone();
+        let current_tz = current_tz.to_string();
+        server.handle_post("/", move |mut req, resp| {
+            let mut body = Vec::new();
+            let mut buf = [0u8; 128];
+            let mut reader = req.reader();
+            // Past the limit the rest of the body is not read, the form is refused anyway
+            while body.len() <= provisioning::MAX_BODY_LEN {
+                let len = reader.read(&mut buf)?;
+                if len == 0 {
+                    break;
+                }
+                body.extend_from_slice(&buf[..len]);
+            }
+            let result = provisioning::parse_body(&body);
+            let message = match result {
+                Ok(submission) => {
+                    let message = format!("Saved, the watch now connects to {}", submission.ssid);
+                    *form_submission.lock().unwrap() = Some(submission);
+                    message
+                }
+                Err(e) => {
+                    warn!("Invalid provisioning form: {e}");
+                    e.to_string()
+                }
+            };
+            resp.send_str(&provisioning::render_page(Some(&message), &current_tz))?;
+            Ok(())
+        })?;
+
+        let dns_running = Arc::new(AtomicBool::new(true));
+        let running = dns_running.clone();
+        let dns_thread = thread::Builder::new()
+            .name("captive-dns".to_string())
+            .stack_size(4096)
+            .spawn(move || {
+                if let Err(e) = serve_dns(ip, &running) {
+                    warn!("Captive DNS stopped: {e:?}");
+                }
+            })?;
+
+        info!("Provisioning portal started on http://{ip}");
+        Ok(Self {
+            _server: server,
+            submission,
+            dns_running,
+            dns_thread: Some(dns_thread),
+        })
+    }
+
+    pub fn take_submission(&self) -> Option<Submission> {
+        self.submission.lock().unwrap().take()
+    }
+}
+
+impl Drop for Portal {
+    fn drop(&mut self) {
+        self.dns_running.store(false, Ordering::Relaxed);
+        if let Some(dns_thread) = self.dns_thread.take() {
+            let _ = dns_thread.join();
+        }
+        info!("Provisioning portal stopped");
+    }
+}
+
+/// Resolve every name to the portal address until `running` is cleared
+fn serve_dns(ip: Ipv4Addr, running: &AtomicBool) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 53))?;
+    socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+    let mut buf = [0u8; 512];
+    while running.load(Ordering::Relaxed) {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(response) = provisioning::dns_response(&buf[..len], ip) {
+            socket.send_to(&response, from)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! Wi-Fi provisioning form served by the SoftAP portal.
+//!
+//! Parsing and validation of the submitted form, rendering of the page and
+//! answers of the captive DNS are plain functions, the HTTP server and the
+//! access point living in `portal`.
+
+use std::net::Ipv4Addr;
+
+use crate::tz::TimeZone;
+
+/// Larger bodies can't come from the provisioning form
+pub const MAX_BODY_LEN: usize = 1024;
+
+/// Credentials and settings submitted through the form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submission {
+    pub ssid: String,
+    pub password: String,
+    /// Time zone to configure, `None` to keep the current one
+    pub tz: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormError {
+    Malformed,
+    TooLarge,
+    MissingSsid,
+    SsidTooLong,
+    /// WPA2 passphrases are 8 to 63 characters, or 64 hex digits
+    InvalidPassword,
+    UnknownTimeZone(String),
+}
+
+impl std::fmt::Display for FormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FormError::Malformed => f.write_str("Malformed form"),
+            FormError::TooLarge => f.write_str("The form is too large"),
+            FormError::MissingSsid => f.write_str("The network name is required"),
+            FormError::SsidTooLong => f.write_str("The network name is limited to 32 bytes"),
+            FormError::InvalidPassword => {
+                f.write_str("The password must be empty or 8 to 63 characters long")
+            }
+            FormError::UnknownTimeZone(tz) => f.write_fmt(format_args!("Unknown time zone {tz}")),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+/// Decode an `application/x-www-form-urlencoded` component
+pub fn url_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Parse and validate the body of the form
+pub fn parse_form(body: &str) -> Result<Submission, FormError> {
+    let mut ssid = None;
+    let mut password = String::new();
+    let mut tz = None;
+    for pair in body.trim_end().split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = url_decode(value).ok_or(FormError::Malformed)?;
+        match key {
+            "ssid" => ssid = Some(value),
+            "password" => password = value,
+            "tz" => tz = Some(value),
+            _ => {}
+        }
+    }
+
+    let ssid = ssid.ok_or(FormError::MissingSsid)?;
+    // Leading and trailing spaces are almost always typing mistakes
+    let ssid = ssid.trim().to_string();
+    if ssid.is_empty() {
+        return Err(FormError::MissingSsid);
+    }
+    if ssid.len() > 32 {
+        return Err(FormError::SsidTooLong);
+    }
+
+    let psk = password.len() == 64 && password.bytes().all(|b| b.is_ascii_hexdigit());
+    if !password.is_empty() && !(8..=63).contains(&password.len()) && !psk {
+        return Err(FormError::InvalidPassword);
+    }
+
+    let tz = match tz.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(tz) => match TimeZone::new(tz) {
+            Ok(zone) => Some(zone.id),
+            Err(_) => return Err(FormError::UnknownTimeZone(tz.to_string())),
+        },
+    };
+
+    Ok(Submission { ssid, password, tz })
+}
+
+/// Parse and validate the raw body of the form, refusing it past `MAX_BODY_LEN`
+pub fn parse_body(body: &[u8]) -> Result<Submission, FormError> {
+    if body.len() > MAX_BODY_LEN {
+        return Err(FormError::TooLarge);
+    }
+    let body = std::str::from_utf8(body).map_err(|_| FormError::Malformed)?;
+    parse_form(body)
+}
+
+/// Escape text inserted in the HTML page
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the form, with a message after a submission
+pub fn render_page(message: Option<&str>, current_tz: &str) -> String {
+    let mut zones = String::from("<option value=\"\">Keep current</option>");
+    for zone in TimeZone::names() {
+        let selected = if zone == current_tz { " selected" } else { "" };
+        zones.push_str(&format!("<option{selected}>{zone}</option>"));
+    }
+    let message = message
+        .map(|message| format!("<p><b>{}</b></p>", escape_html(message)))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\">\
+         <title>T-Watch setup</title></head><body>\
+         <h1>T-Watch setup</h1>{message}\
+         <form method=\"post\" action=\"/\">\
+         <p><label>Network <input name=\"ssid\" maxlength=\"32\" required></label></p>\
+         <p><label>Password <input name=\"password\" type=\"password\" maxlength=\"64\">\
+         </label></p>\
+         <p><label>Time zone <select name=\"tz\">{zones}</select></label></p>\
+         <p><input type=\"submit\" value=\"Save\"></p>\
+         </form></body></html>"
+    )
+}
+
+/// Answer a DNS query for any A record with `ip`, so that clients open the portal
+pub fn dns_response(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    const HEADER_LEN: usize = 12;
+    if query.len() <= HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    // Only standard queries with a single question are answered
+    if flags & 0x8000 != 0 || (flags >> 11) & 0xf != 0 || questions != 1 {
+        return None;
+    }
+
+    // Question name, as a sequence of labels ending with an empty one
+    let mut end = HEADER_LEN;
+    loop {
+        let len = *query.get(end)? as usize;
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        end += 1 + len;
+        if len == 0 {
+            break;
+        }
+    }
+    let qtype = u16::from_be_bytes([*query.get(end)?, *query.get(end + 1)?]);
+    end += 4;
+    if end > query.len() {
+        return None;
+    }
+
+    let mut response = query[..end].to_vec();
+    // Response, recursion desired copied, recursion available
+    response[2] = 0x80 | (query[2] & 0x01);
+    response[3] = 0x80;
+    // No authority nor additional records
+    response[8..12].fill(0);
+    if qtype == 1 {
+        response[6..8].copy_from_slice(&1u16.to_be_bytes());
+        // Pointer to the question name, type A, class IN, TTL 60s
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0, 0, 0, 60, 0, 4]);
+        response.extend_from_slice(&ip.octets());
+    } else {
+        response[6..8].fill(0);
+    }
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_components() {
+        assert_eq!(url_decode("My+Wi-Fi%21").as_deref(), Some("My Wi-Fi!"));
+        assert_eq!(url_decode("caf%C3%A9").as_deref(), Some("café"));
+        assert_eq!(url_decode("100%"), None);
+        assert_eq!(url_decode("%zz"), None);
+        assert_eq!(url_decode("%ff"), None);
+    }
+
+    #[test]
+    fn parses_the_form() {
+        assert_eq!(
+            parse_form("ssid=+Home+&password=secret%2B42&tz=europe%2Flondon\r\n"),
+            Ok(Submission {
+                ssid: "Home".to_string(),
+                password: "secret+42".to_string(),
+                tz: Some("Europe/London".to_string()),
+            })
+        );
+        // Open network, current time zone kept
+        assert_eq!(
+            parse_form("ssid=Cafe&password=&tz=&extra=1"),
+            Ok(Submission {
+                ssid: "Cafe".to_string(),
+                password: String::new(),
+                tz: None,
+            })
+        );
+        let psk = "0123456789abcdef".repeat(4);
+        assert_eq!(
+            parse_form(&format!("ssid=Home&password={psk}"))
+                .unwrap()
+                .password,
+            psk
+        );
+    }
+
+    #[test]
+    fn validates_the_form() {
+        assert_eq!(parse_form("password=secret42"), Err(FormError::MissingSsid));
+        assert_eq!(parse_form("ssid=+++"), Err(FormError::MissingSsid));
+        let ssid = "a".repeat(33);
+        assert_eq!(
+            parse_form(&format!("ssid={ssid}")),
+            Err(FormError::SsidTooLong)
+        );
+        assert_eq!(
+            parse_form("ssid=Home&password=short"),
+            Err(FormError::InvalidPassword)
+        );
+        let password = "z".repeat(64);
+        assert_eq!(
+            parse_form(&format!("ssid=Home&password={password}")),
+            Err(FormError::InvalidPassword)
+        );
+        assert_eq!(
+            parse_form("ssid=Home&tz=Mars%2FOlympus"),
+            Err(FormError::UnknownTimeZone("Mars/Olympus".to_string()))
+        );
+        assert_eq!(parse_form("ssid=Home%2"), Err(FormError::Malformed));
+    }
+
+    #[test]
+    fn refuses_large_or_invalid_bodies() {
+        assert_eq!(parse_body(b"ssid=Home").unwrap().ssid, "Home");
+        assert_eq!(parse_body(b"ssid=Home\xff"), Err(FormError::Malformed));
+        // Truncating would parse a valid but different form
+        let mut body = b"ssid=Home&password=".to_vec();
+        body.resize(MAX_BODY_LEN + 1, b'a');
+        assert_eq!(parse_body(&body), Err(FormError::TooLarge));
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_html("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn answers_dns_queries() {
+        let ip = Ipv4Addr::new(192, 168, 71, 1);
+        // Query for example.com, type A, class IN
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        let response = dns_response(&query, ip).unwrap();
+        assert_eq!(&response[..4], &[0x12, 0x34, 0x81, 0x80]);
+        assert_eq!(&response[4..8], &[0, 1, 0, 1]);
+        assert_eq!(&response[12..query.len()], &query[12..]);
+        assert_eq!(&response[response.len() - 4..], &ip.octets());
+
+        // No answer for other types
+        let len = query.len();
+        query[len - 3] = 28;
+        let response = dns_response(&query, ip).unwrap();
+        assert_eq!(&response[6..8], &[0, 0]);
+        assert_eq!(response.len(), query.len());
+
+        // Responses and truncated queries are ignored
+        query[2] = 0x81;
+        assert_eq!(dns_response(&query, ip), None);
+        query[2] = 0x01;
+        assert_eq!(dns_response(&query[..len - 2], ip), None);
+    }
+}
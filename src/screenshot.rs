@@ -0,0 +1,156 @@
+//! Screenshots of the framebuffer, sent over the serial console and decoded
+//! by `tools/screenshot.py`.
+//!
+//! The screen is sent as one frame per row, so that a corrupted row can be
+//! told apart from the logs around it. All integers are little-endian:
+//! - `TWSS` magic
+//! - width, height and index of the row (u16 each)
+//! - length of the pixels (u16) and the pixels
+//! - CRC-32 of the frame from the width to the pixels (u32)
+//!
+//! The RGB565 pixels are compressed by runs: a byte `n` below 128 is followed
+//! by `n + 1` different pixels, a byte `n` from 128 by a pixel repeated
+//! `n - 126` times.
+
+use crate::utils::crc32;
+
+pub const MAGIC: &[u8; 4] = b"TWSS";
+
+const MAX_LITERALS: usize = 128;
+const MAX_RUN: usize = 129;
+
+/// Compress a row of pixels, `value` giving their RGB565 value
+fn compress<P: Copy + PartialEq>(pixels: &[P], value: impl Fn(P) -> u16, out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < pixels.len() {
+        let run = pixels[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&pixel| pixel == pixels[i])
+            .count();
+        if run >= 2 {
+            out.push((run + 126) as u8);
+            out.extend_from_slice(&value(pixels[i]).to_le_bytes());
+            i += run;
+            continue;
+        }
+
+        // Up to the next run
+        let start = i;
+        while i < pixels.len() && i - start < MAX_LITERALS && pixels.get(i + 1) != Some(&pixels[i])
+        {
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        for &pixel in &pixels[start..i] {
+            out.extend_from_slice(&value(pixel).to_le_bytes());
+        }
+    }
+}
+
+/// Frame of the row `index` of a screen of `width` by `height` pixels
+pub fn encode_row<P: Copy + PartialEq>(
+    width: u16,
+    height: u16,
+    index: u16,
+    pixels: &[P],
+    value: impl Fn(P) -> u16,
+) -> Vec<u8> {
+    let mut compressed = Vec::with_capacity(pixels.len());
+    compress(pixels, value, &mut compressed);
+
+    let mut frame = Vec::with_capacity(compressed.len() + 16);
+    frame.extend_from_slice(MAGIC);
+    for field in [width, height, index, compressed.len() as u16] {
+        frame.extend_from_slice(&field.to_le_bytes());
+    }
+    frame.extend_from_slice(&compressed);
+    let crc = crc32(&frame[MAGIC.len()..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Same as `decompress` of `tools/screenshot.py`
+    fn decompress(mut data: &[u8]) -> Vec<u16> {
+        let pixel = |data: &[u8], i: usize| u16::from_le_bytes([data[2 * i], data[2 * i + 1]]);
+        let mut pixels = Vec::new();
+        while let Some((&n, rest)) = data.split_first() {
+            if n < 128 {
+                let count = n as usize + 1;
+                pixels.extend((0..count).map(|i| pixel(rest, i)));
+                data = &rest[2 * count..];
+            } else {
+                pixels.resize(pixels.len() + n as usize - 126, pixel(rest, 0));
+                data = &rest[2..];
+            }
+        }
+        pixels
+    }
+
+    /// Pixels of a frame, checked as `Screenshot.feed` of the script does
+    fn decode_row(frame: &[u8], width: u16, height: u16, index: u16) -> Vec<u16> {
+        assert_eq!(&frame[..4], MAGIC);
+        let field = |i: usize| u16::from_le_bytes([frame[4 + 2 * i], frame[5 + 2 * i]]);
+        assert_eq!((field(0), field(1), field(2)), (width, height, index));
+        let len = field(3) as usize;
+        assert!(len <= 3 * width as usize + 2);
+        assert_eq!(frame.len(), 12 + len + 4);
+        let crc = u32::from_le_bytes(frame[12 + len..].try_into().unwrap());
+        assert_eq!(crc32(&frame[4..12 + len]), crc);
+        decompress(&frame[12..12 + len])
+    }
+
+    fn round_trip(pixels: &[u16]) -> Vec<u8> {
+        let width = pixels.len() as u16;
+        let frame = encode_row(width, 240, 7, pixels, |p| p);
+        assert_eq!(decode_row(&frame, width, 240, 7), pixels);
+        frame
+    }
+
+    #[test]
+    fn computes_the_crc_of_zlib() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn compresses_runs() {
+        let frame = round_trip(&[0xf800; 240]);
+        // Runs of at most 129 pixels
+        assert_eq!(&frame[12..frame.len() - 4], [255, 0, 0xf8, 237, 0, 0xf8]);
+        round_trip(&[0x1234; 129]);
+        round_trip(&[0x1234; 130]);
+        round_trip(&[0x1234; 2]);
+    }
+
+    #[test]
+    fn keeps_different_pixels_as_they_are() {
+        let frame = round_trip(&[1, 2]);
+        assert_eq!(&frame[12..frame.len() - 4], [1, 1, 0, 2, 0]);
+        round_trip(&[7]);
+        // Literals of at most 128 pixels, the worst case for the script
+        let alternating: Vec<u16> = (0..240).map(|i| i % 2).collect();
+        round_trip(&alternating);
+        let distinct: Vec<u16> = (0..240).collect();
+        round_trip(&distinct);
+    }
+
+    #[test]
+    fn mixes_runs_and_literals() {
+        let mut pixels = vec![1, 2, 3, 3, 3, 4, 5, 5, 6];
+        pixels.extend([0xffff; 200]);
+        pixels.extend(0..30);
+        round_trip(&pixels);
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn converts_the_pixels() {
+        let frame = encode_row(2, 1, 0, &[true, false], |p| if p { 0xffff } else { 0 });
+        assert_eq!(decode_row(&frame, 2, 1, 0), [0xffff, 0]);
+    }
+}
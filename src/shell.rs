@@ -0,0 +1,518 @@
+//! Commands of the serial console, see `console` for the UART side.
+//!
+//! A command is a line of words separated by spaces, the first one being its
+//! name. Coordinates are the ones of the display, the origin being its top
+//! left corner.
+
+use ft6x36::Direction;
+
+use crate::calendar::{self, DateError};
+use crate::feedback::{Cue, MIN_INTENSITY};
+use crate::haptics::Pattern;
+
+/// Lowest backlight level which can be set, as on the light tile
+pub const MIN_BRIGHTNESS: u8 = 10;
+
+/// Usage and description of the commands, in the order of `help`
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("help", "List the commands"),
+    ("status", "Show the battery, power rails and sensors"),
+    ("time", "Show the local time"),
+    ("time YYYY-MM-DD HH:MM[:SS]", "Set the local time"),
+    ("tiles", "List the tiles"),
+    ("tile NAME", "Show a tile"),
+    ("touch X Y", "Touch the screen at a point"),
+    ("swipe up|down|left|right", "Swipe on the screen"),
+    ("brightness [10-100]", "Show or set the backlight level"),
+    ("settings", "Dump the settings"),
+    ("screenshot", "Send the screen to tools/screenshot.py"),
+    ("crash", "Show the report of the last crash"),
+    ("crash clear", "Forget the last crash"),
+    ("metrics", "Show the timings, free heap and task stacks"),
+    ("metrics reset", "Clear the timings"),
+    ("log [all]", "Show the recent warnings, or all records"),
+    ("log clear", "Forget the records"),
+    ("vibrate PATTERN", "Play a vibration pattern"),
+    ("vibrate stop", "Stop the motor"),
+    ("haptics", "Show the vibration settings"),
+    ("haptics on|off", "Turn the input feedback on or off"),
+    ("haptics 20-100", "Set the vibration intensity"),
+    ("haptics CUE on|off", "Toggle tap, detent, tile or hold"),
+    ("sleep", "Turn the screen off"),
+];
+
+/// Date and time, in the time zone of the watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl LocalTime {
+    /// Seconds since the Unix epoch, as if the time was UTC
+    pub fn timestamp(&self) -> i64 {
+        calendar::days_from_civil(self.year as i64, self.month, self.day) * 86_400
+            + self.hours as i64 * 3600
+            + self.minutes as i64 * 60
+            + self.seconds as i64
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Help,
+    Status,
+    Time,
+    SetTime(LocalTime),
+    Tiles,
+    Tile(String),
+    Touch {
+        x: u16,
+        y: u16,
+    },
+    Swipe(Direction),
+    /// Show the backlight level, or set it
+    Brightness(Option<u8>),
+    Settings,
+    Screenshot,
+    Crash,
+    ClearCrash,
+    Metrics,
+    ResetMetrics,
+    /// Show the warnings and errors, or all the records
+    Log {
+        all: bool,
+    },
+    ClearLog,
+    Vibrate(Pattern),
+    StopVibrating,
+    /// Show the vibration settings, or change one
+    Haptics(Option<HapticsSetting>),
+    Sleep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HapticsSetting {
+    /// Play the cues confirming the input
+    Enabled(bool),
+    Intensity(u8),
+    Cue(Cue, bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellError {
+    Unknown(String),
+    /// Wrong arguments, with the usage of the command
+    Usage(&'static str),
+    Date(DateError),
+}
+
+impl std::fmt::Display for ShellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShellError::Unknown(name) => {
+                f.write_fmt(format_args!("unknown command {name}, try help"))
+            }
+            ShellError::Usage(usage) => f.write_fmt(format_args!("usage: {usage}")),
+            ShellError::Date(e) => f.write_fmt(format_args!("{e}")),
+        }
+    }
+}
+
+impl std::error::Error for ShellError {}
+
+/// Usage of a command, the last one listed in `COMMANDS` for its name
+fn usage(command: &str) -> ShellError {
+    COMMANDS
+        .iter()
+        .rev()
+        .map(|(usage, _)| *usage)
+        .find(|usage| usage.split(' ').next() == Some(command))
+        .map_or_else(
+            || ShellError::Unknown(command.to_string()),
+            ShellError::Usage,
+        )
+}
+
+/// Parse a line, `None` when it is blank
+pub fn parse(line: &str) -> Result<Option<Command>, ShellError> {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name.to_ascii_lowercase(),
+        None => return Ok(None),
+    };
+    let args: Vec<&str> = words.collect();
+    let command = match (name.as_str(), args.as_slice()) {
+        ("help" | "?", []) => Command::Help,
+        ("status", []) => Command::Status,
+        ("time", []) => Command::Time,
+        ("time", [date, time]) => Command::SetTime(parse_time(date, time)?),
+        ("tiles", []) => Command::Tiles,
+        ("tile", [name]) => Command::Tile(name.to_ascii_lowercase()),
+        ("touch", [x, y]) => match (parse_coordinate(x), parse_coordinate(y)) {
+            (Some(x), Some(y)) => Command::Touch { x, y },
+            _ => return Err(usage("touch")),
+        },
+        ("swipe", [direction]) => Command::Swipe(match direction.to_ascii_lowercase().as_str() {
+            "up" => Direction::Up,
+            "down" => Direction::Down,
+            "left" => Direction::Left,
+            "right" => Direction::Right,
+            _ => return Err(usage("swipe")),
+        }),
+        ("brightness", []) => Command::Brightness(None),
+        ("brightness", [level]) => match level.parse::<u8>() {
+            Ok(level) if (MIN_BRIGHTNESS..=100).contains(&level) => {
+                Command::Brightness(Some(level))
+            }
+            _ => return Err(usage("brightness")),
+        },
+        ("settings", []) => Command::Settings,
+        ("screenshot", []) => Command::Screenshot,
+        ("crash", []) => Command::Crash,
+        ("crash", ["clear"]) => Command::ClearCrash,
+        ("metrics", []) => Command::Metrics,
+        ("metrics", ["reset"]) => Command::ResetMetrics,
+        ("log", []) => Command::Log { all: false },
+        ("log", ["all"]) => Command::Log { all: true },
+        ("log", ["clear"]) => Command::ClearLog,
+        ("vibrate", ["stop"]) => Command::StopVibrating,
+        ("vibrate", [name]) => match Pattern::from_name(&name.to_ascii_lowercase()) {
+            Some(pattern) => Command::Vibrate(pattern),
+            None => return Err(usage("vibrate")),
+        },
+        ("haptics", []) => Command::Haptics(None),
+        ("haptics", args) => Command::Haptics(Some(parse_haptics(args)?)),
+        ("sleep", []) => Command::Sleep,
+        (name, _) => return Err(usage(name)),
+    };
+    Ok(Some(command))
+}
+
+fn parse_coordinate(value: &str) -> Option<u16> {
+    value.parse().ok().filter(|&value| value < 240)
+}
+
+/// Parse `on`, `off`, an intensity, or a cue followed by `on` or `off`
+fn parse_haptics(args: &[&str]) -> Result<HapticsSetting, ShellError> {
+    let setting = match args {
+        [value] => match (parse_switch(value), value.parse::<u8>()) {
+            (Some(on), _) => HapticsSetting::Enabled(on),
+            (None, Ok(intensity)) if (MIN_INTENSITY..=100).contains(&intensity) => {
+                HapticsSetting::Intensity(intensity)
+            }
+            _ => return Err(usage("haptics")),
+        },
+        [cue, value] => {
+            let cue = Cue::from_name(&cue.to_ascii_lowercase());
+            match (cue, parse_switch(value)) {
+                (Some(cue), Some(on)) => HapticsSetting::Cue(cue, on),
+                _ => return Err(usage("haptics")),
+            }
+        }
+        _ => return Err(usage("haptics")),
+    };
+    Ok(setting)
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse `YYYY-MM-DD` and `HH:MM` or `HH:MM:SS`
+fn parse_time(date: &str, time: &str) -> Result<LocalTime, ShellError> {
+    let numbers = |value: &str, separator: char| -> Option<Vec<u16>> {
+        value.split(separator).map(|n| n.parse().ok()).collect()
+    };
+    let (date, time) = match (numbers(date, '-'), numbers(time, ':')) {
+        (Some(date), Some(time)) => (date, time),
+        _ => return Err(usage("time")),
+    };
+    let (year, month, day, hours, minutes, seconds) = match (date.as_slice(), time.as_slice()) {
+        ([year, month, day], [hours, minutes]) => (*year, *month, *day, *hours, *minutes, 0),
+        ([year, month, day], [hours, minutes, seconds]) => {
+            (*year, *month, *day, *hours, *minutes, *seconds)
+        }
+        _ => return Err(usage("time")),
+    };
+    // Larger values are rejected by the validation as they are
+    let byte = |value: u16| value.min(u8::MAX as u16) as u8;
+    let time = LocalTime {
+        year,
+        month: byte(month),
+        day: byte(day),
+        hours: byte(hours),
+        minutes: byte(minutes),
+        seconds: byte(seconds),
+    };
+    calendar::validate(
+        time.year,
+        time.month,
+        time.day,
+        time.hours,
+        time.minutes,
+        time.seconds,
+    )
+    .map_err(ShellError::Date)?;
+    Ok(time)
+}
+
+/// Text of the `help` command
+pub fn help() -> String {
+    let width = COMMANDS
+        .iter()
+        .map(|(usage, _)| usage.len())
+        .max()
+        .unwrap_or_default();
+    COMMANDS
+        .iter()
+        .map(|(usage, description)| format!("{usage:width$}  {description}\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> Command {
+        match parse(line) {
+            Ok(Some(command)) => command,
+            result => panic!("{line}: {result:?}"),
+        }
+    }
+
+    fn error(line: &str) -> ShellError {
+        match parse(line) {
+            Err(e) => e,
+            result => panic!("{line}: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("   ").unwrap().is_none());
+        assert!(matches!(command("help"), Command::Help));
+        assert!(matches!(command("?"), Command::Help));
+        assert!(matches!(command(" STATUS "), Command::Status));
+        assert!(matches!(command("time"), Command::Time));
+        assert!(matches!(command("tiles"), Command::Tiles));
+        assert!(matches!(command("tile Weather"), Command::Tile(name) if name == "weather"));
+        assert!(matches!(command("settings"), Command::Settings));
+        assert!(matches!(command("screenshot"), Command::Screenshot));
+        assert!(matches!(command("crash"), Command::Crash));
+        assert!(matches!(command("crash clear"), Command::ClearCrash));
+        assert!(matches!(command("metrics"), Command::Metrics));
+        assert!(matches!(command("metrics reset"), Command::ResetMetrics));
+        assert!(matches!(command("log"), Command::Log { all: false }));
+        assert!(matches!(command("log all"), Command::Log { all: true }));
+        assert!(matches!(command("log clear"), Command::ClearLog));
+        assert!(matches!(
+            command("vibrate Alarm"),
+            Command::Vibrate(Pattern::Alarm)
+        ));
+        assert!(matches!(command("vibrate stop"), Command::StopVibrating));
+        assert!(matches!(command("sleep"), Command::Sleep));
+    }
+
+    #[test]
+    fn parses_the_time() {
+        let time = match command("time 2024-02-29 13:05") {
+            Command::SetTime(time) => time,
+            command => panic!("{command:?}"),
+        };
+        assert_eq!(
+            time,
+            LocalTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hours: 13,
+                minutes: 5,
+                seconds: 0,
+            }
+        );
+        assert_eq!(time.timestamp(), 1709211900);
+        assert!(matches!(
+            command("time 2024-02-29 13:05:59"),
+            Command::SetTime(LocalTime { seconds: 59, .. })
+        ));
+
+        assert_eq!(
+            error("time 2023-02-29 13:05"),
+            ShellError::Date(DateError::Day(29))
+        );
+        assert_eq!(
+            error("time 2023-02-28 24:00"),
+            ShellError::Date(DateError::Hours(24))
+        );
+        assert_eq!(
+            error("time 2023-02-28 1:300"),
+            ShellError::Date(DateError::Minutes(255))
+        );
+        let usage = ShellError::Usage("time YYYY-MM-DD HH:MM[:SS]");
+        for line in [
+            "time x",
+            "time 2023-02 10:00",
+            "time 2023-02-28 10",
+            "time 2023-02-28 a:b",
+        ] {
+            assert_eq!(error(line), usage, "{line}");
+        }
+    }
+
+    #[test]
+    fn bounds_the_coordinates() {
+        assert!(matches!(
+            command("touch 0 0"),
+            Command::Touch { x: 0, y: 0 }
+        ));
+        assert!(matches!(
+            command("touch 10 239"),
+            Command::Touch { x: 10, y: 239 }
+        ));
+        for line in [
+            "touch 10 240",
+            "touch 240 10",
+            "touch -1 2",
+            "touch 1",
+            "touch a b",
+        ] {
+            assert_eq!(error(line), ShellError::Usage("touch X Y"), "{line}");
+        }
+
+        assert!(matches!(
+            command("swipe Left"),
+            Command::Swipe(Direction::Left)
+        ));
+        assert!(matches!(command("swipe up"), Command::Swipe(Direction::Up)));
+        assert!(matches!(
+            command("swipe down"),
+            Command::Swipe(Direction::Down)
+        ));
+        assert!(matches!(
+            command("swipe RIGHT"),
+            Command::Swipe(Direction::Right)
+        ));
+        assert_eq!(
+            error("swipe sideways"),
+            ShellError::Usage("swipe up|down|left|right")
+        );
+    }
+
+    #[test]
+    fn bounds_the_brightness() {
+        assert!(matches!(command("brightness"), Command::Brightness(None)));
+        assert!(matches!(
+            command("brightness 10"),
+            Command::Brightness(Some(10))
+        ));
+        assert!(matches!(
+            command("brightness 100"),
+            Command::Brightness(Some(100))
+        ));
+        for line in [
+            "brightness 9",
+            "brightness 101",
+            "brightness 300",
+            "brightness max",
+        ] {
+            assert_eq!(
+                error(line),
+                ShellError::Usage("brightness [10-100]"),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_usage_errors() {
+        assert_eq!(error("reboot"), ShellError::Unknown("reboot".to_string()));
+        assert_eq!(
+            error("reboot").to_string(),
+            "unknown command reboot, try help"
+        );
+        // The last usage listed for the command
+        assert_eq!(error("tile"), ShellError::Usage("tile NAME"));
+        assert_eq!(error("tiles x"), ShellError::Usage("tiles"));
+        assert_eq!(error("sleep now"), ShellError::Usage("sleep"));
+        assert_eq!(error("crash all"), ShellError::Usage("crash clear"));
+        assert_eq!(error("log 2"), ShellError::Usage("log clear"));
+        assert_eq!(error("vibrate buzz"), ShellError::Usage("vibrate stop"));
+        assert_eq!(
+            error("brightness 5").to_string(),
+            "usage: brightness [10-100]"
+        );
+    }
+
+    #[test]
+    fn lists_every_command() {
+        let help = help();
+        assert_eq!(help.lines().count(), COMMANDS.len());
+        for (usage, description) in COMMANDS {
+            assert!(
+                help.contains(usage) && help.contains(description),
+                "{usage}"
+            );
+        }
+    }
+
+    fn haptics(line: &str) -> Result<Option<HapticsSetting>, ShellError> {
+        match parse(line)? {
+            Some(Command::Haptics(setting)) => Ok(setting),
+            command => panic!("{command:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_haptics() {
+        assert_eq!(haptics("haptics"), Ok(None));
+        assert_eq!(
+            haptics("haptics OFF"),
+            Ok(Some(HapticsSetting::Enabled(false)))
+        );
+        assert_eq!(
+            haptics("haptics on"),
+            Ok(Some(HapticsSetting::Enabled(true)))
+        );
+        assert_eq!(
+            haptics("haptics 20"),
+            Ok(Some(HapticsSetting::Intensity(20)))
+        );
+        assert_eq!(
+            haptics("haptics 100"),
+            Ok(Some(HapticsSetting::Intensity(100)))
+        );
+        assert_eq!(
+            haptics("haptics tile off"),
+            Ok(Some(HapticsSetting::Cue(Cue::TileChange, false)))
+        );
+        assert_eq!(
+            haptics("haptics Hold on"),
+            Ok(Some(HapticsSetting::Cue(Cue::LongPress, true)))
+        );
+    }
+
+    #[test]
+    fn rejects_haptics() {
+        let usage = Err(ShellError::Usage("haptics CUE on|off"));
+        for line in [
+            "haptics 19",
+            "haptics 101",
+            "haptics yes",
+            "haptics swipe on",
+            "haptics tap maybe",
+            "haptics tap on x",
+        ] {
+            assert_eq!(haptics(line), usage, "{line}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+#[cfg(feature = "esp")]
+use std::sync::Arc;
+
+use anyhow::Result;
+
+#[cfg(feature = "esp")]
+use embedded_svc::storage::{RawStorage, StorageBase};
+#[cfg(feature = "esp")]
+use esp_idf_svc::nvs::EspDefaultNvs;
+#[cfg(feature = "esp")]
+use esp_idf_svc::nvs_storage::EspNvsStorage;
+
+use log::*;
+
+#[cfg(feature = "esp")]
+const NAMESPACE: &str = "twatch";
+
+/// Values which can be stored in NVS
+///
+/// Encodings are versioned by the implementors; `decode` returns `None` when
+/// the stored bytes can't be understood, in which case the value is dropped.
+pub trait Persist: Sized {
+    fn encode(&self) -> Vec<u8>;
+
+    fn decode(data: &[u8]) -> Option<Self>;
+}
+
+impl Persist for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        String::from_utf8(data.to_vec()).ok()
+    }
+}
+
+#[cfg(feature = "esp")]
+pub struct Storage {
+    nvs: EspNvsStorage,
+}
+
+#[cfg(feature = "esp")]
+impl Storage {
+    pub fn new(default_nvs: Arc<EspDefaultNvs>) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvsStorage::new_default(default_nvs, NAMESPACE, true)?,
+        })
+    }
+
+    pub fn get<T: Persist>(&self, key: &str) -> Result<Option<T>> {
+        let len = match self.nvs.len(key)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let mut buf = vec![0u8; len];
+        let value = self
+            .nvs
+            .get_raw(key, &mut buf)?
+            .and_then(|data| T::decode(data));
+        if value.is_none() {
+            warn!("Discarding unreadable value for key {key}");
+        }
+        Ok(value)
+    }
+
+    pub fn put<T: Persist>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.nvs.put_raw(key, &value.encode())?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        self.nvs.remove(key)?;
+        Ok(())
+    }
+}
+
+/// Values kept in RAM, for the tests on the host
+#[cfg(not(feature = "esp"))]
+#[derive(Default)]
+pub struct Storage {
+    values: std::collections::HashMap<String, Vec<u8>>,
+}
+
+#[cfg(not(feature = "esp"))]
+impl Storage {
+    pub fn get<T: Persist>(&self, key: &str) -> Result<Option<T>> {
+        let data = match self.values.get(key) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let value = T::decode(data);
+        if value.is_none() {
+            warn!("Discarding unreadable value for key {key}");
+        }
+        Ok(value)
+    }
+
+    pub fn put<T: Persist>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.values.insert(key.to_string(), value.encode());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        self.values.remove(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Layout of text in the fixed-width fonts of the display.
+
+/// Split a text in lines of at most `width` characters, breaking between words
+/// when possible
+///
+/// The last line ends with "..." when the text doesn't fit in `max_lines`.
+pub fn wrap_text(text: &str, width: usize, max_lines: usize) -> Vec<String> {
+    if width < 4 || max_lines == 0 {
+        return Vec::new();
+    }
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut truncated = false;
+    'words: for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        loop {
+            let len = line.chars().count();
+            let space = if len == 0 { 0 } else { 1 };
+            if len + space + word.len() <= width {
+                if space == 1 {
+                    line.push(' ');
+                }
+                line.extend(word.iter());
+                continue 'words;
+            }
+            if len > 0 {
+                lines.push(std::mem::take(&mut line));
+            } else {
+                // Break words longer than a line
+                let rest = word.split_off(width);
+                lines.push(word.iter().collect());
+                word = rest;
+            }
+            if lines.len() == max_lines {
+                truncated = true;
+                break 'words;
+            }
+        }
+    }
+    if !line.is_empty() {
+        if lines.len() < max_lines {
+            lines.push(line);
+        } else {
+            truncated = true;
+        }
+    }
+    if truncated {
+        if let Some(last) = lines.last_mut() {
+            let mut chars: Vec<char> = last.chars().collect();
+            chars.truncate(width - 3);
+            *last = chars.into_iter().collect::<String>() + "...";
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_between_words() {
+        assert_eq!(
+            wrap_text("The quick brown fox jumps", 10, 5),
+            ["The quick", "brown fox", "jumps"]
+        );
+        assert_eq!(wrap_text("  spaced \n out  ", 20, 2), ["spaced out"]);
+        assert_eq!(wrap_text("exactly ten", 11, 1), ["exactly ten"]);
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        assert_eq!(
+            wrap_text("abcdefghijklmnop", 6, 5),
+            ["abcdef", "ghijkl", "mnop"]
+        );
+        assert_eq!(
+            wrap_text("ab abcdefgh cd", 4, 5),
+            ["ab", "abcd", "efgh", "cd"]
+        );
+        // Counted in characters rather than bytes
+        assert_eq!(
+            wrap_text("\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}", 4, 2),
+            ["\u{e9}\u{e9}\u{e9}\u{e9}", "\u{e9}"]
+        );
+    }
+
+    #[test]
+    fn ellipsizes_what_does_not_fit() {
+        assert_eq!(wrap_text("one two three four", 9, 1), ["one tw..."]);
+        assert_eq!(
+            wrap_text("one two three four", 9, 2),
+            ["one two", "three..."]
+        );
+        assert_eq!(wrap_text("abcdefghijkl", 5, 2), ["abcde", "fg..."]);
+    }
+
+    #[test]
+    fn handles_empty_input() {
+        assert!(wrap_text("", 10, 3).is_empty());
+        assert!(wrap_text("   ", 10, 3).is_empty());
+        assert!(wrap_text("text", 10, 0).is_empty());
+        assert!(wrap_text("text", 3, 1).is_empty());
+    }
+}
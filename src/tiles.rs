@@ -1,8 +1,20 @@
+pub(crate) mod alarm;
+pub(crate) mod crash;
+pub(crate) mod diagnostics;
 pub(crate) mod hello;
 pub(crate) mod light;
+pub(crate) mod logs;
 pub(crate) mod motor;
+pub(crate) mod music;
+pub(crate) mod notifications;
+pub(crate) mod power;
+pub(crate) mod ring;
+pub(crate) mod settime;
 pub(crate) mod sleep;
 pub(crate) mod time;
+pub(crate) mod update;
+pub(crate) mod weather;
+pub(crate) mod wifi;
 pub(crate) mod ferris;
 
 use std::{thread, time::Duration};
@@ -45,6 +57,42 @@ impl std::fmt::Debug for (dyn WatchTile + Send + 'static) {
     }
 }
 
+/// Tiles which can be shown by name, from the serial console
+pub(crate) const TILES: &[(&str, fn() -> Box<dyn WatchTile + Send>)] = &[
+    ("alarms", new::<alarm::AlarmTile>),
+    ("crash", new::<crash::CrashTile>),
+    ("diagnostics", new::<diagnostics::DiagnosticsTile>),
+    ("ferris", new::<ferris::FerrisTile>),
+    ("hello", new::<hello::HelloTile>),
+    ("light", new::<light::LightTile>),
+    ("logs", new::<logs::LogsTile>),
+    ("motor", new::<motor::MotorTile>),
+    ("music", new::<music::MusicTile>),
+    ("notifications", new::<notifications::NotificationsTile>),
+    ("power", new::<power::PowerTile>),
+    ("settime", new::<settime::SetTimeTile>),
+    ("time", new::<time::TimeTile>),
+    ("update", new::<update::UpdateTile>),
+    ("weather", new::<weather::WeatherTile>),
+    ("wifi", new::<wifi::WifiTile>),
+];
+
+fn new<T: WatchTile + Send + Default + 'static>() -> Box<dyn WatchTile + Send> {
+    Box::new(T::default())
+}
+
+pub(crate) fn by_name(name: &str) -> Option<Box<dyn WatchTile + Send>> {
+    TILES
+        .iter()
+        .find(|(tile, _)| *tile == name)
+        .map(|(_, new)| new())
+}
+
+/// Convert touch screen coordinates to display coordinates, the x axis being mirrored
+pub(crate) fn touch_point(x: impl Into<i32>, y: impl Into<i32>) -> Point {
+    Point::new(239 - x.into(), y.into())
+}
+
 pub(crate) fn move_to_tile(
     hal: &mut Hal<'static>,
     _from: &mut impl WatchTile,
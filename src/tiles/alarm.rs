@@ -0,0 +1,371 @@
+use anyhow::Result;
+
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::Text,
+    Drawable,
+};
+use ft6x36::{Direction, TouchEvent};
+use profont::{PROFONT_12_POINT, PROFONT_18_POINT, PROFONT_24_POINT};
+use u8g2_fonts::{
+    fonts,
+    types::{FontColor, HorizontalAlignment, VerticalPosition},
+    FontRenderer,
+};
+
+use log::*;
+
+use crate::{
+    alarms::{Alarm, Weekdays, MAX_ALARMS},
+    events::{Kind, TwatchEvent},
+    feedback::Cue,
+    tiles::{touch_point, WatchTile},
+    twatch::Hal,
+};
+
+const ROW_HEIGHT: i32 = 34;
+const VISIBLE_ROWS: usize = 5;
+const LIST_TOP: i32 = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wheel {
+    Hours,
+    Minutes,
+}
+
+enum Mode {
+    List {
+        scroll: usize,
+    },
+    Edit {
+        index: Option<usize>,
+        alarm: Alarm,
+        wheel: Wheel,
+    },
+}
+
+/// List, toggle and edit the alarms of the `AlarmService`
+pub struct AlarmTile {
+    alarms: Vec<Alarm>,
+    mode: Mode,
+}
+
+impl Default for AlarmTile {
+    fn default() -> Self {
+        Self {
+            alarms: Vec::new(),
+            mode: Mode::List { scroll: 0 },
+        }
+    }
+}
+
+unsafe impl Send for AlarmTile {}
+
+impl WatchTile for AlarmTile {
+    fn name(&self) -> &str {
+        "Alarms"
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind, &mut self.mode) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info)), Mode::List { scroll }) => match dir {
+                Direction::Right => {
+                    let mut time_tile = crate::tiles::time::TimeTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut time_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(time_tile))))
+                }
+                Direction::Left => {
+                    let mut power_tile = crate::tiles::power::PowerTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut power_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(power_tile))))
+                }
+                Direction::Up => {
+                    if *scroll + VISIBLE_ROWS < self.alarms.len() {
+                        *scroll += 1;
+                    }
+                    self.refresh(hal);
+                    None
+                }
+                Direction::Down => {
+                    *scroll = scroll.saturating_sub(1);
+                    self.refresh(hal);
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info)), Mode::Edit { alarm, wheel, .. }) => {
+                let step = match dir {
+                    Direction::Up => 1,
+                    Direction::Down => -1,
+                    _ => return None,
+                };
+                spin(alarm, *wheel, step);
+                hal.cue(Cue::Detent);
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p)), _) => {
+                let point = touch_point(p.x, p.y);
+                match self.mode {
+                    Mode::List { scroll } => self.touch_list(hal, point, scroll),
+                    Mode::Edit { .. } => self.touch_edit(hal, point),
+                }
+                self.refresh(hal);
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        match &self.mode {
+            Mode::List { scroll } => self.display_list(hal, *scroll),
+            Mode::Edit {
+                index,
+                alarm,
+                wheel,
+            } => self.display_edit(hal, index.is_none(), alarm, *wheel),
+        }
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.alarms = hal.alarms.alarms().to_vec();
+    }
+}
+
+impl AlarmTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing alarms: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing alarms: {e:?}"));
+    }
+
+    fn touch_list(&mut self, hal: &mut Hal<'static>, point: Point, scroll: usize) {
+        if point.y < LIST_TOP {
+            return;
+        }
+        let row = ((point.y - LIST_TOP) / ROW_HEIGHT) as usize;
+        if row >= VISIBLE_ROWS {
+            if self.alarms.len() < MAX_ALARMS {
+                self.mode = Mode::Edit {
+                    index: None,
+                    alarm: Alarm::new(7, 0),
+                    wheel: Wheel::Hours,
+                };
+            }
+            return;
+        }
+        let index = scroll + row;
+        if let Some(alarm) = self.alarms.get(index) {
+            if point.x >= 170 {
+                let mut alarm = *alarm;
+                alarm.enabled = !alarm.enabled;
+                hal.alarms
+                    .update(&mut hal.clock, &mut hal.storage, &hal.tz, index, alarm)
+                    .unwrap_or_else(|e| warn!("Unable to toggle alarm: {e:?}"));
+            } else {
+                self.mode = Mode::Edit {
+                    index: Some(index),
+                    alarm: *alarm,
+                    wheel: Wheel::Hours,
+                };
+            }
+        }
+    }
+
+    fn touch_edit(&mut self, hal: &mut Hal<'static>, point: Point) {
+        let (index, alarm, wheel) = match &mut self.mode {
+            Mode::Edit {
+                index,
+                alarm,
+                wheel,
+            } => (*index, alarm, wheel),
+            Mode::List { .. } => return,
+        };
+        match point.y {
+            // Wheels: tap to select, tap above or below the value to spin it
+            0..=139 => {
+                *wheel = if point.x < 120 {
+                    Wheel::Hours
+                } else {
+                    Wheel::Minutes
+                };
+                match point.y {
+                    0..=49 => {
+                        spin(alarm, *wheel, 1);
+                        hal.cue(Cue::Detent);
+                    }
+                    100..=139 => {
+                        spin(alarm, *wheel, -1);
+                        hal.cue(Cue::Detent);
+                    }
+                    _ => {}
+                }
+            }
+            // Repeat days
+            150..=184 => alarm.repeat.toggle((point.x / 34).min(6) as u8),
+            // Delete or cancel / save
+            195..=239 => {
+                let alarm = *alarm;
+                let result = match (index, point.x < 120) {
+                    (Some(index), true) => hal.alarms.remove(&mut hal.clock, &mut hal.storage, &hal.tz, index),
+                    (None, true) => Ok(()),
+                    (Some(index), false) => {
+                        hal.alarms
+                            .update(&mut hal.clock, &mut hal.storage, &hal.tz, index, alarm)
+                    }
+                    (None, false) => {
+                        hal.alarms
+                            .add(&mut hal.clock, &mut hal.storage, &hal.tz, alarm)
+                    }
+                };
+                result.unwrap_or_else(|e| warn!("Unable to save alarm: {e:?}"));
+                self.mode = Mode::List { scroll: 0 };
+            }
+            _ => {}
+        }
+    }
+
+    fn display_list(&self, hal: &mut Hal<'static>, scroll: usize) -> Result<()> {
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+
+        Text::new("Alarms", Point::new(70, 26), style).draw(&mut hal.display)?;
+
+        for (row, alarm) in self.alarms.iter().skip(scroll).take(VISIBLE_ROWS).enumerate() {
+            let y = LIST_TOP + row as i32 * ROW_HEIGHT;
+            let color = if alarm.enabled {
+                Rgb565::WHITE
+            } else {
+                Rgb565::BLUE
+            };
+            Text::new(
+                &format!("{alarm}"),
+                Point::new(6, y + 24),
+                MonoTextStyle::new(&PROFONT_24_POINT, color),
+            )
+            .draw(&mut hal.display)?;
+            Text::new(
+                &format!("{}", alarm.repeat),
+                Point::new(96, y + 20),
+                MonoTextStyle::new(&PROFONT_12_POINT, color),
+            )
+            .draw(&mut hal.display)?;
+            draw_toggle(hal, Point::new(190, y + 6), alarm.enabled)?;
+        }
+
+        if self.alarms.len() < MAX_ALARMS {
+            Text::new("+ New alarm", Point::new(50, 230), small_style).draw(&mut hal.display)?;
+        }
+        Ok(())
+    }
+
+    fn display_edit(
+        &self,
+        hal: &mut Hal<'static>,
+        new: bool,
+        alarm: &Alarm,
+        wheel: Wheel,
+    ) -> Result<()> {
+        let font = FontRenderer::new::<fonts::u8g2_font_logisoso42_tn>();
+        let small_font = FontRenderer::new::<fonts::u8g2_font_logisoso20_tn>();
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+
+        for (x, value, modulo, w) in [
+            (60, alarm.hours as i32, 24, Wheel::Hours),
+            (180, alarm.minutes as i32, 60, Wheel::Minutes),
+        ] {
+            let color = if w == wheel {
+                Rgb565::WHITE
+            } else {
+                Rgb565::BLUE
+            };
+            for (y, offset) in [(40, 1), (130, -1)] {
+                small_font
+                    .render_aligned(
+                        format!("{:02}", (value + offset).rem_euclid(modulo)).as_str(),
+                        Point::new(x, y),
+                        VerticalPosition::Baseline,
+                        HorizontalAlignment::Center,
+                        FontColor::Transparent(Rgb565::BLUE),
+                        &mut hal.display,
+                    )
+                    .expect("Unable to render wheel");
+            }
+            font.render_aligned(
+                format!("{value:02}").as_str(),
+                Point::new(x, 95),
+                VerticalPosition::Baseline,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(color),
+                &mut hal.display,
+            )
+            .expect("Unable to render wheel");
+        }
+        Text::new(":", Point::new(114, 85), style).draw(&mut hal.display)?;
+
+        for (day, label) in Weekdays::LABELS.iter().enumerate() {
+            let top_left = Point::new(day as i32 * 34 + 1, 150);
+            let rect = Rectangle::new(top_left, Size::new(32, 34));
+            if alarm.repeat.contains(day as u8) {
+                rect.into_styled(PrimitiveStyle::with_fill(Rgb565::BLUE))
+                    .draw(&mut hal.display)?;
+            } else {
+                rect.into_styled(PrimitiveStyle::with_stroke(Rgb565::BLUE, 1))
+                    .draw(&mut hal.display)?;
+            }
+            Text::new(label, top_left + Point::new(5, 23), small_style).draw(&mut hal.display)?;
+        }
+
+        let cancel = if new { "Cancel" } else { "Delete" };
+        Text::new(cancel, Point::new(20, 225), style).draw(&mut hal.display)?;
+        Text::new("Save", Point::new(150, 225), style).draw(&mut hal.display)?;
+        Ok(())
+    }
+}
+
+fn spin(alarm: &mut Alarm, wheel: Wheel, step: i32) {
+    match wheel {
+        Wheel::Hours => alarm.hours = (alarm.hours as i32 + step).rem_euclid(24) as u8,
+        Wheel::Minutes => alarm.minutes = (alarm.minutes as i32 + step).rem_euclid(60) as u8,
+    }
+}
+
+fn draw_toggle(hal: &mut Hal<'static>, top_left: Point, on: bool) -> Result<()> {
+    let style = PrimitiveStyleBuilder::new()
+        .stroke_width(2)
+        .stroke_color(Rgb565::BLUE)
+        .build();
+    Rectangle::new(top_left, Size::new(40, 22))
+        .into_styled(style)
+        .draw(&mut hal.display)?;
+    let (offset, color) = if on {
+        (20, Rgb565::GREEN)
+    } else {
+        (2, Rgb565::BLUE)
+    };
+    Rectangle::new(top_left + Point::new(offset, 2), Size::new(18, 18))
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(&mut hal.display)?;
+    Ok(())
+}
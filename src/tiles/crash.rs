@@ -0,0 +1,132 @@
+use anyhow::Result;
+
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_14_POINT, PROFONT_18_POINT};
+
+use crate::crash::CrashReport;
+use crate::events::{Kind, TwatchEvent};
+use crate::text::wrap_text;
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+
+/// Touches below this line clear the report
+const CLEAR_TOP: i32 = 200;
+
+/// Report of the last crash, shown at the boot after it
+///
+/// The whole report with the last log lines is given by the `crash` command of
+/// the console. Any swipe goes to the time tile.
+#[derive(Default)]
+pub struct CrashTile {
+    report: Option<CrashReport>,
+    safe_mode: bool,
+}
+
+unsafe impl Send for CrashTile {}
+
+impl CrashTile {
+    fn leave(&mut self, hal: &mut Hal<'static>, dir: &Direction) -> Option<TwatchEvent> {
+        let mut time_tile = crate::tiles::time::TimeTile::default();
+        let _ = crate::tiles::move_to_tile(hal, self, &mut time_tile, dir);
+        Some(TwatchEvent::new(Kind::NewTile(Box::new(time_tile))))
+    }
+}
+
+impl WatchTile for CrashTile {
+    fn name(&self) -> &str {
+        "Crash"
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => self.leave(hal, dir),
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if touch_point(p.x, p.y).y < CLEAR_TOP || self.report.is_none() {
+                    return Some(event);
+                }
+                hal.clear_crash_report()
+                    .unwrap_or_else(|e| warn!("Unable to clear crash report: {e:?}"));
+                self.leave(hal, &Direction::Up)
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let (title, color) = if self.safe_mode {
+            ("Safe mode", Rgb565::CSS_ORANGE)
+        } else {
+            ("Crashed", Rgb565::RED)
+        };
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, color);
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_LIGHT_GRAY);
+
+        Text::new(title, Point::new(10, 30), title_style).draw(&mut hal.display)?;
+        let report = match &self.report {
+            Some(report) => report,
+            None => {
+                Text::with_alignment(
+                    "No crash",
+                    Point::new(120, 120),
+                    text_style,
+                    Alignment::Center,
+                )
+                .draw(&mut hal.display)?;
+                return Ok(());
+            }
+        };
+
+        let reason = match report.uptime {
+            0 => report.reason.clone(),
+            uptime => format!("{} after {uptime}s", report.reason),
+        };
+        Text::new(&reason, Point::new(10, 52), small_style).draw(&mut hal.display)?;
+        for (i, line) in wrap_text(&report.message, 22, 5).iter().enumerate() {
+            Text::new(line, Point::new(10, 78 + 18 * i as i32), text_style)
+                .draw(&mut hal.display)?;
+        }
+        for (i, line) in wrap_text(&report.location, 30, 2).iter().enumerate() {
+            Text::new(line, Point::new(10, 172 + 14 * i as i32), small_style)
+                .draw(&mut hal.display)?;
+        }
+
+        Rectangle::new(Point::new(40, CLEAR_TOP), Size::new(160, 32))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 2))
+            .draw(&mut hal.display)?;
+        Text::with_alignment(
+            "Clear",
+            Point::new(120, CLEAR_TOP + 21),
+            text_style,
+            Alignment::Center,
+        )
+        .draw(&mut hal.display)?;
+        Ok(())
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.report = hal.crash_report();
+        self.safe_mode = hal.safe_mode;
+    }
+}
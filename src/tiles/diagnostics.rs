@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::Text;
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_18_POINT};
+
+use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::memory::{self, Heap, TaskStack};
+use crate::metrics::{self, millis, Histogram, Metric};
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+
+/// Touches below this line reset the timings
+const RESET_TOP: i32 = 212;
+/// Tasks shown, the closest to overflowing their stack
+const STACKS: usize = 3;
+/// Bytes left on a stack under which it is shown in orange
+const LOW_STACK: u32 = 1024;
+
+/// Live timings of the event loop, display and I2C, with the free heap and
+/// the task stacks, see `metrics`
+#[derive(Default)]
+pub struct DiagnosticsTile {
+    histograms: [Histogram; Metric::COUNT],
+    heap: Option<Heap>,
+    stacks: Vec<TaskStack>,
+    timer: Option<EspTimer>,
+}
+
+unsafe impl Send for DiagnosticsTile {}
+
+impl DiagnosticsTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing diagnostics: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing diagnostics: {e:?}"));
+    }
+}
+
+impl WatchTile for DiagnosticsTile {
+    fn name(&self) -> &str {
+        "Diagnostics"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let mut timer_loop = hal.eventloop.clone();
+        let mut periodic_timer = EspTimerService::new()?.timer(move || {
+            let _ = timer_loop.post(
+                &TwatchRawEvent::Timer.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        periodic_timer.every(Duration::from_secs(1))?;
+        self.timer = Some(periodic_timer);
+        Ok(())
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Right => {
+                    let mut update_tile = crate::tiles::update::UpdateTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut update_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(update_tile))))
+                }
+                Direction::Left => {
+                    let mut logs_tile = crate::tiles::logs::LogsTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut logs_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(logs_tile))))
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if touch_point(p.x, p.y).y < RESET_TOP {
+                    return Some(event);
+                }
+                metrics::reset();
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Timer) => {
+                self.refresh(hal);
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+        let style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::WHITE);
+        let header_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_LIGHT_GRAY);
+        let low_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_ORANGE);
+
+        Text::new("Diagnostics", Point::new(10, 30), title_style).draw(&mut hal.display)?;
+        let header = format!("{:<6}{:>7}{:>7}{:>7}", "ms", "p50", "p95", "max");
+        Text::new(&header, Point::new(10, 54), header_style).draw(&mut hal.display)?;
+        for (i, (metric, histogram)) in Metric::ALL.iter().zip(&self.histograms).enumerate() {
+            let line = match (histogram.percentile(50), histogram.percentile(95)) {
+                (Some(p50), Some(p95)) => format!(
+                    "{:<6}{:>7}{:>7}{:>7}",
+                    metric.name(),
+                    millis(p50),
+                    millis(p95),
+                    millis(histogram.max)
+                ),
+                _ => format!("{:<6}{:>7}", metric.name(), "-"),
+            };
+            Text::new(&line, Point::new(10, 72 + 17 * i as i32), style).draw(&mut hal.display)?;
+        }
+
+        if let Some(heap) = self.heap {
+            let line = format!(
+                "Heap {}k min {}k blk {}k",
+                heap.free / 1024,
+                heap.min_free / 1024,
+                heap.largest_block / 1024
+            );
+            Text::new(&line, Point::new(10, 150), style).draw(&mut hal.display)?;
+        }
+        for (i, stack) in self.stacks.iter().enumerate() {
+            let line = format!("{:<16}{:>6} B", stack.name, stack.high_water);
+            let style = if stack.high_water < LOW_STACK {
+                low_style
+            } else {
+                style
+            };
+            Text::new(&line, Point::new(10, 170 + 16 * i as i32), style).draw(&mut hal.display)?;
+        }
+
+        Text::new("Tap here to reset", Point::new(50, 230), header_style).draw(&mut hal.display)?;
+        Ok(())
+    }
+
+    fn update_state(&mut self, _hal: &mut Hal<'static>) {
+        self.histograms = metrics::histograms();
+        self.heap = Some(memory::heap());
+        self.stacks = memory::stacks();
+        self.stacks.truncate(STACKS);
+    }
+}
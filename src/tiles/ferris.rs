@@ -17,6 +17,10 @@ pub struct FerrisTile {}
 unsafe impl Send for FerrisTile {}
 
 impl WatchTile for FerrisTile {
+    fn name(&self) -> &str {
+        "Ferris"
+    }
+
     fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
         self.display_tile(hal)?;
         hal.display.commit_display()
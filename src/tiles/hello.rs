@@ -19,6 +19,10 @@ pub struct HelloTile {}
 unsafe impl Send for HelloTile {}
 
 impl WatchTile for HelloTile {
+    fn name(&self) -> &str {
+        "Hello"
+    }
+
     fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
         self.display_tile(hal)?;
         hal.display.commit_display()
@@ -20,6 +20,7 @@ use u8g2_fonts::{
 
 use crate::{
     events::{Kind, TwatchEvent},
+    feedback::Cue,
     tiles::WatchTile,
 };
 
@@ -29,6 +30,10 @@ pub struct LightTile {}
 unsafe impl Send for LightTile {}
 
 impl WatchTile for LightTile {
+    fn name(&self) -> &str {
+        "Light"
+    }
+
     fn run(&mut self, hal: &mut crate::twatch::Hal<'static>) -> anyhow::Result<()> {
         self.display_tile(hal)?;
         hal.display.commit_display()?;
@@ -103,11 +108,14 @@ impl WatchTile for LightTile {
             },
             (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
                 if p.y >= 80 && p.y <= 170 {
-                    let mut level = hal.display.get_display_level();
-                    if p.x <= 120 {
-                        level = min(100, level + 15);
+                    let previous = hal.display.get_display_level();
+                    let level = if p.x <= 120 {
+                        min(100, previous + 15)
                     } else {
-                        level = max(10, level.saturating_sub(15));
+                        max(10, previous.saturating_sub(15))
+                    };
+                    if level != previous {
+                        hal.cue(Cue::Detent);
                     }
                     hal.display
                         .set_display_level(level)
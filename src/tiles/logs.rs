@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_18_POINT};
+
+use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::logbook::{self, Entry};
+use crate::text::wrap_text;
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+
+/// Touches above this line switch between the warnings and all the records
+const TITLE_BOTTOM: i32 = 40;
+const TOP: i32 = 56;
+const BOTTOM: i32 = 236;
+const LINE_HEIGHT: i32 = 15;
+/// Characters of a line in `PROFONT_12_POINT`
+const LINE_LEN: usize = 27;
+/// Lines of a message, the rest is given by the `log` console command
+const MESSAGE_LINES: usize = 3;
+
+fn color(level: Level) -> Rgb565 {
+    match level {
+        Level::Error => Rgb565::RED,
+        Level::Warn => Rgb565::CSS_ORANGE,
+        Level::Info => Rgb565::WHITE,
+        Level::Debug | Level::Trace => Rgb565::CSS_DIM_GRAY,
+    }
+}
+
+/// Header and message of an entry, as drawn
+fn lines(entry: &Entry) -> (String, Vec<String>) {
+    let header = if entry.earlier {
+        format!("{} earlier {}", entry.time(), entry.target)
+    } else {
+        format!("{} {}", entry.time(), entry.target)
+    };
+    (
+        header.chars().take(LINE_LEN + 2).collect(),
+        wrap_text(&entry.message, LINE_LEN, MESSAGE_LINES),
+    )
+}
+
+/// Recent warnings and errors, newest first
+///
+/// Swiping up and down scrolls by a page, tapping the title shows all the
+/// records or only the warnings and errors again. New records are shown when
+/// scrolled to the top.
+#[derive(Default)]
+pub struct LogsTile {
+    all: bool,
+    /// Index of the first entry shown
+    offset: usize,
+    entries: Vec<Entry>,
+    /// `logbook::count` when the entries were read
+    count: u32,
+    timer: Option<EspTimer>,
+}
+
+unsafe impl Send for LogsTile {}
+
+impl LogsTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing log: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing log: {e:?}"));
+    }
+
+    /// Entries fitting on the screen from `offset`, at least one
+    fn page_len(&self, offset: usize) -> usize {
+        let mut y = TOP;
+        let mut len = 0;
+        for entry in self.entries.iter().skip(offset) {
+            y += LINE_HEIGHT * (1 + lines(entry).1.len() as i32);
+            if y > BOTTOM && len > 0 {
+                break;
+            }
+            len += 1;
+        }
+        len.max(1)
+    }
+}
+
+impl WatchTile for LogsTile {
+    fn name(&self) -> &str {
+        "Logs"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let mut timer_loop = hal.eventloop.clone();
+        let mut periodic_timer = EspTimerService::new()?.timer(move || {
+            let _ = timer_loop.post(
+                &TwatchRawEvent::Timer.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        periodic_timer.every(Duration::from_secs(1))?;
+        self.timer = Some(periodic_timer);
+        Ok(())
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Right => {
+                    let mut diagnostics_tile =
+                        crate::tiles::diagnostics::DiagnosticsTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut diagnostics_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(diagnostics_tile))))
+                }
+                Direction::Up => {
+                    let next = self.offset + self.page_len(self.offset);
+                    if next < self.entries.len() {
+                        self.offset = next;
+                    }
+                    self.refresh(hal);
+                    None
+                }
+                Direction::Down => {
+                    // Back to the first entry of the previous page
+                    let mut offset = self.offset;
+                    while offset > 0 && self.page_len(offset - 1) + offset - 1 >= self.offset {
+                        offset -= 1;
+                    }
+                    self.offset = offset;
+                    self.refresh(hal);
+                    None
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if touch_point(p.x, p.y).y >= TITLE_BOTTOM {
+                    return Some(event);
+                }
+                self.all = !self.all;
+                self.offset = 0;
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Timer) => {
+                // Kept still while scrolled down
+                if self.offset == 0 && logbook::count() != self.count {
+                    self.refresh(hal);
+                }
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+        let text_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::WHITE);
+
+        let title = if self.all { "Log" } else { "Warnings" };
+        Text::new(title, Point::new(10, 30), title_style).draw(&mut hal.display)?;
+        if self.entries.is_empty() {
+            Text::with_alignment(
+                "Nothing logged",
+                Point::new(120, 120),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+            return Ok(());
+        }
+        let position = format!("{}/{}", self.offset + 1, self.entries.len());
+        Text::with_alignment(
+            &position,
+            Point::new(230, 48),
+            MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_DIM_GRAY),
+            Alignment::Right,
+        )
+        .draw(&mut hal.display)?;
+
+        let len = self.page_len(self.offset);
+        let mut y = TOP + LINE_HEIGHT - 4;
+        for entry in self.entries.iter().skip(self.offset).take(len) {
+            let (header, message) = lines(entry);
+            let header_style = MonoTextStyle::new(&PROFONT_12_POINT, color(entry.level));
+            Text::new(&header, Point::new(6, y), header_style).draw(&mut hal.display)?;
+            for line in message {
+                y += LINE_HEIGHT;
+                Text::new(&line, Point::new(14, y), text_style).draw(&mut hal.display)?;
+            }
+            y += LINE_HEIGHT;
+        }
+        Ok(())
+    }
+
+    fn update_state(&mut self, _hal: &mut Hal<'static>) {
+        self.count = logbook::count();
+        let level = if self.all { Level::Trace } else { Level::Warn };
+        self.entries = logbook::entries(level);
+        self.offset = self.offset.min(self.entries.len().saturating_sub(1));
+    }
+}
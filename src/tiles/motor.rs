@@ -1,5 +1,3 @@
-use std::{thread, time::Duration};
-
 use embedded_graphics::{
     mono_font::MonoTextStyle,
     pixelcolor::Rgb565,
@@ -8,52 +6,94 @@ use embedded_graphics::{
     text::Text,
     Drawable,
 };
-use embedded_hal::digital::blocking::OutputPin;
 use ft6x36::{Direction, TouchEvent};
-use profont::PROFONT_24_POINT;
+use profont::{PROFONT_18_POINT, PROFONT_24_POINT};
 
 use log::*;
 
 use crate::{
     events::{Kind, TwatchEvent},
-    tiles::WatchTile,
+    feedback::{Cue, Feedback, MIN_INTENSITY},
+    haptics::Pattern,
+    tiles::{touch_point, WatchTile},
+    twatch::Hal,
 };
 
+const ROWS_TOP: i32 = 40;
+const ROW_HEIGHT: i32 = 32;
+/// Touches below this line change the intensity
+const INTENSITY_TOP: i32 = 204;
+const INTENSITY_STEP: i32 = 20;
+
+/// Plays the vibration patterns, tap the one playing to stop it, and sets the
+/// input feedback and the intensity
 #[derive(Default)]
 pub struct MotorTile {}
 
 unsafe impl Send for MotorTile {}
 
+impl MotorTile {
+    fn set_feedback(&mut self, hal: &mut Hal<'static>, feedback: Feedback) {
+        hal.set_feedback(feedback)
+            .unwrap_or_else(|e| warn!("Unable to store haptics settings: {e:?}"));
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing motor: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing motor: {e:?}"));
+    }
+}
+
 impl WatchTile for MotorTile {
-    fn run(&mut self, hal: &mut crate::twatch::Hal<'static>) -> anyhow::Result<()> {
+    fn name(&self) -> &str {
+        "Motor"
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> anyhow::Result<()> {
         self.display_tile(hal)?;
         hal.display.commit_display()?;
 
         Ok(())
     }
 
-    fn display_tile(&self, hal: &mut crate::twatch::Hal<'static>) -> anyhow::Result<()> {
+    fn display_tile(&self, hal: &mut Hal<'static>) -> anyhow::Result<()> {
         let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let pattern_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+        let off_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::CSS_DIM_GRAY);
 
         Text::new("Motor", Point::new(0, 30), style).draw(&mut hal.display)?;
+        let (ticks, ticks_style) = if hal.feedback.enabled {
+            ("Ticks on", pattern_style)
+        } else {
+            ("Ticks off", off_style)
+        };
+        Text::new(ticks, Point::new(126, 28), ticks_style).draw(&mut hal.display)?;
 
         let rect_style = PrimitiveStyleBuilder::new()
             .stroke_width(2)
             .stroke_color(Rgb565::BLUE)
             .build();
 
-        Rectangle::new(Point::new(20, 80), Size::new(200, 90))
-            .into_styled(rect_style)
-            .draw(&mut hal.display)?;
+        for (i, pattern) in Pattern::ALL.iter().enumerate() {
+            let top = ROWS_TOP + ROW_HEIGHT * i as i32;
+            Rectangle::new(Point::new(20, top), Size::new(200, ROW_HEIGHT as u32 - 4))
+                .into_styled(rect_style)
+                .draw(&mut hal.display)?;
+            Text::new(pattern.name(), Point::new(34, top + 20), pattern_style)
+                .draw(&mut hal.display)?;
+        }
 
-        Text::new("Vibrate", Point::new(60, 130), style).draw(&mut hal.display)?;
+        let intensity = format!("-  {:>3}%  +", hal.feedback.intensity);
+        Text::new(&intensity, Point::new(48, 230), pattern_style).draw(&mut hal.display)?;
 
         Ok(())
     }
 
     fn process_event(
         &mut self,
-        hal: &mut crate::twatch::Hal<'static>,
+        hal: &mut Hal<'static>,
         event: crate::events::TwatchEvent,
     ) -> Option<crate::events::TwatchEvent> {
         match (&event.time, &event.kind) {
@@ -69,14 +109,35 @@ impl WatchTile for MotorTile {
                 }
             },
             (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
-                if p.y >= 80 && p.y <= 170 {
-                    hal.motor.set_high().expect("Unable to swith motor on");
-                    thread::sleep(Duration::from_millis(200));
-                    hal.motor.set_low().expect("Unable to swith motor off");
-                    None
+                let point = touch_point(p.x, p.y);
+                let mut feedback = hal.feedback;
+                if point.y >= INTENSITY_TOP {
+                    let step = if point.x < 120 {
+                        -INTENSITY_STEP
+                    } else {
+                        INTENSITY_STEP
+                    };
+                    let intensity = feedback.intensity as i32 + step;
+                    feedback.intensity = intensity.clamp(MIN_INTENSITY as i32, 100) as u8;
+                    if feedback != hal.feedback {
+                        self.set_feedback(hal, feedback);
+                        // Felt at the new intensity
+                        hal.cue(Cue::Detent);
+                    }
+                } else if point.y >= ROWS_TOP {
+                    let pattern = Pattern::ALL.get(((point.y - ROWS_TOP) / ROW_HEIGHT) as usize)?;
+                    let playing = hal.haptics.playing();
+                    hal.haptics.cancel();
+                    if playing != Some(*pattern) {
+                        hal.haptics.play(*pattern);
+                    }
+                } else if point.x >= 120 {
+                    feedback.enabled = !feedback.enabled;
+                    self.set_feedback(hal, feedback);
                 } else {
-                    Some(event)
+                    return Some(event);
                 }
+                None
             }
             _ => Some(event),
         }
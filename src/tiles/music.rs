@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle, Triangle};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_14_POINT, PROFONT_18_POINT};
+
+use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::gadgetbridge::{Command, MusicCommand, NowPlaying, PhoneEvent};
+use crate::text::wrap_text;
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+
+const CONTROLS_TOP: i32 = 140;
+const VOLUME_TOP: i32 = 195;
+
+/// Remote control of the music playing on the phone
+#[derive(Default)]
+pub struct MusicTile {
+    connected: bool,
+    now_playing: NowPlaying,
+    position: Option<u32>,
+    timer: Option<EspTimer>,
+}
+
+unsafe impl Send for MusicTile {}
+
+impl MusicTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        self.update_timer(hal)
+            .unwrap_or_else(|e| warn!("Unable to update the music timer: {e:?}"));
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing music: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing music: {e:?}"));
+    }
+
+    /// Redraw every second to move the position, only while playing
+    fn update_timer(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let playing = self.connected && self.now_playing.is_playing();
+        if !playing {
+            // Dropping the timer stops it
+            self.timer = None;
+        } else if self.timer.is_none() {
+            let mut timer_loop = hal.eventloop.clone();
+            let mut periodic_timer = EspTimerService::new()?.timer(move || {
+                let _ = timer_loop.post(
+                    &TwatchRawEvent::Timer.into(),
+                    Some(Duration::from_millis(0)),
+                );
+            })?;
+            periodic_timer.every(Duration::from_secs(1))?;
+            self.timer = Some(periodic_timer);
+        }
+        Ok(())
+    }
+
+    fn command_at(&self, point: Point) -> Option<MusicCommand> {
+        let command = match (point.y, point.x) {
+            (y, _) if y < CONTROLS_TOP => return None,
+            (y, x) if y < VOLUME_TOP - 5 => match x {
+                0..=79 => MusicCommand::Previous,
+                80..=159 if self.now_playing.is_playing() => MusicCommand::Pause,
+                80..=159 => MusicCommand::Play,
+                _ => MusicCommand::Next,
+            },
+            (y, _) if y < VOLUME_TOP => return None,
+            (_, x) if x < 120 => MusicCommand::VolumeDown,
+            _ => MusicCommand::VolumeUp,
+        };
+        Some(command)
+    }
+
+    fn display_controls(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let color = if self.connected {
+            Rgb565::WHITE
+        } else {
+            Rgb565::CSS_DIM_GRAY
+        };
+        let fill = PrimitiveStyle::with_fill(color);
+        let center = CONTROLS_TOP + 25;
+
+        // Previous
+        Rectangle::new(Point::new(28, center - 12), Size::new(4, 24))
+            .into_styled(fill)
+            .draw(&mut hal.display)?;
+        Triangle::new(
+            Point::new(52, center - 12),
+            Point::new(52, center + 12),
+            Point::new(33, center),
+        )
+        .into_styled(fill)
+        .draw(&mut hal.display)?;
+
+        if self.now_playing.is_playing() {
+            for x in [108, 124] {
+                Rectangle::new(Point::new(x, center - 15), Size::new(8, 30))
+                    .into_styled(fill)
+                    .draw(&mut hal.display)?;
+            }
+        } else {
+            Triangle::new(
+                Point::new(108, center - 15),
+                Point::new(108, center + 15),
+                Point::new(134, center),
+            )
+            .into_styled(fill)
+            .draw(&mut hal.display)?;
+        }
+
+        // Next
+        Triangle::new(
+            Point::new(188, center - 12),
+            Point::new(188, center + 12),
+            Point::new(207, center),
+        )
+        .into_styled(fill)
+        .draw(&mut hal.display)?;
+        Rectangle::new(Point::new(208, center - 12), Size::new(4, 24))
+            .into_styled(fill)
+            .draw(&mut hal.display)?;
+
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, color);
+        for (i, label) in ["Vol -", "Vol +"].iter().enumerate() {
+            let left = 10 + 115 * i as i32;
+            Rectangle::new(Point::new(left, VOLUME_TOP), Size::new(105, 36))
+                .into_styled(PrimitiveStyle::with_stroke(color, 2))
+                .draw(&mut hal.display)?;
+            Text::with_alignment(
+                label,
+                Point::new(left + 52, VOLUME_TOP + 24),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_time(seconds: u32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+impl WatchTile for MusicTile {
+    fn name(&self) -> &str {
+        "Music"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.update_timer(hal)
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Up => {
+                    let mut time_tile = crate::tiles::time::TimeTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut time_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(time_tile))))
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                let command = match self.command_at(touch_point(p.x, p.y)) {
+                    Some(command) => command,
+                    None => return Some(event),
+                };
+                hal.ble
+                    .send(&Command::Music(command))
+                    .unwrap_or_else(|e| warn!("Unable to send {command:?}: {e:?}"));
+                None
+            }
+            (_, Kind::Timer) => {
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Phone(PhoneEvent::MusicInfo(_) | PhoneEvent::MusicState(_))) => {
+                self.refresh(hal);
+                Some(event)
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::CSS_ORANGE);
+        let track_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+        let artist_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::CSS_LIGHT_GRAY);
+        let small_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_DIM_GRAY);
+
+        Text::new("Music", Point::new(10, 24), title_style).draw(&mut hal.display)?;
+
+        let info = &self.now_playing.info;
+        if !self.connected {
+            Text::with_alignment(
+                "Phone not connected",
+                Point::new(120, 80),
+                artist_style,
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+        } else if info.track.is_empty() {
+            Text::with_alignment(
+                "Nothing playing",
+                Point::new(120, 80),
+                artist_style,
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+        } else {
+            for (i, line) in wrap_text(&info.track, 18, 2).iter().enumerate() {
+                Text::new(line, Point::new(10, 56 + 22 * i as i32), track_style)
+                    .draw(&mut hal.display)?;
+            }
+            let artist = if info.album.is_empty() {
+                info.artist.clone()
+            } else {
+                format!("{} - {}", info.artist, info.album)
+            };
+            if let Some(artist) = wrap_text(&artist, 22, 1).first() {
+                Text::new(artist, Point::new(10, 102), artist_style).draw(&mut hal.display)?;
+            }
+        }
+
+        if let (Some(position), Some(duration)) = (self.position, info.duration) {
+            Rectangle::new(Point::new(10, 110), Size::new(220, 4))
+                .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_DIM_GRAY))
+                .draw(&mut hal.display)?;
+            if duration > 0 {
+                Rectangle::new(Point::new(10, 110), Size::new(220 * position / duration, 4))
+                    .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_ORANGE))
+                    .draw(&mut hal.display)?;
+            }
+            Text::new(&format_time(position), Point::new(10, 130), small_style)
+                .draw(&mut hal.display)?;
+            Text::with_alignment(
+                &format_time(duration),
+                Point::new(230, 130),
+                small_style,
+                Alignment::Right,
+            )
+            .draw(&mut hal.display)?;
+        }
+
+        self.display_controls(hal)
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        let now = crate::utils::uptime();
+        self.connected = hal.ble.is_connected();
+        self.now_playing = hal.now_playing.clone();
+        self.position = self.now_playing.position(now);
+    }
+}
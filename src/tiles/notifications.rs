@@ -0,0 +1,200 @@
+use anyhow::Result;
+
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_14_POINT, PROFONT_18_POINT};
+
+use crate::calendar;
+use crate::events::{Kind, TwatchEvent};
+use crate::gadgetbridge::{Command, NotificationAction};
+use crate::notifications::Notification;
+use crate::text::wrap_text;
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+
+/// Touches below this line clear the inbox, once confirmed
+const CLEAR_TOP: i32 = 200;
+
+/// Received notifications, one card at a time
+///
+/// Swiping up and down browses the inbox, swiping sideways dismisses the card.
+/// The inbox is cleared by tapping the button twice.
+#[derive(Default)]
+pub struct NotificationsTile {
+    index: usize,
+    count: usize,
+    notification: Option<Notification>,
+    now: i64,
+    /// "Clear all" was tapped, waiting for the confirmation
+    confirming: bool,
+}
+
+unsafe impl Send for NotificationsTile {}
+
+impl NotificationsTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing notifications: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing notifications: {e:?}"));
+    }
+
+    fn dismiss(&mut self, hal: &mut Hal<'static>) {
+        let id = match &self.notification {
+            Some(notification) => notification.id,
+            None => return,
+        };
+        if let Some(remote_id) = hal
+            .inbox
+            .dismiss(&mut hal.storage, id)
+            .and_then(|n| n.remote_id)
+        {
+            let command = Command::Notification {
+                id: remote_id,
+                action: NotificationAction::Dismiss,
+            };
+            hal.ble
+                .send(&command)
+                .unwrap_or_else(|e| warn!("Unable to dismiss on phone: {e:?}"));
+        }
+    }
+}
+
+impl WatchTile for NotificationsTile {
+    fn name(&self) -> &str {
+        "Notifications"
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(..))) if self.confirming => {
+                // Cancels the clearing
+                self.confirming = false;
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Down if self.index == 0 => {
+                    let mut time_tile = crate::tiles::time::TimeTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut time_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(time_tile))))
+                }
+                Direction::Down => {
+                    self.index -= 1;
+                    self.refresh(hal);
+                    None
+                }
+                Direction::Up => {
+                    if self.index + 1 < self.count {
+                        self.index += 1;
+                    }
+                    self.refresh(hal);
+                    None
+                }
+                Direction::Left | Direction::Right => {
+                    self.dismiss(hal);
+                    self.refresh(hal);
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if touch_point(p.x, p.y).y < CLEAR_TOP || hal.inbox.is_empty() {
+                    return Some(event);
+                }
+                self.confirming = !self.confirming;
+                if !self.confirming {
+                    hal.inbox.clear(&mut hal.storage);
+                }
+                self.refresh(hal);
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let title_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::CSS_ORANGE);
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_DIM_GRAY);
+
+        let notification = match &self.notification {
+            Some(notification) => notification,
+            None => {
+                Text::with_alignment(
+                    "No notifications",
+                    Point::new(120, 120),
+                    text_style,
+                    Alignment::Center,
+                )
+                .draw(&mut hal.display)?;
+                return Ok(());
+            }
+        };
+
+        let header = format!(
+            "{}/{} {}",
+            self.index + 1,
+            self.count,
+            notification.age(self.now)
+        );
+        Text::new(&header, Point::new(10, 20), small_style).draw(&mut hal.display)?;
+        if let Some(app) = wrap_text(&notification.app, 20, 1).first() {
+            Text::new(app, Point::new(10, 40), small_style).draw(&mut hal.display)?;
+        }
+        if let Some(title) = wrap_text(&notification.title, 18, 1).first() {
+            Text::new(title, Point::new(10, 66), title_style).draw(&mut hal.display)?;
+        }
+        for (i, line) in wrap_text(&notification.body, 22, 6).iter().enumerate() {
+            Text::new(line, Point::new(10, 92 + 18 * i as i32), text_style)
+                .draw(&mut hal.display)?;
+        }
+
+        let (label, color) = if self.confirming {
+            ("Tap to confirm", Rgb565::RED)
+        } else {
+            ("Clear all", Rgb565::WHITE)
+        };
+        Rectangle::new(Point::new(40, CLEAR_TOP), Size::new(160, 32))
+            .into_styled(PrimitiveStyle::with_stroke(color, 2))
+            .draw(&mut hal.display)?;
+        Text::with_alignment(
+            label,
+            Point::new(120, CLEAR_TOP + 21),
+            MonoTextStyle::new(&PROFONT_14_POINT, color),
+            Alignment::Center,
+        )
+        .draw(&mut hal.display)?;
+        Ok(())
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.count = hal.inbox.len();
+        self.index = self.index.min(self.count.saturating_sub(1));
+        self.notification = hal.inbox.get(self.index).cloned();
+        self.now = calendar::utc_now();
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::Text;
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_18_POINT, PROFONT_24_POINT};
+
+use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::pmu::{Consumption, Rail, RailState, State};
+use crate::tiles::WatchTile;
+use crate::twatch::Hal;
+
+/// Live battery current, coulomb counter and power rails
+pub struct PowerTile {
+    current: f32,
+    consumption: Consumption,
+    rails: Option<[RailState; Rail::COUNT]>,
+    timer: Option<EspTimer>,
+}
+
+impl Default for PowerTile {
+    fn default() -> Self {
+        Self {
+            current: 0.0,
+            consumption: Consumption::default(),
+            rails: None,
+            timer: None,
+        }
+    }
+}
+
+unsafe impl Send for PowerTile {}
+
+impl WatchTile for PowerTile {
+    fn name(&self) -> &str {
+        "Power"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let mut timer_loop = hal.eventloop.clone();
+        let mut periodic_timer = EspTimerService::new()?.timer(move || {
+            let _ = timer_loop.post(
+                &TwatchRawEvent::Timer.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        periodic_timer.every(Duration::from_secs(1))?;
+        self.timer = Some(periodic_timer);
+        Ok(())
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Right => {
+                    let mut alarm_tile = crate::tiles::alarm::AlarmTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut alarm_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(alarm_tile))))
+                }
+                Direction::Left => {
+                    let mut wifi_tile = crate::tiles::wifi::WifiTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut wifi_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(wifi_tile))))
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if p.y >= 190 {
+                    hal.pmu
+                        .reset_coulomb_counter()
+                        .unwrap_or_else(|e| warn!("Unable to reset coulomb counter: {e:?}"));
+                    None
+                } else {
+                    Some(event)
+                }
+            }
+            (_, Kind::Timer) => {
+                self.update_state(hal);
+                let _ = self
+                    .display_tile(hal)
+                    .map_err(|e| warn!("Error refreshing state: {e:?}"));
+                let _ = hal
+                    .display
+                    .commit_display()
+                    .map_err(|e| warn!("Error refreshing state: {e:?}"));
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let medium_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::WHITE);
+
+        let current = format!("{:>6.1} mA", self.current);
+        Text::new(&current, Point::new(30, 30), style).draw(&mut hal.display)?;
+
+        let consumption = format!(
+            "avg {:.1}mA  {}s",
+            self.consumption.average_ma,
+            self.consumption.elapsed.as_secs()
+        );
+        Text::new(&consumption, Point::new(10, 60), medium_style).draw(&mut hal.display)?;
+        let counters = format!(
+            "+{:.2}mAh -{:.2}mAh",
+            self.consumption.charged_mah, self.consumption.discharged_mah
+        );
+        Text::new(&counters, Point::new(10, 85), medium_style).draw(&mut hal.display)?;
+
+        if let Some(rails) = self.rails {
+            for (i, rail_state) in rails.iter().enumerate() {
+                let color = match rail_state.state {
+                    State::On => Rgb565::GREEN,
+                    State::Off => Rgb565::WHITE,
+                };
+                Text::new(
+                    &format!("{rail_state}"),
+                    Point::new(10, 110 + 15 * i as i32),
+                    MonoTextStyle::new(&PROFONT_12_POINT, color),
+                )
+                .draw(&mut hal.display)?;
+            }
+        }
+
+        Text::new("Tap here to reset", Point::new(60, 220), small_style).draw(&mut hal.display)?;
+        Ok(())
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        match hal.pmu.get_battery_current() {
+            Ok(current) => self.current = current,
+            Err(err) => error!("Error reading battery current: {}", err),
+        }
+        match hal.pmu.get_consumption() {
+            Ok(consumption) => self.consumption = consumption,
+            Err(err) => error!("Error reading coulomb counter: {}", err),
+        }
+        self.rails = Some(hal.pmu.rail_states());
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::Text,
+    Drawable,
+};
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use profont::PROFONT_24_POINT;
+use u8g2_fonts::{
+    fonts,
+    types::{FontColor, HorizontalAlignment, VerticalPosition},
+    FontRenderer,
+};
+
+use log::*;
+
+use crate::{
+    alarms::Alarm,
+    events::{Kind, TwatchEvent, TwatchRawEvent},
+    haptics::Pattern,
+    tiles::{touch_point, WatchTile},
+    twatch::Hal,
+};
+
+/// Stop ringing and snooze after one minute without user action
+const MAX_RING_TICKS: u32 = 120;
+
+/// Shown while an alarm rings, offering to dismiss or snooze it
+pub struct RingTile {
+    alarm: Alarm,
+    ticks: u32,
+    timer: Option<EspTimer>,
+}
+
+impl RingTile {
+    pub fn new(alarm: Alarm) -> Self {
+        Self {
+            alarm,
+            ticks: 0,
+            timer: None,
+        }
+    }
+
+    fn stop(&mut self, hal: &mut Hal<'static>, snooze: bool) -> Option<TwatchEvent> {
+        self.timer = None;
+        hal.haptics.cancel();
+        if snooze {
+            hal.alarms
+                .snooze(&mut hal.clock, &mut hal.storage, &hal.tz)
+                .unwrap_or_else(|e| warn!("Unable to snooze alarm: {e:?}"));
+        }
+        let mut time_tile = crate::tiles::time::TimeTile::default();
+        let _ = crate::tiles::move_to_tile(hal, self, &mut time_tile, &Direction::Up);
+        Some(TwatchEvent::new(Kind::NewTile(Box::new(time_tile))))
+    }
+}
+
+unsafe impl Send for RingTile {}
+
+impl WatchTile for RingTile {
+    fn name(&self) -> &str {
+        "Ring"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let mut timer_loop = hal.eventloop.clone();
+        let mut periodic_timer = EspTimerService::new()?.timer(move || {
+            let _ = timer_loop.post(
+                &TwatchRawEvent::Timer.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        periodic_timer.every(Duration::from_millis(500))?;
+        self.timer = Some(periodic_timer);
+        hal.haptics.cancel();
+        hal.haptics.play(Pattern::Alarm);
+        Ok(())
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let font = FontRenderer::new::<fonts::u8g2_font_logisoso42_tn>();
+
+        font.render_aligned(
+            format!("{}", self.alarm).as_str(),
+            Point::new(120, 60),
+            VerticalPosition::Baseline,
+            HorizontalAlignment::Center,
+            FontColor::Transparent(Rgb565::WHITE),
+            &mut hal.display,
+        )
+        .expect("Unable to render alarm time");
+
+        let rect_style = PrimitiveStyleBuilder::new()
+            .stroke_width(2)
+            .stroke_color(Rgb565::BLUE)
+            .build();
+
+        Rectangle::new(Point::new(10, 100), Size::new(105, 90))
+            .into_styled(rect_style)
+            .draw(&mut hal.display)?;
+        Text::new("Snooze", Point::new(18, 152), style).draw(&mut hal.display)?;
+
+        Rectangle::new(Point::new(125, 100), Size::new(105, 90))
+            .into_styled(rect_style)
+            .draw(&mut hal.display)?;
+        Text::new("Stop", Point::new(148, 152), style).draw(&mut hal.display)?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                let point = touch_point(p.x, p.y);
+                if point.y >= 100 && point.y <= 190 {
+                    self.stop(hal, point.x < 120)
+                } else {
+                    None
+                }
+            }
+            (_, Kind::Timer) => {
+                self.ticks += 1;
+                if self.ticks >= MAX_RING_TICKS {
+                    info!("Alarm {} not acknowledged, snoozing", self.alarm);
+                    return self.stop(hal, true);
+                }
+                None
+            }
+            (_, Kind::PmuButtonPressed) => self.stop(hal, false),
+            // Swallow swipes, the alarm has to be acknowledged
+            (_, Kind::Touch(_)) => None,
+            _ => Some(event),
+        }
+    }
+}
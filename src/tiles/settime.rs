@@ -0,0 +1,295 @@
+use anyhow::Result;
+
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::Text,
+    Drawable,
+};
+use ft6x36::{Direction, TouchEvent};
+use pcf8563::DateTime;
+use profont::{PROFONT_18_POINT, PROFONT_24_POINT};
+use u8g2_fonts::{
+    fonts,
+    types::{FontColor, HorizontalAlignment, VerticalPosition},
+    FontRenderer,
+};
+
+use log::*;
+
+use crate::{
+    calendar,
+    events::{Kind, TwatchEvent},
+    feedback::Cue,
+    tiles::{touch_point, WatchTile},
+    twatch::Hal,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Year,
+    Month,
+    Day,
+    Hours,
+    Minutes,
+}
+
+/// Set the RTC date and time
+pub struct SetTimeTile {
+    year: u16,
+    month: u8,
+    day: u8,
+    hours: u8,
+    minutes: u8,
+    field: Field,
+    /// Shown when the RTC lost its time, e.g. after the battery was drained
+    prompt: bool,
+    error: Option<calendar::DateError>,
+}
+
+impl Default for SetTimeTile {
+    fn default() -> Self {
+        Self {
+            year: calendar::MIN_YEAR,
+            month: 1,
+            day: 1,
+            hours: 0,
+            minutes: 0,
+            field: Field::Hours,
+            prompt: false,
+            error: None,
+        }
+    }
+}
+
+unsafe impl Send for SetTimeTile {}
+
+impl SetTimeTile {
+    pub fn new(time: &DateTime, prompt: bool) -> Self {
+        Self {
+            year: calendar::MIN_YEAR + time.year as u16,
+            month: time.month.clamp(1, 12),
+            day: time.day.max(1),
+            hours: time.hours,
+            minutes: time.minutes,
+            prompt,
+            ..Default::default()
+        }
+    }
+
+    fn step(&mut self, step: i32) {
+        let wrap =
+            |value: i32, min: i32, max: i32| (value - min + step).rem_euclid(max - min + 1) + min;
+        match self.field {
+            Field::Year => {
+                self.year = wrap(
+                    self.year as i32,
+                    calendar::MIN_YEAR as i32,
+                    calendar::MAX_YEAR as i32,
+                ) as u16
+            }
+            Field::Month => self.month = wrap(self.month as i32, 1, 12) as u8,
+            Field::Day => {
+                let days = calendar::days_in_month(self.year, self.month) as i32;
+                self.day = wrap(self.day as i32, 1, days) as u8
+            }
+            Field::Hours => self.hours = wrap(self.hours as i32, 0, 23) as u8,
+            Field::Minutes => self.minutes = wrap(self.minutes as i32, 0, 59) as u8,
+        }
+        // Keep the day valid when changing month or year
+        self.day = self.day.min(calendar::days_in_month(self.year, self.month));
+        self.error = None;
+    }
+
+    fn save(&mut self, hal: &mut Hal<'static>) -> Option<TwatchEvent> {
+        if let Err(e) =
+            calendar::validate(self.year, self.month, self.day, self.hours, self.minutes, 0)
+        {
+            warn!("Refusing to set time: {e}");
+            self.error = Some(e);
+            return None;
+        }
+        let time = DateTime {
+            year: (self.year - calendar::MIN_YEAR) as u8,
+            month: self.month,
+            day: self.day,
+            weekday: calendar::weekday(self.year, self.month, self.day),
+            hours: self.hours,
+            minutes: self.minutes,
+            seconds: 0,
+        };
+        if let Err(e) = hal.set_local_datetime(&time) {
+            warn!("Unable to set time: {e:?}");
+            return None;
+        }
+        let mut time_tile = crate::tiles::time::TimeTile::default();
+        let _ = crate::tiles::move_to_tile(hal, self, &mut time_tile, &Direction::Up);
+        Some(TwatchEvent::new(Kind::NewTile(Box::new(time_tile))))
+    }
+
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing time setting: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing time setting: {e:?}"));
+    }
+}
+
+impl WatchTile for SetTimeTile {
+    fn name(&self) -> &str {
+        "Set time"
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => {
+                match dir {
+                    Direction::Up => self.step(1),
+                    Direction::Down => self.step(-1),
+                    _ => {
+                        info!("Swipe: {dir:?}");
+                        return None;
+                    }
+                }
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                let point = touch_point(p.x, p.y);
+                match (point.x, point.y) {
+                    (0..=99, 30..=99) => self.field = Field::Year,
+                    (100..=169, 30..=99) => self.field = Field::Month,
+                    (170..=239, 30..=99) => self.field = Field::Day,
+                    (0..=119, 100..=179) => self.field = Field::Hours,
+                    (120..=239, 100..=179) => self.field = Field::Minutes,
+                    (0..=79, 180..=239) => {
+                        self.step(-1);
+                        hal.cue(Cue::Detent);
+                    }
+                    (160..=239, 180..=239) => {
+                        self.step(1);
+                        hal.cue(Cue::Detent);
+                    }
+                    (80..=159, 180..=239) => return self.save(hal),
+                    (_, 0..=29) if hal.wifi.has_networks() => {
+                        info!("Time synchronization requested");
+                        hal.time_sync.request();
+                        hal.poll_network_now();
+                    }
+                    _ => return None,
+                }
+                self.refresh(hal);
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let date_font = FontRenderer::new::<fonts::u8g2_font_logisoso26_tn>();
+        let time_font = FontRenderer::new::<fonts::u8g2_font_logisoso50_tn>();
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+
+        match (&self.error, self.prompt) {
+            (Some(e), _) => Text::new(
+                &format!("{e}"),
+                Point::new(10, 20),
+                MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::RED),
+            )
+            .draw(&mut hal.display)?,
+            (None, true) => Text::new(
+                "Clock lost, set it",
+                Point::new(10, 20),
+                MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::YELLOW),
+            )
+            .draw(&mut hal.display)?,
+            (None, false) => match hal.time_sync.last_sync() {
+                Some(report) => {
+                    let at = calendar::from_timestamp(hal.tz.to_local(report.at));
+                    Text::new(
+                        &format!(
+                            "Synced {:02}:{:02} {:+}s",
+                            at.hours, at.minutes, report.drift
+                        ),
+                        Point::new(10, 20),
+                        small_style,
+                    )
+                    .draw(&mut hal.display)?
+                }
+                None => {
+                    Text::new("Set time", Point::new(66, 20), small_style).draw(&mut hal.display)?
+                }
+            },
+        };
+
+        let color = |field: Field| {
+            if field == self.field {
+                Rgb565::WHITE
+            } else {
+                Rgb565::BLUE
+            }
+        };
+        for (text, x, field) in [
+            (format!("{}", self.year), 50, Field::Year),
+            (format!("{:02}", self.month), 135, Field::Month),
+            (format!("{:02}", self.day), 205, Field::Day),
+        ] {
+            date_font
+                .render_aligned(
+                    text.as_str(),
+                    Point::new(x, 80),
+                    VerticalPosition::Baseline,
+                    HorizontalAlignment::Center,
+                    FontColor::Transparent(color(field)),
+                    &mut hal.display,
+                )
+                .expect("Unable to render date");
+        }
+        for (text, x, field) in [
+            (format!("{:02}", self.hours), 65, Field::Hours),
+            (format!("{:02}", self.minutes), 175, Field::Minutes),
+        ] {
+            time_font
+                .render_aligned(
+                    text.as_str(),
+                    Point::new(x, 165),
+                    VerticalPosition::Baseline,
+                    HorizontalAlignment::Center,
+                    FontColor::Transparent(color(field)),
+                    &mut hal.display,
+                )
+                .expect("Unable to render time");
+        }
+        Text::new(":", Point::new(112, 150), style).draw(&mut hal.display)?;
+
+        let rect_style = PrimitiveStyleBuilder::new()
+            .stroke_width(2)
+            .stroke_color(Rgb565::BLUE)
+            .build();
+        for (x, label) in [(0, " -"), (80, "Set"), (160, " +")] {
+            Rectangle::new(Point::new(x + 2, 184), Size::new(76, 54))
+                .into_styled(rect_style)
+                .draw(&mut hal.display)?;
+            Text::new(label, Point::new(x + 16, 220), style).draw(&mut hal.display)?;
+        }
+
+        Ok(())
+    }
+}
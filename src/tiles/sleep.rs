@@ -12,6 +12,10 @@ pub struct SleepTile {}
 unsafe impl Send for SleepTile {}
 
 impl WatchTile for SleepTile {
+    fn name(&self) -> &str {
+        "Sleep"
+    }
+
     fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
         hal.light_sleep()
     }
@@ -22,7 +22,11 @@ use u8g2_fonts::{self, fonts, FontRenderer};
 use accelerometer::Accelerometer;
 use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
 
+use crate::errors::{self, Device, TwatchError};
 use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::tiles::music::MusicTile;
+use crate::tiles::notifications::NotificationsTile;
+use crate::tiles::settime::SetTimeTile;
 use crate::tiles::WatchTile;
 use crate::twatch::Hal;
 
@@ -30,6 +34,8 @@ pub struct TimeTile {
     battery_level: f32,
     time: DateTime,
     accel: F32x3,
+    /// Code of the last error reading the peripherals
+    error: Option<u16>,
     timer: Option<EspTimer>,
 }
 
@@ -47,6 +53,7 @@ impl Default for TimeTile {
                 seconds: 0,
             },
             accel: F32x3::default(),
+            error: None,
             timer: None,
         }
     }
@@ -55,6 +62,10 @@ impl Default for TimeTile {
 unsafe impl Send for TimeTile {}
 
 impl WatchTile for TimeTile {
+    fn name(&self) -> &str {
+        "Time"
+    }
+
     fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
         let mut timer_loop = hal.eventloop.clone();
         let mut periodic_timer = EspTimerService::new()?.timer(move || {
@@ -88,6 +99,21 @@ impl WatchTile for TimeTile {
                     let _ = crate::tiles::move_to_tile(hal, self, &mut ferris_tile, dir);
                     Some(TwatchEvent::new(Kind::NewTile(Box::new(ferris_tile))))
                 }
+                Direction::Left => {
+                    let mut alarm_tile = crate::tiles::alarm::AlarmTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut alarm_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(alarm_tile))))
+                }
+                Direction::Down => {
+                    let mut music_tile = MusicTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut music_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(music_tile))))
+                }
+                Direction::Up => {
+                    let mut notifications_tile = NotificationsTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut notifications_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(notifications_tile))))
+                }
                 _ => {
                     info!("Swipe: {dir:?}");
                     let _ = self
@@ -96,6 +122,20 @@ impl WatchTile for TimeTile {
                     None
                 }
             },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if p.y >= 70 && p.y <= 150 {
+                    let mut settime_tile = SetTimeTile::new(&self.time, false);
+                    let _ = crate::tiles::move_to_tile(
+                        hal,
+                        self,
+                        &mut settime_tile,
+                        &Direction::Down,
+                    );
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(settime_tile))))
+                } else {
+                    Some(event)
+                }
+            }
             (_, Kind::Timer) => {
                 self.update_state(hal);
                 let _ = self
@@ -141,22 +181,42 @@ impl WatchTile for TimeTile {
             self.accel.x, self.accel.y, self.accel.z
         );
         Text::new(&accel, Point::new(30, 220), small_style).draw(&mut hal.display)?;
+
+        if let Some(code) = self.error {
+            let error_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::RED);
+            Text::new(&format!("E{code}"), Point::new(30, 200), error_style)
+                .draw(&mut hal.display)?;
+        }
         Ok(())
     }
 
     fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.error = None;
         match hal.pmu.get_battery_percentage() {
             Ok(battery_level) => self.battery_level = battery_level,
-            Err(err) => error!("Error updating battery level: {}", err),
+            Err(err) => {
+                error!("Error updating battery level: {}", err);
+                self.error = errors::code(&err);
+            }
         }
-        match hal.clock.get_datetime() {
+        match hal.local_now() {
             Ok(time) => self.time = time,
-            Err(err) => error!("Error getting time: {:?}", err),
+            Err(err) => {
+                error!("Error getting time: {:?}", err);
+                self.error = errors::code(&err);
+            }
         }
 
-        match hal.accel.accel_norm() {
+        match hal
+            .accel
+            .accel_norm()
+            .map_err(TwatchError::driver(Device::Accel, "accel norm"))
+        {
             Ok(accel) => self.accel = accel,
-            Err(err) => error!("Error updating accelerometer values: {:?}", err),
+            Err(err) => {
+                error!("Error updating accelerometer values: {}", err);
+                self.error = Some(err.code());
+            }
         }
     }
 }
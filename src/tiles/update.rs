@@ -0,0 +1,169 @@
+use anyhow::Result;
+
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_14_POINT, PROFONT_18_POINT, PROFONT_24_POINT};
+
+use crate::events::{Kind, TwatchEvent};
+use crate::firmware;
+use crate::ota::{self, Installer, UpdateStatus};
+use crate::text::wrap_text;
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+
+const BUTTON_TOP: i32 = 195;
+
+/// Firmware version and progress of the updates, over Wi-Fi or BLE
+pub struct UpdateTile {
+    status: UpdateStatus,
+    configured: bool,
+    running: bool,
+}
+
+impl Default for UpdateTile {
+    fn default() -> Self {
+        Self {
+            status: UpdateStatus::Idle,
+            configured: false,
+            running: false,
+        }
+    }
+}
+
+impl UpdateTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing update: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing update: {e:?}"));
+    }
+}
+
+impl WatchTile for UpdateTile {
+    fn name(&self) -> &str {
+        "Update"
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Right => {
+                    let mut weather_tile = crate::tiles::weather::WeatherTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut weather_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(weather_tile))))
+                }
+                Direction::Left => {
+                    let mut diagnostics_tile =
+                        crate::tiles::diagnostics::DiagnosticsTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut diagnostics_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(diagnostics_tile))))
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if touch_point(p.x, p.y).y < BUTTON_TOP || !self.configured || self.running {
+                    return Some(event);
+                }
+                info!("Firmware update requested");
+                hal.update_service.request();
+                hal.poll_network_now();
+                None
+            }
+            (_, Kind::Update(_)) => {
+                self.refresh(hal);
+                Some(event)
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let medium_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+
+        Text::new("Update", Point::new(10, 30), style).draw(&mut hal.display)?;
+        Text::new(
+            &format!("Version {}", firmware::VERSION),
+            Point::new(10, 64),
+            medium_style,
+        )
+        .draw(&mut hal.display)?;
+
+        let status = if self.configured {
+            self.status.to_string()
+        } else {
+            "Build with TWATCH_OTA_URL and TWATCH_OTA_PUBLIC_KEY".to_string()
+        };
+        let color = match self.status {
+            UpdateStatus::Failed(_) => Rgb565::RED,
+            _ => Rgb565::CSS_LIGHT_GRAY,
+        };
+        let status_style = MonoTextStyle::new(&PROFONT_14_POINT, color);
+        for (i, line) in wrap_text(&status, 22, 3).iter().enumerate() {
+            Text::new(line, Point::new(10, 100 + 20 * i as i32), status_style)
+                .draw(&mut hal.display)?;
+        }
+
+        if let UpdateStatus::Downloading(percent) = self.status {
+            Rectangle::new(Point::new(10, 166), Size::new(220, 8))
+                .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_DIM_GRAY))
+                .draw(&mut hal.display)?;
+            Rectangle::new(
+                Point::new(10, 166),
+                Size::new(220 * percent as u32 / 100, 8),
+            )
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_ORANGE))
+            .draw(&mut hal.display)?;
+        }
+
+        let color = if self.configured && !self.running {
+            Rgb565::WHITE
+        } else {
+            Rgb565::CSS_DIM_GRAY
+        };
+        Rectangle::new(Point::new(40, BUTTON_TOP), Size::new(160, 36))
+            .into_styled(PrimitiveStyle::with_stroke(color, 2))
+            .draw(&mut hal.display)?;
+        Text::with_alignment(
+            "Check",
+            Point::new(120, BUTTON_TOP + 24),
+            MonoTextStyle::new(&PROFONT_14_POINT, color),
+            Alignment::Center,
+        )
+        .draw(&mut hal.display)?;
+        Ok(())
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.status = hal.installer.status();
+        self.configured = ota::MANIFEST_URL.is_some() && ota::PUBLIC_KEY.is_some();
+        self.running = hal.update_service.is_running();
+    }
+}
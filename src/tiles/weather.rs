@@ -0,0 +1,346 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Circle, Line, PrimitiveStyle, Rectangle, Triangle};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_12_POINT, PROFONT_14_POINT, PROFONT_24_POINT};
+
+use crate::alarms::Weekdays;
+use crate::calendar;
+use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::text::wrap_text;
+use crate::tiles::WatchTile;
+use crate::twatch::Hal;
+use crate::weather::{Condition, Report, Source};
+
+/// Current conditions and forecast of the next days
+#[derive(Default)]
+pub struct WeatherTile {
+    report: Option<Report>,
+    /// UTC time in seconds since the Unix epoch
+    now: i64,
+    /// Local date, in days since 1970-01-01
+    today: i64,
+    timer: Option<EspTimer>,
+}
+
+unsafe impl Send for WeatherTile {}
+
+impl WeatherTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing weather: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing weather: {e:?}"));
+    }
+
+    fn display_report(&self, hal: &mut Hal<'static>, report: &Report) -> Result<()> {
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_12_POINT, Rgb565::CSS_LIGHT_GRAY);
+
+        let age = report.age(self.now);
+        let (updated, color) = match age {
+            _ if report.is_stale(self.now) => (format!("stale, {} h old", age / 3600), Rgb565::RED),
+            0..=59 => ("updated now".to_string(), Rgb565::CSS_DIM_GRAY),
+            60..=3599 => (format!("{} min ago", age / 60), Rgb565::CSS_DIM_GRAY),
+            _ => (format!("{} h ago", age / 3600), Rgb565::CSS_DIM_GRAY),
+        };
+        let source = match report.source {
+            Source::Phone => "phone",
+            Source::Http => "web",
+        };
+        let updated_style = MonoTextStyle::new(&PROFONT_12_POINT, color);
+        Text::new(
+            &format!("{updated} ({source})"),
+            Point::new(10, 38),
+            updated_style,
+        )
+        .draw(&mut hal.display)?;
+
+        draw_icon(hal, report.condition, Point::new(40, 72), 48)?;
+        let end = Text::new(
+            &format!("{:.0}", report.temperature),
+            Point::new(76, 84),
+            style,
+        )
+        .draw(&mut hal.display)?;
+        draw_degrees(hal, end, style)?;
+
+        let today = report.today(self.today);
+        let high = report.high.or_else(|| today.map(|d| d.high));
+        let low = report.low.or_else(|| today.map(|d| d.low));
+        if let Some(text) = wrap_text(&report.text, 10, 1).first() {
+            Text::new(text, Point::new(140, 68), text_style).draw(&mut hal.display)?;
+        }
+        if let (Some(high), Some(low)) = (high, low) {
+            Text::new(
+                &format!("{high:.0}/{low:.0}"),
+                Point::new(140, 88),
+                text_style,
+            )
+            .draw(&mut hal.display)?;
+        }
+
+        let mut details = Vec::new();
+        if let Some(humidity) = report.humidity {
+            details.push(format!("Hum {humidity}%"));
+        }
+        if let Some(wind) = report.wind {
+            details.push(format!("Wind {wind:.0} km/h"));
+        }
+        Text::new(&details.join("  "), Point::new(10, 124), small_style).draw(&mut hal.display)?;
+
+        for (i, day) in report.upcoming(self.today).take(3).enumerate() {
+            let center = 40 + 80 * i as i32;
+            Text::with_alignment(
+                Weekdays::LABELS[day.weekday() as usize],
+                Point::new(center, 160),
+                text_style,
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+            draw_icon(hal, day.condition, Point::new(center, 186), 28)?;
+            Text::with_alignment(
+                &format!("{:.0}/{:.0}", day.high, day.low),
+                Point::new(center, 222),
+                small_style,
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+        }
+        Ok(())
+    }
+}
+
+/// Draw a degree sign and "C" after a temperature ending at `end`
+fn draw_degrees(
+    hal: &mut Hal<'static>,
+    end: Point,
+    style: MonoTextStyle<'static, Rgb565>,
+) -> Result<()> {
+    Circle::new(end + Point::new(2, -17), 6)
+        .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 2))
+        .draw(&mut hal.display)?;
+    Text::new("C", end + Point::new(10, 0), style).draw(&mut hal.display)?;
+    Ok(())
+}
+
+/// Draw a condition icon of `size` pixels centered on `center`
+fn draw_icon(hal: &mut Hal<'static>, condition: Condition, center: Point, size: u32) -> Result<()> {
+    let s = size as i32;
+    let sun = PrimitiveStyle::with_fill(Rgb565::YELLOW);
+    let cloud_color = match condition {
+        Condition::Rain | Condition::Thunderstorm => Rgb565::CSS_DIM_GRAY,
+        _ => Rgb565::CSS_LIGHT_GRAY,
+    };
+    let cloud = PrimitiveStyle::with_fill(cloud_color);
+
+    let draw_cloud = |hal: &mut Hal<'static>, center: Point| -> Result<()> {
+        Circle::with_center(center + Point::new(-s / 4, s / 12), size / 2)
+            .into_styled(cloud)
+            .draw(&mut hal.display)?;
+        Circle::with_center(center + Point::new(s / 12, -s / 12), size * 2 / 3)
+            .into_styled(cloud)
+            .draw(&mut hal.display)?;
+        Circle::with_center(center + Point::new(s / 3, s / 8), size / 3)
+            .into_styled(cloud)
+            .draw(&mut hal.display)?;
+        Rectangle::new(
+            center + Point::new(-s / 4, s / 12),
+            Size::new(size * 7 / 12, size / 4),
+        )
+        .into_styled(cloud)
+        .draw(&mut hal.display)?;
+        Ok(())
+    };
+    // Cloud moved up to leave room for the precipitations
+    let high_cloud = center - Point::new(0, s / 6);
+
+    match condition {
+        Condition::Clear => {
+            Circle::with_center(center, size * 2 / 3)
+                .into_styled(sun)
+                .draw(&mut hal.display)?;
+        }
+        Condition::PartlyCloudy => {
+            Circle::with_center(center + Point::new(s / 6, -s / 6), size / 2)
+                .into_styled(sun)
+                .draw(&mut hal.display)?;
+            draw_cloud(hal, center + Point::new(-s / 12, s / 8))?;
+        }
+        Condition::Cloudy => draw_cloud(hal, center)?,
+        Condition::Fog => {
+            for i in -1..=1 {
+                let y = center.y + i * s / 4;
+                Line::new(
+                    Point::new(center.x - s / 2 + 2, y),
+                    Point::new(center.x + s / 2 - 2, y),
+                )
+                .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_LIGHT_GRAY, 3))
+                .draw(&mut hal.display)?;
+            }
+        }
+        Condition::Drizzle | Condition::Rain => {
+            draw_cloud(hal, high_cloud)?;
+            let drops = if condition == Condition::Rain { 3 } else { 2 };
+            for i in 0..drops {
+                let x = center.x - s / 4 + i * s / 4 + if drops == 2 { s / 8 } else { 0 };
+                Line::new(
+                    Point::new(x, center.y + s / 4),
+                    Point::new(x - s / 12, center.y + s / 2),
+                )
+                .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_DEEP_SKY_BLUE, 2))
+                .draw(&mut hal.display)?;
+            }
+        }
+        Condition::Snow => {
+            draw_cloud(hal, high_cloud)?;
+            for i in -1..=1 {
+                Circle::with_center(Point::new(center.x + i * s / 4, center.y + s * 3 / 8), 5)
+                    .into_styled(PrimitiveStyle::with_fill(Rgb565::WHITE))
+                    .draw(&mut hal.display)?;
+            }
+        }
+        Condition::Thunderstorm => {
+            draw_cloud(hal, high_cloud)?;
+            Triangle::new(
+                center + Point::new(s / 12, s / 6),
+                center + Point::new(-s / 8, s * 3 / 8),
+                center + Point::new(s / 24, s * 3 / 8),
+            )
+            .into_styled(sun)
+            .draw(&mut hal.display)?;
+            Triangle::new(
+                center + Point::new(s / 24, s * 3 / 8),
+                center + Point::new(-s / 12, s / 2),
+                center + Point::new(s / 8, s / 3),
+            )
+            .into_styled(sun)
+            .draw(&mut hal.display)?;
+        }
+        Condition::Unknown => {
+            Text::with_alignment(
+                "?",
+                center + Point::new(0, 8),
+                MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::CSS_LIGHT_GRAY),
+                Alignment::Center,
+            )
+            .draw(&mut hal.display)?;
+        }
+    }
+    Ok(())
+}
+
+impl WatchTile for WeatherTile {
+    fn name(&self) -> &str {
+        "Weather"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let mut timer_loop = hal.eventloop.clone();
+        let mut periodic_timer = EspTimerService::new()?.timer(move || {
+            let _ = timer_loop.post(
+                &TwatchRawEvent::Timer.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        periodic_timer.every(Duration::from_secs(5))?;
+        self.timer = Some(periodic_timer);
+        Ok(())
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Right => {
+                    let mut wifi_tile = crate::tiles::wifi::WifiTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut wifi_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(wifi_tile))))
+                }
+                Direction::Left => {
+                    let mut update_tile = crate::tiles::update::UpdateTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut update_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(update_tile))))
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(_))) => {
+                info!("Weather update requested");
+                hal.weather_service.request();
+                hal.poll_network_now();
+                None
+            }
+            (_, Kind::Timer) => {
+                self.refresh(hal);
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let text_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+
+        let location = self
+            .report
+            .as_ref()
+            .map(|r| r.location.as_str())
+            .filter(|l| !l.is_empty())
+            .unwrap_or("Weather");
+        if let Some(location) = wrap_text(location, 20, 1).first() {
+            Text::new(location, Point::new(10, 20), text_style).draw(&mut hal.display)?;
+        }
+
+        match &self.report {
+            Some(report) => self.display_report(hal, report),
+            None => {
+                let message = if hal.weather_service.url.is_some() {
+                    "Waiting for data"
+                } else {
+                    "Connect the phone"
+                };
+                Text::with_alignment(message, Point::new(120, 120), text_style, Alignment::Center)
+                    .draw(&mut hal.display)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.report = hal.weather.clone();
+        self.now = calendar::utc_now();
+        self.today = hal.tz.to_local(self.now).div_euclid(86_400);
+    }
+}
@@ -0,0 +1,191 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::event_bus::Postbox;
+use embedded_svc::timer::{PeriodicTimer, TimerService};
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
+use ft6x36::{Direction, TouchEvent};
+use log::*;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Text};
+use embedded_graphics::Drawable;
+
+use profont::{PROFONT_14_POINT, PROFONT_18_POINT, PROFONT_24_POINT};
+
+use crate::display::WifiIndicator;
+use crate::events::{Kind, TwatchEvent, TwatchRawEvent};
+use crate::tiles::{touch_point, WatchTile};
+use crate::twatch::Hal;
+use crate::wifi::{AP_IP, AP_SSID};
+
+/// Wi-Fi status and provisioning through the SoftAP portal
+pub struct WifiTile {
+    indicator: WifiIndicator,
+    ssid: Option<String>,
+    ip: Option<Ipv4Addr>,
+    rssi: Option<i8>,
+    networks: usize,
+    timer: Option<EspTimer>,
+}
+
+impl Default for WifiTile {
+    fn default() -> Self {
+        Self {
+            indicator: WifiIndicator::Off,
+            ssid: None,
+            ip: None,
+            rssi: None,
+            networks: 0,
+            timer: None,
+        }
+    }
+}
+
+unsafe impl Send for WifiTile {}
+
+impl WifiTile {
+    fn refresh(&mut self, hal: &mut Hal<'static>) {
+        self.update_state(hal);
+        let _ = self
+            .display_tile(hal)
+            .map_err(|e| warn!("Error refreshing state: {e:?}"));
+        let _ = hal
+            .display
+            .commit_display()
+            .map_err(|e| warn!("Error refreshing state: {e:?}"));
+    }
+}
+
+impl WatchTile for WifiTile {
+    fn name(&self) -> &str {
+        "Wi-Fi"
+    }
+
+    fn init(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        let mut timer_loop = hal.eventloop.clone();
+        let mut periodic_timer = EspTimerService::new()?.timer(move || {
+            let _ = timer_loop.post(
+                &TwatchRawEvent::Timer.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        periodic_timer.every(Duration::from_secs(1))?;
+        self.timer = Some(periodic_timer);
+        Ok(())
+    }
+
+    fn run(&mut self, hal: &mut Hal<'static>) -> Result<()> {
+        self.update_state(hal);
+        self.display_tile(hal)?;
+        hal.display.commit_display()?;
+
+        Ok(())
+    }
+
+    fn process_event(
+        &mut self,
+        hal: &mut Hal<'static>,
+        event: crate::events::TwatchEvent,
+    ) -> Option<TwatchEvent> {
+        match (&event.time, &event.kind) {
+            (_, Kind::Touch(TouchEvent::Swipe(dir, _info))) => match dir {
+                Direction::Right => {
+                    let mut power_tile = crate::tiles::power::PowerTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut power_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(power_tile))))
+                }
+                Direction::Left => {
+                    let mut weather_tile = crate::tiles::weather::WeatherTile::default();
+                    let _ = crate::tiles::move_to_tile(hal, self, &mut weather_tile, dir);
+                    Some(TwatchEvent::new(Kind::NewTile(Box::new(weather_tile))))
+                }
+                _ => {
+                    info!("Swipe: {dir:?}");
+                    None
+                }
+            },
+            (_, Kind::Touch(TouchEvent::TouchOnePoint(p))) => {
+                if touch_point(p.x, p.y).y < 190 {
+                    return Some(event);
+                }
+                let result = if self.indicator == WifiIndicator::AccessPoint {
+                    hal.stop_provisioning()
+                } else {
+                    hal.start_provisioning()
+                };
+                if let Err(e) = result {
+                    warn!("Unable to switch provisioning: {e:?}");
+                }
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Timer) => {
+                self.refresh(hal);
+                None
+            }
+            (_, Kind::Wifi(_)) => {
+                self.refresh(hal);
+                Some(event)
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn display_tile(&self, hal: &mut Hal<'static>) -> Result<()> {
+        let style = MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::WHITE);
+        let medium_style = MonoTextStyle::new(&PROFONT_18_POINT, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&PROFONT_14_POINT, Rgb565::WHITE);
+
+        Text::new("Wi-Fi", Point::new(10, 30), style).draw(&mut hal.display)?;
+
+        let provisioning = self.indicator == WifiIndicator::AccessPoint;
+        let lines = if provisioning {
+            vec![
+                "Join network".to_string(),
+                AP_SSID.to_string(),
+                "then browse".to_string(),
+                format!("http://{AP_IP}"),
+            ]
+        } else {
+            let mut lines = vec![match (&self.ssid, self.indicator) {
+                (Some(ssid), WifiIndicator::Connected { .. }) => ssid.clone(),
+                (_, WifiIndicator::Off) => "Off".to_string(),
+                _ => "Connecting...".to_string(),
+            }];
+            if let Some(ip) = self.ip {
+                lines.push(format!("{ip}"));
+            }
+            if let Some(rssi) = self.rssi {
+                lines.push(format!("{rssi} dBm"));
+            }
+            lines.push(format!("{} networks", self.networks));
+            lines
+        };
+        for (i, line) in lines.iter().enumerate() {
+            Text::new(line, Point::new(10, 70 + 28 * i as i32), medium_style)
+                .draw(&mut hal.display)?;
+        }
+
+        Rectangle::new(Point::new(40, 195), Size::new(160, 36))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 2))
+            .draw(&mut hal.display)?;
+        let button = if provisioning { "Stop" } else { "Setup" };
+        Text::with_alignment(button, Point::new(120, 219), small_style, Alignment::Center)
+            .draw(&mut hal.display)?;
+        Ok(())
+    }
+
+    fn update_state(&mut self, hal: &mut Hal<'static>) {
+        self.indicator = hal.wifi.indicator();
+        self.ssid = hal.wifi.ssid();
+        self.ip = hal.wifi.ip();
+        self.rssi = hal.wifi.rssi();
+        self.networks = hal.wifi.networks().len();
+    }
+}
@@ -0,0 +1,403 @@
+//! Periodic time synchronization state machine.
+//!
+//! The hardware is reached through the `Link`, `TimeSource` and `Rtc` traits
+//! and time is passed explicitly to `TimeSync::poll`, so the state machine
+//! does not depend on the ESP32.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+/// Network link needed by the time source, brought up only while syncing
+pub trait Link {
+    fn connect(&mut self) -> Result<()>;
+
+    fn is_connected(&mut self) -> bool;
+
+    fn disconnect(&mut self) -> Result<()>;
+}
+
+/// Source of the current UTC time, e.g. SNTP
+pub trait TimeSource {
+    fn start(&mut self) -> Result<()>;
+
+    /// UTC time in seconds since the Unix epoch, once available
+    fn poll(&mut self) -> Option<i64>;
+
+    fn stop(&mut self);
+}
+
+/// Clock updated by the synchronization
+pub trait Rtc {
+    /// UTC time in seconds since the Unix epoch
+    fn read(&mut self) -> Result<i64>;
+
+    fn write(&mut self, utc: i64) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    Idle,
+    Connecting { since: Duration },
+    Syncing { since: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncReport {
+    /// UTC time of the synchronization in seconds since the Unix epoch
+    pub at: i64,
+    /// RTC time minus source time before the RTC was updated, in seconds
+    pub drift: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncError {
+    ConnectTimeout,
+    SyncTimeout,
+    Failed,
+}
+
+impl std::fmt::Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_fmt(format_args!("{:?}", self))
+    }
+}
+
+pub struct TimeSync {
+    /// Delay between two successful synchronizations
+    pub interval: Duration,
+    /// Delay before retrying a failed synchronization
+    pub retry: Duration,
+    /// Maximum duration of each of the connection and synchronization steps
+    pub timeout: Duration,
+    state: SyncState,
+    next_at: Duration,
+    last: Option<SyncReport>,
+    last_error: Option<SyncError>,
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(12 * 3600),
+            retry: Duration::from_secs(15 * 60),
+            timeout: Duration::from_secs(30),
+            state: SyncState::Idle,
+            next_at: Duration::ZERO,
+            last: None,
+            last_error: None,
+        }
+    }
+}
+
+impl TimeSync {
+    pub fn last_sync(&self) -> Option<SyncReport> {
+        self.last
+    }
+
+    pub fn last_error(&self) -> Option<SyncError> {
+        self.last_error
+    }
+
+    /// Synchronize at the next poll instead of waiting for the interval
+    pub fn request(&mut self) {
+        self.next_at = Duration::ZERO;
+    }
+
+    /// Advance the state machine, `now` being a monotonic time as
+    /// `utils::uptime`: the clock being synchronized jumps when it is set
+    ///
+    /// Returns the report of a synchronization completed during this poll.
+    pub fn poll(
+        &mut self,
+        now: Duration,
+        link: &mut impl Link,
+        source: &mut impl TimeSource,
+        rtc: &mut impl Rtc,
+    ) -> Option<SyncReport> {
+        let result = match self.state {
+            SyncState::Idle if now >= self.next_at => link
+                .connect()
+                .map(|_| self.state = SyncState::Connecting { since: now })
+                .map_err(|e| {
+                    log::warn!("Unable to connect: {e:?}");
+                    SyncError::Failed
+                }),
+            SyncState::Idle => Ok(()),
+            SyncState::Connecting { since } => {
+                if link.is_connected() {
+                    source
+                        .start()
+                        .map(|_| self.state = SyncState::Syncing { since: now })
+                        .map_err(|e| {
+                            log::warn!("Unable to start time source: {e:?}");
+                            SyncError::Failed
+                        })
+                } else if now.saturating_sub(since) > self.timeout {
+                    Err(SyncError::ConnectTimeout)
+                } else {
+                    Ok(())
+                }
+            }
+            SyncState::Syncing { since } => match source.poll() {
+                Some(utc) => {
+                    let report = rtc
+                        .read()
+                        .and_then(|rtc_time| rtc.write(utc).map(|_| rtc_time - utc))
+                        .map(|drift| SyncReport { at: utc, drift });
+                    match report {
+                        Ok(report) => {
+                            self.finish(now, link, source, Ok(report));
+                            return Some(report);
+                        }
+                        Err(e) => {
+                            log::warn!("Unable to update RTC: {e:?}");
+                            Err(SyncError::Failed)
+                        }
+                    }
+                }
+                None if now.saturating_sub(since) > self.timeout => Err(SyncError::SyncTimeout),
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = result {
+            self.finish(now, link, source, Err(e));
+        }
+        None
+    }
+
+    fn finish(
+        &mut self,
+        now: Duration,
+        link: &mut impl Link,
+        source: &mut impl TimeSource,
+        result: Result<SyncReport, SyncError>,
+    ) {
+        source.stop();
+        if let Err(e) = link.disconnect() {
+            log::warn!("Unable to disconnect: {e:?}");
+        }
+        self.state = SyncState::Idle;
+        match result {
+            Ok(report) => {
+                self.last = Some(report);
+                self.last_error = None;
+                self.next_at = now + self.interval;
+            }
+            Err(e) => {
+                self.last_error = Some(e);
+                self.next_at = now + self.retry;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeLink {
+        connected: bool,
+        up: bool,
+        fail: bool,
+    }
+
+    impl Link for FakeLink {
+        fn connect(&mut self) -> Result<()> {
+            if self.fail {
+                anyhow::bail!("No network");
+            }
+            self.up = true;
+            Ok(())
+        }
+
+        fn is_connected(&mut self) -> bool {
+            self.up && self.connected
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            self.up = false;
+            Ok(())
+        }
+    }
+
+    /// NTP server answering `utc` once started, if set
+    #[derive(Default)]
+    struct FakeNtp {
+        utc: Option<i64>,
+        started: bool,
+    }
+
+    impl TimeSource for FakeNtp {
+        fn start(&mut self) -> Result<()> {
+            self.started = true;
+            Ok(())
+        }
+
+        fn poll(&mut self) -> Option<i64> {
+            self.utc.filter(|_| self.started)
+        }
+
+        fn stop(&mut self) {
+            self.started = false;
+        }
+    }
+
+    struct FakeRtc(i64);
+
+    impl Rtc for FakeRtc {
+        fn read(&mut self) -> Result<i64> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, utc: i64) -> Result<()> {
+            self.0 = utc;
+            Ok(())
+        }
+    }
+
+    const UTC: i64 = 1_700_000_000;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn synchronizes() {
+        let mut sync = TimeSync::default();
+        let mut link = FakeLink {
+            connected: true,
+            ..Default::default()
+        };
+        let mut ntp = FakeNtp {
+            utc: Some(UTC),
+            ..Default::default()
+        };
+        let mut rtc = FakeRtc(UTC + 42);
+
+        assert_eq!(sync.poll(secs(0), &mut link, &mut ntp, &mut rtc), None);
+        assert_eq!(sync.state, SyncState::Connecting { since: secs(0) });
+        assert_eq!(sync.poll(secs(1), &mut link, &mut ntp, &mut rtc), None);
+        assert_eq!(sync.state, SyncState::Syncing { since: secs(1) });
+        let report = SyncReport { at: UTC, drift: 42 };
+        assert_eq!(
+            sync.poll(secs(2), &mut link, &mut ntp, &mut rtc),
+            Some(report)
+        );
+        assert_eq!(rtc.0, UTC);
+        assert_eq!(sync.state, SyncState::Idle);
+        assert_eq!(sync.last_sync(), Some(report));
+        assert!(!link.up && !ntp.started);
+
+        // Next one after the interval
+        let next = secs(2) + sync.interval;
+        sync.poll(next - secs(1), &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Idle);
+        sync.poll(next, &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Connecting { since: next });
+    }
+
+    #[test]
+    fn request_skips_the_interval() {
+        let mut sync = TimeSync::default();
+        let mut link = FakeLink {
+            connected: true,
+            ..Default::default()
+        };
+        let mut ntp = FakeNtp {
+            utc: Some(UTC),
+            ..Default::default()
+        };
+        let mut rtc = FakeRtc(UTC);
+        for now in 0..3 {
+            sync.poll(secs(now), &mut link, &mut ntp, &mut rtc);
+        }
+        assert!(sync.last_sync().is_some());
+
+        sync.request();
+        sync.poll(secs(3), &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Connecting { since: secs(3) });
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let mut sync = TimeSync::default();
+        let mut link = FakeLink::default();
+        let mut ntp = FakeNtp::default();
+        let mut rtc = FakeRtc(UTC);
+
+        sync.poll(secs(0), &mut link, &mut ntp, &mut rtc);
+        sync.poll(sync.timeout, &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Connecting { since: secs(0) });
+        let late = sync.timeout + secs(1);
+        sync.poll(late, &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Idle);
+        assert_eq!(sync.last_error(), Some(SyncError::ConnectTimeout));
+        assert!(!link.up);
+
+        // Retried later
+        sync.poll(late + sync.retry - secs(1), &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Idle);
+        sync.poll(late + sync.retry, &mut link, &mut ntp, &mut rtc);
+        assert!(matches!(sync.state, SyncState::Connecting { .. }));
+    }
+
+    #[test]
+    fn sync_timeout() {
+        let mut sync = TimeSync::default();
+        let mut link = FakeLink {
+            connected: true,
+            ..Default::default()
+        };
+        let mut ntp = FakeNtp::default();
+        let mut rtc = FakeRtc(UTC);
+
+        sync.poll(secs(0), &mut link, &mut ntp, &mut rtc);
+        sync.poll(secs(1), &mut link, &mut ntp, &mut rtc);
+        let late = secs(1) + sync.timeout + secs(1);
+        assert_eq!(sync.poll(late, &mut link, &mut ntp, &mut rtc), None);
+        assert_eq!(sync.last_error(), Some(SyncError::SyncTimeout));
+        assert_eq!(rtc.0, UTC);
+        assert!(!link.up && !ntp.started);
+    }
+
+    #[test]
+    fn connect_failure() {
+        let mut sync = TimeSync::default();
+        let mut link = FakeLink {
+            fail: true,
+            ..Default::default()
+        };
+        let mut ntp = FakeNtp::default();
+        let mut rtc = FakeRtc(UTC);
+
+        sync.poll(secs(0), &mut link, &mut ntp, &mut rtc);
+        assert_eq!(sync.state, SyncState::Idle);
+        assert_eq!(sync.last_error(), Some(SyncError::Failed));
+    }
+
+    #[test]
+    fn success_clears_the_error() {
+        let mut sync = TimeSync::default();
+        let mut link = FakeLink::default();
+        let mut ntp = FakeNtp {
+            utc: Some(UTC),
+            ..Default::default()
+        };
+        let mut rtc = FakeRtc(0);
+
+        sync.poll(secs(0), &mut link, &mut ntp, &mut rtc);
+        let late = sync.timeout + secs(1);
+        sync.poll(late, &mut link, &mut ntp, &mut rtc);
+        assert!(sync.last_error().is_some());
+
+        link.connected = true;
+        let retry = late + sync.retry;
+        for now in 0..3 {
+            sync.poll(retry + secs(now), &mut link, &mut ntp, &mut rtc);
+        }
+        assert_eq!(sync.last_error(), None);
+        assert_eq!(sync.last_sync().map(|report| report.drift), Some(-UTC));
+    }
+}
@@ -1,6 +1,7 @@
+use std::sync::Arc;
 use std::time::Duration;
 
-use embedded_hal_0_2::digital::v2::OutputPin;
+use embedded_hal_0_2::blocking::i2c::WriteRead;
 use esp_idf_hal::{
     delay,
     gpio::{self, Gpio23, InterruptType, Output, SubscribedInput},
@@ -16,46 +17,157 @@ use anyhow::Result;
 
 use log::*;
 
-use embedded_svc::{event_bus::Postbox, sys_time::SystemTime};
+use embedded_graphics::primitives::ContainsPoint;
+use embedded_svc::{
+    event_bus::Postbox,
+    timer::{OnceTimer, PeriodicTimer, TimerService},
+};
+use esp_idf_svc::netif::EspNetifStack;
 use esp_idf_svc::notify::EspNotify;
+use esp_idf_svc::nvs::EspDefaultNvs;
+use esp_idf_svc::sysloop::EspSysLoopStack;
+use esp_idf_svc::timer::{EspTimer, EspTimerService};
 
+use accelerometer::Accelerometer;
 use display_interface_spi::SPIInterfaceNoCS;
 
 use bma423::Bma423;
-use ft6x36::Ft6x36;
-use pcf8563::PCF8563;
+use ft6x36::{Ft6x36, SwipeInfo, TouchEvent};
+use pcf8563::{DateTime, PCF8563};
 
 use crate::{
-    display::{Backlight, TwatchDisplay},
+    alarms::AlarmService,
+    ble::{Ble, BleEvent},
+    calendar,
+    console::Console,
+    crash::CrashReport,
+    dfu::{self, Transfer},
+    gadgetbridge::{CallState, Command, NowPlaying, PhoneEvent},
+    display::{Backlight, Popup, TwatchDisplay},
+    feedback::{Cue, Feedback, LongPress},
+    firmware::{self, HttpInstaller, OtaSlot},
+    haptics::{Haptics, Pattern},
+    health::{Bus, HealthMonitor, Recovery},
+    http::HttpFetcher,
+    logbook, memory,
+    metrics::{self, Metric},
+    notifications::{Inbox, Notification, Notifier, Priority},
+    ota::{self, Installer, PublicKey, UpdateService, UpdateStatus},
+    panic,
     pmu::Pmu,
+    provisioning::Submission,
+    shell::{self, Command as ShellCommand, HapticsSetting},
+    storage::Storage,
     tiles::{self, WatchTile},
+    timesync::{self, TimeSync},
+    tz::TimeZone,
+    vibrator::Vibrator,
+    utils::{self, measure_exec_time},
+    watchdog,
+    weather::{self, Report, WeatherService},
+    wifi::{Sntp, WifiEvent, WifiManager},
 };
-use crate::{pmu::State, types::*};
+use crate::{pmu::Rail, types::*};
 
 pub use crate::errors::*;
 pub use crate::events::*;
 
+const PCF8563_ADDRESS: u8 = 0x51;
+const PCF8563_VL_SECONDS: u8 = 0x02;
+const PCF8563_VOLTAGE_LOW: u8 = 1 << 7;
+
+const TZ_STORAGE_KEY: &str = "tz";
+/// Time zone used until one is configured, IANA name or POSIX TZ rule
+const DEFAULT_TZ: Option<&str> = option_env!("TWATCH_TZ");
+
+const CRASH_STORAGE_KEY: &str = "crash";
+
+const FEEDBACK_STORAGE_KEY: &str = "feedback";
+
+const WEATHER_STORAGE_KEY: &str = "weather";
+/// Forecast endpoint, see `weather`, and the name of its location
+const WEATHER_URL: Option<&str> = option_env!("TWATCH_WEATHER_URL");
+const WEATHER_LOCATION: Option<&str> = option_env!("TWATCH_WEATHER_LOCATION");
+
+/// Polls of the network services while the Wi-Fi is off, scheduled ones
+/// running hourly at most
+const NETWORK_IDLE_POLL: Duration = Duration::from_secs(60);
+
+/// Time a notification pop-up stays over the current tile
+const POPUP_DURATION: Duration = Duration::from_secs(6);
+
 pub struct Hal<'a> {
     pub pmu: Pmu<'a>,
     pub pmu_irq_pin: gpio::Gpio35<SubscribedInput>,
     pub display: TwatchDisplay,
-    pub motor: gpio::Gpio4<Output>,
+    /// Plays the vibration patterns without blocking
+    pub haptics: Haptics,
+    /// Vibrations confirming the input, and their intensity
+    pub feedback: Feedback,
+    /// A cue was played for the event being handled
+    cued: bool,
     pub clock: PCF8563<EspSharedBusI2c0<'a>>,
+    clock_registers: EspSharedBusI2c0<'a>,
     pub rtc_irq: gpio::Gpio37<SubscribedInput>,
+    pub alarms: AlarmService,
+    /// The RTC runs in UTC, this is the zone used to display local time
+    pub tz: TimeZone,
     pub accel: Bma423<EspSharedBusI2c0<'a>>,
     pub accel_irq: gpio::Gpio39<SubscribedInput>,
-    pub touch_screen: Ft6x36<EspI2c1>,
+    pub touch_screen: Ft6x36<EspMonitoredI2c1>,
     pub touch_irq: gpio::Gpio38<SubscribedInput>,
+    /// Failures of the devices on the I2C buses
+    pub health: HealthMonitor,
     pub eventloop: EspNotify,
+    pub storage: Storage,
+    /// Off in safe mode, connected only while a service needs it
+    pub wifi: WifiManager,
+    pub ble: Ble,
+    pub sntp: Sntp,
+    pub time_sync: TimeSync,
+    /// Polls the Wi-Fi and the services using it, at `network_period`
+    network_timer: Option<EspTimer>,
+    network_period: Option<Duration>,
+    pub inbox: Inbox,
+    /// Delivers notifications from other threads through the event loop
+    pub notifier: Notifier,
+    overlay_timer: Option<EspTimer>,
+    heartbeat_timer: Option<EspTimer>,
+    /// Uptime of the last save of the warnings and errors, see `logbook`
+    logs_saved: Duration,
+    /// Music playing on the phone
+    pub now_playing: NowPlaying,
+    /// Last weather report, from the phone or downloaded
+    pub weather: Option<Report>,
+    pub weather_service: WeatherService,
+    http: HttpFetcher,
+    pub update_service: UpdateService,
+    /// Reports the progress of the update through the event loop
+    pub installer: HttpInstaller,
+    /// Update received over BLE
+    dfu: Transfer<OtaSlot>,
+    console: Console,
+    /// Started without the network, as the firmware kept crashing
+    pub safe_mode: bool,
 }
 
 pub struct Twatch<'a> {
     pub hal: Hal<'a>,
     pub current_tile: Box<dyn WatchTile + Send>,
+    /// Whether the task of the event loop is watched, see `watchdog`
+    watched: bool,
+    long_press: LongPress,
 }
 
 impl Twatch<'static> {
-    pub fn new(peripherals: Peripherals, eventloop: EspNotify) -> Self {
+    pub fn new(
+        peripherals: Peripherals,
+        eventloop: EspNotify,
+        default_nvs: Arc<EspDefaultNvs>,
+        netif_stack: Arc<EspNetifStack>,
+        sys_loop_stack: Arc<EspSysLoopStack>,
+        safe_mode: bool,
+    ) -> Self {
         let pins = peripherals.pins;
         let backlight = pins
             .gpio12
@@ -111,6 +223,9 @@ impl Twatch<'static> {
             .gpio4
             .into_output()
             .expect("Unable to set gpio4 to output");
+        let vibrator = Vibrator::new(peripherals.ledc.channel1, peripherals.ledc.timer1, motor)
+            .expect("Unable to initialize motor");
+        let haptics = Haptics::start(vibrator).expect("Unable to start haptics");
 
         let i2c0 = peripherals.i2c0;
         let sda = pins
@@ -126,11 +241,14 @@ impl Twatch<'static> {
         let i2c0 = i2c::Master::<i2c::I2C0, _, _>::new(i2c0, i2c::MasterPins { sda, scl }, config)
             .expect("Unable to initialize I2C0");
 
-        let i2c0_shared_bus: &'static _ = shared_bus::new_std!(crate::types::EspI2c0 = i2c0)
-            .expect("Unable to initialize shared_bus");
+        let i2c0_shared_bus: &'static _ = Box::leak(Box::new(shared_bus::BusManager::<
+            crate::bus::SharedI2c0,
+        >::new(i2c0)));
         info!("I2c shared bus initialized");
+        let health = HealthMonitor::default();
 
-        let clock = PCF8563::new(i2c0_shared_bus.acquire_i2c());
+        let clock = PCF8563::new(health.monitor(i2c0_shared_bus.acquire_i2c()));
+        let clock_registers = health.monitor(i2c0_shared_bus.acquire_i2c());
         let rtc_irq = pins
             .gpio37
             .into_input()
@@ -147,7 +265,10 @@ impl Twatch<'static> {
         }
         .expect("Unable to register handler for rtc IRQ");
 
-        let pmu = Pmu::new(i2c0_shared_bus.acquire_i2c());
+        let pmu = Pmu::new(
+            health.monitor(i2c0_shared_bus.acquire_i2c()),
+            health.monitor(i2c0_shared_bus.acquire_i2c()),
+        );
         let pmu_irq_pin = pins
             .gpio35
             .into_input()
@@ -164,7 +285,7 @@ impl Twatch<'static> {
         }
         .expect("Unable to register handler for pmu irq");
 
-        let accel = Bma423::new(i2c0_shared_bus.acquire_i2c());
+        let accel = Bma423::new(health.monitor(i2c0_shared_bus.acquire_i2c()));
         let accel_irq = pins.gpio39.into_input().expect("Unable to set gpio39 to input");
         let mut accel_eventloop = eventloop.clone();
         let accel_irq = unsafe {
@@ -188,7 +309,7 @@ impl Twatch<'static> {
         let i2c1 = i2c::Master::<i2c::I2C1, _, _>::new(i2c1, i2c::MasterPins { sda, scl }, config)
             .expect("Unable to initialize I2C1");
 
-        let touch_screen = Ft6x36::new(i2c1, ft6x36::Dimension(240, 240));
+        let touch_screen = Ft6x36::new(health.monitor(i2c1), ft6x36::Dimension(240, 240));
         let touch_irq = pins.gpio38.into_input().expect("Unable to set gpio38 to input");
         let mut touch_loop = eventloop.clone();
         let touch_irq = unsafe {
@@ -204,28 +325,75 @@ impl Twatch<'static> {
         }
         .expect("Unable to register handler for touch irq");
 
+        let storage = Storage::new(default_nvs.clone()).expect("Unable to open NVS storage");
+
+        let wifi = WifiManager::new(netif_stack, sys_loop_stack, default_nvs)
+            .expect("Unable to initialize Wi-Fi");
+        let ble = if safe_mode {
+            Ble::disabled()
+        } else {
+            Ble::new(eventloop.clone()).expect("Unable to initialize BLE")
+        };
+        let notifier = Notifier::new(eventloop.clone());
+        let installer = HttpInstaller::new(eventloop.clone());
+        let console = Console::new(eventloop.clone());
+
         let hal = Hal {
             pmu,
             pmu_irq_pin,
             display,
-            motor,
+            haptics,
+            feedback: Feedback::default(),
+            cued: false,
             clock,
+            clock_registers,
             rtc_irq,
+            alarms: AlarmService::default(),
+            tz: TimeZone::default(),
             accel,
             accel_irq,
             touch_screen,
             touch_irq,
+            health,
             eventloop,
+            storage,
+            wifi,
+            ble,
+            sntp: Sntp::default(),
+            time_sync: TimeSync::default(),
+            network_timer: None,
+            network_period: None,
+            inbox: Inbox::default(),
+            notifier,
+            overlay_timer: None,
+            heartbeat_timer: None,
+            logs_saved: Duration::ZERO,
+            now_playing: NowPlaying::default(),
+            weather: None,
+            weather_service: WeatherService::default(),
+            http: HttpFetcher::new(weather::MAX_RESPONSE_LEN),
+            update_service: UpdateService::default(),
+            installer,
+            dfu: Transfer::new(
+                PublicKey::builtin().ok(),
+                firmware::VERSION,
+                OtaSlot::open_at,
+            ),
+            console,
+            safe_mode,
         };
 
         Twatch {
             hal,
             current_tile: Box::new(tiles::hello::HelloTile::default()),
+            watched: false,
+            long_press: LongPress::default(),
         }
     }
 
     pub fn init(&mut self) -> Result<()> {
         info!("Initializing twatch");
+        logbook::load(&self.hal.storage);
 
         info!("Initializing PMU");
         self.hal.pmu.init()?;
@@ -234,47 +402,152 @@ impl Twatch<'static> {
         self.hal.display.init(&mut delay::Ets)?;
 
         info!("Initializing screen power");
-        self.hal.pmu.set_screen_power(State::On)?;
+        self.hal.pmu.acquire(Rail::BACKLIGHT)?;
         self.hal.display.set_display_level(25u32)?;
+        for rail_state in self.hal.pmu.rail_states() {
+            info!("Power rail: {rail_state}");
+        }
 
         info!("Initializing touch screen");
-        self.hal.touch_screen.init().map_err(TwatchError::from)?;
-        match self.hal.touch_screen.get_info() {
-            Some(info) => info!("Touch screen info: {info:?}"),
-            None => warn!("No info"),
-        }
+        self.hal.init_touch_screen()?;
 
         info!("Initializing accelerometer");
-        self.hal
-            .accel
-            .init(&mut delay::Ets)
-            .map_err(TwatchError::from)?;
-        let chip_id = self.hal.accel.get_chip_id().map_err(TwatchError::from)?;
-        info!("BMA423 chip id: {}", chip_id as u8);
+        self.hal.init_accel()?;
+
+        info!("Initializing haptics");
+        self.hal.feedback = self
+            .hal
+            .storage
+            .get(FEEDBACK_STORAGE_KEY)
+            .unwrap_or_else(|e| {
+                warn!("Unable to load the haptics settings: {e:?}");
+                None
+            })
+            .unwrap_or_default();
+        self.hal.haptics.set_intensity(self.hal.feedback.intensity);
 
+        info!("Initializing time");
+        let tz = self
+            .hal
+            .storage
+            .get::<String>(TZ_STORAGE_KEY)
+            .unwrap_or_else(|e| {
+                warn!("Unable to load the time zone: {e:?}");
+                None
+            })
+            .or_else(|| DEFAULT_TZ.map(String::from));
+        if let Some(tz) = tz {
+            match TimeZone::new(&tz) {
+                Ok(tz) => self.hal.tz = tz,
+                Err(e) => warn!("Ignoring time zone: {e}"),
+            }
+        }
+        self.hal.sync_system_time()?;
+        if self.hal.is_clock_voltage_low()? {
+            warn!("RTC voltage low, time has to be set");
+            let now = self.hal.local_now()?;
+            self.current_tile = Box::new(tiles::settime::SetTimeTile::new(&now, true));
+        }
+
+        if self.hal.safe_mode {
+            warn!("Safe mode, Wi-Fi and BLE are off");
+        } else {
+            info!("Initializing Wi-Fi");
+            if let Err(e) = self.hal.wifi.load(&mut self.hal.storage) {
+                warn!("Unable to load the Wi-Fi networks: {e:?}");
+            }
+            self.hal.update_network_timer();
+        }
+
+        info!("Initializing alarms");
+        if let Err(e) = self.hal.alarms.load(&self.hal.storage) {
+            warn!("Unable to load the alarms: {e:?}");
+        }
+        // The alarm may have rung while in deep sleep, before the IRQ handler was registered
+        let rang = self
+            .hal
+            .clock
+            .get_alarm_flag()
+            .map_err(TwatchError::driver(Device::Clock, "get alarm flag"))
+            .unwrap_or_else(|e| {
+                warn!("Unable to read the alarm flag: {e:?}");
+                false
+            });
+        if rang {
+            self.hal
+                .eventloop
+                .post(&TwatchRawEvent::Rtc.into(), Some(Duration::from_millis(0)))?;
+        } else if let Err(e) =
+            self.hal
+                .alarms
+                .schedule(&mut self.hal.clock, &mut self.hal.storage, &self.hal.tz)
+        {
+            warn!("Unable to schedule the alarms: {e:?}");
+        }
+
+        info!("Initializing notifications");
+        if let Err(e) = self.hal.inbox.load(&self.hal.storage) {
+            warn!("Unable to load the notifications: {e:?}");
+        }
+        let mut overlay_loop = self.hal.eventloop.clone();
+        let timer = EspTimerService::new()?.timer(move || {
+            let _ = overlay_loop.post(
+                &TwatchRawEvent::Overlay.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        self.hal.overlay_timer = Some(timer);
+
+        info!("Initializing weather");
+        // Only a cache, not worth failing the boot
+        self.hal.weather = self
+            .hal
+            .storage
+            .get(WEATHER_STORAGE_KEY)
+            .unwrap_or_else(|e| {
+                warn!("Unable to load the last weather report: {e:?}");
+                None
+            });
+        self.hal.weather_service.url = WEATHER_URL.map(String::from);
+        self.hal.weather_service.location = WEATHER_LOCATION.unwrap_or_default().to_string();
+
+        info!("Running firmware {}", firmware::VERSION);
+        self.hal.update_service.url = ota::MANIFEST_URL.map(String::from);
+
+        info!("Initializing console");
         self.hal
-            .accel
-            .set_accel_config(
-                bma423::AccelConfigOdr::Odr100,
-                bma423::AccelConfigBandwidth::NormAvg4,
-                bma423::AccelConfigPerfMode::Continuous,
-                bma423::AccelRange::Range2g,
-            )
-            .map_err(TwatchError::from)?;
+            .console
+            .start()
+            .unwrap_or_else(|e| warn!("Unable to start console: {e:?}"));
+
+        info!("Initializing watchdog");
+        let mut heartbeat_loop = self.hal.eventloop.clone();
+        let mut timer = EspTimerService::new()?.timer(move || {
+            let _ = heartbeat_loop.post(
+                &TwatchRawEvent::Heartbeat.into(),
+                Some(Duration::from_millis(0)),
+            );
+        })?;
+        timer.every(watchdog::HEARTBEAT)?;
+        self.hal.heartbeat_timer = Some(timer);
 
         Ok(())
     }
 
     fn process_raw_event(&mut self, raw_event: TwatchRawEvent) -> Option<TwatchEvent> {
-        let time = esp_idf_svc::systime::EspSystemTime {}.now();
+        let time = utils::uptime();
         match raw_event {
             TwatchRawEvent::Touch => {
                 log::debug!("Touch event");
+                let touch_event = self.hal.touch_screen.get_touch_event().ok()?;
+                // The screen keeps reporting while a finger stays down
+                if self.long_press.touch(time, touch_event.p1.is_some()) {
+                    debug!("Long press");
+                    self.hal.cue(Cue::LongPress);
+                }
                 self.hal
                     .touch_screen
-                    .get_touch_event()
-                    .ok()
-                    .and_then(|touch_event| self.hal.touch_screen.process_event(time, touch_event))
+                    .process_event(time, touch_event)
                     .map(|touch_event| TwatchEvent::new(Kind::Touch(touch_event)))
             }
             TwatchRawEvent::Accel => {
@@ -290,12 +563,58 @@ impl Twatch<'static> {
             }
             TwatchRawEvent::Rtc => {
                 info!("Rtc Event");
-                None
+                match self.hal.alarms.on_irq(
+                    &mut self.hal.clock,
+                    &mut self.hal.storage,
+                    &self.hal.tz,
+                ) {
+                    Ok(alarm) => alarm.map(|alarm| TwatchEvent::new(Kind::Alarm(alarm))),
+                    Err(e) => {
+                        warn!("Error handling RTC alarm: {e:?}");
+                        None
+                    }
+                }
             }
             TwatchRawEvent::Timer => {
                 info!("Timer event");
                 Some(TwatchEvent::new(Kind::Timer))
             }
+            TwatchRawEvent::Network => self
+                .hal
+                .poll_network(time)
+                .map(|event| TwatchEvent::new(Kind::Wifi(event))),
+            TwatchRawEvent::Ble => self.hal.poll_ble().map(TwatchEvent::new),
+            TwatchRawEvent::Notification => {
+                let (notification, pending) = self.hal.notifier.take()?;
+                if pending {
+                    let _ = self.hal.eventloop.post(
+                        &TwatchRawEvent::Notification.into(),
+                        Some(Duration::from_millis(0)),
+                    );
+                }
+                Some(TwatchEvent::new(Kind::Notification(notification)))
+            }
+            TwatchRawEvent::Overlay => Some(TwatchEvent::new(Kind::OverlayTimeout)),
+            TwatchRawEvent::Update => {
+                Some(TwatchEvent::new(Kind::Update(self.hal.installer.status())))
+            }
+            TwatchRawEvent::Shell => {
+                let (command, pending) = self.hal.console.take()?;
+                if pending {
+                    let _ = self
+                        .hal
+                        .eventloop
+                        .post(&TwatchRawEvent::Shell.into(), Some(Duration::from_millis(0)));
+                }
+                self.run_command(command)
+            }
+            TwatchRawEvent::Heartbeat => {
+                self.hal.save_logs();
+                if self.hal.dfu.is_idle(time) {
+                    self.hal.release_dfu();
+                }
+                None
+            }
             _ => {
                 warn!("Unhandled event: {:?}", &raw_event);
                 None
@@ -303,26 +622,159 @@ impl Twatch<'static> {
         }
     }
 
-    pub fn process_event(&mut self, raw_event: TwatchRawEvent) {
-        let _ = self.process_raw_event(raw_event).map(move |event| {
+    /// Handle the notifications and the input of their pop-up, which is drawn
+    /// over the current tile
+    fn process_overlay_event(&mut self, event: TwatchEvent) -> Option<TwatchEvent> {
+        let showing = self.hal.display.popup.is_some();
+        match event.kind {
+            Kind::Notification(notification) => {
+                self.show_notification(notification);
+                None
+            }
+            Kind::OverlayTimeout => {
+                self.hide_popup();
+                None
+            }
+            Kind::Touch(TouchEvent::TouchOnePoint(p))
+                if showing && Popup::AREA.contains(tiles::touch_point(p.x, p.y)) =>
+            {
+                self.hal.display.popup = None;
+                self.hal.cue(Cue::TileChange);
+                let mut tile = Box::new(tiles::notifications::NotificationsTile::default());
+                let _ = tile.init(&mut self.hal);
+                let _ = tile.run(&mut self.hal);
+                self.current_tile = tile;
+                None
+            }
+            Kind::Touch(_) | Kind::PmuButtonPressed if showing => {
+                self.hide_popup();
+                None
+            }
+            kind => Some(TwatchEvent {
+                time: event.time,
+                kind,
+            }),
+        }
+    }
+
+    fn show_notification(&mut self, notification: Notification) {
+        info!("Notification from {}: {}", notification.app, notification.title);
+        let hal = &mut self.hal;
+        let priority = notification.priority;
+        let popup = Popup {
+            title: if notification.title.is_empty() {
+                notification.app.clone()
+            } else {
+                notification.title.clone()
+            },
+            text: notification.body.clone(),
+        };
+        hal.inbox.push(&mut hal.storage, notification);
+
+        match (priority, hal.is_sleeping()) {
+            (Priority::Low, _) | (Priority::Normal, true) => return,
+            (Priority::High, true) => {
+                hal.wake_up()
+                    .unwrap_or_else(|e| warn!("Error waking up: {}", e));
+                let mut tile = Box::new(tiles::time::TimeTile::default());
+                let _ = tile.init(hal);
+                self.current_tile = tile;
+            }
+            _ => {}
+        }
+
+        self.hal.display.popup = Some(popup);
+        self.hal.haptics.play(Pattern::Notification);
+        if let Some(timer) = &mut self.hal.overlay_timer {
+            timer
+                .after(POPUP_DURATION)
+                .unwrap_or_else(|e| warn!("Unable to start pop-up timer: {e:?}"));
+        }
+        let _ = self.current_tile.run(&mut self.hal);
+    }
+
+    fn hide_popup(&mut self) {
+        if self.hal.display.popup.take().is_some() {
+            let _ = self.current_tile.run(&mut self.hal);
+        }
+    }
+
+    /// Handle the events posted since the last wakeup, as bits of
+    /// `TwatchRawEvent`
+    pub fn process_events(&mut self, bits: u32) {
+        for raw_event in TwatchRawEvent::split(bits) {
+            self.process_event(raw_event);
+        }
+    }
+
+    fn process_event(&mut self, raw_event: TwatchRawEvent) {
+        if !self.watched {
+            watchdog::register().unwrap_or_else(|e| warn!("Unable to watch event loop: {e:?}"));
+            self.watched = true;
+        }
+        let _display = panic::lock_display();
+        let tile = self.current_tile.name().to_string();
+        watchdog::begin(raw_event, &tile);
+        measure_exec_time!(
+            self.handle_event(raw_event),
+            format_args!("{raw_event:?} in {tile} tile"),
+            Metric::Event
+        );
+        self.hal.check_health();
+        watchdog::end();
+    }
+
+    fn handle_event(&mut self, raw_event: TwatchRawEvent) {
+        let _ = self.process_raw_event(raw_event).and_then(move |event| {
+            let event = self.process_overlay_event(event)?;
             let current_tile = &mut self.current_tile;
             let hal = &mut self.hal;
-            if let Some(event) = current_tile.process_event(hal, event) {
+            let tap = matches!(event.kind, Kind::Touch(TouchEvent::TouchOnePoint(_)));
+            hal.cued = false;
+            let event = current_tile.process_event(hal, event);
+            // Taps handled without a cue of their own, as on a button
+            if tap && event.is_none() && !hal.cued {
+                hal.cue(Cue::Tap);
+            }
+            if let Some(event) = event {
                 match (event.time, event.kind) {
                     (_t, Kind::NewTile(mut tile)) => {
+                        hal.cue(Cue::TileChange);
                         let _ = tile.init(hal);
                         self.current_tile = tile;
                     }
-                    (_t, Kind::PmuButtonPressed) => {
-                        hal.light_sleep()
-                            .unwrap_or_else(|e| warn!("Error going to light sleep: {}", e));
-                        let mut tile = Box::new(crate::tiles::sleep::SleepTile::default());
+                    (_t, Kind::PmuButtonPressed) => self.sleep(),
+                    (_t, Kind::Alarm(alarm)) => {
+                        info!("Alarm {alarm} is ringing");
+                        hal.display.popup = None;
+                        if hal.is_sleeping() {
+                            hal.wake_up()
+                                .unwrap_or_else(|e| warn!("Error waking up: {}", e));
+                        }
+                        let mut tile = Box::new(crate::tiles::ring::RingTile::new(alarm));
                         let _ = tile.init(hal);
+                        let _ = tile.run(hal);
                         self.current_tile = tile;
                     }
+                    (_t, Kind::Wifi(event)) => info!("Wi-Fi: {:?}", event),
+                    (_t, Kind::Phone(PhoneEvent::SetTime { utc, offset })) => {
+                        info!("Time set by phone, offset {:?}h", offset);
+                        hal.set_utc_time(utc)
+                            .unwrap_or_else(|e| warn!("Error setting time: {}", e));
+                    }
+                    (_t, Kind::Phone(PhoneEvent::NotificationDismissed(id))) => {
+                        hal.inbox.dismiss_remote(&mut hal.storage, id);
+                    }
+                    (_t, Kind::Phone(event)) => info!("Phone: {:?}", event),
+                    (_t, Kind::Update(UpdateStatus::Done { version })) => {
+                        info!("Firmware {version} installed");
+                        firmware::restart();
+                    }
+                    (_t, Kind::Update(status)) => info!("Update: {status}"),
                     (_t, event) => warn!("Unhandled event: {:?}", &event),
                 }
             }
+            Some(())
         });
     }
 
@@ -330,30 +782,556 @@ impl Twatch<'static> {
         self.current_tile.run(&mut self.hal)?;
         Ok(())
     }
+
+    /// Keep the report of the crash before this boot and show it
+    pub fn show_crash(&mut self, report: CrashReport) {
+        if let Err(e) = self.hal.storage.put(CRASH_STORAGE_KEY, &report) {
+            warn!("Unable to store crash report: {e:?}");
+        }
+        let mut tile = Box::new(tiles::crash::CrashTile::default());
+        let _ = tile.init(&mut self.hal);
+        self.current_tile = tile;
+    }
+
+    fn sleep(&mut self) {
+        self.hal
+            .light_sleep()
+            .unwrap_or_else(|e| warn!("Error going to light sleep: {}", e));
+        let mut tile = Box::new(crate::tiles::sleep::SleepTile::default());
+        let _ = tile.init(&mut self.hal);
+        self.current_tile = tile;
+    }
+
+    /// Run a command of the serial console, returning the input it injects
+    fn run_command(&mut self, command: ShellCommand) -> Option<TwatchEvent> {
+        let hal = &mut self.hal;
+        match command {
+            ShellCommand::Help => print!("{}", shell::help()),
+            ShellCommand::Status => hal.print_status(),
+            ShellCommand::Time => match hal.local_now() {
+                Ok(now) => println!(
+                    "20{:02}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+                    now.year, now.month, now.day, now.hours, now.minutes, now.seconds, hal.tz
+                ),
+                Err(e) => println!("Error reading time: {e:?}"),
+            },
+            ShellCommand::SetTime(time) => {
+                match hal.set_local_datetime(&calendar::from_timestamp(time.timestamp())) {
+                    Ok(()) => println!("ok"),
+                    Err(e) => println!("Error setting time: {e:?}"),
+                }
+            }
+            ShellCommand::Tiles => {
+                for (name, _) in tiles::TILES {
+                    println!("{name}");
+                }
+            }
+            ShellCommand::Tile(name) => match tiles::by_name(&name) {
+                Some(mut tile) => {
+                    if hal.is_sleeping() {
+                        hal.wake_up()
+                            .unwrap_or_else(|e| warn!("Error waking up: {}", e));
+                    }
+                    hal.display.popup = None;
+                    let _ = tile.init(hal);
+                    let _ = tile.run(hal);
+                    self.current_tile = tile;
+                }
+                None => println!("unknown tile {name}, try tiles"),
+            },
+            // The x axis of the touch screen is mirrored, see `tiles::touch_point`
+            ShellCommand::Touch { x, y } => {
+                let point = ft6x36::Point { x: 239 - x, y };
+                return Some(TwatchEvent::new(Kind::Touch(TouchEvent::TouchOnePoint(point))));
+            }
+            ShellCommand::Swipe(direction) => {
+                let info = SwipeInfo {
+                    velocity: 0,
+                    point: ft6x36::Point { x: 120, y: 120 },
+                };
+                return Some(TwatchEvent::new(Kind::Touch(TouchEvent::Swipe(direction, info))));
+            }
+            ShellCommand::Brightness(None) => {
+                println!("{}", hal.display.get_display_level())
+            }
+            ShellCommand::Brightness(Some(level)) => hal
+                .display
+                .set_display_level(level)
+                .unwrap_or_else(|e| println!("Error setting brightness: {e:?}")),
+            ShellCommand::Settings => hal.print_settings(),
+            ShellCommand::Screenshot => {
+                self.current_tile.update_state(hal);
+                let result = self.current_tile.display_tile(hal).and_then(|_| {
+                    hal.display.screenshot(|frame| {
+                        // About 10s at 115200 bauds, as long as the watchdog
+                        // timeout
+                        watchdog::feed();
+                        hal.console.write(frame)
+                    })
+                });
+                if let Err(e) = result {
+                    println!("Error taking screenshot: {e:?}");
+                }
+            }
+            ShellCommand::Crash => match hal.crash_report() {
+                Some(report) => println!("{report}"),
+                None => println!("no crash"),
+            },
+            ShellCommand::ClearCrash => match hal.clear_crash_report() {
+                Ok(()) => println!("ok"),
+                Err(e) => println!("Error clearing crash report: {e:?}"),
+            },
+            ShellCommand::Metrics => hal.print_metrics(),
+            ShellCommand::ResetMetrics => {
+                metrics::reset();
+                println!("ok");
+            }
+            ShellCommand::Log { all } => {
+                let level = if all { Level::Trace } else { Level::Warn };
+                for entry in logbook::entries(level).iter().rev() {
+                    println!("{entry}");
+                }
+            }
+            ShellCommand::ClearLog => {
+                logbook::clear(&mut hal.storage);
+                println!("ok");
+            }
+            ShellCommand::Vibrate(pattern) => hal.haptics.play(pattern),
+            ShellCommand::StopVibrating => hal.haptics.cancel(),
+            ShellCommand::Haptics(None) => println!("{}", hal.feedback),
+            ShellCommand::Haptics(Some(setting)) => {
+                let mut feedback = hal.feedback;
+                match setting {
+                    HapticsSetting::Enabled(on) => feedback.enabled = on,
+                    HapticsSetting::Intensity(intensity) => feedback.intensity = intensity,
+                    HapticsSetting::Cue(cue, on) => feedback.set_cue(cue, on),
+                }
+                hal.set_feedback(feedback)
+                    .unwrap_or_else(|e| println!("Error setting haptics: {e:?}"));
+            }
+            ShellCommand::Sleep => self.sleep(),
+        }
+        None
+    }
+}
+
+impl timesync::Rtc for PCF8563<EspSharedBusI2c0<'static>> {
+    fn read(&mut self) -> Result<i64> {
+        let utc = self
+            .get_datetime()
+            .map_err(TwatchError::driver(Device::Clock, "get datetime"))?;
+        Ok(calendar::to_timestamp(&utc))
+    }
+
+    fn write(&mut self, utc: i64) -> Result<()> {
+        self.set_datetime(&calendar::from_timestamp(utc))
+            .map_err(TwatchError::driver(Device::Clock, "set datetime"))?;
+        Ok(())
+    }
+}
+
+impl Recovery for Hal<'static> {
+    fn clear_bus(&mut self, bus: Bus) -> Result<()> {
+        crate::bus::clear(bus)
+    }
+
+    fn reinit(&mut self, device: Device) -> Result<()> {
+        match device {
+            Device::Pmu => self.pmu.reinit(),
+            // The alarms are kept by the clock, reading it is enough
+            Device::Clock => self.is_clock_voltage_low().map(|_| ()),
+            Device::Accel => self.init_accel(),
+            Device::Touch => self.init_touch_screen(),
+            Device::Display => Ok(()),
+        }
+    }
 }
 
 impl Hal<'static> {
+    fn init_touch_screen(&mut self) -> Result<()> {
+        self.touch_screen
+            .init()
+            .map_err(TwatchError::driver(Device::Touch, "init"))?;
+        match self.touch_screen.get_info() {
+            Some(info) => info!("Touch screen info: {info:?}"),
+            None => warn!("No info"),
+        }
+        Ok(())
+    }
+
+    fn init_accel(&mut self) -> Result<()> {
+        self.accel
+            .init(&mut delay::Ets)
+            .map_err(TwatchError::driver(Device::Accel, "init"))?;
+        let chip_id = self
+            .accel
+            .get_chip_id()
+            .map_err(TwatchError::driver(Device::Accel, "get chip id"))?;
+        info!("BMA423 chip id: {}", chip_id as u8);
+
+        self.accel
+            .set_accel_config(
+                bma423::AccelConfigOdr::Odr100,
+                bma423::AccelConfigBandwidth::NormAvg4,
+                bma423::AccelConfigPerfMode::Continuous,
+                bma423::AccelRange::Range2g,
+            )
+            .map_err(TwatchError::driver(Device::Accel, "set accel config"))?;
+        Ok(())
+    }
+
+    /// Keep the new warnings and errors in NVS, at most every
+    /// `logbook::SAVE_PERIOD`
+    fn save_logs(&mut self) {
+        let now = utils::uptime();
+        if now.saturating_sub(self.logs_saved) >= logbook::SAVE_PERIOD {
+            logbook::save(&mut self.storage);
+            self.logs_saved = now;
+        }
+    }
+
+    /// Recover the devices of the I2C buses failing repeatedly, and show the
+    /// degraded ones in the status bar
+    fn check_health(&mut self) {
+        let now = utils::uptime();
+        let health = self.health.clone();
+        for (device, result) in health.check(now, self) {
+            match result {
+                Ok(()) => info!("{device} recovered"),
+                Err(e) => warn!("Unable to recover {device}: {e:?}"),
+            }
+        }
+        self.display.status.degraded = health.is_degraded();
+    }
+
     pub fn light_sleep(&mut self) -> Result<()> {
         self.display.set_display_off()?;
-        self.pmu.set_screen_power(State::Off)?;
+        self.pmu.release(Rail::BACKLIGHT)?;
+
+        Ok(())
+    }
+
+    /// The PCF8563 flags its time as unreliable once its supply voltage dropped,
+    /// until the time is set again
+    pub fn is_clock_voltage_low(&mut self) -> Result<bool> {
+        let mut seconds = [0u8; 1];
+        self.clock_registers
+            .write_read(PCF8563_ADDRESS, &[PCF8563_VL_SECONDS], &mut seconds)
+            .map_err(TwatchError::i2c(
+                Device::Clock,
+                "read",
+                Some(PCF8563_VL_SECONDS),
+            ))?;
+        Ok(seconds[0] & PCF8563_VOLTAGE_LOW != 0)
+    }
+
+    /// Current local time
+    pub fn local_now(&mut self) -> Result<DateTime> {
+        let utc = self
+            .clock
+            .get_datetime()
+            .map_err(TwatchError::driver(Device::Clock, "get datetime"))?;
+        Ok(calendar::from_timestamp(
+            self.tz.to_local(calendar::to_timestamp(&utc)),
+        ))
+    }
+
+    /// Set the RTC from a local time
+    pub fn set_local_datetime(&mut self, time: &DateTime) -> Result<()> {
+        let utc = calendar::from_timestamp(self.tz.to_utc(calendar::to_timestamp(time)));
+        info!(
+            "Setting time to 20{:02}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            utc.year, utc.month, utc.day, utc.hours, utc.minutes, utc.seconds
+        );
+        self.clock
+            .set_datetime(&utc)
+            .map_err(TwatchError::driver(Device::Clock, "set datetime"))?;
+        self.sync_system_time()?;
+        // Alarms are programmed relative to the current time
+        self.alarms
+            .schedule(&mut self.clock, &mut self.storage, &self.tz)
+    }
+
+    pub fn set_time_zone(&mut self, id: &str) -> Result<()> {
+        let tz = TimeZone::new(id)?;
+        self.storage.put(TZ_STORAGE_KEY, &tz.id)?;
+        info!("Time zone set to {tz}");
+        self.tz = tz;
+        self.sync_system_time()?;
+        self.alarms
+            .schedule(&mut self.clock, &mut self.storage, &self.tz)
+    }
+
+    /// Advance the Wi-Fi connection and the time synchronization using it
+    ///
+    /// `now` is the uptime.
+    fn poll_network(&mut self, now: Duration) -> Option<WifiEvent> {
+        let mut event = self.wifi.poll(now, &mut self.storage);
+        if let Some(submission) = self.wifi.take_submission() {
+            event = Some(self.apply_provisioning(now, submission));
+        }
+        if self.wifi.has_networks() {
+            if let Some(report) = self
+                .time_sync
+                .poll(now, &mut self.wifi, &mut self.sntp, &mut self.clock)
+            {
+                info!("Time synchronized, drift was {}s", report.drift);
+                if let Err(e) = self.sync_system_time().and_then(|_| {
+                    self.alarms
+                        .schedule(&mut self.clock, &mut self.storage, &self.tz)
+                }) {
+                    warn!("Error applying synchronized time: {e:?}");
+                }
+            }
+            if let Some(report) = self.weather_service.poll(
+                now,
+                calendar::utc_now(),
+                &mut self.wifi,
+                &mut self.http,
+            ) {
+                self.set_weather(report);
+            }
+        }
+        self.update_service.poll(now, &mut self.wifi, &mut self.installer);
+        self.display.status.wifi = self.wifi.indicator();
+        self.update_network_timer();
+        event
+    }
+
+    /// Poll the network every second while the Wi-Fi is used, and only from
+    /// time to time while it is off, for the services to start on schedule
+    fn update_network_timer(&mut self) {
+        let period = if !self.wifi.is_off() {
+            Some(Duration::from_secs(1))
+        } else if self.wifi.has_networks() {
+            Some(NETWORK_IDLE_POLL)
+        } else {
+            None
+        };
+        if period == self.network_period {
+            return;
+        }
+        // Dropping the timer stops it
+        self.network_timer = None;
+        self.network_period = None;
+        if let Some(period) = period {
+            let mut network_loop = self.eventloop.clone();
+            let timer = EspTimerService::new()
+                .and_then(|service| {
+                    service.timer(move || {
+                        let _ = network_loop.post(
+                            &TwatchRawEvent::Network.into(),
+                            Some(Duration::from_millis(0)),
+                        );
+                    })
+                })
+                .and_then(|mut timer| timer.every(period).map(|_| timer));
+            match timer {
+                Ok(timer) => {
+                    self.network_timer = Some(timer);
+                    self.network_period = Some(period);
+                }
+                Err(e) => warn!("Unable to start the Wi-Fi timer: {e:?}"),
+            }
+        }
+    }
+
+    /// Poll the network services at once, after a request from a tile
+    pub fn poll_network_now(&mut self) {
+        let _ = self.eventloop.post(
+            &TwatchRawEvent::Network.into(),
+            Some(Duration::from_millis(0)),
+        );
+    }
+
+    pub fn start_provisioning(&mut self) -> Result<()> {
+        let result = self.wifi.start_provisioning(utils::uptime(), &self.tz.id);
+        self.update_network_timer();
+        result
+    }
+
+    pub fn stop_provisioning(&mut self) -> Result<()> {
+        let result = self.wifi.stop_provisioning(utils::uptime());
+        self.update_network_timer();
+        result
+    }
+
+    /// Store the settings received by the portal and leave provisioning
+    fn apply_provisioning(&mut self, now: Duration, submission: Submission) -> WifiEvent {
+        info!("Provisioned Wi-Fi network {}", submission.ssid);
+        if let Err(e) = self
+            .wifi
+            .add_network(&mut self.storage, &submission.ssid, &submission.password)
+        {
+            warn!("Unable to store Wi-Fi network: {e:?}");
+        }
+        if let Some(tz) = &submission.tz {
+            self.set_time_zone(tz)
+                .unwrap_or_else(|e| warn!("Unable to set time zone: {e:?}"));
+        }
+        self.wifi
+            .stop_provisioning(now)
+            .unwrap_or_else(|e| warn!("Unable to stop provisioning: {e:?}"));
+        self.time_sync.request();
+        WifiEvent::Provisioned {
+            ssid: submission.ssid,
+        }
+    }
+
+    /// Handle the next event of the BLE link, returning the ones for the tiles
+    fn poll_ble(&mut self) -> Option<Kind> {
+        let (event, pending) = self.ble.take_event()?;
+        if pending {
+            let _ = self
+                .eventloop
+                .post(&TwatchRawEvent::Ble.into(), Some(Duration::from_millis(0)));
+        }
+        match event {
+            BleEvent::Connected => {
+                info!("Phone connected");
+                let version = Command::Version {
+                    firmware: env!("CARGO_PKG_VERSION").to_string(),
+                    hardware: "T-Watch 2020".to_string(),
+                };
+                let status = Command::Status {
+                    battery: self.pmu.get_battery_percentage().unwrap_or_default() as u8,
+                    charging: self.pmu.get_battery_current().unwrap_or_default() < 0.0,
+                };
+                for command in [version, status] {
+                    self.ble
+                        .send(&command)
+                        .unwrap_or_else(|e| warn!("Unable to send to phone: {e:?}"));
+                }
+                None
+            }
+            BleEvent::Disconnected => {
+                info!("Phone disconnected");
+                self.release_dfu();
+                None
+            }
+            BleEvent::Passkey(passkey) => {
+                self.notifier.send(Notification::new(
+                    "Bluetooth",
+                    "Pairing code",
+                    &format!("{passkey:06}"),
+                    Priority::High,
+                ));
+                None
+            }
+            BleEvent::Phone(PhoneEvent::Notification(notification)) => {
+                self.notifier.send(Notification::from_phone(notification));
+                None
+            }
+            BleEvent::Phone(PhoneEvent::Call(call)) if call.state == CallState::Incoming => {
+                self.notifier.send(Notification::from_call(call));
+                None
+            }
+            BleEvent::Dfu(request) => {
+                self.handle_dfu(request);
+                None
+            }
+            BleEvent::Phone(event) => {
+                let now = utils::uptime();
+                self.now_playing.update(&event, now);
+                if let PhoneEvent::Weather(weather) = &event {
+                    let report =
+                        Report::from_phone(weather, calendar::utc_now(), self.weather.as_ref());
+                    self.set_weather(report);
+                }
+                Some(Kind::Phone(event))
+            }
+        }
+    }
 
-        self.pmu.set_audio_power(State::Off)?;
+    /// Apply a firmware update request, reporting the progress to the update
+    /// tile as for the updates over Wi-Fi
+    fn handle_dfu(&mut self, request: Result<dfu::Request, dfu::DfuError>) {
+        let before = self.dfu.percent();
+        let response = self.dfu.handle(utils::uptime(), request);
+        if let Some(response) = &response {
+            self.ble
+                .send_dfu(response)
+                .unwrap_or_else(|e| warn!("Unable to answer update request: {e:?}"));
+        }
+        let status = match (response, self.dfu.percent()) {
+            (Some(dfu::Response::Done), _) => UpdateStatus::Done {
+                version: self.dfu.version().to_string(),
+            },
+            (Some(dfu::Response::Error(failure)), _) => {
+                UpdateStatus::Failed(format!("Update failed: {failure:?}"))
+            }
+            (_, Some(percent)) if Some(percent) != before => UpdateStatus::Downloading(percent),
+            (_, None) if before.is_some() => UpdateStatus::Failed("Update aborted".to_string()),
+            _ => return,
+        };
+        self.installer.report(status);
+    }
 
+    /// Close the slot of the update over BLE for the update over Wi-Fi to be
+    /// able to use it, the phone resumes the transfer by starting it again
+    fn release_dfu(&mut self) {
+        if self.dfu.release() {
+            self.installer
+                .report(UpdateStatus::Failed("Update interrupted".to_string()));
+        }
+    }
+
+    /// Keep a weather report, cached in NVS to be shown while offline
+    fn set_weather(&mut self, report: Report) {
+        info!("Weather: {} {:.1}C", report.text, report.temperature);
+        if let Err(e) = self.storage.put(WEATHER_STORAGE_KEY, &report) {
+            warn!("Unable to store weather: {e:?}");
+        }
+        self.weather = Some(report);
+    }
+
+    /// Set the RTC from a UTC time given in seconds since the Unix epoch
+    pub fn set_utc_time(&mut self, utc: i64) -> Result<()> {
+        timesync::Rtc::write(&mut self.clock, utc)?;
+        self.sync_system_time()?;
+        self.alarms
+            .schedule(&mut self.clock, &mut self.storage, &self.tz)
+    }
+
+    /// Seed the ESP32 system clock and libc time zone from the RTC
+    pub fn sync_system_time(&mut self) -> Result<()> {
+        let utc = self
+            .clock
+            .get_datetime()
+            .map_err(TwatchError::driver(Device::Clock, "get datetime"))?;
+        let timeval = esp_idf_sys::timeval {
+            tv_sec: calendar::to_timestamp(&utc) as _,
+            tv_usec: 0,
+        };
+        if unsafe { esp_idf_sys::settimeofday(&timeval, std::ptr::null()) } != 0 {
+            warn!("Unable to set system time");
+        }
+        std::env::set_var("TZ", &self.tz.rule);
+        unsafe { esp_idf_sys::tzset() };
         Ok(())
     }
 
+    pub fn is_sleeping(&self) -> bool {
+        self.pmu.rail_state(Rail::BACKLIGHT).users == 0
+    }
+
     #[allow(dead_code)]
     pub fn deep_sleep(&mut self) -> Result<()> {
         self.display.set_display_off()?;
-        self.pmu.set_screen_power(State::Off)?;
-
-        self.pmu.set_audio_power(State::Off)?;
+        self.pmu.power_off_rails()?;
 
         esp!(unsafe {
             esp_idf_sys::esp_sleep_enable_ext0_wakeup(esp_idf_sys::gpio_num_t_GPIO_NUM_35, 0)
         })?;
+        // Wake up when the RTC alarm rings
+        esp!(unsafe {
+            esp_idf_sys::esp_sleep_enable_ext1_wakeup(
+                1u64 << esp_idf_sys::gpio_num_t_GPIO_NUM_37,
+                esp_idf_sys::esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ALL_LOW,
+            )
+        })?;
 
-        self.motor.set_low()?;
+        self.haptics.cancel();
         esp!(unsafe { esp_idf_sys::rtc_gpio_isolate(esp_idf_sys::gpio_num_t_GPIO_NUM_4) })?;
 
         unsafe {
@@ -362,9 +1340,103 @@ impl Hal<'static> {
         Ok(())
     }
 
+    /// Battery, power rails and sensors, for the serial console
+    fn print_status(&mut self) {
+        match (
+            self.pmu.get_battery_percentage(),
+            self.pmu.get_battery_current(),
+        ) {
+            (Ok(level), Ok(current)) => println!("Battery: {level:.0}% {current:.1}mA"),
+            (Err(e), _) | (_, Err(e)) => println!("Battery: {e:?}"),
+        }
+        for rail_state in self.pmu.rail_states() {
+            println!("Power rail: {rail_state}");
+        }
+        match self.accel.accel_norm() {
+            Ok(accel) => println!(
+                "Accelerometer: {:.2} {:.2} {:.2}",
+                accel.x, accel.y, accel.z
+            ),
+            Err(e) => println!("Accelerometer: {e:?}"),
+        }
+        match self.is_clock_voltage_low() {
+            Ok(low) => println!("Clock voltage low: {low}"),
+            Err(e) => println!("Clock: {e:?}"),
+        }
+        let health = self.health.health();
+        for device in Device::ALL {
+            if Bus::of(device).is_some() {
+                println!("{device}: {}", health.device(device));
+            }
+        }
+        println!("Wi-Fi: {:?} {:?}", self.wifi.indicator(), self.wifi.ip());
+        println!("Sleeping: {}", self.is_sleeping());
+        println!("Safe mode: {}", self.safe_mode);
+    }
+
+    /// Settings kept in NVS or given at build time, without the secrets
+    fn print_settings(&self) {
+        println!("Firmware: {}", firmware::VERSION);
+        println!("Time zone: {} {}", self.tz, self.tz.rule);
+        println!("Brightness: {}", self.display.get_display_level());
+        println!("Haptics: {}", self.feedback);
+        for network in self.wifi.networks() {
+            println!("Wi-Fi network: {}", network.ssid);
+        }
+        for alarm in self.alarms.alarms() {
+            let state = if alarm.enabled { "on" } else { "off" };
+            println!("Alarm: {alarm} {} {state}", alarm.repeat);
+        }
+        println!("Notifications: {}", self.inbox.len());
+        println!(
+            "Weather: {:?} {}",
+            self.weather_service.url, self.weather_service.location
+        );
+        println!("Update: {:?}", self.update_service.url);
+    }
+
+    /// Play the vibration confirming an input, unless it is turned off or
+    /// another pattern, as an alarm, is playing
+    pub fn cue(&mut self, cue: Cue) {
+        self.cued = true;
+        if !self.feedback.plays(cue) || self.is_sleeping() || !self.haptics.is_idle() {
+            return;
+        }
+        self.haptics.play(cue.pattern());
+    }
+
+    pub fn set_feedback(&mut self, feedback: Feedback) -> Result<()> {
+        self.haptics.set_intensity(feedback.intensity);
+        self.feedback = feedback;
+        self.storage.put(FEEDBACK_STORAGE_KEY, &feedback)
+    }
+
+    /// Timings, free heap and task stacks, for the serial console
+    fn print_metrics(&self) {
+        for (metric, histogram) in Metric::ALL.iter().zip(metrics::histograms()) {
+            println!("{}: {histogram}", metric.name());
+        }
+        println!("Heap: {}", memory::heap());
+        for stack in memory::stacks() {
+            println!("Stack of {}: {} bytes left", stack.name, stack.high_water);
+        }
+    }
+
+    /// Report of the last crash, kept until cleared
+    pub fn crash_report(&self) -> Option<CrashReport> {
+        self.storage.get(CRASH_STORAGE_KEY).unwrap_or_else(|e| {
+            warn!("Unable to read crash report: {e:?}");
+            None
+        })
+    }
+
+    pub fn clear_crash_report(&mut self) -> Result<()> {
+        self.storage.remove(CRASH_STORAGE_KEY)
+    }
+
     pub fn wake_up(&mut self) -> Result<()> {
         self.display.set_display_on()?;
-        self.pmu.set_screen_power(State::On)?;
+        self.pmu.acquire(Rail::BACKLIGHT)?;
         self.display.set_display_level(25u32)?;
         Ok(())
     }
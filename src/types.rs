@@ -5,6 +5,8 @@ use esp_idf_hal::{
 
 use display_interface_spi::SPIInterfaceNoCS;
 
+use crate::health::Monitored;
+
 pub type EspSpi2InterfaceNoCS = SPIInterfaceNoCS<
     spi::Master<
         spi::SPI3,
@@ -21,7 +23,6 @@ pub type EspI2c0 =
 
 pub type EspI2c1 = i2c::Master<i2c::I2C1, gpio::Gpio23<gpio::Output>, gpio::Gpio32<gpio::Output>>;
 
-pub type EspSharedBusI2c0<'a> = shared_bus::I2cProxy<
-    'a,
-    std::sync::Mutex<EspI2c0>,
->;
+pub type EspSharedBusI2c0<'a> = Monitored<shared_bus::I2cProxy<'a, crate::bus::SharedI2c0>>;
+
+pub type EspMonitoredI2c1 = Monitored<EspI2c1>;
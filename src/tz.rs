@@ -0,0 +1,492 @@
+//! Local time from the UTC kept by the RTC, using POSIX TZ rules.
+//!
+//! Rules follow the `TZ` environment variable format described in
+//! <https://pubs.opengroup.org/onlinepubs/9699919799/basedefs/V1_chap08.html>,
+//! e.g. `CET-1CEST,M3.5.0,M10.5.0/3`. A few IANA zone names are mapped to
+//! their current rule as the full tz database doesn't fit on the watch.
+
+use crate::calendar;
+
+const DEFAULT_RULE: &str = "UTC0";
+
+/// Rule used when a DST zone doesn't specify its transitions, as glibc does
+const DEFAULT_TRANSITIONS: (Transition, Transition) = (
+    Transition {
+        date: TransitionDate::MonthWeekDay(3, 2, 0),
+        time: 7200,
+    },
+    Transition {
+        date: TransitionDate::MonthWeekDay(11, 1, 0),
+        time: 7200,
+    },
+);
+
+const ZONES: &[(&str, &str)] = &[
+    ("UTC", "UTC0"),
+    ("Etc/UTC", "UTC0"),
+    ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Dublin", "GMT0IST,M3.5.0/1,M10.5.0"),
+    ("Europe/Lisbon", "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Europe/Paris", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Brussels", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Amsterdam", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Madrid", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Rome", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Zurich", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Stockholm", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Warsaw", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Athens", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Helsinki", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Kyiv", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Istanbul", "<+03>-3"),
+    ("Europe/Moscow", "MSK-3"),
+    ("Africa/Cairo", "EET-2EEST,M4.5.5/0,M10.5.4/24"),
+    ("Africa/Johannesburg", "SAST-2"),
+    ("Africa/Lagos", "WAT-1"),
+    ("Asia/Dubai", "<+04>-4"),
+    ("Asia/Tehran", "<+0330>-3:30"),
+    ("Asia/Kolkata", "IST-5:30"),
+    ("Asia/Kathmandu", "<+0545>-5:45"),
+    ("Asia/Bangkok", "<+07>-7"),
+    ("Asia/Shanghai", "CST-8"),
+    ("Asia/Hong_Kong", "HKT-8"),
+    ("Asia/Singapore", "<+08>-8"),
+    ("Asia/Tokyo", "JST-9"),
+    ("Asia/Seoul", "KST-9"),
+    ("Australia/Perth", "AWST-8"),
+    ("Australia/Adelaide", "ACST-9:30ACDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Brisbane", "AEST-10"),
+    ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Melbourne", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Pacific/Auckland", "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+    ("Pacific/Honolulu", "HST10"),
+    ("America/Anchorage", "AKST9AKDT,M3.2.0,M11.1.0"),
+    ("America/Los_Angeles", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Phoenix", "MST7"),
+    ("America/Denver", "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Chicago", "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Mexico_City", "CST6"),
+    ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Toronto", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Halifax", "AST4ADT,M3.2.0,M11.1.0"),
+    ("America/Sao_Paulo", "<-03>3"),
+    ("America/Argentina/Buenos_Aires", "<-03>3"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TzError {
+    UnknownZone(String),
+    Syntax(String),
+}
+
+impl std::fmt::Display for TzError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TzError::UnknownZone(zone) => f.write_fmt(format_args!("unknown time zone {zone}")),
+            TzError::Syntax(rule) => f.write_fmt(format_args!("invalid TZ rule {rule}")),
+        }
+    }
+}
+
+impl std::error::Error for TzError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransitionDate {
+    /// `Jn`: day of year 1..=365, February 29th is never counted
+    Julian(u16),
+    /// `n`: day of year 0..=365, February 29th is counted
+    DayOfYear(u16),
+    /// `Mm.w.d`: day `d` (0 is Sunday) of week `w` (5 is the last) of month `m`
+    MonthWeekDay(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transition {
+    date: TransitionDate,
+    /// Local time of the transition in seconds, may be negative or exceed a day
+    time: i32,
+}
+
+impl Transition {
+    /// Local timestamp of the transition in `year`
+    fn local_timestamp(&self, year: i64) -> i64 {
+        let days = match self.date {
+            TransitionDate::Julian(n) => {
+                let leap_day = (n > 59 && calendar::is_leap_year(year as u16)) as i64;
+                calendar::days_from_civil(year, 1, 1) + n as i64 - 1 + leap_day
+            }
+            TransitionDate::DayOfYear(n) => calendar::days_from_civil(year, 1, 1) + n as i64,
+            TransitionDate::MonthWeekDay(month, week, weekday) => {
+                let first = calendar::days_from_civil(year, month, 1);
+                // 1970-01-01 was a Thursday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (weekday as i64 - first_weekday).rem_euclid(7);
+                day += 7 * (week as i64 - 1);
+                let days_in_month = calendar::days_in_month(year as u16, month) as i64;
+                while day >= first + days_in_month {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        days * 86_400 + self.time as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Dst {
+    /// Offset east of UTC in seconds
+    offset: i32,
+    start: Transition,
+    end: Transition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Configured IANA name or rule
+    pub id: String,
+    /// POSIX TZ rule, as expected by libc
+    pub rule: String,
+    /// Offset east of UTC in seconds
+    offset: i32,
+    dst: Option<Dst>,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::parse(DEFAULT_RULE).expect("Invalid default TZ rule")
+    }
+}
+
+impl TimeZone {
+    /// Build a zone from an IANA name of the built-in table, or a POSIX TZ rule
+    pub fn new(id: &str) -> Result<Self, TzError> {
+        match ZONES.iter().find(|(name, _)| name.eq_ignore_ascii_case(id)) {
+            Some((name, rule)) => Ok(Self {
+                id: name.to_string(),
+                ..Self::parse(rule)?
+            }),
+            // Transition times of rules contain '/' too, e.g. `M10.5.0/3`
+            None if id.contains('/') && !id.contains(',') => {
+                Err(TzError::UnknownZone(id.to_string()))
+            }
+            None => Self::parse(id),
+        }
+    }
+
+    /// IANA names known to `TimeZone::new`
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        ZONES.iter().map(|(name, _)| *name)
+    }
+
+    pub fn parse(rule: &str) -> Result<Self, TzError> {
+        let error = || TzError::Syntax(rule.to_string());
+        let mut parser = Parser { input: rule };
+
+        // The abbreviations are not shown
+        parser.name().ok_or_else(error)?;
+        let offset = -parser.time().ok_or_else(error)?;
+        let dst = if parser.input.is_empty() {
+            None
+        } else {
+            parser.name().ok_or_else(error)?;
+            let dst_offset = match parser.input.chars().next() {
+                Some(',') | None => offset + 3600,
+                Some(_) => -parser.time().ok_or_else(error)?,
+            };
+            let (start, end) = if parser.input.is_empty() {
+                DEFAULT_TRANSITIONS
+            } else {
+                let start = parser.transition().ok_or_else(error)?;
+                let end = parser.transition().ok_or_else(error)?;
+                (start, end)
+            };
+            Some(Dst {
+                offset: dst_offset,
+                start,
+                end,
+            })
+        };
+        if !parser.input.is_empty() {
+            return Err(error());
+        }
+
+        Ok(Self {
+            id: rule.to_string(),
+            rule: rule.to_string(),
+            offset,
+            dst,
+        })
+    }
+
+    /// Offset east of UTC in seconds in effect at the Unix timestamp `utc`
+    pub fn offset_at(&self, utc: i64) -> i32 {
+        let dst = match &self.dst {
+            Some(dst) => dst,
+            None => return self.offset,
+        };
+        let year = calendar::civil_from_days((utc + self.offset as i64).div_euclid(86_400)).0;
+        // Start is given in standard time and end in daylight saving time
+        let start = dst.start.local_timestamp(year) - self.offset as i64;
+        let end = dst.end.local_timestamp(year) - dst.offset as i64;
+        let in_dst = if start < end {
+            start <= utc && utc < end
+        } else {
+            // Southern hemisphere, DST spans the new year
+            !(end <= utc && utc < start)
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.offset
+        }
+    }
+
+    pub fn to_local(&self, utc: i64) -> i64 {
+        utc + self.offset_at(utc) as i64
+    }
+
+    /// UTC timestamp of a local time
+    ///
+    /// Local times repeated when DST ends resolve to their first occurrence,
+    /// local times skipped when DST starts are moved forward by the DST shift.
+    pub fn to_utc(&self, local: i64) -> i64 {
+        let dst_offset = self.dst.as_ref().map_or(self.offset, |dst| dst.offset);
+        let (first, second) = if dst_offset > self.offset {
+            (dst_offset, self.offset)
+        } else {
+            (self.offset, dst_offset)
+        };
+        [first, second]
+            .iter()
+            .map(|offset| local - *offset as i64)
+            .find(|utc| self.to_local(*utc) == local)
+            .unwrap_or(local - second as i64)
+    }
+}
+
+impl std::fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let end = self
+            .input
+            .find(|c| !predicate(c))
+            .unwrap_or(self.input.len());
+        let (taken, rest) = self.input.split_at(end);
+        self.input = rest;
+        taken
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.input.strip_prefix(c) {
+            Some(rest) => {
+                self.input = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Zone name, either alphabetic or quoted as `<+0330>`
+    fn name(&mut self) -> Option<String> {
+        let name = if self.eat('<') {
+            let name = self.take_while(|c| c != '>');
+            if !self.eat('>') {
+                return None;
+            }
+            name
+        } else {
+            self.take_while(|c| c.is_ascii_alphabetic())
+        };
+        (name.len() >= 3).then(|| name.to_string())
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        if digits.is_empty() || digits.len() > 3 {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds
+    fn time(&mut self) -> Option<i32> {
+        let sign = if self.eat('-') {
+            -1
+        } else {
+            self.eat('+');
+            1
+        };
+        let hours = self.number()?;
+        if hours > 167 {
+            return None;
+        }
+        let mut seconds = hours * 3600;
+        for unit in [60, 1] {
+            if !self.eat(':') {
+                break;
+            }
+            let value = self.number()?;
+            if value > 59 {
+                return None;
+            }
+            seconds += value * unit;
+        }
+        Some(sign * seconds)
+    }
+
+    /// `,date[/time]`
+    fn transition(&mut self) -> Option<Transition> {
+        if !self.eat(',') {
+            return None;
+        }
+        let date = if self.eat('M') {
+            let month = self.number()?;
+            let week = self.eat('.').then(|| self.number()).flatten()?;
+            let weekday = self.eat('.').then(|| self.number()).flatten()?;
+            if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                return None;
+            }
+            TransitionDate::MonthWeekDay(month as u8, week as u8, weekday as u8)
+        } else if self.eat('J') {
+            match self.number()? {
+                n @ 1..=365 => TransitionDate::Julian(n as u16),
+                _ => return None,
+            }
+        } else {
+            match self.number()? {
+                n @ 0..=365 => TransitionDate::DayOfYear(n as u16),
+                _ => return None,
+            }
+        };
+        let time = if self.eat('/') { self.time()? } else { 7200 };
+        Some(Transition { date, time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds since the Unix epoch of a date and time, without a zone
+    fn timestamp(year: i64, month: u8, day: u8, hours: i64, minutes: i64) -> i64 {
+        calendar::days_from_civil(year, month, day) * 86_400 + hours * 3600 + minutes * 60
+    }
+
+    #[test]
+    fn parses_rules() {
+        let tz = TimeZone::new("Europe/Paris").unwrap();
+        assert_eq!(tz.id, "Europe/Paris");
+        assert_eq!(tz.rule, "CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(
+            TimeZone::new("<+0545>-5:45").unwrap().offset,
+            5 * 3600 + 45 * 60
+        );
+        assert_eq!(
+            TimeZone::new("Mars/Olympus"),
+            Err(TzError::UnknownZone("Mars/Olympus".to_string()))
+        );
+        for rule in [
+            "",
+            "UT0",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,M13.5.0,M10.5.0",
+            "UTC0x",
+        ] {
+            assert!(TimeZone::parse(rule).is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn spring_forward() {
+        let tz = TimeZone::new("Europe/Paris").unwrap();
+        // Clocks go from 02:00 CET to 03:00 CEST on 2024-03-31, at 01:00 UTC
+        let transition = timestamp(2024, 3, 31, 1, 0);
+        assert_eq!(tz.offset_at(transition - 1), 3600);
+        assert_eq!(tz.offset_at(transition), 2 * 3600);
+        assert_eq!(
+            tz.to_local(transition - 1),
+            timestamp(2024, 3, 31, 1, 59) + 59
+        );
+        assert_eq!(tz.to_local(transition), timestamp(2024, 3, 31, 3, 0));
+        // 02:30 doesn't exist, it is moved forward by an hour
+        assert_eq!(
+            tz.to_utc(timestamp(2024, 3, 31, 2, 30)),
+            timestamp(2024, 3, 31, 1, 30)
+        );
+        assert_eq!(
+            tz.to_utc(timestamp(2024, 3, 31, 3, 30)),
+            timestamp(2024, 3, 31, 1, 30)
+        );
+    }
+
+    #[test]
+    fn fall_back() {
+        let tz = TimeZone::new("Europe/Paris").unwrap();
+        // Clocks go from 03:00 CEST back to 02:00 CET on 2024-10-27, at 01:00 UTC
+        let transition = timestamp(2024, 10, 27, 1, 0);
+        assert_eq!(tz.offset_at(transition - 1), 2 * 3600);
+        assert_eq!(tz.offset_at(transition), 3600);
+        assert_eq!(
+            tz.to_local(transition - 1800),
+            timestamp(2024, 10, 27, 2, 30)
+        );
+        assert_eq!(
+            tz.to_local(transition + 1800),
+            timestamp(2024, 10, 27, 2, 30)
+        );
+        // 02:30 happens twice, the first one is kept
+        assert_eq!(tz.to_utc(timestamp(2024, 10, 27, 2, 30)), transition - 1800);
+        assert_eq!(
+            tz.to_utc(timestamp(2024, 10, 27, 3, 30)),
+            transition + 3600 + 1800
+        );
+    }
+
+    #[test]
+    fn north_america() {
+        let tz = TimeZone::new("America/New_York").unwrap();
+        // Second Sunday of March and first Sunday of November, at 02:00 local
+        assert_eq!(tz.offset_at(timestamp(2024, 3, 10, 7, 0) - 1), -5 * 3600);
+        assert_eq!(tz.offset_at(timestamp(2024, 3, 10, 7, 0)), -4 * 3600);
+        assert_eq!(tz.offset_at(timestamp(2024, 11, 3, 6, 0) - 1), -4 * 3600);
+        assert_eq!(tz.offset_at(timestamp(2024, 11, 3, 6, 0)), -5 * 3600);
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        let tz = TimeZone::new("Australia/Sydney").unwrap();
+        // DST spans the new year
+        assert_eq!(tz.offset_at(timestamp(2024, 1, 1, 0, 0)), 11 * 3600);
+        assert_eq!(tz.offset_at(timestamp(2024, 7, 1, 0, 0)), 10 * 3600);
+        assert_eq!(tz.offset_at(timestamp(2024, 12, 31, 12, 0)), 11 * 3600);
+        // Ends on 2024-04-07 at 03:00 AEDT, starts on 2024-10-06 at 02:00 AEST
+        let end = timestamp(2024, 4, 6, 16, 0);
+        assert_eq!(tz.offset_at(end - 1), 11 * 3600);
+        assert_eq!(tz.offset_at(end), 10 * 3600);
+        let start = timestamp(2024, 10, 5, 16, 0);
+        assert_eq!(tz.offset_at(start - 1), 10 * 3600);
+        assert_eq!(tz.offset_at(start), 11 * 3600);
+        assert_eq!(tz.to_utc(timestamp(2024, 10, 6, 2, 30)), start + 1800);
+        assert_eq!(tz.to_utc(timestamp(2024, 4, 7, 2, 30)), end - 1800);
+    }
+
+    #[test]
+    fn fixed_offsets() {
+        let tz = TimeZone::new("Asia/Kolkata").unwrap();
+        let utc = timestamp(2024, 6, 1, 12, 0);
+        assert_eq!(tz.to_local(utc), timestamp(2024, 6, 1, 17, 30));
+        assert_eq!(tz.to_utc(tz.to_local(utc)), utc);
+        assert_eq!(TimeZone::default().to_local(utc), utc);
+    }
+}
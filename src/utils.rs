@@ -1,15 +1,55 @@
-#[allow(dead_code, unused_macros)]
+use std::time::Duration;
 
+use log::*;
+
+/// Execution time above which a warning is logged
+pub const SLOW_EXECUTION: Duration = Duration::from_millis(100);
+
+/// Evaluate `$content`, logging its execution time under the name `$output`,
+/// as a warning when slow, and counting it in the histogram of `$metric`
+#[cfg(feature = "esp")]
 macro_rules! measure_exec_time {
     ($content:expr, $output:expr) => {{
-        use embedded_svc::sys_time::SystemTime;
-        let start = esp_idf_svc::systime::EspSystemTime {}.now();
+        let start = std::time::Instant::now();
+        let result = { $content };
+        $crate::utils::log_exec_time($output, start.elapsed());
+        result
+    }};
+    ($content:expr, $output:expr, $metric:expr) => {{
+        let start = std::time::Instant::now();
         let result = { $content };
-        let end = esp_idf_svc::systime::EspSystemTime {}.now();
-        // log::info!("{} execution time: {:?}", $output, end - start);
+        let elapsed = start.elapsed();
+        $crate::utils::log_exec_time($output, elapsed);
+        $crate::metrics::record($metric, elapsed);
         result
     }};
 }
 
-#[allow(unused_imports)]
+#[cfg(feature = "esp")]
 pub(crate) use measure_exec_time;
+
+/// Time since the boot
+///
+/// Unlike `EspSystemTime`, which follows the wall clock once it is set from
+/// the RTC, SNTP or the phone, it never jumps.
+#[cfg(feature = "esp")]
+pub fn uptime() -> Duration {
+    Duration::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as u64)
+}
+
+pub fn log_exec_time(output: impl std::fmt::Display, elapsed: Duration) {
+    if elapsed >= SLOW_EXECUTION {
+        warn!("{output} took {}ms", elapsed.as_millis());
+    } else {
+        debug!("{output} took {}ms", elapsed.as_millis());
+    }
+}
+
+/// CRC-32 used by zlib and Ethernet
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
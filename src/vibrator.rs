@@ -0,0 +1,36 @@
+//! Motor of the watch driven by PWM, see `haptics` for the patterns.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use esp_idf_hal::{
+    gpio::{Gpio4, Output},
+    ledc::{config::TimerConfig, Channel, Timer, CHANNEL1, TIMER1},
+    prelude::*,
+};
+
+use crate::haptics::Motor;
+
+pub struct Vibrator {
+    channel: Channel<CHANNEL1, TIMER1, Arc<Timer<TIMER1>>, Gpio4<Output>>,
+}
+
+impl Vibrator {
+    pub fn new(channel: CHANNEL1, timer: TIMER1, motor: Gpio4<Output>) -> Result<Self> {
+        // Above the audible range, the motor whistles at lower frequencies
+        let config = TimerConfig::default().frequency(20.kHz().into());
+        let timer1 = Arc::new(Timer::new(timer, &config)?);
+        let mut channel = Channel::new(channel, timer1, motor)?;
+        channel.set_duty(0)?;
+        Ok(Self { channel })
+    }
+}
+
+impl Motor for Vibrator {
+    fn set_level(&mut self, level: u8) -> Result<()> {
+        let duty = level.min(100) as u32 * self.channel.get_max_duty() / 100;
+        self.channel.set_duty(duty)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Watchdog of the event loop.
+//!
+//! The task of the event loop is registered with the task watchdog of ESP-IDF,
+//! fed by each event and by a heartbeat while idle: a handler blocking for
+//! `CONFIG_ESP_TASK_WDT_TIMEOUT_S` resets the watch. Meanwhile the main task
+//! warns about it, naming the event and the tile in the crash report.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use esp_idf_sys::*;
+
+use log::*;
+
+use crate::events::TwatchRawEvent;
+
+/// Period of the heartbeat event feeding the watchdog while idle
+pub const HEARTBEAT: Duration = Duration::from_secs(2);
+/// Time after which a handler still running is reported
+const STUCK_AFTER: Duration = Duration::from_secs(2);
+
+struct Handling {
+    event: TwatchRawEvent,
+    tile: String,
+    since: Instant,
+    reported: bool,
+}
+
+static HANDLING: Mutex<Option<Handling>> = Mutex::new(None);
+
+/// Watch the calling task, the one of the event loop
+pub fn register() -> Result<()> {
+    esp!(unsafe { esp_task_wdt_add(std::ptr::null_mut()) })?;
+    info!("Event loop watched");
+    Ok(())
+}
+
+/// Feed the watchdog and note the event handled by `tile`
+pub fn begin(event: TwatchRawEvent, tile: &str) {
+    unsafe { esp_task_wdt_reset() };
+    *HANDLING.lock().unwrap() = Some(Handling {
+        event,
+        tile: tile.to_string(),
+        since: Instant::now(),
+        reported: false,
+    });
+}
+
+/// Feed the watchdog from a handler which takes long on purpose, e.g. while
+/// writing to the serial port
+pub fn feed() {
+    unsafe { esp_task_wdt_reset() };
+    if let Some(handling) = HANDLING.lock().unwrap().as_mut() {
+        handling.since = Instant::now();
+    }
+}
+
+pub fn end() {
+    *HANDLING.lock().unwrap() = None;
+}
+
+/// Warn once about a handler running for too long, from another task
+pub fn check() {
+    if let Some(handling) = HANDLING.lock().unwrap().as_mut() {
+        let elapsed = handling.since.elapsed();
+        if elapsed >= STUCK_AFTER && !handling.reported {
+            warn!(
+                "{:?} in {} tile running for {}s",
+                handling.event,
+                handling.tile,
+                elapsed.as_secs()
+            );
+            handling.reported = true;
+        }
+    }
+}
@@ -0,0 +1,671 @@
+//! Weather reports received from the phone or fetched over HTTP.
+//!
+//! The HTTP endpoint is expected to answer with the JSON of the Open-Meteo
+//! forecast API, `http://api.open-meteo.com/v1/forecast` queried with the
+//! `latitude`, `longitude`, `current_weather=true`, `timezone=auto` and
+//! `daily=weathercode,temperature_2m_max,temperature_2m_min` parameters.
+//!
+//! As for `timesync`, the network is reached through traits and time is passed
+//! explicitly, so this module does not depend on the ESP32.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::calendar;
+use crate::gadgetbridge;
+use crate::json::{self, Value};
+use crate::storage::Persist;
+use crate::timesync::Link;
+
+const STORAGE_VERSION: u8 = 1;
+
+/// Reports older than this are shown as stale
+pub const STALE_AFTER: i64 = 3 * 3600;
+/// Days of forecast kept, the cache stays useful while the watch is offline
+const MAX_DAYS: usize = 7;
+/// Larger responses are rejected, the forecast API answers with about 1 kB
+pub const MAX_RESPONSE_LEN: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Clear = 0,
+    PartlyCloudy = 1,
+    Cloudy = 2,
+    Fog = 3,
+    Drizzle = 4,
+    Rain = 5,
+    Snow = 6,
+    Thunderstorm = 7,
+    Unknown = 255,
+}
+
+impl Condition {
+    /// Condition of an OpenWeatherMap code, as sent by Gadgetbridge
+    pub fn from_owm(code: u16) -> Self {
+        match code {
+            200..=299 => Condition::Thunderstorm,
+            300..=399 => Condition::Drizzle,
+            500..=599 => Condition::Rain,
+            600..=699 => Condition::Snow,
+            700..=799 => Condition::Fog,
+            800 => Condition::Clear,
+            801 | 802 => Condition::PartlyCloudy,
+            803 | 804 => Condition::Cloudy,
+            _ => Condition::Unknown,
+        }
+    }
+
+    /// Condition of a WMO weather interpretation code, as sent by Open-Meteo
+    pub fn from_wmo(code: u16) -> Self {
+        match code {
+            0 => Condition::Clear,
+            1 | 2 => Condition::PartlyCloudy,
+            3 => Condition::Cloudy,
+            45 | 48 => Condition::Fog,
+            51..=57 => Condition::Drizzle,
+            61..=67 | 80..=82 => Condition::Rain,
+            71..=77 | 85 | 86 => Condition::Snow,
+            95..=99 => Condition::Thunderstorm,
+            _ => Condition::Unknown,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Condition::Clear,
+            1 => Condition::PartlyCloudy,
+            2 => Condition::Cloudy,
+            3 => Condition::Fog,
+            4 => Condition::Drizzle,
+            5 => Condition::Rain,
+            6 => Condition::Snow,
+            7 => Condition::Thunderstorm,
+            _ => Condition::Unknown,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Condition::Clear => "Clear",
+            Condition::PartlyCloudy => "Partly cloudy",
+            Condition::Cloudy => "Cloudy",
+            Condition::Fog => "Fog",
+            Condition::Drizzle => "Drizzle",
+            Condition::Rain => "Rain",
+            Condition::Snow => "Snow",
+            Condition::Thunderstorm => "Thunderstorm",
+            Condition::Unknown => "Unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Phone = 0,
+    Http = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayForecast {
+    /// Local date, in days since 1970-01-01
+    pub day: i64,
+    pub condition: Condition,
+    /// Temperatures in °C
+    pub high: f32,
+    pub low: f32,
+}
+
+impl DayForecast {
+    /// Day of the week, 0 being Sunday
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        (self.day + 4).rem_euclid(7) as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// UTC time of the report in seconds since the Unix epoch
+    pub updated: i64,
+    pub source: Source,
+    /// Current temperature in °C
+    pub temperature: f32,
+    pub condition: Condition,
+    pub text: String,
+    pub high: Option<f32>,
+    pub low: Option<f32>,
+    /// Relative humidity in %
+    pub humidity: Option<u8>,
+    /// Wind speed in km/h
+    pub wind: Option<f32>,
+    /// Wind direction in degrees
+    pub wind_direction: Option<u16>,
+    pub location: String,
+    /// Daily forecast, in chronological order
+    pub forecast: Vec<DayForecast>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WeatherError {
+    Json(json::JsonError),
+    /// Missing or invalid member
+    Missing(&'static str),
+}
+
+impl std::fmt::Display for WeatherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WeatherError::Json(e) => e.fmt(f),
+            WeatherError::Missing(member) => {
+                f.write_fmt(format_args!("missing or invalid {member}"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for WeatherError {}
+
+impl Report {
+    /// Report sent by the phone, which doesn't include a forecast
+    ///
+    /// The forecast of the `previous` report is kept.
+    pub fn from_phone(
+        weather: &gadgetbridge::Weather,
+        updated: i64,
+        previous: Option<&Report>,
+    ) -> Self {
+        let condition = weather
+            .code
+            .map(Condition::from_owm)
+            .unwrap_or(Condition::Unknown);
+        Self {
+            updated,
+            source: Source::Phone,
+            temperature: weather.temperature,
+            condition,
+            text: if weather.text.is_empty() {
+                condition.label().to_string()
+            } else {
+                weather.text.clone()
+            },
+            high: weather.high,
+            low: weather.low,
+            humidity: weather.humidity,
+            wind: weather.wind,
+            wind_direction: weather.wind_direction,
+            location: weather.location.clone(),
+            forecast: previous.map(|r| r.forecast.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// Seconds elapsed since the report
+    pub fn age(&self, now: i64) -> i64 {
+        (now - self.updated).max(0)
+    }
+
+    pub fn is_stale(&self, now: i64) -> bool {
+        self.age(now) > STALE_AFTER
+    }
+
+    /// Forecast of the days following `today`, given in days since 1970-01-01
+    pub fn upcoming(&self, today: i64) -> impl Iterator<Item = &DayForecast> {
+        self.forecast.iter().filter(move |d| d.day > today)
+    }
+
+    /// Forecast of `today`, given in days since 1970-01-01
+    pub fn today(&self, today: i64) -> Option<&DayForecast> {
+        self.forecast.iter().find(|d| d.day == today)
+    }
+}
+
+/// Parse a response of the Open-Meteo forecast API
+///
+/// Both the `current_weather` and the newer `current` blocks are supported.
+pub fn parse_open_meteo(body: &str, updated: i64, location: &str) -> Result<Report, WeatherError> {
+    let root = json::parse(body).map_err(WeatherError::Json)?;
+    let number = |value: &Value, keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| value.get(key))
+            .and_then(Value::as_f64)
+    };
+
+    let current = root
+        .get("current_weather")
+        .or_else(|| root.get("current"))
+        .ok_or(WeatherError::Missing("current"))?;
+    let temperature = number(current, &["temperature", "temperature_2m"])
+        .ok_or(WeatherError::Missing("temperature"))? as f32;
+    let condition = number(current, &["weathercode", "weather_code"])
+        .map(|code| Condition::from_wmo(code as u16))
+        .unwrap_or(Condition::Unknown);
+
+    let mut forecast = Vec::new();
+    if let Some(daily) = root.get("daily") {
+        let column = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| daily.get(key))
+                .and_then(Value::as_array)
+                .unwrap_or_default()
+        };
+        let days = column(&["time"]);
+        let codes = column(&["weathercode", "weather_code"]);
+        let highs = column(&["temperature_2m_max"]);
+        let lows = column(&["temperature_2m_min"]);
+        for (i, day) in days.iter().enumerate().take(MAX_DAYS) {
+            let day = day
+                .as_str()
+                .and_then(parse_date)
+                .ok_or(WeatherError::Missing("daily.time"))?;
+            let (high, low) = match (
+                highs.get(i).and_then(Value::as_f64),
+                lows.get(i).and_then(Value::as_f64),
+            ) {
+                (Some(high), Some(low)) => (high as f32, low as f32),
+                _ => return Err(WeatherError::Missing("daily temperatures")),
+            };
+            forecast.push(DayForecast {
+                day,
+                condition: codes
+                    .get(i)
+                    .and_then(Value::as_f64)
+                    .map(|code| Condition::from_wmo(code as u16))
+                    .unwrap_or(Condition::Unknown),
+                high,
+                low,
+            });
+        }
+    }
+
+    let first = forecast.first();
+    Ok(Report {
+        updated,
+        source: Source::Http,
+        temperature,
+        condition,
+        text: condition.label().to_string(),
+        high: first.map(|d| d.high),
+        low: first.map(|d| d.low),
+        humidity: number(current, &["relative_humidity_2m"]).map(|h| h.clamp(0.0, 100.0) as u8),
+        wind: number(current, &["windspeed", "wind_speed_10m"]).map(|w| w as f32),
+        wind_direction: number(current, &["winddirection", "wind_direction_10m"])
+            .map(|d| (d as i64).rem_euclid(360) as u16),
+        location: location.to_string(),
+        forecast,
+    })
+}
+
+/// Days since 1970-01-01 of an ISO 8601 date, e.g. "2022-10-18"
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.get(..10)?.split('-');
+    let year: u16 = parts.next()?.parse().ok()?;
+    let month: u8 = parts.next()?.parse().ok()?;
+    let day: u8 = parts.next()?.parse().ok()?;
+    // Also refuses an invalid month, which has 0 days
+    if day < 1 || day > calendar::days_in_month(year, month) {
+        return None;
+    }
+    Some(calendar::days_from_civil(year as i64, month, day))
+}
+
+impl Persist for Report {
+    fn encode(&self) -> Vec<u8> {
+        fn put_str(data: &mut Vec<u8>, s: &str) {
+            let s = &s.as_bytes()[..s.len().min(u8::MAX as usize)];
+            data.push(s.len() as u8);
+            data.extend_from_slice(s);
+        }
+        fn put_opt(data: &mut Vec<u8>, value: Option<f32>) {
+            data.push(value.is_some() as u8);
+            data.extend_from_slice(&value.unwrap_or_default().to_le_bytes());
+        }
+
+        let mut data = vec![STORAGE_VERSION];
+        data.extend_from_slice(&self.updated.to_le_bytes());
+        data.push(self.source as u8);
+        data.extend_from_slice(&self.temperature.to_le_bytes());
+        data.push(self.condition as u8);
+        put_str(&mut data, &self.text);
+        put_opt(&mut data, self.high);
+        put_opt(&mut data, self.low);
+        put_opt(&mut data, self.humidity.map(f32::from));
+        put_opt(&mut data, self.wind);
+        put_opt(&mut data, self.wind_direction.map(f32::from));
+        put_str(&mut data, &self.location);
+        data.push(self.forecast.len() as u8);
+        for day in &self.forecast {
+            data.extend_from_slice(&(day.day as i32).to_le_bytes());
+            data.push(day.condition as u8);
+            data.extend_from_slice(&day.high.to_le_bytes());
+            data.extend_from_slice(&day.low.to_le_bytes());
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        struct Reader<'a>(&'a [u8]);
+
+        impl<'a> Reader<'a> {
+            fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+                if self.0.len() < N {
+                    return None;
+                }
+                let (taken, rest) = self.0.split_at(N);
+                self.0 = rest;
+                taken.try_into().ok()
+            }
+            fn u8(&mut self) -> Option<u8> {
+                self.take::<1>().map(|b| b[0])
+            }
+            fn f32(&mut self) -> Option<f32> {
+                self.take().map(f32::from_le_bytes)
+            }
+            fn opt(&mut self) -> Option<Option<f32>> {
+                let present = self.u8()? != 0;
+                let value = self.f32()?;
+                Some(present.then_some(value))
+            }
+            fn string(&mut self) -> Option<String> {
+                let len = self.u8()? as usize;
+                if self.0.len() < len {
+                    return None;
+                }
+                let (s, rest) = self.0.split_at(len);
+                self.0 = rest;
+                String::from_utf8(s.to_vec()).ok()
+            }
+        }
+
+        let mut reader = Reader(data);
+        if reader.u8()? != STORAGE_VERSION {
+            return None;
+        }
+        let updated = i64::from_le_bytes(reader.take()?);
+        let source = match reader.u8()? {
+            0 => Source::Phone,
+            _ => Source::Http,
+        };
+        let temperature = reader.f32()?;
+        let condition = Condition::from_u8(reader.u8()?);
+        let text = reader.string()?;
+        let high = reader.opt()?;
+        let low = reader.opt()?;
+        let humidity = reader.opt()?.map(|h| h as u8);
+        let wind = reader.opt()?;
+        let wind_direction = reader.opt()?.map(|d| d as u16);
+        let location = reader.string()?;
+        let days = reader.u8()? as usize;
+        let mut forecast = Vec::with_capacity(days);
+        for _ in 0..days {
+            forecast.push(DayForecast {
+                day: i32::from_le_bytes(reader.take()?) as i64,
+                condition: Condition::from_u8(reader.u8()?),
+                high: reader.f32()?,
+                low: reader.f32()?,
+            });
+        }
+        Some(Self {
+            updated,
+            source,
+            temperature,
+            condition,
+            text,
+            high,
+            low,
+            humidity,
+            wind,
+            wind_direction,
+            location,
+            forecast,
+        })
+    }
+}
+
+/// Download of a resource, done in the background
+pub trait Fetcher {
+    fn start(&mut self, url: &str) -> Result<()>;
+
+    /// Body of the response, once complete
+    fn poll(&mut self) -> Option<Result<String>>;
+
+    fn stop(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchState {
+    Idle,
+    Connecting { since: Duration },
+    Fetching { since: Duration },
+}
+
+/// Periodic download of the forecast, the network being brought up only while
+/// fetching
+pub struct WeatherService {
+    pub url: Option<String>,
+    pub location: String,
+    /// Delay between two successful downloads
+    pub interval: Duration,
+    /// Delay before retrying a failed download
+    pub retry: Duration,
+    /// Maximum duration of each of the connection and download steps
+    pub timeout: Duration,
+    state: FetchState,
+    next_at: Duration,
+}
+
+impl Default for WeatherService {
+    fn default() -> Self {
+        Self {
+            url: None,
+            location: String::new(),
+            interval: Duration::from_secs(3600),
+            retry: Duration::from_secs(10 * 60),
+            timeout: Duration::from_secs(30),
+            state: FetchState::Idle,
+            next_at: Duration::ZERO,
+        }
+    }
+}
+
+impl WeatherService {
+    /// Download at the next poll instead of waiting for the interval
+    pub fn request(&mut self) {
+        self.next_at = Duration::ZERO;
+    }
+
+    /// Advance the download, `now` being a monotonic time and `utc` the current
+    /// time in seconds since the Unix epoch
+    ///
+    /// Returns the report downloaded during this poll.
+    pub fn poll(
+        &mut self,
+        now: Duration,
+        utc: i64,
+        link: &mut impl Link,
+        fetcher: &mut impl Fetcher,
+    ) -> Option<Report> {
+        let url = self.url.as_ref()?;
+        let result = match self.state {
+            FetchState::Idle if now >= self.next_at => link
+                .connect()
+                .map(|_| self.state = FetchState::Connecting { since: now }),
+            FetchState::Idle => Ok(()),
+            FetchState::Connecting { since } => {
+                if link.is_connected() {
+                    fetcher
+                        .start(url)
+                        .map(|_| self.state = FetchState::Fetching { since: now })
+                } else if now.saturating_sub(since) > self.timeout {
+                    Err(anyhow::anyhow!("Timeout connecting"))
+                } else {
+                    Ok(())
+                }
+            }
+            FetchState::Fetching { since } => match fetcher.poll() {
+                Some(body) => {
+                    let report = body.and_then(|body| {
+                        parse_open_meteo(&body, utc, &self.location).map_err(anyhow::Error::from)
+                    });
+                    let succeeded = report.is_ok();
+                    self.finish(now, link, fetcher, succeeded);
+                    return report
+                        .map_err(|e| log::warn!("Unable to get weather: {e:?}"))
+                        .ok();
+                }
+                None if now.saturating_sub(since) > self.timeout => {
+                    Err(anyhow::anyhow!("Timeout downloading"))
+                }
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = result {
+            log::warn!("Unable to get weather: {e:?}");
+            self.finish(now, link, fetcher, false);
+        }
+        None
+    }
+
+    fn finish(
+        &mut self,
+        now: Duration,
+        link: &mut impl Link,
+        fetcher: &mut impl Fetcher,
+        succeeded: bool,
+    ) {
+        fetcher.stop();
+        if self.state != FetchState::Idle {
+            if let Err(e) = link.disconnect() {
+                log::warn!("Unable to disconnect: {e:?}");
+            }
+        }
+        self.state = FetchState::Idle;
+        self.next_at = now + if succeeded { self.interval } else { self.retry };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Response with the `current_weather` block, for Rennes
+    const CURRENT_WEATHER: &str = r#"{"latitude":48.1,"longitude":-1.6799998,"generationtime_ms":0.4190206527709961,"utc_offset_seconds":7200,"timezone":"Europe/Paris","timezone_abbreviation":"CEST","elevation":42.0,"current_weather":{"temperature":17.3,"windspeed":11.2,"winddirection":245.0,"weathercode":3,"is_day":1,"time":"2023-10-18T14:00"},"daily_units":{"time":"iso8601","weathercode":"wmo code","temperature_2m_max":"°C","temperature_2m_min":"°C"},"daily":{"time":["2023-10-18","2023-10-19","2023-10-20","2023-10-21","2023-10-22","2023-10-23","2023-10-24","2023-10-25"],"weathercode":[3,61,80,2,0,45,95,71],"temperature_2m_max":[18.9,16.2,15.0,14.1,13.8,12.5,15.3,9.0],"temperature_2m_min":[11.4,10.8,9.7,7.2,5.9,6.3,8.0,1.2]}}"#;
+
+    /// Response with the newer `current` block, for Sydney
+    const CURRENT: &str = r#"{"latitude":-33.875,"longitude":151.25,"generationtime_ms":0.0439882278442383,"utc_offset_seconds":39600,"timezone":"Australia/Sydney","timezone_abbreviation":"AEDT","elevation":13.0,"current_units":{"time":"iso8601","interval":"seconds","temperature_2m":"°C","relative_humidity_2m":"%","weather_code":"wmo code","wind_speed_10m":"km/h","wind_direction_10m":"°"},"current":{"time":"2024-01-15T15:00","interval":900,"temperature_2m":27.4,"relative_humidity_2m":58,"weather_code":95,"wind_speed_10m":19.8,"wind_direction_10m":-10},"daily_units":{"time":"iso8601","weather_code":"wmo code","temperature_2m_max":"°C","temperature_2m_min":"°C"},"daily":{"time":["2024-01-15","2024-01-16"],"weather_code":[95,1],"temperature_2m_max":[29.1,26.0],"temperature_2m_min":[21.3,20.2]}}"#;
+
+    const UPDATED: i64 = 1_697_630_400;
+
+    #[test]
+    fn parses_current_weather() {
+        let report = parse_open_meteo(CURRENT_WEATHER, UPDATED, "Rennes").unwrap();
+        assert_eq!(report.updated, UPDATED);
+        assert_eq!(report.source, Source::Http);
+        assert_eq!(report.temperature, 17.3);
+        assert_eq!(report.condition, Condition::Cloudy);
+        assert_eq!(report.text, "Cloudy");
+        assert_eq!((report.high, report.low), (Some(18.9), Some(11.4)));
+        assert_eq!(report.humidity, None);
+        assert_eq!(report.wind, Some(11.2));
+        assert_eq!(report.wind_direction, Some(245));
+        assert_eq!(report.location, "Rennes");
+
+        assert_eq!(report.forecast.len(), MAX_DAYS);
+        let today = calendar::days_from_civil(2023, 10, 18);
+        assert_eq!(
+            report.today(today),
+            Some(&DayForecast {
+                day: today,
+                condition: Condition::Cloudy,
+                high: 18.9,
+                low: 11.4,
+            })
+        );
+        let conditions: Vec<_> = report.upcoming(today).map(|d| d.condition).collect();
+        assert_eq!(
+            conditions,
+            [
+                Condition::Rain,
+                Condition::Rain,
+                Condition::PartlyCloudy,
+                Condition::Clear,
+                Condition::Fog,
+                Condition::Thunderstorm,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_current() {
+        let report = parse_open_meteo(CURRENT, UPDATED, "Sydney").unwrap();
+        assert_eq!(report.temperature, 27.4);
+        assert_eq!(report.condition, Condition::Thunderstorm);
+        assert_eq!(report.humidity, Some(58));
+        assert_eq!(report.wind, Some(19.8));
+        assert_eq!(report.wind_direction, Some(350));
+        assert_eq!((report.high, report.low), (Some(29.1), Some(21.3)));
+        let days: Vec<_> = report.forecast.iter().map(|d| d.day).collect();
+        let first = calendar::days_from_civil(2024, 1, 15);
+        assert_eq!(days, [first, first + 1]);
+    }
+
+    #[test]
+    fn parses_without_forecast() {
+        let body = r#"{"current_weather":{"temperature":-2.5,"weathercode":71}}"#;
+        let report = parse_open_meteo(body, UPDATED, "").unwrap();
+        assert_eq!(report.temperature, -2.5);
+        assert_eq!(report.condition, Condition::Snow);
+        assert_eq!((report.high, report.low), (None, None));
+        assert!(report.forecast.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        let parse = |body: &str| parse_open_meteo(body, UPDATED, "Rennes");
+        assert!(matches!(parse("<html>"), Err(WeatherError::Json(_))));
+        assert_eq!(
+            parse(r#"{"error":true,"reason":"Latitude must be in range"}"#),
+            Err(WeatherError::Missing("current"))
+        );
+        assert_eq!(
+            parse(&CURRENT_WEATHER.replace("\"temperature\":17.3,", "")),
+            Err(WeatherError::Missing("temperature"))
+        );
+        assert_eq!(
+            parse(&CURRENT_WEATHER.replace("2023-10-19", "2023-02-31")),
+            Err(WeatherError::Missing("daily.time"))
+        );
+        assert_eq!(
+            parse(&CURRENT.replace("\"temperature_2m_min\":[21.3,20.2]", "\"x\":[]")),
+            Err(WeatherError::Missing("daily temperatures"))
+        );
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2023-10-18T14:00"), Some(19648));
+        assert_eq!(
+            parse_date("2024-02-29"),
+            Some(calendar::days_from_civil(2024, 2, 29))
+        );
+        for invalid in [
+            "2023-02-29",
+            "2023-02-31",
+            "2023-04-31",
+            "2023-13-01",
+            "2023-00-10",
+            "2023-10-00",
+            "2023-10",
+            "18/10/2023",
+        ] {
+            assert_eq!(parse_date(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn persists() {
+        let report = parse_open_meteo(CURRENT, UPDATED, "Sydney").unwrap();
+        assert_eq!(Report::decode(&report.encode()), Some(report));
+        assert_eq!(Report::decode(&[STORAGE_VERSION + 1]), None);
+    }
+}
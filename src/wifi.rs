@@ -0,0 +1,450 @@
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use embedded_svc::wifi::{
+    AccessPointConfiguration, AuthMethod, ClientConfiguration, ClientConnectionStatus,
+    ClientIpStatus, ClientStatus, Configuration, Status, Wifi as _,
+};
+use esp_idf_svc::netif::EspNetifStack;
+use esp_idf_svc::nvs::EspDefaultNvs;
+use esp_idf_svc::sntp::{EspSntp, SyncStatus};
+use esp_idf_svc::sysloop::EspSysLoopStack;
+use esp_idf_svc::wifi::EspWifi;
+
+use log::*;
+
+use crate::display::WifiIndicator;
+use crate::portal::Portal;
+use crate::provisioning::Submission;
+use crate::storage::{Persist, Storage};
+use crate::timesync::{Link, TimeSource};
+
+/// Station credentials added to the stored networks at boot, given at build time
+const SSID: Option<&str> = option_env!("TWATCH_WIFI_SSID");
+const PASSWORD: Option<&str> = option_env!("TWATCH_WIFI_PASSWORD");
+
+const STORAGE_KEY: &str = "wifi";
+const STORAGE_VERSION: u8 = 1;
+
+pub const MAX_NETWORKS: usize = 5;
+
+/// Open access point started for provisioning
+pub const AP_SSID: &str = "TWatch-setup";
+/// Default address of the esp-idf-svc access point interface
+pub const AP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+
+/// Time given to each network to associate and get an IP address
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub ssid: String,
+    pub password: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Networks(Vec<Network>);
+
+impl Persist for Networks {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![STORAGE_VERSION];
+        for network in &self.0 {
+            for field in [&network.ssid, &network.password] {
+                data.push(field.len() as u8);
+                data.extend_from_slice(field.as_bytes());
+            }
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        fn field(data: &mut &[u8]) -> Option<String> {
+            let (len, rest) = data.split_first()?;
+            if rest.len() < *len as usize {
+                return None;
+            }
+            let (value, rest) = rest.split_at(*len as usize);
+            *data = rest;
+            String::from_utf8(value.to_vec()).ok()
+        }
+
+        let (version, mut data) = data.split_first()?;
+        if *version != STORAGE_VERSION {
+            return None;
+        }
+        let mut networks = Vec::new();
+        while !data.is_empty() {
+            networks.push(Network {
+                ssid: field(&mut data)?,
+                password: field(&mut data)?,
+            });
+        }
+        Some(Self(networks))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WifiEvent {
+    Connected { ssid: String },
+    GotIp(Ipv4Addr),
+    Disconnected,
+    Provisioned { ssid: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Off,
+    Connecting {
+        network: usize,
+        since: Duration,
+        attempt: u32,
+    },
+    Connected {
+        network: usize,
+    },
+    Backoff {
+        until: Duration,
+        attempt: u32,
+    },
+    AccessPoint,
+}
+
+/// Wi-Fi station connecting to stored networks on demand
+///
+/// Users take a reference with `Link::connect` and drop it with
+/// `Link::disconnect`, the radio is stopped when nobody needs it.
+pub struct WifiManager {
+    wifi: EspWifi,
+    networks: Networks,
+    users: u8,
+    state: State,
+    associated: bool,
+    ip: Option<Ipv4Addr>,
+    portal: Option<Portal>,
+}
+
+impl WifiManager {
+    pub fn new(
+        netif_stack: Arc<EspNetifStack>,
+        sys_loop_stack: Arc<EspSysLoopStack>,
+        default_nvs: Arc<EspDefaultNvs>,
+    ) -> Result<Self> {
+        Ok(Self {
+            wifi: EspWifi::new(netif_stack, sys_loop_stack, default_nvs)?,
+            networks: Networks::default(),
+            users: 0,
+            state: State::Off,
+            associated: false,
+            ip: None,
+            portal: None,
+        })
+    }
+
+    pub fn load(&mut self, storage: &mut Storage) -> Result<()> {
+        self.networks = storage.get(STORAGE_KEY)?.unwrap_or_default();
+        if let Some(ssid) = SSID {
+            if !self.networks.0.iter().any(|n| n.ssid == ssid) {
+                self.add_network(storage, ssid, PASSWORD.unwrap_or_default())?;
+            }
+        }
+        info!("{} Wi-Fi networks loaded", self.networks.0.len());
+        Ok(())
+    }
+
+    pub fn networks(&self) -> &[Network] {
+        &self.networks.0
+    }
+
+    pub fn has_networks(&self) -> bool {
+        !self.networks.0.is_empty()
+    }
+
+    /// Add a network or update its password, it is tried first on next connection
+    pub fn add_network(&mut self, storage: &mut Storage, ssid: &str, password: &str) -> Result<()> {
+        if ssid.is_empty() || ssid.len() > 32 || password.len() > 64 {
+            anyhow::bail!("Invalid Wi-Fi credentials for {ssid}");
+        }
+        self.networks.0.retain(|n| n.ssid != ssid);
+        self.networks.0.insert(
+            0,
+            Network {
+                ssid: ssid.to_string(),
+                password: password.to_string(),
+            },
+        );
+        self.networks.0.truncate(MAX_NETWORKS);
+        storage.put(STORAGE_KEY, &self.networks)
+    }
+
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.ip
+    }
+
+    /// Signal strength of the access point in dBm, while connected
+    pub fn rssi(&self) -> Option<i8> {
+        if !self.associated {
+            return None;
+        }
+        let mut ap_info: esp_idf_sys::wifi_ap_record_t = Default::default();
+        match unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) } {
+            esp_idf_sys::ESP_OK => Some(ap_info.rssi),
+            _ => None,
+        }
+    }
+
+    /// Network the station is connected to
+    pub fn ssid(&self) -> Option<String> {
+        match self.state {
+            State::Connected { .. } => self.current_ssid(),
+            _ => None,
+        }
+    }
+
+    pub fn indicator(&self) -> WifiIndicator {
+        match self.state {
+            State::Off => WifiIndicator::Off,
+            State::AccessPoint => WifiIndicator::AccessPoint,
+            State::Connected { .. } if self.ip.is_some() => WifiIndicator::Connected {
+                rssi: self.rssi().unwrap_or(i8::MIN),
+            },
+            _ => WifiIndicator::Connecting,
+        }
+    }
+
+    /// Advance the connection, `now` being a monotonic time
+    pub fn poll(&mut self, now: Duration, storage: &mut Storage) -> Option<WifiEvent> {
+        let (associated, ip) = match self.wifi.get_status() {
+            Status(ClientStatus::Started(ClientConnectionStatus::Connected(ip_status)), _) => (
+                true,
+                match ip_status {
+                    ClientIpStatus::Done(settings) => Some(settings.ip),
+                    _ => None,
+                },
+            ),
+            _ => (false, None),
+        };
+
+        match self.state {
+            State::Off | State::AccessPoint => {}
+            State::Connecting {
+                network,
+                since,
+                attempt,
+            } => {
+                if ip.is_some() {
+                    // Try the last working network first next time, even after a reboot
+                    if network > 0 {
+                        let network = self.networks.0.remove(network);
+                        self.networks.0.insert(0, network);
+                        if let Err(e) = storage.put(STORAGE_KEY, &self.networks) {
+                            warn!("Unable to store Wi-Fi networks: {e:?}");
+                        }
+                    }
+                    self.state = State::Connected { network: 0 };
+                } else if now.saturating_sub(since) > CONNECT_TIMEOUT {
+                    warn!("Timeout connecting to {}", self.networks.0[network].ssid);
+                    if network + 1 < self.networks.0.len() {
+                        self.start_connecting(now, network + 1, attempt);
+                    } else {
+                        let backoff = MIN_BACKOFF
+                            .saturating_mul(1 << attempt.min(16))
+                            .min(MAX_BACKOFF);
+                        info!("No Wi-Fi network available, retrying in {backoff:?}");
+                        let _ = self.wifi.set_configuration(&Configuration::None);
+                        self.state = State::Backoff {
+                            until: now + backoff,
+                            attempt: attempt + 1,
+                        };
+                    }
+                }
+            }
+            State::Connected { .. } => {
+                if !associated {
+                    warn!("Wi-Fi connection lost");
+                    self.start_connecting(now, 0, 0);
+                }
+            }
+            State::Backoff { until, attempt } => {
+                if now >= until {
+                    self.start_connecting(now, 0, attempt);
+                }
+            }
+        }
+
+        let event = match (self.associated, associated, self.ip, ip) {
+            (_, _, None, Some(ip)) => Some(WifiEvent::GotIp(ip)),
+            (false, true, _, _) => Some(WifiEvent::Connected {
+                ssid: self.current_ssid().unwrap_or_default(),
+            }),
+            (true, false, _, _) => Some(WifiEvent::Disconnected),
+            _ => None,
+        };
+        self.associated = associated;
+        self.ip = ip;
+        event
+    }
+
+    /// Neither connecting nor provisioning
+    pub fn is_off(&self) -> bool {
+        self.state == State::Off
+    }
+
+    pub fn is_provisioning(&self) -> bool {
+        self.state == State::AccessPoint
+    }
+
+    /// Start the access point and the portal to enter credentials from a browser
+    pub fn start_provisioning(&mut self, now: Duration, current_tz: &str) -> Result<()> {
+        if self.is_provisioning() {
+            return Ok(());
+        }
+        info!("Starting provisioning access point {AP_SSID}");
+        self.wifi
+            .set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+                ssid: AP_SSID.into(),
+                auth_method: AuthMethod::None,
+                channel: 1,
+                ..Default::default()
+            }))?;
+        let previous = std::mem::replace(&mut self.state, State::AccessPoint);
+        match Portal::start(AP_IP, current_tz) {
+            Ok(portal) => {
+                self.portal = Some(portal);
+                Ok(())
+            }
+            Err(e) => {
+                // Without the portal the access point is useless, back to the station
+                match previous {
+                    State::Connecting { network, .. } | State::Connected { network } => {
+                        self.start_connecting(now, network, 0)
+                    }
+                    _ => {
+                        self.state = previous;
+                        self.wifi.set_configuration(&Configuration::None)?;
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Stop the access point, reconnecting the station if it is still needed
+    pub fn stop_provisioning(&mut self, now: Duration) -> Result<()> {
+        if !self.is_provisioning() {
+            return Ok(());
+        }
+        self.portal = None;
+        if self.users > 0 {
+            self.start_connecting(now, 0, 0);
+        } else {
+            self.state = State::Off;
+            self.wifi.set_configuration(&Configuration::None)?;
+        }
+        Ok(())
+    }
+
+    /// Credentials submitted through the portal since the last call
+    pub fn take_submission(&self) -> Option<Submission> {
+        self.portal.as_ref().and_then(Portal::take_submission)
+    }
+
+    fn current_ssid(&self) -> Option<String> {
+        let network = match self.state {
+            State::Connecting { network, .. } | State::Connected { network } => network,
+            _ => return None,
+        };
+        self.networks.0.get(network).map(|n| n.ssid.clone())
+    }
+
+    fn start_connecting(&mut self, now: Duration, network: usize, attempt: u32) {
+        let Network { ssid, password } = match self.networks.0.get(network) {
+            Some(network) => network.clone(),
+            None => {
+                self.state = State::Backoff {
+                    until: now + MAX_BACKOFF,
+                    attempt,
+                };
+                return;
+            }
+        };
+        info!("Connecting to Wi-Fi {ssid}");
+        let result = self
+            .wifi
+            .set_configuration(&Configuration::Client(ClientConfiguration {
+                ssid: ssid.as_str().into(),
+                password: password.as_str().into(),
+                ..Default::default()
+            }));
+        if let Err(e) = result {
+            warn!("Unable to configure Wi-Fi: {e:?}");
+        }
+        self.state = State::Connecting {
+            network,
+            since: now,
+            attempt,
+        };
+    }
+}
+
+impl Link for WifiManager {
+    fn connect(&mut self) -> Result<()> {
+        if !self.has_networks() {
+            anyhow::bail!("No Wi-Fi network configured");
+        }
+        self.users = self.users.saturating_add(1);
+        // While provisioning, the station is started when the access point stops
+        if self.state == State::Off {
+            self.start_connecting(crate::utils::uptime(), 0, 0);
+        }
+        Ok(())
+    }
+
+    fn is_connected(&mut self) -> bool {
+        self.ip.is_some()
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        self.users = self.users.saturating_sub(1);
+        if self.users == 0 && !matches!(self.state, State::Off | State::AccessPoint) {
+            info!("Stopping Wi-Fi");
+            self.state = State::Off;
+            self.wifi.set_configuration(&Configuration::None)?;
+        }
+        Ok(())
+    }
+}
+
+/// SNTP client, running only while synchronizing
+#[derive(Default)]
+pub struct Sntp {
+    sntp: Option<EspSntp>,
+}
+
+impl TimeSource for Sntp {
+    fn start(&mut self) -> Result<()> {
+        self.sntp = Some(EspSntp::new_default()?);
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Option<i64> {
+        match &self.sntp {
+            Some(sntp) if sntp.get_sync_status() == SyncStatus::Completed => {
+                // SNTP updates the system time
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()
+                    .map(|now| now.as_secs() as i64)
+            }
+            _ => None,
+        }
+    }
+
+    fn stop(&mut self) {
+        self.sntp = None;
+    }
+}
@@ -0,0 +1,155 @@
+#!/usr/bin/env python3
+"""Take a screenshot of the watch over its serial console, as a PNG.
+
+The watch sends the screen row by row, see src/screenshot.rs for the format.
+
+    tools/screenshot.py /dev/ttyUSB0 screenshot.png
+    tools/screenshot.py capture.bin screenshot.png
+
+The first form sends the `screenshot` command and reads the answer, the serial
+monitor must be closed. The second one decodes the raw bytes received after the
+command, e.g. saved by a terminal.
+"""
+
+import argparse
+import os
+import select
+import struct
+import sys
+import time
+import zlib
+
+MAGIC = b"TWSS"
+HEADER = struct.Struct("<4sHHHH")
+TIMEOUT = 30
+
+
+class Screenshot:
+    def __init__(self):
+        self.width = None
+        self.height = None
+        self.rows = {}
+
+    def is_complete(self):
+        return self.height is not None and len(self.rows) == self.height
+
+    def feed(self, data):
+        """Decode the frames found in data, returning the bytes left over"""
+        while True:
+            start = data.find(MAGIC)
+            if start < 0:
+                return data[-(len(MAGIC) - 1):]
+            data = data[start:]
+            if len(data) < HEADER.size:
+                return data
+            _, width, height, index, length = HEADER.unpack_from(data)
+            if width == 0 or length > 3 * width + 2:
+                data = data[1:]
+                continue
+            end = HEADER.size + length + 4
+            if len(data) < end:
+                return data
+            (crc,) = struct.unpack_from("<I", data, end - 4)
+            if zlib.crc32(data[len(MAGIC):end - 4]) != crc:
+                # Not a frame, or a corrupted one
+                data = data[1:]
+                continue
+            if (self.width, self.height) != (width, height):
+                self.width, self.height, self.rows = width, height, {}
+            pixels = decompress(data[HEADER.size:end - 4])
+            if index < height and len(pixels) == width:
+                self.rows[index] = pixels
+            else:
+                print(f"Ignoring invalid row {index}", file=sys.stderr)
+            data = data[end:]
+
+    def png(self):
+        black = [0] * self.width
+        raw = b"".join(
+            b"\0" + bytes(channel for pixel in self.rows.get(y, black) for channel in rgb(pixel))
+            for y in range(self.height)
+        )
+        header = struct.pack(">IIBBBBB", self.width, self.height, 8, 2, 0, 0, 0)
+        return b"\x89PNG\r\n\x1a\n" + b"".join(
+            chunk(kind, content)
+            for kind, content in [(b"IHDR", header), (b"IDAT", zlib.compress(raw)), (b"IEND", b"")]
+        )
+
+
+def decompress(data):
+    pixels = []
+    i = 0
+    while i < len(data):
+        n = data[i]
+        i += 1
+        if n < 128:
+            count = n + 1
+            pixels.extend(struct.unpack_from(f"<{count}H", data, i))
+            i += 2 * count
+        else:
+            (pixel,) = struct.unpack_from("<H", data, i)
+            pixels.extend([pixel] * (n - 126))
+            i += 2
+    return pixels
+
+
+def rgb(pixel):
+    r, g, b = pixel >> 11, (pixel >> 5) & 0x3F, pixel & 0x1F
+    return (r * 255 // 31, g * 255 // 63, b * 255 // 31)
+
+
+def chunk(kind, content):
+    crc = zlib.crc32(kind + content)
+    return struct.pack(">I", len(content)) + kind + content + struct.pack(">I", crc)
+
+
+def read_port(path, baudrate, screenshot):
+    import termios
+    import tty
+
+    fd = os.open(path, os.O_RDWR | os.O_NOCTTY)
+    try:
+        tty.setraw(fd)
+        attributes = termios.tcgetattr(fd)
+        speed = getattr(termios, f"B{baudrate}")
+        attributes[4] = attributes[5] = speed
+        termios.tcsetattr(fd, termios.TCSANOW, attributes)
+        termios.tcflush(fd, termios.TCIOFLUSH)
+        os.write(fd, b"\nscreenshot\n")
+
+        data = b""
+        deadline = time.monotonic() + TIMEOUT
+        while not screenshot.is_complete() and time.monotonic() < deadline:
+            ready, _, _ = select.select([fd], [], [], 1)
+            if ready:
+                data = screenshot.feed(data + os.read(fd, 4096))
+    finally:
+        os.close(fd)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("input", help="serial port of the watch, or file of the raw bytes received")
+    parser.add_argument("output", help="PNG file to write")
+    parser.add_argument("--baudrate", type=int, default=115200)
+    args = parser.parse_args()
+
+    screenshot = Screenshot()
+    if os.path.isfile(args.input):
+        with open(args.input, "rb") as f:
+            screenshot.feed(f.read())
+    else:
+        read_port(args.input, args.baudrate, screenshot)
+
+    if screenshot.height is None:
+        sys.exit("No screenshot received")
+    if not screenshot.is_complete():
+        missing = screenshot.height - len(screenshot.rows)
+        print(f"{missing} rows missing, drawn in black", file=sys.stderr)
+    with open(args.output, "wb") as f:
+        f.write(screenshot.png())
+    print(f"Wrote {screenshot.width}x{screenshot.height} {args.output}")
+
+
+if __name__ == "__main__":
+    main()